@@ -0,0 +1,88 @@
+//! C library functions C compilers know the prototypes of. Declaring them
+//! again with lyth's types, like `int32_t printf(int8_t *, ...)`, conflicts
+//! with the builtin declarations, so their headers are included instead and
+//! the arguments are converted to the parameter types of the prototype.
+//! Only functions returning integers or `void *` are listed, which convert
+//! to lyth's types implicitly.
+
+pub(super) struct Prototype {
+    pub name: &'static str,
+    pub header: &'static str,
+    pub params: &'static [&'static str],
+    pub variadic: bool,
+}
+
+const fn prototype(
+    name: &'static str,
+    header: &'static str,
+    params: &'static [&'static str],
+    variadic: bool,
+) -> Prototype {
+    Prototype {
+        name,
+        header,
+        params,
+        variadic,
+    }
+}
+
+const PROTOTYPES: &[Prototype] = &[
+    prototype("printf", "stdio.h", &["const char *"], true),
+    prototype("sprintf", "stdio.h", &["char *", "const char *"], true),
+    prototype(
+        "snprintf",
+        "stdio.h",
+        &["char *", "size_t", "const char *"],
+        true,
+    ),
+    prototype("puts", "stdio.h", &["const char *"], false),
+    prototype("putchar", "stdio.h", &["int"], false),
+    prototype("getchar", "stdio.h", &[], false),
+    prototype("malloc", "stdlib.h", &["size_t"], false),
+    prototype("calloc", "stdlib.h", &["size_t", "size_t"], false),
+    prototype("realloc", "stdlib.h", &["void *", "size_t"], false),
+    prototype("free", "stdlib.h", &["void *"], false),
+    prototype("abort", "stdlib.h", &[], false),
+    prototype("exit", "stdlib.h", &["int"], false),
+    prototype("abs", "stdlib.h", &["int"], false),
+    prototype("labs", "stdlib.h", &["long"], false),
+    prototype("atoi", "stdlib.h", &["const char *"], false),
+    prototype("atol", "stdlib.h", &["const char *"], false),
+    prototype(
+        "memcpy",
+        "string.h",
+        &["void *", "const void *", "size_t"],
+        false,
+    ),
+    prototype(
+        "memmove",
+        "string.h",
+        &["void *", "const void *", "size_t"],
+        false,
+    ),
+    prototype("memset", "string.h", &["void *", "int", "size_t"], false),
+    prototype(
+        "memcmp",
+        "string.h",
+        &["const void *", "const void *", "size_t"],
+        false,
+    ),
+    prototype("strlen", "string.h", &["const char *"], false),
+    prototype(
+        "strcmp",
+        "string.h",
+        &["const char *", "const char *"],
+        false,
+    ),
+    prototype(
+        "strncmp",
+        "string.h",
+        &["const char *", "const char *", "size_t"],
+        false,
+    ),
+];
+
+/// The prototype of the C library function `symbol`, if it is a known one.
+pub(super) fn lookup(symbol: &str) -> Option<&'static Prototype> {
+    PROTOTYPES.iter().find(|prototype| prototype.name == symbol)
+}
@@ -0,0 +1,1319 @@
+//! Generates portable C99 from a lyth program, for targets LLVM doesn't
+//! support or builds of lyth without LLVM. `Generator` lowers the typed
+//! program `semantics::Checker` produces like `compiler::Compiler` does,
+//! only the output differs.
+//!
+//! Inside functions every value is computed into a temporary of its own,
+//! in the order the compiler evaluates it, so C's unspecified evaluation
+//! order never matters. Arithmetic wraps like it does in LLVM.
+
+mod libc;
+mod prune;
+mod types;
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::token::TokenKind;
+use crate::parser::ast;
+use crate::semantics::typed::{self, Arm, Expr, ExprKind, Place, PlaceKind, Program, Stmt};
+use crate::semantics::{is_dyn, Checker, ARITH_PANIC_HANDLER, BOUNDS_PANIC_HANDLER};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use types::c_ident;
+
+/// How calls refer to a declared function. Arguments are converted to
+/// `casts` if its prototype comes from a C library header.
+struct Function {
+    symbol: String,
+    casts: &'static [&'static str],
+}
+
+/// Generates a C source file from a lyth program.
+pub struct Generator<'a> {
+    file: &'a str,
+    headers: BTreeSet<&'static str>,
+    type_decls: String,
+    types: String,
+    prototypes: String,
+    globals: String,
+    code: String,
+    declared_types: HashSet<String>,
+    defined_types: HashSet<String>,
+    pending_types: Vec<ast::Type>,
+    /// The local declarations and the statements of the function being
+    /// generated, which are joined once it is done.
+    locals: String,
+    body: String,
+    depth: usize,
+    names: usize,
+    terminated: bool,
+    source: String,
+    program: Program,
+    /// The function being generated, `None` for the initializers of
+    /// statics, which are C constant expressions.
+    function: Option<usize>,
+    functions: HashMap<String, Function>,
+    /// The C variables of the locals of the function being generated.
+    variables: Vec<String>,
+    vtables: HashMap<String, String>,
+    thunks: usize,
+    /// The default panic handlers emitted so far.
+    handlers: HashSet<&'static str>,
+    bounds_checks: bool,
+    line_directives: bool,
+}
+
+/// What every generated file starts with. The headers of the C library are
+/// only included for the functions programs declare, see `libc`.
+const PRELUDE: &str = "\
+#include <stdbool.h>
+#include <stdint.h>
+
+#if defined(__GNUC__)
+#define LYTH_ATTR(...) __attribute__((__VA_ARGS__))
+#define LYTH_TRAP() __builtin_trap()
+#define LYTH_UNREACHABLE() __builtin_unreachable()
+#else
+#define LYTH_ATTR(...)
+void abort(void);
+#define LYTH_TRAP() abort()
+#define LYTH_UNREACHABLE() abort()
+#endif
+";
+
+impl<'a> Generator<'a> {
+    /// Creates a generator for the program whose root is `file`, which is
+    /// also the file named in error messages.
+    pub fn new(file: &'a str) -> Self {
+        Self {
+            file,
+            headers: BTreeSet::new(),
+            type_decls: String::new(),
+            types: String::new(),
+            prototypes: String::new(),
+            globals: String::new(),
+            code: String::new(),
+            declared_types: HashSet::new(),
+            defined_types: HashSet::new(),
+            pending_types: Vec::new(),
+            locals: String::new(),
+            body: String::new(),
+            depth: 0,
+            names: 0,
+            terminated: false,
+            source: String::new(),
+            program: Program::default(),
+            function: None,
+            functions: HashMap::new(),
+            variables: Vec::new(),
+            vtables: HashMap::new(),
+            thunks: 0,
+            handlers: HashSet::new(),
+            bounds_checks: true,
+            line_directives: false,
+        }
+    }
+
+    /// Enables or disables the runtime bounds checks on array and slice
+    /// indexing. They are enabled by default.
+    pub fn set_bounds_checks(&mut self, enabled: bool) {
+        self.bounds_checks = enabled;
+    }
+
+    /// Enables or disables `#line` directives, which make C compilers and
+    /// debuggers refer to the lyth source. They are disabled by default.
+    pub fn set_line_directives(&mut self, enabled: bool) {
+        self.line_directives = enabled;
+    }
+
+    /// Generates C for the declarations of a loaded program and writes it
+    /// to `path`, or returns the errors in the program.
+    pub fn compile(&mut self, decls: Vec<ast::Decl>, path: &str) -> Result<(), Vec<Diagnostic>> {
+        self.build(decls)?;
+        self.emit(path);
+        Ok(())
+    }
+
+    /// Generates C for the declarations of a loaded program, or returns
+    /// the errors in the program.
+    pub fn build(&mut self, decls: Vec<ast::Decl>) -> Result<(), Vec<Diagnostic>> {
+        self.program = Checker::new(self.file).check(decls)?;
+
+        for idx in 0..self.program.functions.len() {
+            self.declare_function(idx);
+        }
+        for idx in 0..self.program.statics.len() {
+            self.compile_static(idx);
+        }
+        for idx in 0..self.program.functions.len() {
+            if self.program.functions[idx].body.is_some() {
+                self.compile_function(idx);
+            }
+        }
+
+        self.define_pending_types();
+        Ok(())
+    }
+
+    /// The C source generated so far.
+    pub fn source(&self) -> String {
+        [
+            format!("/* generated by lyth from {} */\n\n", self.file),
+            format!("{}\n", PRELUDE),
+            self.headers
+                .iter()
+                .map(|header| format!("#include <{}>\n", header))
+                .collect(),
+            format!("{}\n", self.type_decls),
+            self.types.clone(),
+            format!("{}\n", self.prototypes),
+            self.globals.clone(),
+            self.code.clone(),
+        ]
+        .concat()
+    }
+
+    /// Writes the C source generated so far to `path`.
+    pub fn emit(&self, path: &str) {
+        std::fs::write(path, self.source())
+            .unwrap_or_else(|err| panic!("could not write `{}`: {}", path, err));
+    }
+
+    /// Declares the function `idx` of the program. Functions of the C
+    /// library are declared by including their header instead.
+    fn declare_function(&mut self, idx: usize) {
+        let function = &self.program.functions[idx];
+        let (name, symbol, variadic) = (
+            function.name.clone(),
+            function.symbol.clone(),
+            function.variadic,
+        );
+        let (params, rtyp) = (function.params.clone(), function.rtyp.clone());
+        let attributes = attribute_list(&function.attrs);
+
+        let prototype = match function.body {
+            Some(_) => None,
+            None => libc::lookup(&symbol),
+        };
+        if let Some(prototype) = prototype {
+            assert!(
+                prototype.params.len() == params.len() && prototype.variadic == variadic,
+                "{}: extern function `{}` doesn't match its C prototype in <{}>",
+                self.file,
+                name,
+                prototype.header
+            );
+            self.headers.insert(prototype.header);
+            self.functions.insert(
+                name,
+                Function {
+                    symbol,
+                    casts: prototype.params,
+                },
+            );
+            return;
+        }
+
+        let mut c_params: Vec<_> = params.iter().map(|typ| self.c_type(typ)).collect();
+        if variadic {
+            assert!(
+                !params.is_empty(),
+                "{}: variadic function `{}` needs a parameter before `...` in C",
+                self.file,
+                name
+            );
+            c_params.push(String::from("..."));
+        }
+        if c_params.is_empty() {
+            c_params.push(String::from("void"));
+        }
+        let return_type = match &rtyp {
+            Some(typ) => self.c_type(typ),
+            None => String::from("void"),
+        };
+
+        self.prototypes.push_str(&format!(
+            "{}{}({});\n",
+            attributes,
+            declaration(&return_type, &symbol),
+            c_params.join(", ")
+        ));
+
+        self.functions.insert(name, Function { symbol, casts: &[] });
+    }
+
+    /// Emits the static `idx` of the program with its initializer, or
+    /// zero-initialized without one.
+    fn compile_static(&mut self, idx: usize) {
+        // outside of functions the initializer is a C constant expression
+        let value = self.program.statics[idx].value.take();
+        let init = value.as_ref().map(|value| self.compile_expr(value));
+        self.program.statics[idx].value = value;
+
+        let decl = &self.program.statics[idx];
+        let (symbol, typ) = (decl.symbol.clone(), decl.typ.clone());
+        let attributes = attribute_list(&decl.attrs);
+        let c_type = self.c_type(&typ);
+        let definition = match init {
+            Some(init) => format!("{} = {}", declaration(&c_type, &symbol), init),
+            None => declaration(&c_type, &symbol),
+        };
+        self.globals
+            .push_str(&format!("{}{};\n\n", attributes, definition));
+    }
+
+    /// Generates the body of the function `idx` of the program.
+    fn compile_function(&mut self, idx: usize) {
+        let body = self.program.functions[idx].body.take().unwrap();
+        let function = &self.program.functions[idx];
+        let (name, params, rtyp) = (
+            function.name.clone(),
+            function.params.clone(),
+            function.rtyp.clone(),
+        );
+        let (locals, file, pos) = (function.locals.clone(), function.file.clone(), function.pos);
+
+        self.function = Some(idx);
+        self.variables = vec![String::new(); locals.len()];
+        self.source = file;
+        self.names = 0;
+        self.depth = 1;
+        self.terminated = false;
+
+        let mut c_params = Vec::new();
+        for (idx, typ) in params.iter().enumerate() {
+            let param = self.variable(&locals[idx].name);
+            c_params.push(declaration(&self.c_type(typ), &param));
+            self.variables[idx] = param;
+        }
+        if c_params.is_empty() {
+            c_params.push(String::from("void"));
+        }
+
+        // the tail of the body is the return value
+        let code = self.compile_expr(&body);
+        if rtyp.is_some() && !self.terminated {
+            self.line(&format!("return {};", code));
+        }
+        self.program.functions[idx].body = Some(body);
+
+        let return_type = match &rtyp {
+            Some(typ) => self.c_type(typ),
+            None => String::from("void"),
+        };
+        if self.line_directives {
+            self.code
+                .push_str(&format!("#line {} {}\n", pos.line, c_string(&self.source)));
+        }
+        let symbol = &self.functions[&name].symbol;
+        let (locals, body) = prune::prune_temporaries(&self.locals, &self.body);
+        self.code.push_str(&format!(
+            "{}({}) {{\n{}{}}}\n\n",
+            declaration(&return_type, symbol),
+            c_params.join(", "),
+            locals,
+            body
+        ));
+        self.locals.clear();
+        self.body.clear();
+
+        self.function = None;
+    }
+
+    /// Appends a statement to the function being generated.
+    fn line(&mut self, line: &str) {
+        self.body.push_str(&"    ".repeat(self.depth));
+        self.body.push_str(line);
+        self.body.push('\n');
+    }
+
+    /// A fresh C name for the lyth variable `name`. Locals are declared at
+    /// the top of the function, so they live as long as the stack slots of
+    /// the LLVM backend do.
+    fn variable(&mut self, name: &str) -> String {
+        self.names += 1;
+        format!("{}_{}", name, self.names)
+    }
+
+    /// Declares a new temporary of type `typ` and returns its name.
+    fn temporary(&mut self, typ: &ast::Type) -> String {
+        self.names += 1;
+        let name = format!("t{}", self.names);
+        self.declare_local(typ, &name);
+        name
+    }
+
+    fn declare_local(&mut self, typ: &ast::Type, name: &str) {
+        let c_type = self.c_type(typ);
+        self.locals
+            .push_str(&format!("    {};\n", declaration(&c_type, name)));
+    }
+
+    /// Assigns `code` to `lvalue`. Aggregates are built by initializers,
+    /// which become compound literals here.
+    fn assign(&mut self, lvalue: &str, code: &str, typ: &ast::Type) {
+        let line = if code.starts_with('{') {
+            format!("{} = ({}){};", lvalue, self.c_type(typ), code)
+        } else {
+            format!("{} = {};", lvalue, code)
+        };
+        self.line(&line);
+    }
+
+    /// Evaluates `code` into a new temporary, so it is computed exactly
+    /// once and here. Outside of functions it stays a constant expression.
+    fn value(&mut self, code: String, typ: &ast::Type) -> String {
+        if self.function.is_none() {
+            return code;
+        }
+        if typ.is_named("void") {
+            self.line(&format!("{};", code));
+            return String::new();
+        }
+
+        let temporary = self.temporary(typ);
+        self.assign(&temporary, &code, typ);
+        temporary
+    }
+
+    /// Returns the vtable `key` of the program, emitting it the first time
+    /// it is needed. Its methods are thunks taking the data pointer of the
+    /// trait object as `self`.
+    fn vtable(&mut self, key: &str) -> String {
+        if let Some(vtable) = self.vtables.get(key) {
+            return vtable.clone();
+        }
+
+        let vtable = &self.program.vtables[key];
+        let (trait_name, functions) = (vtable.trait_name.clone(), vtable.methods.clone());
+        let vtable_type = self.vtable_type(&trait_name);
+
+        let mut thunks = Vec::new();
+        for (method, function) in self
+            .program
+            .object_methods(&trait_name)
+            .iter()
+            .zip(functions)
+        {
+            let callee = self.functions[&function].symbol.clone();
+            let self_type = self
+                .program
+                .functions
+                .iter()
+                .find(|other| other.name == function)
+                .map(|function| function.params[0].clone())
+                .unwrap();
+
+            let self_type = self.c_type(&self_type);
+            let mut params = vec![String::from("void *self")];
+            let mut args = vec![format!("({})self", self_type)];
+            for (idx, (_, typ)) in method.params[1..].iter().enumerate() {
+                let c_type = self.c_type(typ);
+                params.push(declaration(&c_type, &format!("a{}", idx)));
+                args.push(format!("a{}", idx));
+            }
+            let (return_type, call) = match &method.rtyp {
+                Some(rtyp) => (self.c_type(rtyp), "return "),
+                None => (String::from("void"), ""),
+            };
+
+            let thunk = format!("_LF{}", self.thunks);
+            self.thunks += 1;
+            self.globals.push_str(&format!(
+                "static {}({}) {{\n    {}{}({});\n}}\n\n",
+                declaration(&return_type, &thunk),
+                params.join(", "),
+                call,
+                callee,
+                args.join(", ")
+            ));
+            thunks.push(thunk);
+        }
+
+        let name = format!("_LV{}", self.vtables.len());
+        self.globals.push_str(&format!(
+            "static const {} {} = {};\n\n",
+            vtable_type,
+            name,
+            initializer(&thunks)
+        ));
+
+        self.vtables.insert(key.to_string(), name.clone());
+        name
+    }
+
+    /// Turns a pointer into the trait object `typ`, pairing it with the
+    /// vtable `key`.
+    fn build_trait_object(&mut self, ptr: String, typ: &ast::Type, key: &str) -> String {
+        let vtable = self.vtable(key);
+        let object = format!("{{ (void *){}, &{} }}", ptr, vtable);
+        self.value(object, typ)
+    }
+
+    /// Calls the object method `method` of a trait object through its
+    /// vtable. The object's data pointer is passed as `self`.
+    fn compile_dyn_call(
+        &mut self,
+        object: &Expr,
+        method: usize,
+        args: &[Expr],
+        typ: &ast::Type,
+    ) -> String {
+        let trait_name = match object.typ.pointee() {
+            Some(ast::Type::Dyn(trait_name)) => trait_name.clone(),
+            _ => unreachable!("`{}` is not a trait object", object.typ),
+        };
+        let name = self.program.object_methods(&trait_name)[method]
+            .name
+            .clone();
+
+        let object = self.compile_expr(object);
+        let mut call_args = vec![format!("{}.data", object)];
+        for arg in args {
+            call_args.push(self.compile_expr(arg));
+        }
+
+        let callee = format!("{}.vtable->{}", object, c_ident(&name));
+        self.build_call(&callee, call_args, typ)
+    }
+
+    fn build_call(&mut self, callee: &str, args: Vec<String>, typ: &ast::Type) -> String {
+        let code = format!("{}({})", callee, args.join(", "));
+        self.value(code, typ)
+    }
+
+    /// Builds the value of the variant `variant` of the enum `typ` from its
+    /// payload.
+    fn compile_variant(&mut self, typ: &ast::Type, variant: usize, fields: &[Expr]) -> String {
+        let enumeration = self.program.enum_of(typ).unwrap();
+        let (payload, variant) = (
+            enumeration.has_payload(),
+            enumeration.variants[variant].clone(),
+        );
+
+        let tag = self.int_literal(variant.tag as i128, &ast::Type::new_simple("i32"));
+        if !payload {
+            return tag;
+        }
+
+        // unit variants are constants, so they can initialize globals
+        if fields.is_empty() {
+            return self.value(format!("{{ {} }}", tag), typ);
+        }
+
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|field| self.compile_expr(field))
+            .collect();
+
+        let code = format!(
+            "{{ {}, {{ .{} = {} }} }}",
+            tag,
+            c_ident(&variant.name),
+            initializer(&fields)
+        );
+        self.value(code, typ)
+    }
+
+    /// Generates a `match` as a `switch` on the discriminant or value.
+    fn compile_match(&mut self, scrutinee: &Expr, arms: &[Arm], typ: &ast::Type) -> String {
+        let value = self.compile_expr(scrutinee);
+        let scrutinee_type = &scrutinee.typ;
+
+        let payload = self
+            .program
+            .enum_of(scrutinee_type)
+            .is_some_and(|enumeration| enumeration.has_payload());
+        let tag = if payload {
+            format!("{}.tag", value)
+        } else if scrutinee_type.is_named("bool") {
+            // C compilers warn about switching on a `bool`
+            format!("(int){}", value)
+        } else {
+            value.clone()
+        };
+
+        let mut results = Vec::new();
+        let mut bodies = vec![String::new(); arms.len()];
+
+        for (idx, arm) in arms.iter().enumerate() {
+            // every value it matches is taken by an earlier arm
+            if arm.cases.as_ref().is_some_and(Vec::is_empty) {
+                continue;
+            }
+
+            let code = self.nested(&mut bodies[idx], |generator| {
+                generator.bind_captures(arm, &value, scrutinee_type);
+                generator.compile_expr(&arm.body)
+            });
+            if self.terminated {
+                continue;
+            }
+            results.push((idx, code));
+        }
+
+        let reached = !results.is_empty();
+        let merged = self.build_merge(typ, reached);
+        for (idx, code) in results {
+            self.nested(&mut bodies[idx], |generator| {
+                if !typ.is_named("void") {
+                    generator.assign(&merged, &code, typ);
+                }
+                generator.line("break;");
+            });
+        }
+
+        let mut catch_all = false;
+        self.line(&format!("switch ({}) {{", tag));
+        for (arm, body) in arms.iter().zip(bodies) {
+            match &arm.cases {
+                Some(cases) if cases.is_empty() => continue,
+                Some(cases) => {
+                    for case in cases {
+                        let label = self.case_label(*case, scrutinee_type);
+                        self.line(&format!("case {}:", label));
+                    }
+                    self.line("{");
+                }
+                None => {
+                    catch_all = true;
+                    self.line("default: {");
+                }
+            }
+            self.body.push_str(&body);
+            self.line("}");
+        }
+        if !catch_all {
+            self.line("default:");
+            self.line("    LYTH_UNREACHABLE();");
+        }
+        self.line("}");
+
+        self.terminated = !reached;
+        merged
+    }
+
+    /// Binds the locals an arm captures. They refer to a copy of the
+    /// matched value.
+    fn bind_captures(&mut self, arm: &Arm, scrutinee: &str, typ: &ast::Type) {
+        if arm.captures.is_empty() {
+            return;
+        }
+
+        let slot = self.build_temporary(scrutinee, typ);
+        for capture in &arm.captures {
+            let variable = match capture.field {
+                Some((variant, idx)) => {
+                    let variant = &self.program.enum_of(typ).unwrap().variants[variant];
+                    format!("{}.payload.{}._{}", slot, c_ident(&variant.name), idx)
+                }
+                None => slot.clone(),
+            };
+            self.variables[capture.local] = variable;
+        }
+    }
+
+    /// The value an `if` or `match` evaluates to, which the branches that
+    /// get through assign. If none does, the whole expression diverged.
+    fn build_merge(&mut self, typ: &ast::Type, reached: bool) -> String {
+        if !reached || typ.is_named("void") {
+            return self.diverged(typ);
+        }
+
+        self.temporary(typ)
+    }
+
+    /// The label of the case matching `value`.
+    fn case_label(&mut self, value: i128, typ: &ast::Type) -> String {
+        if typ.is_integer() {
+            self.int_literal(value, typ)
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// Copies `code` into a new local, as variables are represented.
+    fn build_temporary(&mut self, code: &str, typ: &ast::Type) -> String {
+        let temporary = self.temporary(typ);
+        self.assign(&temporary, code, typ);
+        temporary
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) {
+        if self.line_directives {
+            let pos = stmt.pos();
+            self.body
+                .push_str(&format!("#line {} {}\n", pos.line, c_string(&self.source)));
+        }
+
+        match stmt {
+            Stmt::Let { local, value, .. } => {
+                let code = self.compile_expr(value);
+
+                // the initializer diverged, nothing after it is reachable
+                if self.terminated {
+                    return;
+                }
+
+                let function = &self.program.functions[self.function.unwrap()];
+                let (name, typ) = (
+                    function.locals[*local].name.clone(),
+                    function.locals[*local].typ.clone(),
+                );
+                let variable = self.variable(&name);
+                self.declare_local(&typ, &variable);
+                self.assign(&variable, &code, &typ);
+                self.variables[*local] = variable;
+            }
+            Stmt::Assign {
+                place, op, value, ..
+            } => {
+                let lvalue = self.compile_place(place);
+
+                let code = match op {
+                    None => self.compile_expr(value),
+                    Some(op) => {
+                        // the place is evaluated only once, `a[f()] += 1` calls `f` once
+                        let current = self.value(lvalue.clone(), &place.typ);
+                        let rhs = self.compile_expr(value);
+
+                        match &place.typ {
+                            ast::Type::Pointer(_) => {
+                                self.build_offset(current, rhs, *op == TokenKind::Minus, &place.typ)
+                            }
+                            typ => self.build_infix(*op, typ, current, rhs),
+                        }
+                    }
+                };
+
+                if !self.terminated {
+                    self.assign(&lvalue, &code, &place.typ);
+                }
+            }
+            Stmt::Return { value, .. } => {
+                match value {
+                    Some(value) => {
+                        let code = self.compile_expr(value);
+                        if !self.terminated {
+                            self.line(&format!("return {};", code));
+                        }
+                    }
+                    None => self.line("return;"),
+                }
+                self.terminated = true;
+            }
+            Stmt::Expr { value, .. } => {
+                self.compile_expr(value);
+            }
+        }
+    }
+
+    /// The value of an expression whose evaluation never finishes, like a
+    /// block ending in `return`. It is never used, only its type matters.
+    fn diverged(&mut self, typ: &ast::Type) -> String {
+        if typ.is_named("void") {
+            String::new()
+        } else {
+            self.temporary(typ)
+        }
+    }
+
+    /// Generates code into `body` instead of the current function body,
+    /// one level deeper, for the branches of conditionals. Afterwards
+    /// `self.terminated` tells whether the branch returned.
+    fn nested<T>(&mut self, body: &mut String, f: impl FnOnce(&mut Self) -> T) -> T {
+        std::mem::swap(&mut self.body, body);
+        self.depth += 1;
+        self.terminated = false;
+
+        let result = f(self);
+
+        self.depth -= 1;
+        std::mem::swap(&mut self.body, body);
+        result
+    }
+
+    /// Generates a block, its value is the tail expression.
+    fn compile_block(
+        &mut self,
+        stmts: &[Stmt],
+        tail: &Option<Box<Expr>>,
+        typ: &ast::Type,
+    ) -> String {
+        for stmt in stmts {
+            self.compile_stmt(stmt);
+            if self.terminated {
+                return self.diverged(typ);
+            }
+        }
+
+        match tail {
+            Some(tail) => self.compile_expr(tail),
+            None => self.diverged(typ),
+        }
+    }
+
+    /// Generates an `if` expression. Branches that diverge don't assign
+    /// the value.
+    fn compile_if(
+        &mut self,
+        cond: &Expr,
+        then: &Expr,
+        elze: &Option<Box<Expr>>,
+        typ: &ast::Type,
+    ) -> String {
+        let cond = self.compile_expr(cond);
+
+        let mut bodies = [String::new(), String::new()];
+        let mut results = Vec::new();
+        for (body, expr) in bodies.iter_mut().zip([Some(then), elze.as_deref()]) {
+            let code = self.nested(body, |generator| match expr {
+                Some(expr) => generator.compile_expr(expr),
+                None => String::new(),
+            });
+            results.push((!self.terminated).then_some(code));
+        }
+
+        let reached = results.iter().any(Option::is_some);
+        let merged = self.build_merge(typ, reached);
+        if !merged.is_empty() {
+            for (body, code) in bodies.iter_mut().zip(results) {
+                if let Some(code) = code {
+                    self.nested(body, |generator| generator.assign(&merged, &code, typ));
+                }
+            }
+        }
+
+        let [then_body, else_body] = bodies;
+        self.line(&format!("if ({}) {{", cond));
+        self.body.push_str(&then_body);
+        if !else_body.is_empty() {
+            self.line("} else {");
+            self.body.push_str(&else_body);
+        }
+        self.line("}");
+
+        self.terminated = !reached;
+        merged
+    }
+
+    /// Generates the C lvalue of a place. Arrays and slices are bounds
+    /// checked when they are indexed.
+    fn compile_place(&mut self, place: &Place) -> String {
+        match &place.kind {
+            PlaceKind::Local(local) => self.variables[*local].clone(),
+            PlaceKind::Static(symbol) => symbol.clone(),
+            PlaceKind::Temporary(value) => {
+                let code = self.compile_expr(value);
+                self.build_temporary(&code, &value.typ)
+            }
+            PlaceKind::Deref(pointer) => format!("(*{})", self.compile_expr(pointer)),
+            PlaceKind::Field { base, index } => {
+                let lvalue = self.compile_place(base);
+                let members = self.program.struct_members(&base.typ).unwrap();
+                format!("{}.{}", lvalue, c_ident(&members[*index].0))
+            }
+            PlaceKind::Index { base, index } => match &base.typ {
+                ast::Type::Array { len, .. } => {
+                    let lvalue = self.compile_place(base);
+                    let idx = self.compile_expr(index);
+                    let len = self.int_literal(*len as i128, &ast::Type::new_simple("i64"));
+                    self.build_bounds_check(&idx, &len);
+                    format!("{}.items[{}]", lvalue, idx)
+                }
+                ast::Type::Slice(_) => {
+                    let slice = self.load(base);
+                    let idx = self.compile_expr(index);
+                    self.build_bounds_check(&idx, &format!("{}.len", slice));
+                    format!("{}.ptr[{}]", slice, idx)
+                }
+                typ => unreachable!("`{}` cannot be indexed", typ),
+            },
+        }
+    }
+
+    /// Reads the value at a place.
+    fn load(&mut self, place: &Place) -> String {
+        let lvalue = self.compile_place(place);
+        self.value(lvalue, &place.typ)
+    }
+
+    /// Calls the bounds panic handler and traps unless `idx < len`.
+    fn build_bounds_check(&mut self, idx: &str, len: &str) {
+        if !self.bounds_checks {
+            return;
+        }
+
+        let handler = self.bounds_panic_handler();
+        self.line(&format!("if ((uint64_t){} >= (uint64_t){}) {{", idx, len));
+        self.line(&format!("    {}({}, {});", handler, idx, len));
+        self.line("    LYTH_TRAP();");
+        self.line("}");
+    }
+
+    /// Calls the arithmetic panic handler and traps if `op` has no result
+    /// for the operands: a division or remainder by zero, a signed
+    /// `MIN / -1` or `MIN % -1`, or a shift by the width of `typ` or more.
+    fn build_arith_check(&mut self, op: TokenKind, typ: &ast::Type, l: &str, r: &str) {
+        let width = match typ.int_width() {
+            Some(width) => width,
+            None => return,
+        };
+
+        let invalid = match op {
+            TokenKind::Slash | TokenKind::Percent if typ.is_signed() => {
+                format!("{} == 0 || ({} == INT{}_MIN && {} == -1)", r, l, width, r)
+            }
+            TokenKind::Slash | TokenKind::Percent => format!("{} == 0", r),
+            // negative amounts are huge as unsigned values
+            TokenKind::ShiftLeft | TokenKind::ShiftRight => {
+                format!("(uint64_t){} >= {}", r, width)
+            }
+            _ => return,
+        };
+
+        let handler = self.arith_panic_handler();
+        self.line(&format!("if ({}) {{", invalid));
+        self.line(&format!("    {}();", handler));
+        self.line("    LYTH_TRAP();");
+        self.line("}");
+    }
+
+    /// Returns the function that is called when an index is out of bounds.
+    /// Programs can provide their own `lyth_panic_bounds(index: i64, len: i64)`,
+    /// either in lyth or in C, otherwise a weak default that traps is emitted.
+    fn bounds_panic_handler(&mut self) -> String {
+        self.panic_handler(
+            BOUNDS_PANIC_HANDLER,
+            "int64_t index, int64_t len) {\n    (void)index;\n    (void)len;",
+        )
+    }
+
+    /// Returns the function that is called when an arithmetic operation has
+    /// no result. Like the bounds handler, programs can provide their own
+    /// `lyth_panic_arith()`.
+    fn arith_panic_handler(&mut self) -> String {
+        self.panic_handler(ARITH_PANIC_HANDLER, "void) {")
+    }
+
+    /// Returns the panic handler `name`, the program's own or a weak
+    /// default that traps. `head` continues the C definition of the
+    /// default after its opening parenthesis.
+    fn panic_handler(&mut self, name: &'static str, head: &str) -> String {
+        if let Some(function) = self.functions.get(name) {
+            return function.symbol.clone();
+        }
+
+        if self.handlers.insert(name) {
+            self.globals.push_str(&format!(
+                "LYTH_ATTR(weak) void {}({}\n    LYTH_TRAP();\n}}\n\n",
+                name, head
+            ));
+        }
+        name.to_string()
+    }
+
+    /// Generates the postfix `!` operator, which unwraps an optional value
+    /// or checks a raw pointer for null, and traps if there is no value.
+    fn compile_unwrap(&mut self, code: String, typ: &ast::Type) -> String {
+        match typ {
+            ast::Type::Optional(_) => {
+                self.line(&format!("if (!{}.has_value) {{", code));
+                self.line("    LYTH_TRAP();");
+                self.line("}");
+                format!("{}.value", code)
+            }
+            _ => {
+                self.line(&format!("if ({} == 0) {{", code));
+                self.line("    LYTH_TRAP();");
+                self.line("}");
+                code
+            }
+        }
+    }
+
+    /// Generates `value == null` and `value != null` for optional values
+    /// and raw pointers.
+    fn compile_null_check(&mut self, code: String, typ: &ast::Type, is_null: bool) -> String {
+        let has_value = match typ {
+            ast::Type::Optional(_) => format!("{}.has_value", code),
+            _ => format!("({} != 0)", code),
+        };
+
+        let code = match is_null {
+            true => format!("(!{})", has_value),
+            false => has_value,
+        };
+        self.value(code, &ast::Type::new_simple("bool"))
+    }
+
+    /// Wraps a value into an optional that holds it.
+    fn build_some(&mut self, typ: &ast::Type, code: String) -> String {
+        self.value(format!("{{ true, {} }}", code), typ)
+    }
+
+    /// Emits a prefix operator on an operand of type `typ`.
+    fn build_prefix(&mut self, op: TokenKind, typ: &ast::Type, x: String) -> String {
+        let code = match op {
+            // negated as unsigned, so the minimum wraps around
+            TokenKind::Minus => format!("(({})-({}){})", self.c_type(typ), wide_unsigned(typ), x),
+            // `!` on booleans and `~` on integers
+            TokenKind::Bang => format!("(!{})", x),
+            TokenKind::Tilde => format!("(({})~{})", self.c_type(typ), x),
+            _ => unreachable!("not a valid prefix operator"),
+        };
+
+        self.value(code, typ)
+    }
+
+    /// Emits a binary operator on two operands of type `typ`.
+    fn build_infix(&mut self, op: TokenKind, typ: &ast::Type, l: String, r: String) -> String {
+        self.build_arith_check(op, typ, &l, &r);
+        let code = match op {
+            // computed unsigned, where overflow wraps instead of being undefined
+            TokenKind::Plus | TokenKind::Minus | TokenKind::Asterisk | TokenKind::ShiftLeft => {
+                let wide = wide_unsigned(typ);
+                format!(
+                    "(({})(({}){} {} ({}){}))",
+                    self.c_type(typ),
+                    wide,
+                    l,
+                    c_operator(op),
+                    wide,
+                    r
+                )
+            }
+            // C leaves shifting negative numbers right to the implementation
+            TokenKind::ShiftRight if typ.is_signed() => format!(
+                "(({})({} < 0 ? ~(~{} >> {}) : {} >> {}))",
+                self.c_type(typ),
+                l,
+                l,
+                r,
+                l,
+                r
+            ),
+            TokenKind::Slash
+            | TokenKind::Percent
+            | TokenKind::ShiftRight
+            | TokenKind::And
+            | TokenKind::Pipe
+            | TokenKind::Caret => {
+                format!("(({})({} {} {}))", self.c_type(typ), l, c_operator(op), r)
+            }
+            // trait objects are the same if they point to the same data
+            TokenKind::Equal | TokenKind::UnEqual if typ.pointee().is_some_and(is_dyn) => {
+                format!("({}.data {} {}.data)", l, c_operator(op), r)
+            }
+            TokenKind::Equal
+            | TokenKind::UnEqual
+            | TokenKind::LessThan
+            | TokenKind::GreaterThan
+            | TokenKind::LessEqual
+            | TokenKind::GreaterEqual => format!("({} {} {})", l, c_operator(op), r),
+            _ => unreachable!("not a valid infix operator"),
+        };
+
+        match op {
+            TokenKind::Equal
+            | TokenKind::UnEqual
+            | TokenKind::LessThan
+            | TokenKind::GreaterThan
+            | TokenKind::LessEqual
+            | TokenKind::GreaterEqual => self.value(code, &ast::Type::new_simple("bool")),
+            _ => self.value(code, typ),
+        }
+    }
+
+    /// Generates `&&` and `||`, which only evaluate their right operand if
+    /// the left one doesn't already decide the result.
+    fn compile_short_circuit(&mut self, op: TokenKind, left: &Expr, right: &Expr) -> String {
+        let bool_type = ast::Type::new_simple("bool");
+        let lhs = self.compile_expr(left);
+        let result = self.build_temporary(&lhs, &bool_type);
+
+        let mut rhs_body = String::new();
+        self.nested(&mut rhs_body, |generator| {
+            let rhs = generator.compile_expr(right);
+            if !generator.terminated {
+                generator.line(&format!("{} = {};", result, rhs));
+            }
+        });
+        // the right operand returning doesn't stop the left one's path
+        self.terminated = false;
+
+        // `a && b` is false without looking at `b` if `a` is false,
+        // `a || b` is true if `a` is true.
+        let condition = match op {
+            TokenKind::LogicalAnd => result.clone(),
+            _ => format!("!{}", result),
+        };
+        self.line(&format!("if ({}) {{", condition));
+        self.body.push_str(&rhs_body);
+        self.line("}");
+
+        result
+    }
+
+    /// Moves the raw pointer `ptr` of type `typ` by `offset` elements.
+    fn build_offset(
+        &mut self,
+        ptr: String,
+        offset: String,
+        negate: bool,
+        typ: &ast::Type,
+    ) -> String {
+        let op = if negate { "-" } else { "+" };
+        self.value(format!("({} {} {})", ptr, op, offset), typ)
+    }
+
+    /// Converts a value with a cast.
+    fn compile_cast(&mut self, code: String, from: &ast::Type, to: &ast::Type) -> String {
+        // references become pointers and trait objects stay what they are
+        let target = self.c_type(to);
+        if self.c_type(from) == target {
+            return code;
+        }
+
+        let is_pointer = |typ: &ast::Type| typ.pointee().is_some();
+        if (is_pointer(from) && to.is_integer()) || (from.is_integer() && is_pointer(to)) {
+            format!("(({})(uintptr_t){})", target, code)
+        } else {
+            // C converts integers by value, which truncates or extends them
+            // by the signedness of the source like LLVM does
+            format!("(({}){})", target, code)
+        }
+    }
+
+    /// A C constant of the integer type `typ`.
+    fn int_literal(&mut self, value: i128, typ: &ast::Type) -> String {
+        let c_type = self.c_type(typ);
+        let width = typ.int_width().unwrap();
+        if width < 32 {
+            return format!("(({}){})", c_type, value);
+        }
+
+        let prefix = if typ.is_signed() { "INT" } else { "UINT" };
+        // the minimum can't be written as a literal, its magnitude overflows
+        if typ.is_signed() && value == -(1 << (width - 1)) {
+            return format!("(-{}{}_C({}) - 1)", prefix, width, -(value + 1));
+        }
+        format!("{}{}_C({})", prefix, width, value)
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> String {
+        let typ = &expr.typ;
+        match &expr.kind {
+            ExprKind::Int(value) => self.int_literal(*value, typ),
+            ExprKind::Bool(b) => b.to_string(),
+            ExprKind::Str(s) => format!("((int8_t *){})", c_string(s)),
+            ExprKind::Null => match typ {
+                ast::Type::Pointer(inner) if !is_dyn(inner) => {
+                    format!("(({})0)", self.c_type(typ))
+                }
+                // an optional without a value only has its flag cleared
+                _ => self.value(String::from("{ 0 }"), typ),
+            },
+            ExprKind::Load(place) => self.load(place),
+            ExprKind::AddrOf(place) => format!("(&{})", self.compile_place(place)),
+            ExprKind::Call { function, args } => {
+                let codes: Vec<_> = args.iter().map(|arg| self.compile_expr(arg)).collect();
+                let function = &self.functions[function];
+                let (callee, casts) = (function.symbol.clone(), function.casts);
+                let args = codes
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, code)| match casts.get(idx) {
+                        Some(cast) => format!("(({}){})", cast, code),
+                        None => code,
+                    })
+                    .collect();
+                self.build_call(&callee, args, typ)
+            }
+            ExprKind::DynCall {
+                object,
+                method,
+                args,
+            } => self.compile_dyn_call(object, *method, args, typ),
+            ExprKind::Variant { variant, fields } => self.compile_variant(typ, *variant, fields),
+            ExprKind::Struct(members) => {
+                let names = self.program.struct_members(typ).unwrap();
+                let mut initializers = Vec::new();
+                for (idx, member) in members {
+                    let code = self.compile_expr(member);
+                    initializers.push(format!(".{} = {}", c_ident(&names[*idx].0), code));
+                }
+                self.value(initializer(&initializers), typ)
+            }
+            ExprKind::Array(items) => {
+                let codes: Vec<_> = items.iter().map(|item| self.compile_expr(item)).collect();
+                self.value(format!("{{ {} }}", initializer(&codes)), typ)
+            }
+            ExprKind::Unary { op, operand } => {
+                let x = self.compile_expr(operand);
+                self.build_prefix(*op, typ, x)
+            }
+            ExprKind::Binary { op, left, right } => {
+                let lhs = self.compile_expr(left);
+                let rhs = self.compile_expr(right);
+                self.build_infix(*op, &left.typ, lhs, rhs)
+            }
+            ExprKind::Logical { op, left, right } => self.compile_short_circuit(*op, left, right),
+            ExprKind::Offset {
+                pointer,
+                offset,
+                negate,
+            } => {
+                let ptr = self.compile_expr(pointer);
+                let offset = self.compile_expr(offset);
+                self.build_offset(ptr, offset, *negate, typ)
+            }
+            ExprKind::Distance { left, right } => {
+                let lhs = self.compile_expr(left);
+                let rhs = self.compile_expr(right);
+                self.value(format!("((int64_t)({} - {}))", lhs, rhs), typ)
+            }
+            ExprKind::Cast(value) => {
+                let code = self.compile_expr(value);
+                let code = self.compile_cast(code, &value.typ, typ);
+                self.value(code, typ)
+            }
+            ExprKind::NullCheck { operand, is_null } => {
+                let code = self.compile_expr(operand);
+                self.compile_null_check(code, &operand.typ, *is_null)
+            }
+            ExprKind::Unwrap(value) => {
+                let code = self.compile_expr(value);
+                self.compile_unwrap(code, &value.typ)
+            }
+            ExprKind::Some(value) => {
+                let code = self.compile_expr(value);
+                self.build_some(typ, code)
+            }
+            ExprKind::Object { pointer, vtable } => {
+                let ptr = self.compile_expr(pointer);
+                self.build_trait_object(ptr, typ, vtable)
+            }
+            ExprKind::Slice { pointer, len } => {
+                let ptr = self.compile_expr(pointer);
+                let len = self.int_literal(*len as i128, &ast::Type::new_simple("i64"));
+                self.value(format!("{{ {}->items, {} }}", ptr, len), typ)
+            }
+            ExprKind::Len(place) => match &place.typ {
+                ast::Type::Array { len, .. } => {
+                    // the array is still evaluated for its side effects
+                    self.compile_place(place);
+                    self.int_literal(*len as i128, typ)
+                }
+                _ => {
+                    let slice = self.load(place);
+                    self.value(format!("{}.len", slice), typ)
+                }
+            },
+            ExprKind::Ptr(place) => {
+                let slice = self.load(place);
+                self.value(format!("{}.ptr", slice), typ)
+            }
+            ExprKind::If { cond, then, elze } => self.compile_if(cond, then, elze, typ),
+            ExprKind::Block { stmts, tail } => self.compile_block(stmts, tail, typ),
+            ExprKind::Match { scrutinee, arms } => self.compile_match(scrutinee, arms, typ),
+        }
+    }
+}
+
+/// Declares `name` with the C type `c_type`.
+fn declaration(c_type: &str, name: &str) -> String {
+    if c_type.ends_with('*') {
+        format!("{}{}", c_type, name)
+    } else {
+        format!("{} {}", c_type, name)
+    }
+}
+
+/// An initializer for a struct with the members or elements `items`.
+fn initializer(items: &[String]) -> String {
+    if items.is_empty() {
+        String::from("{ 0 }")
+    } else {
+        format!("{{ {} }}", items.join(", "))
+    }
+}
+
+/// The attributes of a function or static in front of its declaration.
+fn attribute_list(attrs: &[typed::Attribute]) -> String {
+    let attributes: Vec<_> = attrs
+        .iter()
+        .filter_map(|attr| match attr {
+            typed::Attribute::Section(section) => Some(format!("section({})", c_string(section))),
+            typed::Attribute::Align(align) => Some(format!("aligned({})", align)),
+            // a weak declaration may stay undefined at link time
+            typed::Attribute::Weak => Some(String::from("weak")),
+            typed::Attribute::Function(kind) => function_attribute(kind),
+        })
+        .collect();
+
+    if attributes.is_empty() {
+        String::new()
+    } else {
+        format!("LYTH_ATTR({}) ", attributes.join(", "))
+    }
+}
+
+/// The C operator for the lyth operator `op`.
+fn c_operator(op: TokenKind) -> &'static str {
+    match op {
+        TokenKind::Plus => "+",
+        TokenKind::Minus => "-",
+        TokenKind::Asterisk => "*",
+        TokenKind::Slash => "/",
+        TokenKind::Percent => "%",
+        TokenKind::ShiftLeft => "<<",
+        TokenKind::ShiftRight => ">>",
+        TokenKind::And => "&",
+        TokenKind::Pipe => "|",
+        TokenKind::Caret => "^",
+        TokenKind::Equal => "==",
+        TokenKind::UnEqual => "!=",
+        TokenKind::LessThan => "<",
+        TokenKind::GreaterThan => ">",
+        TokenKind::LessEqual => "<=",
+        TokenKind::GreaterEqual => ">=",
+        _ => panic!("not a valid infix operator"),
+    }
+}
+
+/// The unsigned type wrapping arithmetic on the integer type `typ` is done
+/// in. Narrower types would be promoted to `int`, which overflows.
+fn wide_unsigned(typ: &ast::Type) -> &'static str {
+    match typ.int_width() {
+        Some(64) => "uint64_t",
+        _ => "uint32_t",
+    }
+}
+
+/// The GCC attribute for an LLVM function attribute. C has none for the
+/// `inline` hint, as its `inline` keyword changes linkage.
+fn function_attribute(kind: &str) -> Option<String> {
+    match kind {
+        "inlinehint" => None,
+        "alwaysinline" => Some(String::from("always_inline")),
+        kind => Some(kind.to_string()),
+    }
+}
+
+/// A C string literal with the bytes of `s`.
+fn c_string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            // `?` starts trigraphs
+            b'"' | b'\\' | b'?' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            b' '..=b'~' => literal.push(byte as char),
+            // always three digits, so a digit following isn't taken in
+            byte => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    literal.push('"');
+    literal
+}
@@ -0,0 +1,140 @@
+//! Removes the temporaries of a generated function that are never read.
+//! Every value gets a temporary when it is generated, whether it is used
+//! or not, like the result of a call made for its side effects.
+
+use std::collections::HashMap;
+
+/// Drops the declarations in `locals` and the assignments in `body` of the
+/// temporaries that are never read. Assignments that call a function stay
+/// as expression statements. Dropping an assignment can leave other
+/// temporaries unread, so this repeats until nothing changes.
+pub(super) fn prune_temporaries(locals: &str, body: &str) -> (String, String) {
+    let mut locals: Vec<String> = locals.lines().map(str::to_string).collect();
+    let mut body: Vec<String> = body.lines().map(str::to_string).collect();
+
+    loop {
+        let mut uses = HashMap::new();
+        for line in &body {
+            for word in words(line) {
+                *uses.entry(word).or_insert(0) += 1;
+            }
+        }
+        for line in &body {
+            if let Some((temporary, _)) = assignment(line) {
+                *uses.get_mut(temporary).unwrap() -= 1;
+            }
+        }
+
+        let unread = |name: &str| uses.get(name).copied().unwrap_or(0) == 0;
+
+        let declared = locals.len();
+        locals.retain(|line| !declared_temporary(line).is_some_and(unread));
+
+        let mut pruned = Vec::with_capacity(body.len());
+        for line in &body {
+            match assignment(line) {
+                Some((temporary, code)) if unread(temporary) => {
+                    let indent = &line[..line.len() - line.trim_start().len()];
+                    if is_call(code) {
+                        pruned.push(format!("{}{};", indent, code));
+                    } else if has_call(code) {
+                        pruned.push(format!("{}(void){};", indent, code));
+                    }
+                }
+                _ => pruned.push(line.clone()),
+            }
+        }
+        if locals.len() == declared && pruned == body {
+            break;
+        }
+        body = pruned;
+    }
+
+    (lines(&locals), lines(&body))
+}
+
+fn lines(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// The name of the temporary `line` declares, if it declares one.
+fn declared_temporary(line: &str) -> Option<&str> {
+    let declaration = line.trim().strip_suffix(';')?;
+    let name = declaration.rsplit([' ', '*']).next()?;
+    is_temporary(name).then_some(name)
+}
+
+/// Splits a `tN = code;` line into the temporary and the code.
+fn assignment(line: &str) -> Option<(&str, &str)> {
+    let (temporary, code) = line.trim().strip_suffix(';')?.split_once(" = ")?;
+    is_temporary(temporary).then_some((temporary, code))
+}
+
+fn is_temporary(name: &str) -> bool {
+    name.strip_prefix('t')
+        .is_some_and(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// The identifiers in `line`, together with words in string literals,
+/// which can only make a temporary look used.
+fn words(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+}
+
+/// Whether `code` is a call `f(...)` as a whole.
+fn is_call(code: &str) -> bool {
+    let name_len = code
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(code.len());
+    if name_len == 0 || !code[name_len..].starts_with('(') {
+        return false;
+    }
+
+    // the parenthesis after the name has to close at the end
+    let mut depth = 0;
+    for (idx, c) in outside_strings(code).skip(name_len) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return idx == code.len() - 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Whether `code` calls a function anywhere, which is an identifier
+/// followed by a parenthesis. Casts put a parenthesis after type names
+/// the other way round.
+fn has_call(code: &str) -> bool {
+    let mut previous = ' ';
+    for (_, c) in outside_strings(code) {
+        if c == '(' && (previous.is_ascii_alphanumeric() || previous == '_') {
+            return true;
+        }
+        if !c.is_whitespace() {
+            previous = c;
+        }
+    }
+    false
+}
+
+/// The characters of `code` with their indices, except for those inside
+/// string literals, which are replaced by spaces.
+fn outside_strings(code: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let (mut in_string, mut escaped) = (false, false);
+    code.char_indices().map(move |(idx, c)| {
+        let quote = c == '"' && !escaped;
+        escaped = in_string && c == '\\' && !escaped;
+        if quote {
+            in_string = !in_string;
+            return (idx, ' ');
+        }
+        (idx, if in_string { ' ' } else { c })
+    })
+}
@@ -0,0 +1,237 @@
+//! The C types lyth values are represented by. Scalars map to the types
+//! of `<stdint.h>` and `<stdbool.h>`, everything else to a struct named
+//! after the mangled lyth type, which is defined the first time it is
+//! used. A struct is always defined after the structs it contains by value,
+//! the ones it only points to just need to be declared.
+
+use super::{declaration, Generator};
+use crate::mangle;
+use crate::parser::ast;
+
+impl Generator<'_> {
+    /// The C type of values of type `typ`.
+    pub(super) fn c_type(&mut self, typ: &ast::Type) -> String {
+        match typ {
+            // C has void pointers, unlike LLVM
+            ast::Type::Pointer(inner) if inner.is_named("void") => String::from("void *"),
+            ast::Type::Pointer(inner) | ast::Type::Ref { typ: inner, .. } => match inner.as_ref() {
+                ast::Type::Dyn(trait_name) => self.object_type(trait_name),
+                inner => {
+                    let pointee = self.pointee_type(inner);
+                    match pointee.ends_with('*') {
+                        true => format!("{}*", pointee),
+                        false => format!("{} *", pointee),
+                    }
+                }
+            },
+            ast::Type::Dyn(trait_name) => panic!(
+                "{}: `dyn {}` has no known size, use it behind a reference or pointer",
+                self.file, trait_name
+            ),
+            ast::Type::Array { .. } | ast::Type::Optional(_) | ast::Type::Slice(_) => {
+                self.aggregate_type(typ)
+            }
+            ast::Type::Named { name, .. } => match name.as_str() {
+                "i128" | "u128" => panic!(
+                    "{}: `{}` is not supported by the C backend",
+                    self.file, name
+                ),
+                "i64" => String::from("int64_t"),
+                "u64" => String::from("uint64_t"),
+                "i32" => String::from("int32_t"),
+                "u32" => String::from("uint32_t"),
+                "i16" => String::from("int16_t"),
+                "u16" => String::from("uint16_t"),
+                "i8" => String::from("int8_t"),
+                "u8" => String::from("uint8_t"),
+                "bool" => String::from("bool"),
+                "void" => String::from("void"),
+                name if self.program.structs.contains_key(name) => self.aggregate_type(typ),
+                name => match self.program.enums.get(name) {
+                    Some(enumeration) if enumeration.has_payload() => self.aggregate_type(typ),
+                    Some(_) => String::from("int32_t"),
+                    None => panic!("{}: unknown type `{}`", self.file, name),
+                },
+            },
+        }
+    }
+
+    /// Whether values of type `typ` are represented by a struct.
+    fn is_aggregate(&self, typ: &ast::Type) -> bool {
+        match typ {
+            ast::Type::Array { .. } | ast::Type::Optional(_) | ast::Type::Slice(_) => true,
+            ast::Type::Named { name, .. } => {
+                self.program.structs.contains_key(name)
+                    || self
+                        .program
+                        .enums
+                        .get(name)
+                        .is_some_and(|e| e.has_payload())
+            }
+            _ => false,
+        }
+    }
+
+    /// The type a pointer points to. Structs are only declared, they are
+    /// defined once the type they are used in is, which lets structs point
+    /// back to each other.
+    fn pointee_type(&mut self, typ: &ast::Type) -> String {
+        if !self.is_aggregate(typ) {
+            return self.c_type(typ);
+        }
+
+        let name = mangle::type_name(typ);
+        if !self.defined_types.contains(&name) {
+            self.pending_types.push(typ.clone());
+        }
+        self.declare_type(&name)
+    }
+
+    /// Defines the types only pointed to so far.
+    pub(super) fn define_pending_types(&mut self) {
+        while let Some(typ) = self.pending_types.pop() {
+            self.c_type(&typ);
+        }
+    }
+
+    /// Declares the struct `name`, so it can be pointed to before it is
+    /// defined.
+    fn declare_type(&mut self, name: &str) -> String {
+        if self.declared_types.insert(name.to_string()) {
+            self.type_decls.push_str(&format!("struct {};\n", name));
+        }
+        format!("struct {}", name)
+    }
+
+    /// The struct representing `typ`, defined on first use.
+    fn aggregate_type(&mut self, typ: &ast::Type) -> String {
+        let name = mangle::type_name(typ);
+        let c_name = self.declare_type(&name);
+        // registered before the members, so they can point back to it
+        if !self.defined_types.insert(name) {
+            return c_name;
+        }
+
+        let members = match typ {
+            ast::Type::Array { typ: elem, len } => {
+                assert!(
+                    *len > 0,
+                    "{}: arrays of length 0 are not supported by the C backend",
+                    self.file
+                );
+                let elem = self.c_type(elem);
+                vec![declaration(&elem, &format!("items[{}]", len))]
+            }
+            ast::Type::Optional(inner) => {
+                let inner = self.c_type(inner);
+                vec![String::from("bool has_value"), declaration(&inner, "value")]
+            }
+            ast::Type::Slice(elem) => {
+                let ptr = self.c_type(&ast::Type::new_pointer(elem.as_ref().clone()));
+                vec![declaration(&ptr, "ptr"), String::from("int64_t len")]
+            }
+            ast::Type::Named { name, .. } if self.program.structs.contains_key(name) => {
+                let members = self.program.struct_members(typ).unwrap();
+                members
+                    .iter()
+                    .map(|(member, typ)| declaration(&self.c_type(typ), &c_ident(member)))
+                    .collect()
+            }
+            ast::Type::Named { name, .. } => {
+                // the payload of each variant is a struct in a union
+                let variants = self.program.enums[name].variants.clone();
+                let mut payload = String::from("union {\n");
+                for variant in variants.iter().filter(|v| !v.fields.is_empty()) {
+                    payload.push_str("        struct {\n");
+                    for (idx, field) in variant.fields.iter().enumerate() {
+                        let field = declaration(&self.c_type(field), &format!("_{}", idx));
+                        payload.push_str(&format!("            {};\n", field));
+                    }
+                    payload.push_str(&format!("        }} {};\n", c_ident(&variant.name)));
+                }
+                payload.push_str("    } payload");
+                vec![String::from("int32_t tag"), payload]
+            }
+            _ => unreachable!("`{}` is not an aggregate", typ),
+        };
+
+        self.define_type(&c_name, members);
+        c_name
+    }
+
+    /// Trait objects pair a pointer to the data with a pointer to the
+    /// vtable of its type's implementation of the trait.
+    pub(super) fn object_type(&mut self, trait_name: &str) -> String {
+        let name = mangle::type_name(&ast::Type::Dyn(trait_name.to_string()));
+        let c_name = self.declare_type(&name);
+        if !self.defined_types.insert(name.clone()) {
+            return c_name;
+        }
+
+        let vtable = self.declare_type(&format!("{}_vtable", name));
+        let mut methods = Vec::new();
+        for method in self.program.object_methods(trait_name) {
+            let params: Vec<_> = std::iter::once(String::from("void *"))
+                .chain(method.params[1..].iter().map(|(_, typ)| self.c_type(typ)))
+                .collect();
+            let rtyp = match &method.rtyp {
+                Some(typ) => self.c_type(typ),
+                None => String::from("void"),
+            };
+            methods.push(format!(
+                "{} (*{})({})",
+                rtyp,
+                c_ident(&method.name),
+                params.join(", ")
+            ));
+        }
+
+        self.define_type(&vtable, methods);
+        self.define_type(
+            &c_name,
+            vec![
+                String::from("void *data"),
+                format!("const {} *vtable", vtable),
+            ],
+        );
+        c_name
+    }
+
+    /// The struct holding the methods of `trait_name` for trait objects.
+    pub(super) fn vtable_type(&mut self, trait_name: &str) -> String {
+        let object = self.object_type(trait_name);
+        format!("{}_vtable", object)
+    }
+
+    fn define_type(&mut self, c_name: &str, mut members: Vec<String>) {
+        // C has no empty structs
+        if members.is_empty() {
+            members.push(String::from("char empty"));
+        }
+
+        self.types.push_str(&format!("{} {{\n", c_name));
+        for member in members {
+            self.types.push_str(&format!("    {};\n", member));
+        }
+        self.types.push_str("};\n\n");
+    }
+}
+
+/// Names that can't be used as identifiers in C, or that the included
+/// headers define as macros.
+const RESERVED: &[&str] = &[
+    "auto", "bool", "break", "case", "char", "const", "continue", "default", "do", "double",
+    "else", "enum", "extern", "false", "float", "for", "goto", "if", "inline", "int", "long",
+    "register", "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch",
+    "true", "typedef", "union", "unsigned", "void", "volatile", "while",
+];
+
+/// The C identifier for the lyth identifier `name`, which may be a C
+/// keyword.
+pub(super) fn c_ident(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
@@ -0,0 +1,446 @@
+//! DWARF debug info, emitted with `-g`. Every function gets a subprogram,
+//! every statement a line location and parameters and `let` bindings are
+//! described as variables living in their allocas, so debuggers can step
+//! through lyth source and print locals.
+
+use super::Compiler;
+use crate::parser::ast;
+use llvm_sys::debuginfo::*;
+use llvm_sys::prelude::*;
+use llvm_sys::{core, target, LLVMModuleFlagBehavior};
+use std::collections::{HashMap, HashSet};
+
+// DW_ATE_* encodings of basic types
+const ENCODING_BOOLEAN: LLVMDWARFTypeEncoding = 0x02;
+const ENCODING_SIGNED: LLVMDWARFTypeEncoding = 0x05;
+const ENCODING_UNSIGNED: LLVMDWARFTypeEncoding = 0x08;
+
+const TAG_STRUCTURE_TYPE: u32 = 0x13;
+
+/// The debug info builder and the metadata already created with it.
+pub(super) struct DebugInfo {
+    builder: LLVMDIBuilderRef,
+    unit: LLVMMetadataRef,
+    /// The root file, types are declared in it.
+    root: LLVMMetadataRef,
+    files: HashMap<String, LLVMMetadataRef>,
+    /// Types by their lyth name.
+    types: HashMap<String, LLVMMetadataRef>,
+    /// The structs whose members are being described.
+    incomplete: HashSet<String>,
+    /// The subprogram and file of the function being compiled.
+    scope: Option<(LLVMMetadataRef, LLVMMetadataRef)>,
+}
+
+impl DebugInfo {
+    /// Starts the debug info of `module`, whose root is `file`.
+    pub fn new(module: LLVMModuleRef, file: &str) -> Self {
+        let mut debug = Self {
+            builder: unsafe { LLVMCreateDIBuilder(module) },
+            unit: std::ptr::null_mut(),
+            root: std::ptr::null_mut(),
+            files: HashMap::new(),
+            types: HashMap::new(),
+            incomplete: HashSet::new(),
+            scope: None,
+        };
+
+        let file = debug.file(file);
+        debug.root = file;
+        let producer = "lyth";
+        debug.unit = unsafe {
+            LLVMDIBuilderCreateCompileUnit(
+                debug.builder,
+                LLVMDWARFSourceLanguage::LLVMDWARFSourceLanguageC,
+                file,
+                producer.as_ptr() as *const _,
+                producer.len(),
+                0,
+                "".as_ptr() as *const _,
+                0,
+                0,
+                "".as_ptr() as *const _,
+                0,
+                LLVMDWARFEmissionKind::LLVMDWARFEmissionKindFull,
+                0,
+                0,
+                0,
+                "".as_ptr() as *const _,
+                0,
+                "".as_ptr() as *const _,
+                0,
+            )
+        };
+
+        let flags = [
+            ("Debug Info Version", unsafe { LLVMDebugMetadataVersion() }),
+            ("Dwarf Version", 4),
+        ];
+        for (key, version) in flags {
+            unsafe {
+                let version = core::LLVMConstInt(
+                    core::LLVMInt32TypeInContext(core::LLVMGetModuleContext(module)),
+                    version as u64,
+                    0,
+                );
+                core::LLVMAddModuleFlag(
+                    module,
+                    LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorWarning,
+                    key.as_ptr() as *const _,
+                    key.len(),
+                    core::LLVMValueAsMetadata(version),
+                );
+            }
+        }
+
+        debug
+    }
+
+    fn file(&mut self, path: &str) -> LLVMMetadataRef {
+        if let Some(file) = self.files.get(path) {
+            return *file;
+        }
+
+        let dir = std::env::current_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
+        let file = unsafe {
+            LLVMDIBuilderCreateFile(
+                self.builder,
+                path.as_ptr() as *const _,
+                path.len(),
+                dir.as_ptr() as *const _,
+                dir.len(),
+            )
+        };
+        self.files.insert(path.to_string(), file);
+
+        file
+    }
+
+    /// Resolves the metadata created so far, after which no more can be
+    /// added.
+    pub fn finish(&mut self) {
+        unsafe { LLVMDIBuilderFinalize(self.builder) }
+    }
+}
+
+impl Drop for DebugInfo {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeDIBuilder(self.builder) }
+    }
+}
+
+impl<'a> Compiler<'a> {
+    /// Describes the function `name`, declared at `pos` in `file`, whose
+    /// body is about to be compiled. Its instructions are located in it
+    /// until `debug_end_function`.
+    pub(super) fn debug_function(
+        &mut self,
+        name: &str,
+        params: &[ast::Type],
+        rtyp: Option<&ast::Type>,
+        file: &str,
+        pos: ast::Pos,
+    ) {
+        if self.debug.is_none() {
+            return;
+        }
+
+        let function = self.function.unwrap();
+        // a `None` return type is void, which debug info writes as null
+        let mut types = vec![rtyp.map_or(std::ptr::null_mut(), |typ| self.debug_type(typ))];
+        types.extend(params.iter().map(|typ| self.debug_type(typ)));
+
+        let debug = self.debug.as_mut().unwrap();
+        let file = debug.file(file);
+        unsafe {
+            let function_type = LLVMDIBuilderCreateSubroutineType(
+                debug.builder,
+                file,
+                types.as_mut_ptr(),
+                types.len() as u32,
+                LLVMDIFlagZero,
+            );
+
+            let mut len = 0;
+            let symbol = core::LLVMGetValueName2(function, &mut len);
+            let subprogram = LLVMDIBuilderCreateFunction(
+                debug.builder,
+                file,
+                name.as_ptr() as *const _,
+                name.len(),
+                symbol,
+                len,
+                file,
+                pos.line as u32,
+                function_type,
+                0,
+                1,
+                pos.line as u32,
+                LLVMDIFlagPrototyped,
+                0,
+            );
+            LLVMSetSubprogram(function, subprogram);
+            debug.scope = Some((subprogram, file));
+        }
+
+        self.debug_location(pos);
+    }
+
+    pub(super) fn debug_end_function(&mut self) {
+        if let Some(debug) = &mut self.debug {
+            debug.scope = None;
+            unsafe { core::LLVMSetCurrentDebugLocation2(self.builder, std::ptr::null_mut()) };
+        }
+    }
+
+    /// Locates the instructions built from now on at `pos`.
+    pub(super) fn debug_location(&self, pos: ast::Pos) {
+        if let Some(DebugInfo {
+            scope: Some((subprogram, _)),
+            ..
+        }) = &self.debug
+        {
+            unsafe {
+                let location = LLVMDIBuilderCreateDebugLocation(
+                    self.context,
+                    pos.line as u32,
+                    pos.col as u32,
+                    *subprogram,
+                    std::ptr::null_mut(),
+                );
+                core::LLVMSetCurrentDebugLocation2(self.builder, location);
+            }
+        }
+    }
+
+    /// Describes the local variable `name` stored at `ptr`. Parameters pass
+    /// their 1-based position as `arg`.
+    pub(super) fn debug_variable(
+        &mut self,
+        name: &str,
+        ptr: LLVMValueRef,
+        typ: &ast::Type,
+        arg: Option<u32>,
+        pos: ast::Pos,
+    ) {
+        let Some((subprogram, file)) = self.debug.as_ref().and_then(|debug| debug.scope) else {
+            return;
+        };
+
+        let typ = self.debug_type(typ);
+        let debug = self.debug.as_ref().unwrap();
+        unsafe {
+            let variable = match arg {
+                Some(arg) => LLVMDIBuilderCreateParameterVariable(
+                    debug.builder,
+                    subprogram,
+                    name.as_ptr() as *const _,
+                    name.len(),
+                    arg,
+                    file,
+                    pos.line as u32,
+                    typ,
+                    1,
+                    LLVMDIFlagZero,
+                ),
+                None => LLVMDIBuilderCreateAutoVariable(
+                    debug.builder,
+                    subprogram,
+                    name.as_ptr() as *const _,
+                    name.len(),
+                    file,
+                    pos.line as u32,
+                    typ,
+                    1,
+                    LLVMDIFlagZero,
+                    0,
+                ),
+            };
+
+            let location = LLVMDIBuilderCreateDebugLocation(
+                self.context,
+                pos.line as u32,
+                pos.col as u32,
+                subprogram,
+                std::ptr::null_mut(),
+            );
+            LLVMDIBuilderInsertDeclareAtEnd(
+                debug.builder,
+                ptr,
+                variable,
+                LLVMDIBuilderCreateExpression(debug.builder, std::ptr::null_mut(), 0),
+                location,
+                core::LLVMGetInsertBlock(self.builder),
+            );
+        }
+    }
+
+    /// The debug info type of `typ`. Integers, `bool`, pointers, arrays and
+    /// structs are described in full, other types only by name and size.
+    fn debug_type(&mut self, typ: &ast::Type) -> LLVMMetadataRef {
+        let key = typ.to_string();
+        if let Some(debug_type) = self.debug.as_ref().unwrap().types.get(&key) {
+            return *debug_type;
+        }
+        if typ.is_named("void") {
+            return std::ptr::null_mut();
+        }
+
+        let layout = unsafe { target::LLVMGetModuleDataLayout(self.module) };
+        let llvm_type = self.get_type(typ);
+        let (size, align) = unsafe {
+            (
+                target::LLVMABISizeOfType(layout, llvm_type) * 8,
+                target::LLVMABIAlignmentOfType(layout, llvm_type) * 8,
+            )
+        };
+
+        let debug_type = match typ {
+            _ if typ.is_integer() || typ.is_named("bool") => {
+                let encoding = match typ {
+                    _ if typ.is_named("bool") => ENCODING_BOOLEAN,
+                    _ if typ.is_signed() => ENCODING_SIGNED,
+                    _ => ENCODING_UNSIGNED,
+                };
+                let builder = self.debug.as_ref().unwrap().builder;
+                unsafe {
+                    LLVMDIBuilderCreateBasicType(
+                        builder,
+                        key.as_ptr() as *const _,
+                        key.len(),
+                        size,
+                        encoding,
+                        LLVMDIFlagZero,
+                    )
+                }
+            }
+            ast::Type::Pointer(inner) | ast::Type::Ref { typ: inner, .. }
+                if !crate::semantics::is_dyn(inner) =>
+            {
+                let pointee = self.debug_pointee(inner);
+                let builder = self.debug.as_ref().unwrap().builder;
+                unsafe {
+                    LLVMDIBuilderCreatePointerType(
+                        builder,
+                        pointee,
+                        size,
+                        align,
+                        0,
+                        key.as_ptr() as *const _,
+                        key.len(),
+                    )
+                }
+            }
+            ast::Type::Array { typ: inner, len } => {
+                let element = self.debug_type(inner);
+                let builder = self.debug.as_ref().unwrap().builder;
+                unsafe {
+                    let mut subscripts =
+                        [LLVMDIBuilderGetOrCreateSubrange(builder, 0, *len as i64)];
+                    LLVMDIBuilderCreateArrayType(
+                        builder,
+                        size,
+                        align,
+                        element,
+                        subscripts.as_mut_ptr(),
+                        subscripts.len() as u32,
+                    )
+                }
+            }
+            _ => {
+                // pointers back to the struct from its members see it as
+                // a forward declaration
+                self.debug.as_mut().unwrap().incomplete.insert(key.clone());
+
+                let mut members = Vec::new();
+                if let Some(fields) = self.struct_members(typ) {
+                    for (idx, (member, member_type)) in fields.iter().enumerate() {
+                        let member_debug_type = self.debug_type(member_type);
+                        let member_llvm_type = self.get_type(member_type);
+                        let debug = self.debug.as_ref().unwrap();
+                        unsafe {
+                            members.push(LLVMDIBuilderCreateMemberType(
+                                debug.builder,
+                                debug.root,
+                                member.as_ptr() as *const _,
+                                member.len(),
+                                debug.root,
+                                0,
+                                target::LLVMABISizeOfType(layout, member_llvm_type) * 8,
+                                target::LLVMABIAlignmentOfType(layout, member_llvm_type) * 8,
+                                target::LLVMOffsetOfElement(layout, llvm_type, idx as u32) * 8,
+                                LLVMDIFlagZero,
+                                member_debug_type,
+                            ));
+                        }
+                    }
+                }
+
+                self.debug.as_mut().unwrap().incomplete.remove(&key);
+                self.debug_struct(&key, size, align, &mut members)
+            }
+        };
+
+        self.debug.as_mut().unwrap().types.insert(key, debug_type);
+        debug_type
+    }
+
+    /// The type a pointer points to. Structs that are still being described
+    /// are referred to by a forward declaration.
+    fn debug_pointee(&mut self, typ: &ast::Type) -> LLVMMetadataRef {
+        let name = typ.to_string();
+        let debug = self.debug.as_ref().unwrap();
+        if debug.incomplete.contains(&name) {
+            return unsafe {
+                LLVMDIBuilderCreateForwardDecl(
+                    debug.builder,
+                    TAG_STRUCTURE_TYPE,
+                    name.as_ptr() as *const _,
+                    name.len(),
+                    debug.unit,
+                    debug.root,
+                    0,
+                    0,
+                    0,
+                    0,
+                    name.as_ptr() as *const _,
+                    name.len(),
+                )
+            };
+        }
+
+        self.debug_type(typ)
+    }
+
+    fn debug_struct(
+        &self,
+        name: &str,
+        size: u64,
+        align: u32,
+        members: &mut [LLVMMetadataRef],
+    ) -> LLVMMetadataRef {
+        let debug = self.debug.as_ref().unwrap();
+        unsafe {
+            LLVMDIBuilderCreateStructType(
+                debug.builder,
+                debug.unit,
+                name.as_ptr() as *const _,
+                name.len(),
+                debug.root,
+                0,
+                size,
+                align,
+                LLVMDIFlagZero,
+                std::ptr::null_mut(),
+                members.as_mut_ptr(),
+                members.len() as u32,
+                0,
+                std::ptr::null_mut(),
+                name.as_ptr() as *const _,
+                name.len(),
+            )
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Runs built modules in-process with MCJIT, so lyth functions can be
+//! called directly instead of being linked against C. Modules can be added
+//! to a running JIT, their declarations are resolved against the modules
+//! added before.
+
+use super::Module;
+use crate::mangle;
+use llvm_sys::core;
+use llvm_sys::execution_engine::*;
+use llvm_sys::prelude::*;
+use std::ffi::{CStr, CString};
+use std::sync::Once;
+
+static LINK_MCJIT: Once = Once::new();
+
+/// Modules compiled to machine code in memory.
+pub struct Jit {
+    contexts: Vec<LLVMContextRef>,
+    engine: LLVMExecutionEngineRef,
+}
+
+impl Jit {
+    /// Compiles `module` for the host. Its extern functions are looked up
+    /// in the running process.
+    pub fn new(module: Module) -> Self {
+        LINK_MCJIT.call_once(|| unsafe { LLVMLinkInMCJIT() });
+
+        let (context, module) = module.into_raw();
+        let engine = unsafe {
+            let mut options = std::mem::zeroed::<LLVMMCJITCompilerOptions>();
+            let size = std::mem::size_of::<LLVMMCJITCompilerOptions>();
+            LLVMInitializeMCJITCompilerOptions(&mut options, size);
+            options.OptLevel = 2;
+
+            let mut engine = std::ptr::null_mut();
+            let mut error = std::ptr::null_mut();
+            if LLVMCreateMCJITCompilerForModule(&mut engine, module, &mut options, size, &mut error)
+                != 0
+            {
+                let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+                core::LLVMDisposeMessage(error);
+                panic!("cannot create the JIT: {}", message);
+            }
+            engine
+        };
+
+        Self {
+            contexts: vec![context],
+            engine,
+        }
+    }
+
+    /// Adds `module`, which may use the functions and statics the modules
+    /// added before define, as long as it declares them.
+    pub fn add(&mut self, module: Module) {
+        let (context, module) = module.into_raw();
+        unsafe { LLVMAddModule(self.engine, module) };
+        self.contexts.push(context);
+    }
+
+    /// Returns the function `name`, which is either its symbol or the name
+    /// of a lyth function that isn't generic, as a function pointer of
+    /// type `F`, like `jit.call::<extern "C" fn(i64) -> i64>("test2")`.
+    ///
+    /// # Safety
+    ///
+    /// `F` must be an `extern "C" fn` matching the signature of the
+    /// function, and must not be called after the JIT is dropped.
+    pub unsafe fn call<F: Copy>(&self, name: &str) -> F {
+        assert_eq!(
+            std::mem::size_of::<F>(),
+            std::mem::size_of::<usize>(),
+            "`{}` can only be called through a function pointer",
+            name
+        );
+
+        let address = [name.to_string(), mangle::mangle(name, &[])]
+            .iter()
+            .map(|symbol| {
+                let symbol = CString::new(symbol.as_bytes()).unwrap();
+                LLVMGetFunctionAddress(self.engine, symbol.as_ptr())
+            })
+            .find(|&address| address != 0)
+            .unwrap_or_else(|| panic!("no function `{}` to call", name));
+
+        std::mem::transmute_copy(&(address as usize))
+    }
+
+    /// Runs the `main` function with `args` as its arguments and returns
+    /// its exit status, which is 0 if it doesn't return one.
+    pub fn run_main(&self, args: &[String]) -> i32 {
+        let args: Vec<_> = args
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()).unwrap())
+            .collect();
+        let argv: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let envp = [std::ptr::null()];
+
+        unsafe {
+            let mut main = std::ptr::null_mut();
+            let name = CString::new("main").unwrap();
+            assert!(
+                LLVMFindFunction(self.engine, name.as_ptr(), &mut main) == 0,
+                "no function `main` to run"
+            );
+            LLVMRunFunctionAsMain(
+                self.engine,
+                main,
+                argv.len() as u32,
+                argv.as_ptr(),
+                envp.as_ptr(),
+            )
+        }
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        // the engine owns the modules
+        unsafe {
+            LLVMDisposeExecutionEngine(self.engine);
+            for &context in &self.contexts {
+                core::LLVMContextDispose(context);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+
+    const PROGRAM: &str = "
+        fn test(abc: i64) i64 {
+            return 7 + abc;
+        }
+
+        #[export]
+        fn test2(b: i64) i64 {
+            if b % 2 == 0 {
+                b = test(12);
+            }
+            return b * test(66);
+        }
+    ";
+
+    fn jit(input: &str) -> Jit {
+        let items = Parser::new("test.ly", input).parse().unwrap();
+        let mut compiler = Compiler::new("test.ly");
+        compiler
+            .build(items.into_iter().map(|item| item.decl).collect())
+            .unwrap();
+        Jit::new(compiler.into_module())
+    }
+
+    #[test]
+    fn calls_exported_functions() {
+        let jit = jit(PROGRAM);
+        let test2 = unsafe { jit.call::<extern "C" fn(i64) -> i64>("test2") };
+        assert_eq!(test2(3), 3 * 73);
+        assert_eq!(test2(4), 19 * 73);
+    }
+
+    #[test]
+    fn calls_mangled_functions_by_name() {
+        let jit = jit(PROGRAM);
+        let by_name = unsafe { jit.call::<extern "C" fn(i64) -> i64>("test") };
+        let by_symbol =
+            unsafe { jit.call::<extern "C" fn(i64) -> i64>(&mangle::mangle("test", &[])) };
+        assert_eq!(by_name(1), 8);
+        assert_eq!(by_name as usize, by_symbol as usize);
+    }
+
+    #[test]
+    #[should_panic(expected = "no function `missing` to call")]
+    fn missing_functions() {
+        let jit = jit(PROGRAM);
+        unsafe { jit.call::<extern "C" fn()>("missing") };
+    }
+}
@@ -1,393 +1,2502 @@
+mod debug;
+mod jit;
+
+pub use jit::Jit;
+
+use super::diagnostic::Diagnostic;
 use super::lexer::token::TokenKind;
-use super::parser::{ast, Parser};
+use super::parser::ast;
+use super::semantics::typed::{self, Arm, Expr, ExprKind, Place, PlaceKind, Program, Stmt};
+use super::semantics::{Binding, Checker, Entry, ARITH_PANIC_HANDLER, BOUNDS_PANIC_HANDLER};
+use debug::DebugInfo;
 use llvm_sys::prelude::*;
-use llvm_sys::LLVMIntPredicate;
 use llvm_sys::{core, target, target_machine};
-use std::collections::HashMap;
+use llvm_sys::{LLVMAttributeFunctionIndex, LLVMIntPredicate, LLVMLinkage, LLVMUnnamedAddr};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{CStr, CString};
+use std::sync::Once;
+
+/// A declared function and its LLVM type, to call it.
+struct Function {
+    value: LLVMValueRef,
+    typ: LLVMTypeRef,
+}
 
+/// Compiles a lyth program to an object file. Every compiler owns its
+/// LLVM context, so compilers on different threads don't share any state.
+///
+/// The program is checked by `semantics::Checker` first, the compiler only
+/// lowers the typed program it produces.
 pub struct Compiler<'a> {
     file: &'a str,
-    input: &'a str,
+    context: LLVMContextRef,
     module: LLVMModuleRef,
     builder: LLVMBuilderRef,
+    machine: target_machine::LLVMTargetMachineRef,
+    program: Program,
+    /// The function being compiled and its index in the program.
     function: Option<LLVMValueRef>,
-    function_type: Option<ast::Type>,
-    named_values: HashMap<String, LLVMValueRef>,
+    function_index: usize,
+    functions: HashMap<String, Function>,
+    struct_types: RefCell<HashMap<String, LLVMTypeRef>>,
+    /// Enums without payload are `i32`, the others named structs.
+    enum_types: HashMap<String, LLVMTypeRef>,
+    vtables: HashMap<String, LLVMValueRef>,
+    statics: HashMap<String, LLVMValueRef>,
+    /// The stack slots of the locals of the function being compiled.
+    locals: Vec<LLVMValueRef>,
+    defined: HashSet<String>,
+    bounds_checks: bool,
+    debug: Option<DebugInfo>,
 }
 
+static INIT_TARGET: Once = Once::new();
+
 impl<'a> Compiler<'a> {
-    pub fn new(file: &'a str, input: &'a str) -> Self {
-        unsafe {
+    /// Creates a compiler for the program whose root is `file`, which is
+    /// also the file named in error messages.
+    pub fn new(file: &'a str) -> Self {
+        // the target registry is global, it must only be initialised once
+        INIT_TARGET.call_once(|| unsafe {
             if target::LLVM_InitializeNativeTarget() != 0 {
                 panic!("Could not initialise target");
             }
             if target::LLVM_InitializeNativeAsmPrinter() != 0 {
                 panic!("Could not initialise ASM Printer");
             }
-        }
+        });
+        let context = unsafe { core::LLVMContextCreate() };
         let mod_name = CStr::from_bytes_with_nul(b"lyth-compiled-module\0").unwrap();
+        let module = unsafe { core::LLVMModuleCreateWithNameInContext(mod_name.as_ptr(), context) };
+
+        // the data layout is needed up front to lay out enum payloads
+        let machine = unsafe {
+            let triple = target_machine::LLVMGetDefaultTargetTriple();
+            let mut target = std::ptr::null_mut();
+            let mut error = std::ptr::null_mut();
+            if target_machine::LLVMGetTargetFromTriple(triple, &mut target, &mut error) != 0 {
+                let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+                core::LLVMDisposeMessage(error);
+                panic!("cannot find the host target: {}", message);
+            }
+
+            let cpu = CStr::from_bytes_with_nul(b"generic\0").unwrap();
+            let features = CStr::from_bytes_with_nul(b"\0").unwrap();
+
+            let machine = target_machine::LLVMCreateTargetMachine(
+                target,
+                triple,
+                cpu.as_ptr(),
+                features.as_ptr(),
+                target_machine::LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+                target_machine::LLVMRelocMode::LLVMRelocPIC,
+                target_machine::LLVMCodeModel::LLVMCodeModelDefault,
+            );
+
+            let layout = target_machine::LLVMCreateTargetDataLayout(machine);
+            target::LLVMSetModuleDataLayout(module, layout);
+            target::LLVMDisposeTargetData(layout);
+            core::LLVMSetTarget(module, triple);
+            core::LLVMDisposeMessage(triple);
+
+            machine
+        };
 
         Self {
             file,
-            input,
-            module: unsafe { core::LLVMModuleCreateWithName(mod_name.as_ptr()) },
-            builder: unsafe { core::LLVMCreateBuilder() },
+            context,
+            module,
+            builder: unsafe { core::LLVMCreateBuilderInContext(context) },
+            machine,
+            program: Program::default(),
             function: None,
-            function_type: None,
-            named_values: HashMap::new(),
+            function_index: 0,
+            functions: HashMap::new(),
+            struct_types: RefCell::new(HashMap::new()),
+            enum_types: HashMap::new(),
+            vtables: HashMap::new(),
+            statics: HashMap::new(),
+            locals: Vec::new(),
+            defined: HashSet::new(),
+            bounds_checks: true,
+            debug: None,
         }
     }
 
-    pub fn compile(&mut self, path: &str) {
-        let ast = Parser::new(self.file, self.input).parse();
+    /// Enables or disables the runtime bounds checks on array and slice
+    /// indexing. They are enabled by default.
+    pub fn set_bounds_checks(&mut self, enabled: bool) {
+        self.bounds_checks = enabled;
+    }
 
-        for decl in ast.iter() {
-            match decl {
-                ast::Decl::Function {
-                    name,
-                    params,
-                    body,
-                    rtyp,
-                } => {
-                    self.named_values.clear();
-                    let function_type = unsafe {
-                        let mut param_types = Vec::new();
+    /// Enables or disables emitting DWARF debug info. It is disabled by
+    /// default.
+    pub fn set_debug_info(&mut self, enabled: bool) {
+        self.debug = enabled.then(|| DebugInfo::new(self.module, self.file));
+    }
 
-                        for (_, typ) in params {
-                            param_types.push(self.get_type(typ));
-                        }
+    /// Only declares the functions and statics whose symbols are in
+    /// `symbols` instead of compiling them, as they are defined by modules
+    /// built before, like those the REPL has handed to the JIT.
+    pub fn set_defined(&mut self, symbols: HashSet<String>) {
+        self.defined = symbols;
+    }
 
-                        let return_type = match rtyp {
-                            Some(t) => self.get_type(t),
-                            None => core::LLVMVoidType(),
-                        };
+    /// Compiles the declarations of a loaded program into the object file
+    /// at `path`, or returns the errors in the program.
+    pub fn compile(&mut self, decls: Vec<ast::Decl>, path: &str) -> Result<(), Vec<Diagnostic>> {
+        self.build(decls)?;
+        self.emit(path);
+        Ok(())
+    }
 
-                        core::LLVMFunctionType(
-                            return_type,
-                            param_types.as_mut_ptr(),
-                            param_types.len() as u32,
-                            0,
-                        )
-                    };
+    /// Compiles the declarations of a loaded program into the module of
+    /// the compiler, to be emitted or handed to the JIT, or returns the
+    /// errors in the program.
+    pub fn build(&mut self, decls: Vec<ast::Decl>) -> Result<(), Vec<Diagnostic>> {
+        let program = Checker::new(self.file).check(decls)?;
+        self.lower(program);
+        Ok(())
+    }
 
-                    let function_name = CString::new(name.as_bytes()).unwrap();
-                    self.function = Some(unsafe {
-                        core::LLVMAddFunction(self.module, function_name.as_ptr(), function_type)
-                    });
-                    self.function_type = rtyp.clone();
+    /// Compiles `decls` like `build` and a function `name` without
+    /// parameters running `stmts`, for the REPL. If the last statement is
+    /// an expression the function returns its value as an `i128`, provided
+    /// it is an integer, `bool` or pointer, and its type is returned.
+    /// The `let` statements at the top of `stmts` bind globals instead of
+    /// locals, which are returned to be passed as `bindings` to the entries
+    /// after it.
+    pub fn build_entry(
+        &mut self,
+        decls: Vec<ast::Decl>,
+        bindings: &[Binding],
+        name: &str,
+        stmts: &[ast::Stmt],
+    ) -> Result<Entry, Vec<Diagnostic>> {
+        let (program, entry) = Checker::new(self.file).check_entry(decls, bindings, name, stmts)?;
+        self.lower(program);
+        Ok(entry)
+    }
 
-                    let block_name = CStr::from_bytes_with_nul(b"entry\0").unwrap();
-                    let entry_block = unsafe {
-                        core::LLVMAppendBasicBlock(self.function.unwrap(), block_name.as_ptr())
-                    };
+    /// Emits the checked program into the module.
+    fn lower(&mut self, program: Program) {
+        self.program = program;
 
-                    unsafe { core::LLVMPositionBuilderAtEnd(self.builder, entry_block) };
+        // types first, functions and globals may use them in any order
+        self.declare_enums();
+        for idx in 0..self.program.functions.len() {
+            self.declare_function(idx);
+        }
+        for idx in 0..self.program.statics.len() {
+            self.compile_static(idx);
+        }
 
-                    let mut idx = 0;
-                    for (name, typ) in params {
-                        let param_name = CStr::from_bytes_with_nul(b"param\0").unwrap();
-                        let param_val = unsafe { core::LLVMGetParam(self.function.unwrap(), idx) };
-                        let param_at = unsafe {
-                            core::LLVMBuildAlloca(
-                                self.builder,
-                                self.get_type(typ),
-                                param_name.as_ptr(),
-                            )
-                        };
+        for idx in 0..self.program.functions.len() {
+            let function = &self.program.functions[idx];
+            if function.body.is_some() && !self.defined.contains(&function.symbol) {
+                self.compile_function(idx);
+            }
+        }
+
+        if let Some(debug) = &mut self.debug {
+            debug.finish();
+        }
+    }
 
-                        unsafe { core::LLVMBuildStore(self.builder, param_val, param_at) };
+    /// Writes the module built so far to the object file at `path`.
+    pub fn emit(&self, path: &str) {
+        unsafe {
+            let mut error = std::ptr::null_mut();
 
-                        self.named_values.insert(name.clone(), param_at);
-                        idx += 1;
-                    }
+            let f = CString::new(path.as_bytes()).unwrap();
+            if target_machine::LLVMTargetMachineEmitToFile(
+                self.machine,
+                self.module,
+                f.as_ptr() as *mut i8,
+                target_machine::LLVMCodeGenFileType::LLVMObjectFile,
+                &mut error,
+            ) != 0
+            {
+                let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+                core::LLVMDisposeMessage(error);
+                panic!("cannot write `{}`: {}", path, message);
+            }
+        }
+    }
+
+    /// Takes the module built so far, to run it with the JIT.
+    pub fn into_module(mut self) -> Module {
+        self.debug = None;
+        let module = Module {
+            context: self.context,
+            module: self.module,
+        };
+        self.module = std::ptr::null_mut();
+        module
+    }
 
-                    self.compile_stmt(body);
+    /// The symbols of the functions and statics the module built so far
+    /// defines, except for those private to it.
+    pub fn defined_symbols(&self) -> Vec<String> {
+        let mut symbols = Vec::new();
+        unsafe {
+            let mut function = core::LLVMGetFirstFunction(self.module);
+            while !function.is_null() {
+                if defines_symbol(function) {
+                    symbols.push(value_name(function));
+                }
+                function = core::LLVMGetNextFunction(function);
+            }
 
-                    self.function = None;
-                    self.function_type = None;
+            let mut global = core::LLVMGetFirstGlobal(self.module);
+            while !global.is_null() {
+                if defines_symbol(global) {
+                    symbols.push(value_name(global));
                 }
-                _ => panic!("not implemented"),
+                global = core::LLVMGetNextGlobal(global);
             }
         }
+        symbols
+    }
 
+    /// Turns the definition of a function or static into a declaration,
+    /// for those defined by modules built before. Declarations can't be
+    /// weak like the definitions.
+    fn declare_only(&self, global: LLVMValueRef) {
         unsafe {
-            let triple = target_machine::LLVMGetDefaultTargetTriple();
-            let mut target: target_machine::LLVMTargetRef =
-                std::mem::MaybeUninit::uninit().assume_init();
-            let error = std::mem::MaybeUninit::uninit().assume_init();
-            target_machine::LLVMGetTargetFromTriple(triple, &mut target, error);
+            if !core::LLVMIsAGlobalVariable(global).is_null() {
+                core::LLVMSetInitializer(global, std::ptr::null_mut());
+            }
+            core::LLVMSetLinkage(global, LLVMLinkage::LLVMExternalLinkage);
+        }
+    }
 
-            let cpu = CStr::from_bytes_with_nul(b"generic\0").unwrap();
-            let features = CStr::from_bytes_with_nul(b"\0").unwrap();
+    /// Applies the attributes of a function or static: its section,
+    /// alignment, linkage and the function attributes LLVM knows. A weak
+    /// extern function may stay undefined at link time.
+    fn apply_attributes(&self, global: LLVMValueRef, attrs: &[typed::Attribute], is_extern: bool) {
+        for attr in attrs {
+            match attr {
+                typed::Attribute::Section(section) => {
+                    let section = CString::new(section.as_bytes()).unwrap();
+                    unsafe { core::LLVMSetSection(global, section.as_ptr()) };
+                }
+                typed::Attribute::Align(align) => unsafe { core::LLVMSetAlignment(global, *align) },
+                typed::Attribute::Weak => {
+                    let linkage = match is_extern {
+                        true => LLVMLinkage::LLVMExternalWeakLinkage,
+                        false => LLVMLinkage::LLVMWeakAnyLinkage,
+                    };
+                    unsafe { core::LLVMSetLinkage(global, linkage) };
+                }
+                typed::Attribute::Function(kind) => {
+                    add_function_attribute(self.context, global, kind)
+                }
+            }
+        }
+    }
 
-            let machine = target_machine::LLVMCreateTargetMachine(
-                target,
-                triple,
-                cpu.as_ptr(),
-                features.as_ptr(),
-                target_machine::LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
-                target_machine::LLVMRelocMode::LLVMRelocDefault,
-                target_machine::LLVMCodeModel::LLVMCodeModelDefault,
-            );
+    /// Declares the function `idx` of the program. Functions defined by
+    /// modules built before are only declared.
+    fn declare_function(&mut self, idx: usize) {
+        let function = &self.program.functions[idx];
+        let function_type = unsafe {
+            let mut param_types: Vec<_> = function
+                .params
+                .iter()
+                .map(|typ| self.get_type(typ))
+                .collect();
 
-            // core::LLVMDumpModule(self.module);
+            let return_type = match &function.rtyp {
+                Some(t) => self.get_type(t),
+                None => core::LLVMVoidTypeInContext(self.context),
+            };
 
-            let f = CString::new(path.as_bytes()).unwrap();
-            target_machine::LLVMTargetMachineEmitToFile(
-                machine,
-                self.module,
-                f.as_ptr() as *mut i8,
-                target_machine::LLVMCodeGenFileType::LLVMObjectFile,
-                error,
-            );
+            core::LLVMFunctionType(
+                return_type,
+                param_types.as_mut_ptr(),
+                param_types.len() as u32,
+                function.variadic as LLVMBool,
+            )
+        };
+
+        let function_name = CString::new(function.symbol.as_bytes()).unwrap();
+        let value =
+            unsafe { core::LLVMAddFunction(self.module, function_name.as_ptr(), function_type) };
+        self.apply_attributes(value, &function.attrs, function.body.is_none());
+        if function.body.is_some() && self.defined.contains(&function.symbol) {
+            self.declare_only(value);
         }
+
+        let name = function.name.clone();
+        self.functions.insert(
+            name,
+            Function {
+                value,
+                typ: function_type,
+            },
+        );
     }
 
-    fn compile_stmt(&mut self, stmt: &ast::Stmt) {
-        match stmt {
-            ast::Stmt::Block { body } => {
-                for s in body {
-                    self.compile_stmt(s);
-                }
-            }
-            ast::Stmt::Let { name, typ, value } => {
-                let variable_type = self.get_type(&typ.clone().unwrap());
+    /// Emits the static `idx` of the program with its initializer, or
+    /// zero-initialized without one.
+    fn compile_static(&mut self, idx: usize) {
+        // the initializer is a constant, the builder folds it
+        let value = self.program.statics[idx].value.take();
+        let typ = self.get_type(&self.program.statics[idx].typ);
+        let init = match &value {
+            Some(value) => self.compile_expr(value),
+            None => unsafe { core::LLVMConstNull(typ) },
+        };
+        self.program.statics[idx].value = value;
 
-                let variable_name = CString::new(name.as_bytes()).unwrap();
-                let variable = unsafe {
-                    core::LLVMBuildAlloca(self.builder, variable_type, variable_name.as_ptr())
-                };
+        let decl = &self.program.statics[idx];
+        let global_name = CString::new(decl.symbol.as_bytes()).unwrap();
+        let global = unsafe {
+            let global = core::LLVMAddGlobal(self.module, typ, global_name.as_ptr());
+            core::LLVMSetInitializer(global, init);
+            core::LLVMSetGlobalConstant(global, !decl.mutable as LLVMBool);
+            global
+        };
+        self.apply_attributes(global, &decl.attrs, false);
+        if self.defined.contains(&decl.symbol) {
+            self.declare_only(global);
+        }
 
-                self.named_values.insert(name.clone(), variable);
+        let symbol = decl.symbol.clone();
+        self.statics.insert(symbol, global);
+    }
 
-                let val = self.compile_expr(value);
+    /// Compiles the body of the function `idx` of the program.
+    fn compile_function(&mut self, idx: usize) {
+        let body = self.program.functions[idx].body.take().unwrap();
+        let function = &self.program.functions[idx];
+        let (name, params, rtyp) = (
+            function.name.clone(),
+            function.params.clone(),
+            function.rtyp.clone(),
+        );
+        let (locals, file, pos) = (function.locals.clone(), function.file.clone(), function.pos);
 
-                unsafe { core::LLVMBuildStore(self.builder, val, variable) };
-            }
-            ast::Stmt::If { expr, body, elze } => {
-                let cond = self.compile_expr(expr);
+        self.locals = vec![std::ptr::null_mut(); locals.len()];
+        self.function = Some(self.functions[&name].value);
+        self.function_index = idx;
 
-                let cons_name = CStr::from_bytes_with_nul(b"cons\0").unwrap();
-                let cons_block = unsafe {
-                    core::LLVMAppendBasicBlock(self.function.unwrap(), cons_name.as_ptr())
-                };
+        let block_name = CStr::from_bytes_with_nul(b"entry\0").unwrap();
+        let entry_block = unsafe {
+            core::LLVMAppendBasicBlockInContext(
+                self.context,
+                self.function.unwrap(),
+                block_name.as_ptr(),
+            )
+        };
 
-                let alter_name = CStr::from_bytes_with_nul(b"alter\0").unwrap();
-                let alter_block = unsafe {
-                    core::LLVMAppendBasicBlock(self.function.unwrap(), alter_name.as_ptr())
-                };
+        unsafe { core::LLVMPositionBuilderAtEnd(self.builder, entry_block) };
+        self.debug_function(&name, &params, rtyp.as_ref(), &file, pos);
 
-                let merge_name = CStr::from_bytes_with_nul(b"merge\0").unwrap();
-                let merge_block = unsafe {
-                    core::LLVMAppendBasicBlock(self.function.unwrap(), merge_name.as_ptr())
-                };
+        for (idx, typ) in params.iter().enumerate() {
+            let param_name = CStr::from_bytes_with_nul(b"param\0").unwrap();
+            let param_val = unsafe { core::LLVMGetParam(self.function.unwrap(), idx as u32) };
+            let param_at = unsafe {
+                core::LLVMBuildAlloca(self.builder, self.get_type(typ), param_name.as_ptr())
+            };
 
-                unsafe {
-                    core::LLVMBuildCondBr(self.builder, cond, cons_block, alter_block);
-                    core::LLVMPositionBuilderAtEnd(self.builder, cons_block);
+            unsafe { core::LLVMBuildStore(self.builder, param_val, param_at) };
+            self.debug_variable(&locals[idx].name, param_at, typ, Some(idx as u32 + 1), pos);
+            self.locals[idx] = param_at;
+        }
+
+        // the tail of the body is the return value
+        let val = self.compile_expr(&body);
+        if !self.is_terminated() {
+            unsafe {
+                match rtyp {
+                    Some(_) => core::LLVMBuildRet(self.builder, val),
+                    None => core::LLVMBuildRetVoid(self.builder),
                 }
+            };
+        }
 
-                self.compile_stmt(body);
+        self.program.functions[idx].body = Some(body);
+        self.debug_end_function();
+        self.function = None;
+    }
 
-                unsafe { core::LLVMBuildBr(self.builder, merge_block) };
+    /// The members of a struct type with its type arguments filled in.
+    fn struct_members(&self, typ: &ast::Type) -> Option<Vec<(String, ast::Type)>> {
+        self.program.struct_members(typ)
+    }
 
-                unsafe { core::LLVMPositionBuilderAtEnd(self.builder, alter_block) };
-                if elze.is_some() {
-                    let elze = elze.clone().unwrap();
-                    self.compile_stmt(elze.as_ref());
-                }
-                unsafe { core::LLVMBuildBr(self.builder, merge_block) };
+    /// The LLVM type of a struct instance, created on first use. Every
+    /// combination of type arguments gets its own named struct.
+    fn struct_type(&self, typ: &ast::Type) -> LLVMTypeRef {
+        let symbol = typ.to_string();
+        if let Some(llvm_type) = self.struct_types.borrow().get(&symbol) {
+            return *llvm_type;
+        }
+
+        let members = self.struct_members(typ).unwrap();
+        let struct_name = CString::new(symbol.as_bytes()).unwrap();
+        let llvm_type = unsafe { core::LLVMStructCreateNamed(self.context, struct_name.as_ptr()) };
+        // registered before the members, so they can point back to it
+        self.struct_types.borrow_mut().insert(symbol, llvm_type);
+
+        let mut fields: Vec<_> = members.iter().map(|(_, typ)| self.get_type(typ)).collect();
+        unsafe { core::LLVMStructSetBody(llvm_type, fields.as_mut_ptr(), fields.len() as u32, 0) };
+
+        llvm_type
+    }
+
+    /// A vtable holds pointers to the object methods of a trait, which
+    /// take the object's data pointer as `self`.
+    fn vtable_type(&self, trait_name: &str) -> LLVMTypeRef {
+        unsafe {
+            let mut fields: Vec<_> = self
+                .program
+                .object_methods(trait_name)
+                .iter()
+                .map(|method| core::LLVMPointerType(self.method_type(method), 0))
+                .collect();
+
+            core::LLVMStructTypeInContext(self.context, fields.as_mut_ptr(), fields.len() as u32, 0)
+        }
+    }
+
+    /// The type of an object method in the vtable, which takes the data
+    /// pointer of the trait object as `self`.
+    fn method_type(&self, method: &ast::TraitFunction) -> LLVMTypeRef {
+        unsafe {
+            let mut param_types: Vec<_> = std::iter::once(core::LLVMPointerType(
+                core::LLVMInt8TypeInContext(self.context),
+                0,
+            ))
+            .chain(method.params[1..].iter().map(|(_, typ)| self.get_type(typ)))
+            .collect();
+            let return_type = match &method.rtyp {
+                Some(typ) => self.get_type(typ),
+                None => core::LLVMVoidTypeInContext(self.context),
+            };
+            core::LLVMFunctionType(
+                return_type,
+                param_types.as_mut_ptr(),
+                param_types.len() as u32,
+                0,
+            )
+        }
+    }
+
+    /// Returns the vtable `key` of the program, emitting it the first time
+    /// it is needed.
+    fn vtable(&mut self, key: &str) -> LLVMValueRef {
+        if let Some(vtable) = self.vtables.get(key) {
+            return *vtable;
+        }
+
+        let vtable = &self.program.vtables[key];
+        let vtable_type = self.vtable_type(&vtable.trait_name);
+        let mut methods: Vec<_> = vtable
+            .methods
+            .iter()
+            .enumerate()
+            .map(|(idx, method)| unsafe {
+                core::LLVMConstBitCast(
+                    self.functions[method].value,
+                    core::LLVMStructGetTypeAtIndex(vtable_type, idx as u32),
+                )
+            })
+            .collect();
 
-                unsafe { core::LLVMPositionBuilderAtEnd(self.builder, merge_block) };
+        let name = CString::new(format!("vtable.{}", key)).unwrap();
+        let vtable = unsafe {
+            let init = core::LLVMConstStructInContext(
+                self.context,
+                methods.as_mut_ptr(),
+                methods.len() as u32,
+                0,
+            );
+            let global = core::LLVMAddGlobal(self.module, vtable_type, name.as_ptr());
+            core::LLVMSetInitializer(global, init);
+            core::LLVMSetGlobalConstant(global, 1);
+            core::LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+            core::LLVMSetUnnamedAddress(global, LLVMUnnamedAddr::LLVMGlobalUnnamedAddr);
+            global
+        };
+
+        self.vtables.insert(key.to_string(), vtable);
+        vtable
+    }
+
+    /// Turns a pointer into the trait object `typ`, pairing it with the
+    /// vtable `key`.
+    fn build_trait_object(
+        &mut self,
+        ptr: LLVMValueRef,
+        typ: &ast::Type,
+        key: &str,
+    ) -> LLVMValueRef {
+        let vtable = self.vtable(key);
+        let data_name = CStr::from_bytes_with_nul(b"data\0").unwrap();
+        let object_name = CStr::from_bytes_with_nul(b"object\0").unwrap();
+        unsafe {
+            let data = core::LLVMBuildBitCast(
+                self.builder,
+                ptr,
+                core::LLVMPointerType(core::LLVMInt8TypeInContext(self.context), 0),
+                data_name.as_ptr(),
+            );
+            let object = core::LLVMGetUndef(self.get_type(typ));
+            let object =
+                core::LLVMBuildInsertValue(self.builder, object, data, 0, object_name.as_ptr());
+            core::LLVMBuildInsertValue(self.builder, object, vtable, 1, object_name.as_ptr())
+        }
+    }
+
+    /// Calls the object method `method` of a trait object.
+    fn compile_dyn_call(&mut self, object: &Expr, method: usize, args: &[Expr]) -> LLVMValueRef {
+        let trait_name = match object.typ.pointee() {
+            Some(ast::Type::Dyn(trait_name)) => trait_name.clone(),
+            _ => unreachable!("`{}` is not a trait object", object.typ),
+        };
+        let methods = self.program.object_methods(&trait_name);
+        let object = self.compile_expr(object);
+
+        let data_name = CStr::from_bytes_with_nul(b"data\0").unwrap();
+        let vtable_name = CStr::from_bytes_with_nul(b"vtable\0").unwrap();
+        let method_name = CStr::from_bytes_with_nul(b"method\0").unwrap();
+        let func_type = self.method_type(&methods[method]);
+        let (data, func) = unsafe {
+            let data = core::LLVMBuildExtractValue(self.builder, object, 0, data_name.as_ptr());
+            let vtable = core::LLVMBuildExtractValue(self.builder, object, 1, vtable_name.as_ptr());
+            let slot = core::LLVMBuildStructGEP2(
+                self.builder,
+                self.vtable_type(&trait_name),
+                vtable,
+                method as u32,
+                method_name.as_ptr(),
+            );
+            (
+                data,
+                core::LLVMBuildLoad2(
+                    self.builder,
+                    core::LLVMPointerType(func_type, 0),
+                    slot,
+                    method_name.as_ptr(),
+                ),
+            )
+        };
+
+        let mut func_args = vec![data];
+        for arg in args {
+            func_args.push(self.compile_expr(arg));
+        }
+
+        self.build_call(
+            func,
+            func_type,
+            &mut func_args,
+            methods[method].rtyp.is_some(),
+        )
+    }
+
+    fn build_call(
+        &mut self,
+        func: LLVMValueRef,
+        func_type: LLVMTypeRef,
+        args: &mut [LLVMValueRef],
+        returns: bool,
+    ) -> LLVMValueRef {
+        // values of type void cannot be named
+        let n = match returns {
+            true => CStr::from_bytes_with_nul(b"call\0").unwrap(),
+            false => CStr::from_bytes_with_nul(b"\0").unwrap(),
+        };
+
+        unsafe {
+            core::LLVMBuildCall2(
+                self.builder,
+                func_type,
+                func,
+                args.as_mut_ptr(),
+                args.len() as u32,
+                n.as_ptr(),
+            )
+        }
+    }
+
+    /// Creates the LLVM types of the enums. Enums with payloads are named,
+    /// so payloads can point back to the enum, and their storage is laid
+    /// out once all of them exist.
+    fn declare_enums(&mut self) {
+        let mut names: Vec<_> = self.program.enums.keys().cloned().collect();
+        names.sort();
+
+        for name in &names {
+            let typ = if self.program.enums[name].has_payload() {
+                let enum_name = CString::new(name.as_bytes()).unwrap();
+                unsafe { core::LLVMStructCreateNamed(self.context, enum_name.as_ptr()) }
+            } else {
+                unsafe { core::LLVMInt32TypeInContext(self.context) }
+            };
+            self.enum_types.insert(name.clone(), typ);
+        }
+
+        for name in &names {
+            let enumeration = &self.program.enums[name];
+            if !enumeration.has_payload() {
+                continue;
             }
-            ast::Stmt::Assign { name, op: _, value } => {
-                let ptr = self.named_values[name];
-                let val = self.compile_expr(value);
 
-                unsafe { core::LLVMBuildStore(self.builder, val, ptr) };
+            // the payload is stored in integers of the largest alignment
+            // any variant needs, enough of them to hold the largest variant
+            let (size, align) = unsafe {
+                let data = target::LLVMGetModuleDataLayout(self.module);
+                enumeration
+                    .variants
+                    .iter()
+                    .fold((0, 1), |(size, align), variant| {
+                        let typ = self.payload_type(variant);
+                        (
+                            size.max(target::LLVMABISizeOfType(data, typ)),
+                            align.max(target::LLVMABIAlignmentOfType(data, typ)),
+                        )
+                    })
+            };
+
+            unsafe {
+                let storage = core::LLVMArrayType(
+                    core::LLVMIntTypeInContext(self.context, align * 8),
+                    size.div_ceil(align as u64) as u32,
+                );
+                let mut fields = [core::LLVMInt32TypeInContext(self.context), storage];
+                core::LLVMStructSetBody(
+                    self.enum_types[name],
+                    fields.as_mut_ptr(),
+                    fields.len() as u32,
+                    0,
+                );
             }
-            ast::Stmt::Return { value } => match value {
-                Some(val) => {
-                    let val = self.compile_expr(val);
-                    unsafe { core::LLVMBuildRet(self.builder, val) };
-                }
-                None => {
-                    unsafe { core::LLVMBuildRetVoid(self.builder) };
-                }
-            },
-            _ => panic!("not implemented"),
         }
     }
 
-    fn compile_expr(&mut self, expr: &Box<ast::Expr>) -> LLVMValueRef {
-        match expr.as_ref() {
-            ast::Expr::Literal(l) => match l {
-                ast::Lit::Integer(i) => unsafe {
-                    core::LLVMConstInt(core::LLVMInt64Type(), *i as u64, 0)
-                },
-                ast::Lit::Boolean(b) => unsafe {
-                    core::LLVMConstInt(core::LLVMInt1Type(), if *b { 1 } else { 0 }, 0)
-                },
-                _ => panic!("not implemented"),
-            },
-            ast::Expr::Call { name, args } => {
-                let mut func_args = Vec::new();
+    /// The struct holding the fields of a variant in the payload storage.
+    fn payload_type(&self, variant: &typed::Variant) -> LLVMTypeRef {
+        let mut fields: Vec<_> = variant
+            .fields
+            .iter()
+            .map(|typ| self.get_type(typ))
+            .collect();
+        unsafe {
+            core::LLVMStructTypeInContext(self.context, fields.as_mut_ptr(), fields.len() as u32, 0)
+        }
+    }
 
-                for arg in args {
-                    func_args.push(self.compile_expr(arg));
-                }
+    /// The `i32` constant of a discriminant.
+    fn tag(&self, tag: i32) -> LLVMValueRef {
+        unsafe { core::LLVMConstInt(core::LLVMInt32TypeInContext(self.context), tag as u64, 1) }
+    }
 
-                let func_name = CString::new(name.as_bytes()).unwrap();
-                let func = unsafe { core::LLVMGetNamedFunction(self.module, func_name.as_ptr()) };
-                let n = CStr::from_bytes_with_nul(b"call\0").unwrap();
+    /// Builds the value of the variant `variant` of the enum `typ` from its
+    /// payload.
+    fn compile_variant(
+        &mut self,
+        typ: &ast::Type,
+        variant: usize,
+        fields: &[Expr],
+    ) -> LLVMValueRef {
+        let enumeration = self.program.enum_of(typ).unwrap();
+        let (payload, variant) = (
+            enumeration.has_payload(),
+            enumeration.variants[variant].clone(),
+        );
+        let tag = self.tag(variant.tag);
+        let enum_type = self.get_type(typ);
 
-                unsafe {
-                    core::LLVMBuildCall(
-                        self.builder,
-                        func,
-                        func_args.as_mut_ptr(),
-                        func_args.len() as u32,
-                        n.as_ptr(),
-                    )
-                }
+        if !payload {
+            return tag;
+        }
+
+        if fields.is_empty() {
+            // folds to a constant, so unit variants can initialize globals
+            let name = CStr::from_bytes_with_nul(b"variant\0").unwrap();
+            return unsafe {
+                core::LLVMBuildInsertValue(
+                    self.builder,
+                    core::LLVMConstNull(enum_type),
+                    tag,
+                    0,
+                    name.as_ptr(),
+                )
+            };
+        }
+
+        let vals: Vec<_> = fields
+            .iter()
+            .map(|field| self.compile_expr(field))
+            .collect();
+
+        unsafe {
+            let name = CStr::from_bytes_with_nul(b"variant\0").unwrap();
+            let slot = core::LLVMBuildAlloca(self.builder, enum_type, name.as_ptr());
+
+            let tag_ptr =
+                core::LLVMBuildStructGEP2(self.builder, enum_type, slot, 0, name.as_ptr());
+            core::LLVMBuildStore(self.builder, tag, tag_ptr);
+
+            let payload = self.payload_ptr(slot, enum_type, &variant);
+            let payload_type = self.payload_type(&variant);
+            for (idx, val) in vals.into_iter().enumerate() {
+                let field = core::LLVMBuildStructGEP2(
+                    self.builder,
+                    payload_type,
+                    payload,
+                    idx as u32,
+                    name.as_ptr(),
+                );
+                core::LLVMBuildStore(self.builder, val, field);
             }
-            ast::Expr::Prefix { op, expr } => {
-                let x = self.compile_expr(expr);
 
-                match op {
-                    TokenKind::Minus => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"neg\0").unwrap();
-                        core::LLVMBuildNeg(self.builder, x, name.as_ptr())
-                    },
-                    TokenKind::Bang => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"not\0").unwrap();
-                        core::LLVMBuildNot(self.builder, x, name.as_ptr())
-                    },
-                    _ => panic!("not a valid prefix operator"),
-                }
+            core::LLVMBuildLoad2(self.builder, enum_type, slot, name.as_ptr())
+        }
+    }
+
+    /// Views the payload storage of the enum of LLVM type `typ` at `slot`
+    /// as the fields of `variant`.
+    fn payload_ptr(
+        &mut self,
+        slot: LLVMValueRef,
+        typ: LLVMTypeRef,
+        variant: &typed::Variant,
+    ) -> LLVMValueRef {
+        let name = CStr::from_bytes_with_nul(b"payload\0").unwrap();
+        unsafe {
+            let storage = core::LLVMBuildStructGEP2(self.builder, typ, slot, 1, name.as_ptr());
+            let typ = core::LLVMPointerType(self.payload_type(variant), 0);
+            core::LLVMBuildBitCast(self.builder, storage, typ, name.as_ptr())
+        }
+    }
+
+    /// Compiles a `match`. The discriminant or value selects the arm
+    /// through a `switch`.
+    fn compile_match(&mut self, scrutinee: &Expr, arms: &[Arm], typ: &ast::Type) -> LLVMValueRef {
+        let value = self.compile_expr(scrutinee);
+        let scrutinee_type = &scrutinee.typ;
+
+        let payload = self
+            .program
+            .enum_of(scrutinee_type)
+            .is_some_and(|enumeration| enumeration.has_payload());
+        let (tag, tag_type) = if payload {
+            let name = CStr::from_bytes_with_nul(b"tag\0").unwrap();
+            unsafe {
+                (
+                    core::LLVMBuildExtractValue(self.builder, value, 0, name.as_ptr()),
+                    core::LLVMInt32TypeInContext(self.context),
+                )
             }
-            ast::Expr::Infix { op, left, right } => {
-                let lhs = self.compile_expr(left);
-                let rhs = self.compile_expr(right);
+        } else {
+            (value, self.get_type(scrutinee_type))
+        };
+        let switch_block = unsafe { core::LLVMGetInsertBlock(self.builder) };
 
-                match op {
-                    TokenKind::Plus => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"add\0").unwrap();
-                        core::LLVMBuildAdd(self.builder, lhs, rhs, name.as_ptr())
-                    },
-                    TokenKind::Minus => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"sub\0").unwrap();
-                        core::LLVMBuildSub(self.builder, lhs, rhs, name.as_ptr())
-                    },
-                    TokenKind::Asterisk => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"mul\0").unwrap();
-                        core::LLVMBuildMul(self.builder, lhs, rhs, name.as_ptr())
-                    },
-                    TokenKind::Slash => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"div\0").unwrap();
-                        core::LLVMBuildSDiv(self.builder, lhs, rhs, name.as_ptr())
-                    },
-                    TokenKind::Percent => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"mod\0").unwrap();
-                        core::LLVMBuildSRem(self.builder, lhs, rhs, name.as_ptr())
-                    },
-                    TokenKind::And => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"and\0").unwrap();
-                        core::LLVMBuildAnd(self.builder, lhs, rhs, name.as_ptr())
-                    },
-                    TokenKind::Pipe => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"or\0").unwrap();
-                        core::LLVMBuildOr(self.builder, lhs, rhs, name.as_ptr())
-                    },
-                    TokenKind::Caret => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"xor\0").unwrap();
-                        core::LLVMBuildXor(self.builder, lhs, rhs, name.as_ptr())
-                    },
-                    TokenKind::Equal => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"eq\0").unwrap();
-                        core::LLVMBuildICmp(
-                            self.builder,
-                            LLVMIntPredicate::LLVMIntEQ,
-                            lhs,
-                            rhs,
-                            name.as_ptr(),
-                        )
-                    },
-                    TokenKind::UnEqual => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"ue\0").unwrap();
-                        core::LLVMBuildICmp(
-                            self.builder,
-                            LLVMIntPredicate::LLVMIntNE,
-                            lhs,
-                            rhs,
-                            name.as_ptr(),
-                        )
-                    },
-                    TokenKind::LessThan => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"lt\0").unwrap();
-                        core::LLVMBuildICmp(
-                            self.builder,
-                            LLVMIntPredicate::LLVMIntSLT,
-                            lhs,
-                            rhs,
-                            name.as_ptr(),
-                        )
-                    },
-                    TokenKind::GreaterThan => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"gt\0").unwrap();
-                        core::LLVMBuildICmp(
-                            self.builder,
-                            LLVMIntPredicate::LLVMIntSGT,
-                            lhs,
-                            rhs,
-                            name.as_ptr(),
-                        )
-                    },
-                    TokenKind::LessEqual => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"le\0").unwrap();
-                        core::LLVMBuildICmp(
-                            self.builder,
-                            LLVMIntPredicate::LLVMIntSLE,
-                            lhs,
-                            rhs,
-                            name.as_ptr(),
-                        )
-                    },
-                    TokenKind::GreaterEqual => unsafe {
-                        let name = CStr::from_bytes_with_nul(b"ge\0").unwrap();
-                        core::LLVMBuildICmp(
-                            self.builder,
-                            LLVMIntPredicate::LLVMIntSGE,
-                            lhs,
-                            rhs,
-                            name.as_ptr(),
-                        )
-                    },
-                    _ => panic!("not a valid infix operator"),
-                }
+        let mut cases = Vec::new();
+        let mut blocks = Vec::new();
+        let mut catch_all = None;
+
+        for arm in arms {
+            let block = unsafe {
+                let name = CStr::from_bytes_with_nul(b"arm\0").unwrap();
+                core::LLVMAppendBasicBlockInContext(
+                    self.context,
+                    self.function.unwrap(),
+                    name.as_ptr(),
+                )
+            };
+
+            match &arm.cases {
+                Some(values) => cases.extend(
+                    values
+                        .iter()
+                        .map(|value| (self.int(tag_type, *value), block)),
+                ),
+                None => catch_all = Some(block),
             }
-            ast::Expr::Ident(name) => unsafe {
-                let load_name = CStr::from_bytes_with_nul(b"load\0").unwrap();
-                core::LLVMBuildLoad(self.builder, self.named_values[name], load_name.as_ptr())
+            blocks.push(block);
+        }
+
+        // without a catch-all the arms cover every value
+        let default = match catch_all {
+            Some(block) => block,
+            None => unsafe {
+                let name = CStr::from_bytes_with_nul(b"nomatch\0").unwrap();
+                let block = core::LLVMAppendBasicBlockInContext(
+                    self.context,
+                    self.function.unwrap(),
+                    name.as_ptr(),
+                );
+                core::LLVMPositionBuilderAtEnd(self.builder, block);
+                core::LLVMBuildUnreachable(self.builder);
+                block
             },
+        };
 
-            _ => panic!("not implemented"),
+        unsafe {
+            core::LLVMPositionBuilderAtEnd(self.builder, switch_block);
+            let switch = core::LLVMBuildSwitch(self.builder, tag, default, cases.len() as u32);
+            for (tag, block) in cases {
+                core::LLVMAddCase(switch, tag, block);
+            }
         }
-    }
 
-    fn get_type(&self, typ: &ast::Type) -> LLVMTypeRef {
-        unsafe {
-            match typ.name.as_ref() {
-                "i128" => core::LLVMInt128Type(),
-                "i64" => core::LLVMInt64Type(),
-                "i32" => core::LLVMInt32Type(),
-                "i16" => core::LLVMInt16Type(),
-                "i8" => core::LLVMInt8Type(),
-                "bool" => core::LLVMInt1Type(),
+        let merge_block = unsafe {
+            let name = CStr::from_bytes_with_nul(b"merge\0").unwrap();
+            core::LLVMAppendBasicBlockInContext(self.context, self.function.unwrap(), name.as_ptr())
+        };
+
+        let mut vals = Vec::new();
+        let mut incoming = Vec::new();
 
-                _ => panic!("unknown type"),
+        for (arm, block) in arms.iter().zip(blocks) {
+            unsafe { core::LLVMPositionBuilderAtEnd(self.builder, block) };
+            self.bind_captures(arm, value, scrutinee_type);
+
+            let val = self.compile_expr(&arm.body);
+            if self.is_terminated() {
+                continue;
             }
+
+            vals.push(val);
+            incoming.push(unsafe { core::LLVMGetInsertBlock(self.builder) });
+            unsafe { core::LLVMBuildBr(self.builder, merge_block) };
+        }
+
+        unsafe { core::LLVMPositionBuilderAtEnd(self.builder, merge_block) };
+        self.build_merge(typ, vals, incoming)
+    }
+
+    /// Binds the locals an arm captures. They refer to a copy of the
+    /// matched value.
+    fn bind_captures(&mut self, arm: &Arm, scrutinee: LLVMValueRef, typ: &ast::Type) {
+        if arm.captures.is_empty() {
+            return;
         }
+
+        let slot = self.build_temporary(scrutinee, typ);
+        for capture in &arm.captures {
+            let (variant, idx) = match capture.field {
+                Some(field) => field,
+                None => {
+                    self.locals[capture.local] = slot;
+                    continue;
+                }
+            };
+
+            let variant = self.program.enum_of(typ).unwrap().variants[variant].clone();
+            let enum_type = self.get_type(typ);
+            let payload = self.payload_ptr(slot, enum_type, &variant);
+            let payload_type = self.payload_type(&variant);
+            let name = CStr::from_bytes_with_nul(b"capture\0").unwrap();
+            self.locals[capture.local] = unsafe {
+                core::LLVMBuildStructGEP2(
+                    self.builder,
+                    payload_type,
+                    payload,
+                    idx as u32,
+                    name.as_ptr(),
+                )
+            };
+        }
+    }
+
+    /// Joins the values flowing out of the branches of an `if` or `match`
+    /// into `merge_block`, where the builder is positioned.
+    fn build_merge(
+        &mut self,
+        typ: &ast::Type,
+        mut vals: Vec<LLVMValueRef>,
+        mut blocks: Vec<LLVMBasicBlockRef>,
+    ) -> LLVMValueRef {
+        // every branch diverged, so does the whole expression
+        if blocks.is_empty() {
+            unsafe { core::LLVMBuildUnreachable(self.builder) };
+            return self.diverged(typ);
+        }
+
+        if typ.is_named("void") {
+            return self.diverged(typ);
+        }
+
+        unsafe {
+            let phi_name = CStr::from_bytes_with_nul(b"merge\0").unwrap();
+            let phi = core::LLVMBuildPhi(self.builder, self.get_type(typ), phi_name.as_ptr());
+            core::LLVMAddIncoming(
+                phi,
+                vals.as_mut_ptr(),
+                blocks.as_mut_ptr(),
+                blocks.len() as u32,
+            );
+            phi
+        }
+    }
+
+    /// Stores `value` in a new stack slot, as variables are represented.
+    fn build_temporary(&mut self, value: LLVMValueRef, typ: &ast::Type) -> LLVMValueRef {
+        let name = CStr::from_bytes_with_nul(b"tmp\0").unwrap();
+        unsafe {
+            let slot = core::LLVMBuildAlloca(self.builder, self.get_type(typ), name.as_ptr());
+            core::LLVMBuildStore(self.builder, value, slot);
+            slot
+        }
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) {
+        self.debug_location(stmt.pos());
+
+        match stmt {
+            Stmt::Let { local, value, pos } => {
+                let val = self.compile_expr(value);
+                let variable = &self.program.functions[self.function_index].locals[*local];
+                let (name, typ) = (variable.name.clone(), variable.typ.clone());
+
+                let variable_name = CString::new(name.as_bytes()).unwrap();
+                let variable = unsafe {
+                    core::LLVMBuildAlloca(self.builder, self.get_type(&typ), variable_name.as_ptr())
+                };
+
+                unsafe { core::LLVMBuildStore(self.builder, val, variable) };
+                self.debug_variable(&name, variable, &typ, None, *pos);
+                self.locals[*local] = variable;
+            }
+            Stmt::Assign {
+                place, op, value, ..
+            } => {
+                let ptr = self.compile_place(place);
+
+                let val = match op {
+                    None => self.compile_expr(value),
+                    Some(op) => {
+                        // the place is evaluated only once, `a[f()] += 1` calls `f` once
+                        let load_name = CStr::from_bytes_with_nul(b"load\0").unwrap();
+                        let current = unsafe {
+                            core::LLVMBuildLoad2(
+                                self.builder,
+                                self.get_type(&place.typ),
+                                ptr,
+                                load_name.as_ptr(),
+                            )
+                        };
+                        let rhs = self.compile_expr(value);
+
+                        match &place.typ {
+                            ast::Type::Pointer(pointee) => {
+                                self.build_offset(current, rhs, *op == TokenKind::Minus, pointee)
+                            }
+                            typ => self.build_infix(*op, typ, current, rhs),
+                        }
+                    }
+                };
+
+                unsafe { core::LLVMBuildStore(self.builder, val, ptr) };
+            }
+            Stmt::Return { value, .. } => match value {
+                Some(value) => {
+                    let val = self.compile_expr(value);
+                    if !self.is_terminated() {
+                        unsafe { core::LLVMBuildRet(self.builder, val) };
+                    }
+                }
+                None => unsafe {
+                    core::LLVMBuildRetVoid(self.builder);
+                },
+            },
+            Stmt::Expr { value, .. } => {
+                self.compile_expr(value);
+            }
+        }
+    }
+
+    /// Whether the current block already ends in a terminator, e.g. after
+    /// a `return`. Code that follows is unreachable and is not emitted.
+    fn is_terminated(&self) -> bool {
+        unsafe {
+            let block = core::LLVMGetInsertBlock(self.builder);
+            !core::LLVMGetBasicBlockTerminator(block).is_null()
+        }
+    }
+
+    /// The value of an expression whose evaluation never finishes, like a
+    /// block ending in `return`. It is never used, only its type matters.
+    fn diverged(&self, typ: &ast::Type) -> LLVMValueRef {
+        if typ.is_named("void") {
+            std::ptr::null_mut()
+        } else {
+            unsafe { core::LLVMGetUndef(self.get_type(typ)) }
+        }
+    }
+
+    /// Compiles a block, its value is the tail expression.
+    fn compile_block(
+        &mut self,
+        stmts: &[Stmt],
+        tail: &Option<Box<Expr>>,
+        typ: &ast::Type,
+    ) -> LLVMValueRef {
+        for stmt in stmts {
+            self.compile_stmt(stmt);
+            if self.is_terminated() {
+                return self.diverged(typ);
+            }
+        }
+
+        match tail {
+            Some(tail) => self.compile_expr(tail),
+            None => self.diverged(typ),
+        }
+    }
+
+    fn compile_if(
+        &mut self,
+        cond: &Expr,
+        then: &Expr,
+        elze: &Option<Box<Expr>>,
+        typ: &ast::Type,
+    ) -> LLVMValueRef {
+        let cond = self.compile_expr(cond);
+
+        let (then_block, else_block, merge_block) = unsafe {
+            let then_name = CStr::from_bytes_with_nul(b"then\0").unwrap();
+            let else_name = CStr::from_bytes_with_nul(b"else\0").unwrap();
+            let merge_name = CStr::from_bytes_with_nul(b"merge\0").unwrap();
+            let function = self.function.unwrap();
+            (
+                core::LLVMAppendBasicBlockInContext(self.context, function, then_name.as_ptr()),
+                core::LLVMAppendBasicBlockInContext(self.context, function, else_name.as_ptr()),
+                core::LLVMAppendBasicBlockInContext(self.context, function, merge_name.as_ptr()),
+            )
+        };
+
+        unsafe { core::LLVMBuildCondBr(self.builder, cond, then_block, else_block) };
+
+        let mut vals = Vec::new();
+        let mut blocks = Vec::new();
+
+        for (block, expr) in [(then_block, Some(then)), (else_block, elze.as_deref())] {
+            unsafe { core::LLVMPositionBuilderAtEnd(self.builder, block) };
+
+            let val = match expr {
+                Some(expr) => self.compile_expr(expr),
+                None => std::ptr::null_mut(),
+            };
+            if self.is_terminated() {
+                continue;
+            }
+
+            vals.push(val);
+            blocks.push(unsafe { core::LLVMGetInsertBlock(self.builder) });
+            unsafe { core::LLVMBuildBr(self.builder, merge_block) };
+        }
+
+        unsafe { core::LLVMPositionBuilderAtEnd(self.builder, merge_block) };
+        self.build_merge(typ, vals, blocks)
+    }
+
+    /// Computes the address of a place. Arrays and slices are bounds
+    /// checked when they are indexed.
+    fn compile_place(&mut self, place: &Place) -> LLVMValueRef {
+        match &place.kind {
+            PlaceKind::Local(local) => self.locals[*local],
+            PlaceKind::Static(symbol) => self.statics[symbol],
+            PlaceKind::Temporary(value) => {
+                let val = self.compile_expr(value);
+                self.build_temporary(val, &value.typ)
+            }
+            PlaceKind::Deref(pointer) => self.compile_expr(pointer),
+            PlaceKind::Field { base, index } => {
+                let ptr = self.compile_place(base);
+                let name = CStr::from_bytes_with_nul(b"field\0").unwrap();
+                unsafe {
+                    core::LLVMBuildStructGEP2(
+                        self.builder,
+                        self.get_type(&base.typ),
+                        ptr,
+                        *index as u32,
+                        name.as_ptr(),
+                    )
+                }
+            }
+            PlaceKind::Index { base, index } => match &base.typ {
+                ast::Type::Array { typ, len } => {
+                    let ptr = self.compile_place(base);
+                    let first = self.array_first(ptr, &base.typ);
+                    let idx = self.compile_expr(index);
+                    let len = unsafe {
+                        core::LLVMConstInt(
+                            core::LLVMInt64TypeInContext(self.context),
+                            *len as u64,
+                            0,
+                        )
+                    };
+                    self.build_bounds_check(idx, len);
+                    self.element_ptr(first, typ, idx)
+                }
+                ast::Type::Slice(typ) => {
+                    let slice = self.load(base);
+                    let (first, len) = self.slice_parts(slice);
+                    let idx = self.compile_expr(index);
+                    self.build_bounds_check(idx, len);
+                    self.element_ptr(first, typ, idx)
+                }
+                typ => unreachable!("`{}` cannot be indexed", typ),
+            },
+        }
+    }
+
+    /// Reads the value at a place.
+    fn load(&mut self, place: &Place) -> LLVMValueRef {
+        let ptr = self.compile_place(place);
+        let name = CStr::from_bytes_with_nul(b"load\0").unwrap();
+        unsafe { core::LLVMBuildLoad2(self.builder, self.get_type(&place.typ), ptr, name.as_ptr()) }
+    }
+
+    /// Returns a pointer to the first element of the array of type `typ`
+    /// behind `array`.
+    fn array_first(&mut self, array: LLVMValueRef, typ: &ast::Type) -> LLVMValueRef {
+        let name = CStr::from_bytes_with_nul(b"first\0").unwrap();
+        unsafe {
+            let zero = core::LLVMConstInt(core::LLVMInt64TypeInContext(self.context), 0, 0);
+            let mut indices = [zero, zero];
+            core::LLVMBuildInBoundsGEP2(
+                self.builder,
+                self.get_type(typ),
+                array,
+                indices.as_mut_ptr(),
+                indices.len() as u32,
+                name.as_ptr(),
+            )
+        }
+    }
+
+    fn build_slice(
+        &mut self,
+        typ: &ast::Type,
+        ptr: LLVMValueRef,
+        len: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let name = CStr::from_bytes_with_nul(b"slice\0").unwrap();
+        unsafe {
+            let slice = core::LLVMGetUndef(self.get_type(typ));
+            let slice = core::LLVMBuildInsertValue(self.builder, slice, ptr, 0, name.as_ptr());
+            core::LLVMBuildInsertValue(self.builder, slice, len, 1, name.as_ptr())
+        }
+    }
+
+    fn slice_parts(&mut self, slice: LLVMValueRef) -> (LLVMValueRef, LLVMValueRef) {
+        let ptr_name = CStr::from_bytes_with_nul(b"ptr\0").unwrap();
+        let len_name = CStr::from_bytes_with_nul(b"len\0").unwrap();
+        unsafe {
+            (
+                core::LLVMBuildExtractValue(self.builder, slice, 0, ptr_name.as_ptr()),
+                core::LLVMBuildExtractValue(self.builder, slice, 1, len_name.as_ptr()),
+            )
+        }
+    }
+
+    /// Returns a pointer to the element `idx` of type `typ` after `first`.
+    fn element_ptr(
+        &mut self,
+        first: LLVMValueRef,
+        typ: &ast::Type,
+        mut idx: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let name = CStr::from_bytes_with_nul(b"elem\0").unwrap();
+        unsafe {
+            core::LLVMBuildGEP2(
+                self.builder,
+                self.get_type(typ),
+                first,
+                &mut idx,
+                1,
+                name.as_ptr(),
+            )
+        }
+    }
+
+    /// Branches to the bounds panic handler unless `idx < len`.
+    fn build_bounds_check(&mut self, idx: LLVMValueRef, len: LLVMValueRef) {
+        if !self.bounds_checks {
+            return;
+        }
+
+        let in_bounds = unsafe {
+            let name = CStr::from_bytes_with_nul(b"inbounds\0").unwrap();
+            core::LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntULT,
+                idx,
+                len,
+                name.as_ptr(),
+            )
+        };
+
+        let handler = self.bounds_panic_handler();
+        self.build_check(in_bounds, handler, &mut [idx, len]);
+    }
+
+    /// Branches to the arithmetic panic handler if `op` has no result for
+    /// the operands: a division or remainder by zero, a signed `MIN / -1`
+    /// or `MIN % -1`, or a shift by the width of `typ` or more.
+    fn build_arith_check(
+        &mut self,
+        op: TokenKind,
+        typ: &ast::Type,
+        lhs: LLVMValueRef,
+        rhs: LLVMValueRef,
+    ) {
+        let width = match typ.int_width() {
+            Some(width) => width,
+            None => return,
+        };
+        let llvm_type = self.get_type(typ);
+
+        let valid = unsafe {
+            match op {
+                TokenKind::Slash | TokenKind::Percent => {
+                    let name = CStr::from_bytes_with_nul(b"nonzero\0").unwrap();
+                    let nonzero = core::LLVMBuildICmp(
+                        self.builder,
+                        LLVMIntPredicate::LLVMIntNE,
+                        rhs,
+                        core::LLVMConstInt(llvm_type, 0, 0),
+                        name.as_ptr(),
+                    );
+                    if !typ.is_signed() {
+                        nonzero
+                    } else {
+                        // the words of `MIN`, the lowest `width` bits are used
+                        let min = i128::MIN >> (128 - width);
+                        let words = [min as u64, (min >> 64) as u64];
+                        let min = core::LLVMConstIntOfArbitraryPrecision(
+                            llvm_type,
+                            words.len() as u32,
+                            words.as_ptr(),
+                        );
+
+                        let name = CStr::from_bytes_with_nul(b"is_min\0").unwrap();
+                        let is_min = core::LLVMBuildICmp(
+                            self.builder,
+                            LLVMIntPredicate::LLVMIntEQ,
+                            lhs,
+                            min,
+                            name.as_ptr(),
+                        );
+                        let name = CStr::from_bytes_with_nul(b"not_minus_one\0").unwrap();
+                        let not_minus_one = core::LLVMBuildICmp(
+                            self.builder,
+                            LLVMIntPredicate::LLVMIntNE,
+                            rhs,
+                            core::LLVMConstAllOnes(llvm_type),
+                            name.as_ptr(),
+                        );
+                        let name = CStr::from_bytes_with_nul(b"not_min\0").unwrap();
+                        let not_min = core::LLVMBuildNot(self.builder, is_min, name.as_ptr());
+                        let name = CStr::from_bytes_with_nul(b"no_overflow\0").unwrap();
+                        let no_overflow =
+                            core::LLVMBuildOr(self.builder, not_min, not_minus_one, name.as_ptr());
+                        let name = CStr::from_bytes_with_nul(b"divisible\0").unwrap();
+                        core::LLVMBuildAnd(self.builder, nonzero, no_overflow, name.as_ptr())
+                    }
+                }
+                // negative amounts are huge as unsigned values
+                TokenKind::ShiftLeft | TokenKind::ShiftRight => {
+                    let name = CStr::from_bytes_with_nul(b"in_range\0").unwrap();
+                    core::LLVMBuildICmp(
+                        self.builder,
+                        LLVMIntPredicate::LLVMIntULT,
+                        rhs,
+                        core::LLVMConstInt(llvm_type, width as u64, 0),
+                        name.as_ptr(),
+                    )
+                }
+                _ => return,
+            }
+        };
+
+        let handler = self.arith_panic_handler();
+        self.build_check(valid, handler, &mut []);
+    }
+
+    /// Continues in a new block if `ok` holds and otherwise calls `handler`
+    /// with `args` and traps if it returns.
+    fn build_check(&mut self, ok: LLVMValueRef, handler: LLVMValueRef, args: &mut [LLVMValueRef]) {
+        unsafe {
+            let ok_name = CStr::from_bytes_with_nul(b"check_ok\0").unwrap();
+            let ok_block = core::LLVMAppendBasicBlockInContext(
+                self.context,
+                self.function.unwrap(),
+                ok_name.as_ptr(),
+            );
+            let fail_name = CStr::from_bytes_with_nul(b"check_fail\0").unwrap();
+            let fail_block = core::LLVMAppendBasicBlockInContext(
+                self.context,
+                self.function.unwrap(),
+                fail_name.as_ptr(),
+            );
+
+            core::LLVMBuildCondBr(self.builder, ok, ok_block, fail_block);
+
+            core::LLVMPositionBuilderAtEnd(self.builder, fail_block);
+            let call_name = CStr::from_bytes_with_nul(b"\0").unwrap();
+            core::LLVMBuildCall2(
+                self.builder,
+                core::LLVMGlobalGetValueType(handler),
+                handler,
+                args.as_mut_ptr(),
+                args.len() as u32,
+                call_name.as_ptr(),
+            );
+            self.build_trap();
+
+            core::LLVMPositionBuilderAtEnd(self.builder, ok_block);
+        }
+    }
+
+    /// Returns the function that is called when an index is out of bounds.
+    /// Programs can provide their own `lyth_panic_bounds(index: i64, len: i64)`,
+    /// either in lyth or in C, otherwise a weak default that traps is emitted.
+    fn bounds_panic_handler(&mut self) -> LLVMValueRef {
+        let i64_type = ast::Type::new_simple("i64");
+        self.panic_handler(BOUNDS_PANIC_HANDLER, &[i64_type.clone(), i64_type])
+    }
+
+    /// Returns the function that is called when an arithmetic operation has
+    /// no result. Like the bounds handler, programs can provide their own
+    /// `lyth_panic_arith()`.
+    fn arith_panic_handler(&mut self) -> LLVMValueRef {
+        self.panic_handler(ARITH_PANIC_HANDLER, &[])
+    }
+
+    /// Returns the panic handler `name` taking `params`, the program's own
+    /// or a weak default that traps.
+    fn panic_handler(&mut self, name: &str, params: &[ast::Type]) -> LLVMValueRef {
+        if let Some(function) = self.functions.get(name) {
+            return function.value;
+        }
+
+        let handler_name = CString::new(name).unwrap();
+        let handler = unsafe { core::LLVMGetNamedFunction(self.module, handler_name.as_ptr()) };
+        if !handler.is_null() {
+            return handler;
+        }
+
+        unsafe {
+            let mut param_types: Vec<_> = params.iter().map(|typ| self.get_type(typ)).collect();
+            let handler_type = core::LLVMFunctionType(
+                core::LLVMVoidTypeInContext(self.context),
+                param_types.as_mut_ptr(),
+                param_types.len() as u32,
+                0,
+            );
+            let handler = core::LLVMAddFunction(self.module, handler_name.as_ptr(), handler_type);
+            if self.defined.contains(name) {
+                return handler;
+            }
+            core::LLVMSetLinkage(handler, LLVMLinkage::LLVMWeakAnyLinkage);
+
+            let current_block = core::LLVMGetInsertBlock(self.builder);
+            // the handler has no debug info, unlike the function using it
+            let current_location = core::LLVMGetCurrentDebugLocation2(self.builder);
+            core::LLVMSetCurrentDebugLocation2(self.builder, std::ptr::null_mut());
+
+            let block_name = CStr::from_bytes_with_nul(b"entry\0").unwrap();
+            let entry_block =
+                core::LLVMAppendBasicBlockInContext(self.context, handler, block_name.as_ptr());
+            core::LLVMPositionBuilderAtEnd(self.builder, entry_block);
+
+            self.build_trap();
+
+            core::LLVMPositionBuilderAtEnd(self.builder, current_block);
+            core::LLVMSetCurrentDebugLocation2(self.builder, current_location);
+            handler
+        }
+    }
+
+    /// Aborts the program with `llvm.trap`, ending the current block.
+    fn build_trap(&mut self) {
+        unsafe {
+            let trap_name = CStr::from_bytes_with_nul(b"llvm.trap\0").unwrap();
+            let mut trap = core::LLVMGetNamedFunction(self.module, trap_name.as_ptr());
+            if trap.is_null() {
+                let trap_type = core::LLVMFunctionType(
+                    core::LLVMVoidTypeInContext(self.context),
+                    std::ptr::null_mut(),
+                    0,
+                    0,
+                );
+                trap = core::LLVMAddFunction(self.module, trap_name.as_ptr(), trap_type);
+            }
+
+            let call_name = CStr::from_bytes_with_nul(b"\0").unwrap();
+            core::LLVMBuildCall2(
+                self.builder,
+                core::LLVMGlobalGetValueType(trap),
+                trap,
+                std::ptr::null_mut(),
+                0,
+                call_name.as_ptr(),
+            );
+            core::LLVMBuildUnreachable(self.builder);
+        }
+    }
+
+    /// Compiles the postfix `!` operator, which unwraps an optional value
+    /// or checks a raw pointer for null, and traps if there is no value.
+    fn compile_unwrap(&mut self, value: LLVMValueRef, typ: &ast::Type) -> LLVMValueRef {
+        let has_value_name = CStr::from_bytes_with_nul(b"has_value\0").unwrap();
+        let (has_value, result) = unsafe {
+            match typ {
+                ast::Type::Optional(_) => {
+                    let unwrapped_name = CStr::from_bytes_with_nul(b"unwrapped\0").unwrap();
+                    (
+                        core::LLVMBuildExtractValue(
+                            self.builder,
+                            value,
+                            0,
+                            has_value_name.as_ptr(),
+                        ),
+                        core::LLVMBuildExtractValue(
+                            self.builder,
+                            value,
+                            1,
+                            unwrapped_name.as_ptr(),
+                        ),
+                    )
+                }
+                _ => (
+                    core::LLVMBuildIsNotNull(self.builder, value, has_value_name.as_ptr()),
+                    value,
+                ),
+            }
+        };
+
+        unsafe {
+            let some_name = CStr::from_bytes_with_nul(b"some\0").unwrap();
+            let some_block = core::LLVMAppendBasicBlockInContext(
+                self.context,
+                self.function.unwrap(),
+                some_name.as_ptr(),
+            );
+            let none_name = CStr::from_bytes_with_nul(b"none\0").unwrap();
+            let none_block = core::LLVMAppendBasicBlockInContext(
+                self.context,
+                self.function.unwrap(),
+                none_name.as_ptr(),
+            );
+
+            core::LLVMBuildCondBr(self.builder, has_value, some_block, none_block);
+
+            core::LLVMPositionBuilderAtEnd(self.builder, none_block);
+            self.build_trap();
+
+            core::LLVMPositionBuilderAtEnd(self.builder, some_block);
+        }
+
+        result
+    }
+
+    /// Compiles `value == null` and `value != null` for optional values and
+    /// raw pointers.
+    fn compile_null_check(
+        &mut self,
+        value: LLVMValueRef,
+        typ: &ast::Type,
+        is_null: bool,
+    ) -> LLVMValueRef {
+        let name = CStr::from_bytes_with_nul(b"has_value\0").unwrap();
+        let has_value = unsafe {
+            match typ {
+                ast::Type::Optional(_) => {
+                    core::LLVMBuildExtractValue(self.builder, value, 0, name.as_ptr())
+                }
+                _ => core::LLVMBuildIsNotNull(self.builder, value, name.as_ptr()),
+            }
+        };
+
+        match is_null {
+            true => unsafe {
+                let name = CStr::from_bytes_with_nul(b"is_null\0").unwrap();
+                core::LLVMBuildNot(self.builder, has_value, name.as_ptr())
+            },
+            false => has_value,
+        }
+    }
+
+    /// Wraps a value into an optional that holds it.
+    fn build_some(&mut self, typ: &ast::Type, val: LLVMValueRef) -> LLVMValueRef {
+        let name = CStr::from_bytes_with_nul(b"some\0").unwrap();
+        unsafe {
+            let optional = core::LLVMGetUndef(self.get_type(typ));
+            let has_value = core::LLVMConstInt(core::LLVMInt1TypeInContext(self.context), 1, 0);
+            let optional =
+                core::LLVMBuildInsertValue(self.builder, optional, has_value, 0, name.as_ptr());
+            core::LLVMBuildInsertValue(self.builder, optional, val, 1, name.as_ptr())
+        }
+    }
+
+    /// Emits a prefix operator.
+    fn build_prefix(&mut self, op: TokenKind, x: LLVMValueRef) -> LLVMValueRef {
+        unsafe {
+            match op {
+                TokenKind::Minus => {
+                    let name = CStr::from_bytes_with_nul(b"neg\0").unwrap();
+                    core::LLVMBuildNeg(self.builder, x, name.as_ptr())
+                }
+                // `!` on booleans and `~` on integers
+                TokenKind::Bang => {
+                    let name = CStr::from_bytes_with_nul(b"not\0").unwrap();
+                    core::LLVMBuildNot(self.builder, x, name.as_ptr())
+                }
+                TokenKind::Tilde => {
+                    let name = CStr::from_bytes_with_nul(b"bitnot\0").unwrap();
+                    core::LLVMBuildNot(self.builder, x, name.as_ptr())
+                }
+                _ => unreachable!("not a valid prefix operator"),
+            }
+        }
+    }
+
+    /// Emits a binary operator on two operands of type `typ`.
+    fn build_infix(
+        &mut self,
+        op: TokenKind,
+        typ: &ast::Type,
+        lhs: LLVMValueRef,
+        rhs: LLVMValueRef,
+    ) -> LLVMValueRef {
+        // pointers are compared as unsigned addresses
+        let signed = typ.is_signed();
+        self.build_arith_check(op, typ, lhs, rhs);
+
+        match op {
+            TokenKind::Plus => unsafe {
+                let name = CStr::from_bytes_with_nul(b"add\0").unwrap();
+                core::LLVMBuildAdd(self.builder, lhs, rhs, name.as_ptr())
+            },
+            TokenKind::Minus => unsafe {
+                let name = CStr::from_bytes_with_nul(b"sub\0").unwrap();
+                core::LLVMBuildSub(self.builder, lhs, rhs, name.as_ptr())
+            },
+            TokenKind::Asterisk => unsafe {
+                let name = CStr::from_bytes_with_nul(b"mul\0").unwrap();
+                core::LLVMBuildMul(self.builder, lhs, rhs, name.as_ptr())
+            },
+            TokenKind::Slash => unsafe {
+                let name = CStr::from_bytes_with_nul(b"div\0").unwrap();
+                if signed {
+                    core::LLVMBuildSDiv(self.builder, lhs, rhs, name.as_ptr())
+                } else {
+                    core::LLVMBuildUDiv(self.builder, lhs, rhs, name.as_ptr())
+                }
+            },
+            TokenKind::Percent => unsafe {
+                let name = CStr::from_bytes_with_nul(b"mod\0").unwrap();
+                if signed {
+                    core::LLVMBuildSRem(self.builder, lhs, rhs, name.as_ptr())
+                } else {
+                    core::LLVMBuildURem(self.builder, lhs, rhs, name.as_ptr())
+                }
+            },
+            TokenKind::ShiftLeft => unsafe {
+                let name = CStr::from_bytes_with_nul(b"shl\0").unwrap();
+                core::LLVMBuildShl(self.builder, lhs, rhs, name.as_ptr())
+            },
+            TokenKind::ShiftRight => unsafe {
+                // signed integers shift in their sign bit, unsigned ones zeros
+                let name = CStr::from_bytes_with_nul(b"shr\0").unwrap();
+                if signed {
+                    core::LLVMBuildAShr(self.builder, lhs, rhs, name.as_ptr())
+                } else {
+                    core::LLVMBuildLShr(self.builder, lhs, rhs, name.as_ptr())
+                }
+            },
+            TokenKind::And => unsafe {
+                let name = CStr::from_bytes_with_nul(b"and\0").unwrap();
+                core::LLVMBuildAnd(self.builder, lhs, rhs, name.as_ptr())
+            },
+            TokenKind::Pipe => unsafe {
+                let name = CStr::from_bytes_with_nul(b"or\0").unwrap();
+                core::LLVMBuildOr(self.builder, lhs, rhs, name.as_ptr())
+            },
+            TokenKind::Caret => unsafe {
+                let name = CStr::from_bytes_with_nul(b"xor\0").unwrap();
+                core::LLVMBuildXor(self.builder, lhs, rhs, name.as_ptr())
+            },
+            TokenKind::Equal => unsafe {
+                let name = CStr::from_bytes_with_nul(b"eq\0").unwrap();
+                core::LLVMBuildICmp(
+                    self.builder,
+                    LLVMIntPredicate::LLVMIntEQ,
+                    lhs,
+                    rhs,
+                    name.as_ptr(),
+                )
+            },
+            TokenKind::UnEqual => unsafe {
+                let name = CStr::from_bytes_with_nul(b"ue\0").unwrap();
+                core::LLVMBuildICmp(
+                    self.builder,
+                    LLVMIntPredicate::LLVMIntNE,
+                    lhs,
+                    rhs,
+                    name.as_ptr(),
+                )
+            },
+            TokenKind::LessThan => unsafe {
+                let name = CStr::from_bytes_with_nul(b"lt\0").unwrap();
+                core::LLVMBuildICmp(
+                    self.builder,
+                    if signed {
+                        LLVMIntPredicate::LLVMIntSLT
+                    } else {
+                        LLVMIntPredicate::LLVMIntULT
+                    },
+                    lhs,
+                    rhs,
+                    name.as_ptr(),
+                )
+            },
+            TokenKind::GreaterThan => unsafe {
+                let name = CStr::from_bytes_with_nul(b"gt\0").unwrap();
+                core::LLVMBuildICmp(
+                    self.builder,
+                    if signed {
+                        LLVMIntPredicate::LLVMIntSGT
+                    } else {
+                        LLVMIntPredicate::LLVMIntUGT
+                    },
+                    lhs,
+                    rhs,
+                    name.as_ptr(),
+                )
+            },
+            TokenKind::LessEqual => unsafe {
+                let name = CStr::from_bytes_with_nul(b"le\0").unwrap();
+                core::LLVMBuildICmp(
+                    self.builder,
+                    if signed {
+                        LLVMIntPredicate::LLVMIntSLE
+                    } else {
+                        LLVMIntPredicate::LLVMIntULE
+                    },
+                    lhs,
+                    rhs,
+                    name.as_ptr(),
+                )
+            },
+            TokenKind::GreaterEqual => unsafe {
+                let name = CStr::from_bytes_with_nul(b"ge\0").unwrap();
+                core::LLVMBuildICmp(
+                    self.builder,
+                    if signed {
+                        LLVMIntPredicate::LLVMIntSGE
+                    } else {
+                        LLVMIntPredicate::LLVMIntUGE
+                    },
+                    lhs,
+                    rhs,
+                    name.as_ptr(),
+                )
+            },
+            _ => unreachable!("not a valid infix operator"),
+        }
+    }
+
+    /// Compiles `&&` and `||`, which only evaluate their right operand if
+    /// the left one doesn't already decide the result.
+    fn compile_short_circuit(&mut self, op: TokenKind, left: &Expr, right: &Expr) -> LLVMValueRef {
+        let lhs = self.compile_expr(left);
+
+        unsafe {
+            let lhs_block = core::LLVMGetInsertBlock(self.builder);
+
+            let rhs_name = CStr::from_bytes_with_nul(b"rhs\0").unwrap();
+            let rhs_block = core::LLVMAppendBasicBlockInContext(
+                self.context,
+                self.function.unwrap(),
+                rhs_name.as_ptr(),
+            );
+            let merge_name = CStr::from_bytes_with_nul(b"merge\0").unwrap();
+            let merge_block = core::LLVMAppendBasicBlockInContext(
+                self.context,
+                self.function.unwrap(),
+                merge_name.as_ptr(),
+            );
+
+            // `a && b` is false without looking at `b` if `a` is false,
+            // `a || b` is true if `a` is true.
+            let short_circuit = if op == TokenKind::LogicalAnd {
+                core::LLVMBuildCondBr(self.builder, lhs, rhs_block, merge_block);
+                0
+            } else {
+                core::LLVMBuildCondBr(self.builder, lhs, merge_block, rhs_block);
+                1
+            };
+
+            core::LLVMPositionBuilderAtEnd(self.builder, rhs_block);
+            let rhs = self.compile_expr(right);
+            let rhs_block = core::LLVMGetInsertBlock(self.builder);
+            core::LLVMBuildBr(self.builder, merge_block);
+
+            core::LLVMPositionBuilderAtEnd(self.builder, merge_block);
+            let phi_name = CStr::from_bytes_with_nul(b"logical\0").unwrap();
+            let phi = core::LLVMBuildPhi(
+                self.builder,
+                core::LLVMInt1TypeInContext(self.context),
+                phi_name.as_ptr(),
+            );
+            let mut values = [
+                core::LLVMConstInt(core::LLVMInt1TypeInContext(self.context), short_circuit, 0),
+                rhs,
+            ];
+            let mut blocks = [lhs_block, rhs_block];
+            core::LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+            phi
+        }
+    }
+
+    /// Moves a raw pointer to elements of type `pointee` by `offset`
+    /// elements, backwards if `negate` is set.
+    fn build_offset(
+        &mut self,
+        pointer: LLVMValueRef,
+        mut offset: LLVMValueRef,
+        negate: bool,
+        pointee: &ast::Type,
+    ) -> LLVMValueRef {
+        if negate {
+            let name = CStr::from_bytes_with_nul(b"neg\0").unwrap();
+            offset = unsafe { core::LLVMBuildNeg(self.builder, offset, name.as_ptr()) };
+        }
+
+        let name = CStr::from_bytes_with_nul(b"offset\0").unwrap();
+        unsafe {
+            core::LLVMBuildGEP2(
+                self.builder,
+                self.get_type(pointee),
+                pointer,
+                &mut offset,
+                1,
+                name.as_ptr(),
+            )
+        }
+    }
+
+    /// The distance in elements of type `pointee` between two raw pointers.
+    fn build_distance(
+        &mut self,
+        lhs: LLVMValueRef,
+        rhs: LLVMValueRef,
+        pointee: &ast::Type,
+    ) -> LLVMValueRef {
+        // the byte distance divided by the element size, like C
+        let name = CStr::from_bytes_with_nul(b"distance\0").unwrap();
+        unsafe {
+            let lhs = core::LLVMBuildPtrToInt(
+                self.builder,
+                lhs,
+                core::LLVMInt64TypeInContext(self.context),
+                name.as_ptr(),
+            );
+            let rhs = core::LLVMBuildPtrToInt(
+                self.builder,
+                rhs,
+                core::LLVMInt64TypeInContext(self.context),
+                name.as_ptr(),
+            );
+            let bytes = core::LLVMBuildSub(self.builder, lhs, rhs, name.as_ptr());
+            let size = core::LLVMSizeOf(self.get_type(pointee));
+            core::LLVMBuildExactSDiv(self.builder, bytes, size, name.as_ptr())
+        }
+    }
+
+    /// Converts a value of type `from` to `to`, as the checker allows.
+    fn compile_cast(
+        &mut self,
+        val: LLVMValueRef,
+        from: &ast::Type,
+        to: &ast::Type,
+    ) -> LLVMValueRef {
+        let name = CStr::from_bytes_with_nul(b"cast\0").unwrap();
+        let target = self.get_type(to);
+
+        // references become pointers and trait objects stay what they are
+        if self.get_type(from) == target {
+            return val;
+        }
+
+        let is_pointer = |typ: &ast::Type| typ.pointee().is_some();
+
+        unsafe {
+            match (from, to) {
+                (from, to) if from.is_integer() && to.is_integer() => {
+                    if from.int_width() > to.int_width() {
+                        core::LLVMBuildTrunc(self.builder, val, target, name.as_ptr())
+                    } else if from.is_signed() {
+                        core::LLVMBuildSExt(self.builder, val, target, name.as_ptr())
+                    } else {
+                        core::LLVMBuildZExt(self.builder, val, target, name.as_ptr())
+                    }
+                }
+                // enums without payload cast to their discriminant
+                (from, to) if to.is_integer() && self.program.enum_of(from).is_some() => {
+                    self.compile_cast(val, &ast::Type::new_simple("i32"), to)
+                }
+                (from, to) if from.is_named("bool") && to.is_integer() => {
+                    core::LLVMBuildZExt(self.builder, val, target, name.as_ptr())
+                }
+                (from, to) if is_pointer(from) && is_pointer(to) => {
+                    core::LLVMBuildPointerCast(self.builder, val, target, name.as_ptr())
+                }
+                (from, to) if is_pointer(from) && to.is_integer() => {
+                    core::LLVMBuildPtrToInt(self.builder, val, target, name.as_ptr())
+                }
+                (from, to) if from.is_integer() && is_pointer(to) => {
+                    core::LLVMBuildIntToPtr(self.builder, val, target, name.as_ptr())
+                }
+                (from, to) => unreachable!("cannot cast `{}` to `{}`", from, to),
+            }
+        }
+    }
+
+    /// The integer constant `value` of LLVM type `typ`, truncated to its
+    /// width.
+    fn int(&self, typ: LLVMTypeRef, value: i128) -> LLVMValueRef {
+        let words = [value as u64, (value >> 64) as u64];
+        unsafe { core::LLVMConstIntOfArbitraryPrecision(typ, words.len() as u32, words.as_ptr()) }
+    }
+
+    /// A pointer to a nul-terminated copy of `s`. It is built without the
+    /// builder, so strings can initialize globals.
+    fn compile_string(&mut self, s: &str) -> LLVMValueRef {
+        let string = CString::new(s.as_bytes()).unwrap();
+        let name = CStr::from_bytes_with_nul(b"str\0").unwrap();
+        let typ = ast::Type::new_pointer(ast::Type::new_simple("i8"));
+
+        unsafe {
+            let bytes = string.as_bytes_with_nul();
+            let init = core::LLVMConstStringInContext(
+                self.context,
+                bytes.as_ptr() as *const _,
+                bytes.len() as u32,
+                1,
+            );
+            let global = core::LLVMAddGlobal(self.module, core::LLVMTypeOf(init), name.as_ptr());
+            core::LLVMSetInitializer(global, init);
+            core::LLVMSetGlobalConstant(global, 1);
+            core::LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+            core::LLVMSetUnnamedAddress(global, LLVMUnnamedAddr::LLVMGlobalUnnamedAddr);
+            core::LLVMConstPointerCast(global, self.get_type(&typ))
+        }
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> LLVMValueRef {
+        let typ = &expr.typ;
+        match &expr.kind {
+            ExprKind::Int(value) => self.int(self.get_type(typ), *value),
+            ExprKind::Bool(b) => unsafe {
+                core::LLVMConstInt(core::LLVMInt1TypeInContext(self.context), *b as u64, 0)
+            },
+            ExprKind::Str(s) => self.compile_string(s),
+            ExprKind::Null => unsafe { core::LLVMConstNull(self.get_type(typ)) },
+            ExprKind::Load(place) => self.load(place),
+            ExprKind::AddrOf(place) => self.compile_place(place),
+            ExprKind::Call { function, args } => {
+                let mut vals: Vec<_> = args.iter().map(|arg| self.compile_expr(arg)).collect();
+                let function = &self.functions[function];
+                let (value, function_type) = (function.value, function.typ);
+                self.build_call(value, function_type, &mut vals, !typ.is_named("void"))
+            }
+            ExprKind::DynCall {
+                object,
+                method,
+                args,
+            } => self.compile_dyn_call(object, *method, args),
+            ExprKind::Variant { variant, fields } => self.compile_variant(typ, *variant, fields),
+            ExprKind::Struct(members) => {
+                let vals: Vec<_> = members
+                    .iter()
+                    .map(|(idx, member)| (*idx, self.compile_expr(member)))
+                    .collect();
+
+                let name = CStr::from_bytes_with_nul(b"member\0").unwrap();
+                let mut val = unsafe { core::LLVMGetUndef(self.get_type(typ)) };
+                for (idx, member) in vals {
+                    val = unsafe {
+                        core::LLVMBuildInsertValue(
+                            self.builder,
+                            val,
+                            member,
+                            idx as u32,
+                            name.as_ptr(),
+                        )
+                    };
+                }
+                val
+            }
+            ExprKind::Array(items) => {
+                let vals: Vec<_> = items.iter().map(|item| self.compile_expr(item)).collect();
+
+                let name = CStr::from_bytes_with_nul(b"array\0").unwrap();
+                let mut val = unsafe { core::LLVMGetUndef(self.get_type(typ)) };
+                for (idx, item) in vals.into_iter().enumerate() {
+                    val = unsafe {
+                        core::LLVMBuildInsertValue(
+                            self.builder,
+                            val,
+                            item,
+                            idx as u32,
+                            name.as_ptr(),
+                        )
+                    };
+                }
+                val
+            }
+            ExprKind::Unary { op, operand } => {
+                let x = self.compile_expr(operand);
+                self.build_prefix(*op, x)
+            }
+            ExprKind::Binary { op, left, right } => {
+                let mut lhs = self.compile_expr(left);
+                let mut rhs = self.compile_expr(right);
+                // trait objects are the same if they point to the same data
+                if left
+                    .typ
+                    .pointee()
+                    .is_some_and(|pointee| matches!(pointee, ast::Type::Dyn(_)))
+                {
+                    let name = CStr::from_bytes_with_nul(b"data\0").unwrap();
+                    unsafe {
+                        lhs = core::LLVMBuildExtractValue(self.builder, lhs, 0, name.as_ptr());
+                        rhs = core::LLVMBuildExtractValue(self.builder, rhs, 0, name.as_ptr());
+                    }
+                }
+                self.build_infix(*op, &left.typ, lhs, rhs)
+            }
+            ExprKind::Logical { op, left, right } => self.compile_short_circuit(*op, left, right),
+            ExprKind::Offset {
+                pointer,
+                offset,
+                negate,
+            } => {
+                let ptr = self.compile_expr(pointer);
+                let offset = self.compile_expr(offset);
+                self.build_offset(ptr, offset, *negate, pointer.typ.pointee().unwrap())
+            }
+            ExprKind::Distance { left, right } => {
+                let lhs = self.compile_expr(left);
+                let rhs = self.compile_expr(right);
+                self.build_distance(lhs, rhs, left.typ.pointee().unwrap())
+            }
+            ExprKind::Cast(value) => {
+                let val = self.compile_expr(value);
+                self.compile_cast(val, &value.typ, typ)
+            }
+            ExprKind::NullCheck { operand, is_null } => {
+                let val = self.compile_expr(operand);
+                self.compile_null_check(val, &operand.typ, *is_null)
+            }
+            ExprKind::Unwrap(value) => {
+                let val = self.compile_expr(value);
+                self.compile_unwrap(val, &value.typ)
+            }
+            ExprKind::Some(value) => {
+                let val = self.compile_expr(value);
+                self.build_some(typ, val)
+            }
+            ExprKind::Object { pointer, vtable } => {
+                let ptr = self.compile_expr(pointer);
+                self.build_trait_object(ptr, typ, vtable)
+            }
+            ExprKind::Slice { pointer, len } => {
+                let ptr = self.compile_expr(pointer);
+                let first = self.array_first(ptr, pointer.typ.pointee().unwrap());
+                let len = unsafe {
+                    core::LLVMConstInt(core::LLVMInt64TypeInContext(self.context), *len as u64, 0)
+                };
+                self.build_slice(typ, first, len)
+            }
+            ExprKind::Len(place) => match &place.typ {
+                ast::Type::Array { len, .. } => {
+                    // the array is still evaluated for its side effects
+                    self.compile_place(place);
+                    unsafe {
+                        core::LLVMConstInt(
+                            core::LLVMInt64TypeInContext(self.context),
+                            *len as u64,
+                            0,
+                        )
+                    }
+                }
+                _ => {
+                    let slice = self.load(place);
+                    self.slice_parts(slice).1
+                }
+            },
+            ExprKind::Ptr(place) => {
+                let slice = self.load(place);
+                self.slice_parts(slice).0
+            }
+            ExprKind::If { cond, then, elze } => self.compile_if(cond, then, elze, typ),
+            ExprKind::Block { stmts, tail } => self.compile_block(stmts, tail, typ),
+            ExprKind::Match { scrutinee, arms } => self.compile_match(scrutinee, arms, typ),
+        }
+    }
+
+    fn get_type(&self, typ: &ast::Type) -> LLVMTypeRef {
+        unsafe {
+            match typ {
+                // LLVM has no void pointers, C uses `i8*` for them as well
+                ast::Type::Pointer(inner) if inner.is_named("void") => {
+                    core::LLVMPointerType(core::LLVMInt8TypeInContext(self.context), 0)
+                }
+                // trait objects pair a pointer to the data with a vtable
+                ast::Type::Pointer(inner) | ast::Type::Ref { typ: inner, .. } => {
+                    match inner.as_ref() {
+                        ast::Type::Dyn(trait_name) => {
+                            let mut fields = [
+                                core::LLVMPointerType(core::LLVMInt8TypeInContext(self.context), 0),
+                                core::LLVMPointerType(self.vtable_type(trait_name), 0),
+                            ];
+                            core::LLVMStructTypeInContext(
+                                self.context,
+                                fields.as_mut_ptr(),
+                                fields.len() as u32,
+                                0,
+                            )
+                        }
+                        inner => core::LLVMPointerType(self.get_type(inner), 0),
+                    }
+                }
+                ast::Type::Dyn(trait_name) => {
+                    unreachable!("`dyn {}` has no known size", trait_name)
+                }
+                ast::Type::Array { typ, len } => {
+                    core::LLVMArrayType(self.get_type(typ), *len as u32)
+                }
+                ast::Type::Optional(typ) => {
+                    let mut fields = [
+                        core::LLVMInt1TypeInContext(self.context),
+                        self.get_type(typ),
+                    ];
+                    core::LLVMStructTypeInContext(
+                        self.context,
+                        fields.as_mut_ptr(),
+                        fields.len() as u32,
+                        0,
+                    )
+                }
+                ast::Type::Slice(typ) => {
+                    let mut fields = [
+                        core::LLVMPointerType(self.get_type(typ), 0),
+                        core::LLVMInt64TypeInContext(self.context),
+                    ];
+                    core::LLVMStructTypeInContext(
+                        self.context,
+                        fields.as_mut_ptr(),
+                        fields.len() as u32,
+                        0,
+                    )
+                }
+                ast::Type::Named { name, .. } => match name.as_ref() {
+                    "i128" | "u128" => core::LLVMInt128TypeInContext(self.context),
+                    "i64" | "u64" => core::LLVMInt64TypeInContext(self.context),
+                    "i32" | "u32" => core::LLVMInt32TypeInContext(self.context),
+                    "i16" | "u16" => core::LLVMInt16TypeInContext(self.context),
+                    "i8" | "u8" => core::LLVMInt8TypeInContext(self.context),
+                    "bool" => core::LLVMInt1TypeInContext(self.context),
+                    "void" => core::LLVMVoidTypeInContext(self.context),
+                    name if self.program.structs.contains_key(name) => self.struct_type(typ),
+                    name => self.enum_types[name],
+                },
+            }
+        }
+    }
+}
+
+impl Drop for Compiler<'_> {
+    fn drop(&mut self) {
+        // the debug info builder refers to the module, it goes first
+        self.debug = None;
+        unsafe {
+            core::LLVMDisposeBuilder(self.builder);
+            target_machine::LLVMDisposeTargetMachine(self.machine);
+            // null once the module was taken by `into_module`
+            if !self.module.is_null() {
+                core::LLVMDisposeModule(self.module);
+                core::LLVMContextDispose(self.context);
+            }
+        }
+    }
+}
+
+/// A built module together with the context it lives in, taken from a
+/// compiler by `Compiler::into_module`.
+pub struct Module {
+    context: LLVMContextRef,
+    module: LLVMModuleRef,
+}
+
+impl Module {
+    /// Gives up ownership of the context and the module.
+    fn into_raw(self) -> (LLVMContextRef, LLVMModuleRef) {
+        let module = std::mem::ManuallyDrop::new(self);
+        (module.context, module.module)
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        unsafe {
+            core::LLVMDisposeModule(self.module);
+            core::LLVMContextDispose(self.context);
+        }
+    }
+}
+
+unsafe impl Send for Module {}
+
+// All LLVM objects of a compiler live in its own context and are only
+// reachable through it, so it can be moved to another thread.
+unsafe impl Send for Compiler<'_> {}
+
+fn value_name(value: LLVMValueRef) -> String {
+    unsafe {
+        let mut len = 0;
+        let name = core::LLVMGetValueName2(value, &mut len);
+        let name = std::slice::from_raw_parts(name as *const u8, len);
+        String::from_utf8_lossy(name).into_owned()
+    }
+}
+
+/// Whether `global` is a function or static other modules can use.
+fn defines_symbol(global: LLVMValueRef) -> bool {
+    unsafe {
+        core::LLVMIsDeclaration(global) == 0
+            && !matches!(
+                core::LLVMGetLinkage(global),
+                LLVMLinkage::LLVMPrivateLinkage | LLVMLinkage::LLVMInternalLinkage
+            )
+    }
+}
+
+fn add_function_attribute(context: LLVMContextRef, function: LLVMValueRef, kind: &str) {
+    unsafe {
+        let id = core::LLVMGetEnumAttributeKindForName(kind.as_ptr() as *const _, kind.len());
+        let attribute = core::LLVMCreateEnumAttribute(context, id, 0);
+        core::LLVMAddAttributeAtIndex(function, LLVMAttributeFunctionIndex, attribute);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use llvm_sys::analysis;
+
+    /// Builds `input` and prints the LLVM IR of its module.
+    fn ir(input: &str) -> String {
+        ir_of(Compiler::new("test.ly"), input)
+    }
+
+    /// Builds `input` with `compiler`, verifies its module and prints its
+    /// LLVM IR.
+    fn ir_of(mut compiler: Compiler, input: &str) -> String {
+        let items = Parser::new("test.ly", input).parse().unwrap();
+        compiler
+            .build(items.into_iter().map(|item| item.decl).collect())
+            .unwrap();
+        unsafe {
+            let mut error = std::ptr::null_mut();
+            let invalid = analysis::LLVMVerifyModule(
+                compiler.module,
+                analysis::LLVMVerifierFailureAction::LLVMReturnStatusAction,
+                &mut error,
+            );
+            let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+            core::LLVMDisposeMessage(error);
+            assert!(invalid == 0, "invalid module: {}", message);
+
+            let text = core::LLVMPrintModuleToString(compiler.module);
+            let ir = CStr::from_ptr(text).to_string_lossy().into_owned();
+            core::LLVMDisposeMessage(text);
+            ir
+        }
+    }
+
+    /// The first line of `ir` containing `text`.
+    fn line<'a>(ir: &'a str, text: &str) -> &'a str {
+        ir.lines()
+            .find(|line| line.contains(text))
+            .unwrap_or_else(|| panic!("no `{}` in\n{}", text, ir))
+    }
+
+    /// The definition of the function `symbol` in `ir`.
+    fn function<'a>(ir: &'a str, symbol: &str) -> &'a str {
+        let header = format!("@{}(", symbol);
+        let start = ir
+            .match_indices("define ")
+            .map(|(start, _)| start)
+            .find(|&start| ir[start..].lines().next().unwrap().contains(&header))
+            .unwrap_or_else(|| panic!("no function `{}` in\n{}", symbol, ir));
+        let end = ir[start..]
+            .find("\n}\n")
+            .map_or(ir.len(), |end| start + end + 2);
+        &ir[start..end]
+    }
+
+    #[test]
+    fn extern_functions_are_declared() {
+        let ir = ir("
+            extern \"C\" fn printf(fmt: *i8, ...) i32;
+            extern fn exit(status: i32);
+            fn main() i32 { printf(\"%d\\n\", 5); exit(1); 0 }
+        ");
+        assert!(line(&ir, "declare i32 @printf(").ends_with(", ...)"));
+        assert_eq!(line(&ir, "@exit("), "declare void @exit(i32)");
+        assert!(line(&ir, "call i32 (").contains("...) @printf("));
+    }
+
+    #[test]
+    fn bounds_checks() {
+        let input = "
+            fn get(a: [i64; 3], i: i64) i64 { a[i] }
+            fn at(s: [i64], i: i64) i64 { s[i] }
+        ";
+        let ir = ir(input);
+        assert!(line(&ir, "@_LN3getE(").starts_with("define"));
+        assert!(line(&ir, "%inbounds = ").ends_with(", 3"));
+        assert!(line(&ir, "%len)").contains("call void @lyth_panic_bounds("));
+        // without a handler in the program, the default one only traps
+        assert!(line(&ir, "@lyth_panic_bounds(i64 %0").starts_with("define weak void"));
+
+        let mut compiler = Compiler::new("test.ly");
+        compiler.set_bounds_checks(false);
+        let ir = ir_of(compiler, input);
+        assert!(!ir.contains("inbounds ="), "{}", ir);
+        assert!(!ir.contains("lyth_panic_bounds"), "{}", ir);
+    }
+
+    #[test]
+    fn shifts_by_signedness() {
+        let ir = ir("
+            fn signed(x: i32, n: i32) i32 { x >> n }
+            fn unsigned(x: u32, n: u32) u32 { x >> n }
+            fn left(x: u32, n: u32) u32 { x << n }
+        ");
+        assert!(function(&ir, "_LN6signedE").contains(" = ashr i32 "));
+        assert!(function(&ir, "_LN8unsignedE").contains(" = lshr i32 "));
+        assert!(function(&ir, "_LN4leftE").contains(" = shl i32 "));
+    }
+
+    #[test]
+    fn unwrapping_null_traps() {
+        let ir = ir("
+            fn optional(x: ?i64) i64 { x! }
+            fn pointer(p: *i64) *i64 { p! }
+        ");
+        assert!(function(&ir, "_LN8optionalE").contains("call void @llvm.trap()"));
+        assert!(function(&ir, "_LN7pointerE").contains("call void @llvm.trap()"));
+    }
+
+    #[test]
+    fn if_expressions_join_in_a_phi() {
+        let ir = ir("fn f(c: bool) i64 { if c { 1 } else { 2 } }");
+        assert!(function(&ir, "_LN1fE").contains(" = phi i64 [ 1, %"));
+    }
+
+    #[test]
+    fn globals() {
+        let ir = ir("
+            const LIMIT: u8 = 250;
+            static TABLE: [i64; 2] = [1, 2];
+            static mut TOTAL: i64 = 5;
+            fn f() i64 { TOTAL += 1; TOTAL + TABLE[1] + LIMIT as i64 }
+        ");
+        assert_eq!(
+            line(&ir, "@_LN5TABLEE = "),
+            "@_LN5TABLEE = constant [2 x i64] [i64 1, i64 2]"
+        );
+        assert_eq!(line(&ir, "@_LN5TOTALE = "), "@_LN5TOTALE = global i64 5");
+        // constants are inlined where they are used
+        assert!(!ir.contains("LIMIT"), "{}", ir);
+        assert!(function(&ir, "_LN1fE").contains(", 250\n"));
+    }
+
+    #[test]
+    fn match_is_a_switch() {
+        let ir = ir("
+            enum Color { Red, Green = 5, Blue }
+            fn f(c: Color) i64 { match c { Color::Red => 1, Color::Green => 2, _ => 3 } }
+        ");
+        let f = function(&ir, "_LN1fE");
+        assert!(f.contains(" = phi i64 "), "{}", f);
+        let switch = &f[f.find("switch ").unwrap_or_else(|| panic!("{}", f))..];
+        assert!(switch.contains("i32 0, label %"), "{}", f);
+        assert!(switch.contains("i32 5, label %"), "{}", f);
+    }
+
+    #[test]
+    fn trait_objects_call_through_vtables() {
+        let ir = ir("
+            trait Shape { fn area(&self) i64; fn twice(&self) i64 { self.area() * 2 } }
+            struct Rect { w: i64, h: i64 }
+            impl Shape for Rect { fn area(&self) i64 { self.w * self.h } }
+            fn dynamic(s: &dyn Shape) i64 { s.twice() }
+            fn f(r: &Rect) i64 { dynamic(r) }
+        ");
+        let vtable = line(&ir, "@\"vtable.<Rect as Shape>\" = ");
+        let area = vtable.find("@_LNXN4RectEN5ShapeE4areaE").unwrap();
+        let twice = vtable.find("@_LNXN4RectEN5ShapeE5twiceE").unwrap();
+        assert!(area < twice, "{}", vtable);
+
+        // `twice` is the second method of the vtable
+        let dynamic = function(&ir, "_LN7dynamicE");
+        assert!(line(dynamic, "%method = ").ends_with("i32 0, i32 1"));
+        assert!(line(dynamic, "%call = ").contains("call i64 %method1("));
+    }
+
+    #[test]
+    fn attributes() {
+        let ir = ir("
+            #[inline(always)] #[cold] #[section = \".hot\"] fn f() {}
+            #[noinline] #[weak] fn g() {}
+            #[align(16)] #[section = \".tables\"] static A: i64 = 1;
+            #[weak] static mut B: i64 = 1;
+        ");
+        assert_eq!(
+            line(&ir, "@_LN1AE = "),
+            "@_LN1AE = constant i64 1, section \".tables\", align 16"
+        );
+        assert_eq!(line(&ir, "@_LN1BE = "), "@_LN1BE = weak global i64 1");
+        assert!(line(&ir, "@_LN1fE(").ends_with(" section \".hot\" {"));
+        assert!(line(&ir, "@_LN1gE(").starts_with("define weak void"));
+        assert_eq!(
+            line(&ir, "; Function Attrs: alwaysinline"),
+            "; Function Attrs: alwaysinline cold"
+        );
+        assert_eq!(
+            line(&ir, "; Function Attrs: noinline"),
+            "; Function Attrs: noinline"
+        );
+    }
+
+    #[test]
+    fn inline_keyword() {
+        let ir = ir("inline fn f() {} inline(always) fn g() {}");
+        assert_eq!(
+            line(&ir, "; Function Attrs: inlinehint"),
+            "; Function Attrs: inlinehint"
+        );
+        assert_eq!(
+            line(&ir, "; Function Attrs: alwaysinline"),
+            "; Function Attrs: alwaysinline"
+        );
+    }
+
+    #[test]
+    fn debug_info() {
+        let mut compiler = Compiler::new("test.ly");
+        compiler.set_debug_info(true);
+        let ir = ir_of(
+            compiler,
+            "fn test(abc: i64) i64 {
+  return 7 + abc;
+}
+
+struct P { x: i64, y: u8 }
+
+#[export]
+fn test2(b: i64) i64 {
+  let p = P { x: 1, y: 2 };
+  if b % 2 == 0 {
+    b = test(12);
+  }
+  return b * test(66);
+}
+",
+        );
+        assert!(line(&ir, "!DICompileUnit(").contains("producer: \"lyth\""));
+        assert!(line(&ir, "!DIFile(").contains("filename: \"test.ly\""));
+        assert!(line(&ir, "!DISubprogram(name: \"test\"").contains("linkageName: \"_LN4testE\""));
+        assert!(line(&ir, "!DISubprogram(name: \"test2\"").contains(", line: 8,"));
+
+        // parameters and `let` bindings are variables in their allocas
+        assert!(line(&ir, "!DILocalVariable(name: \"b\"").contains("arg: 1,"));
+        assert!(line(&ir, "!DILocalVariable(name: \"p\"").contains(", line: 9,"));
+        assert!(line(&ir, "!DICompositeType(").contains("name: \"P\""));
+        assert!(line(&ir, "!DIDerivedType(tag: DW_TAG_member, name: \"y\"").contains("offset: 64"));
+        let test2 = function(&ir, "test2");
+        assert_eq!(test2.matches("call void @llvm.dbg.declare(").count(), 2);
+
+        // every statement is located at its line
+        let store = line(test2, "store i64 %call, ");
+        let location = &store[store.rfind('!').unwrap()..];
+        assert!(line(&ir, &format!("{} = !DILocation(", location)).contains("line: 11, column: 5"));
+    }
+
+    #[test]
+    fn debug_info_in_objects() {
+        let path = std::env::temp_dir().join(format!("lyth-debug-{}.o", std::process::id()));
+        let path = path.display().to_string();
+        let items = Parser::new("test.ly", "fn f(x: i64) i64 {\n  let y = x;\n  y\n}\n")
+            .parse()
+            .unwrap();
+        let mut compiler = Compiler::new("test.ly");
+        compiler.set_debug_info(true);
+        compiler
+            .compile(items.into_iter().map(|item| item.decl).collect(), &path)
+            .unwrap();
+
+        let object = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let contains = |name: &str| {
+            object
+                .windows(name.len())
+                .any(|bytes| bytes == name.as_bytes())
+        };
+        assert!(contains("debug_info"));
+        assert!(contains("debug_line"));
+        assert!(contains("test.ly"));
+    }
+
+    #[test]
+    fn compilers_in_parallel() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Compiler>();
+        assert_send::<Module>();
+
+        // every compiler has a context of its own
+        let irs: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|n| {
+                    scope.spawn(move || {
+                        let input = format!("struct P {{ x: i64 }} fn f{}(p: P) i64 {{ p.x }}", n);
+                        ir(&input)
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        for (n, ir) in irs.iter().enumerate() {
+            assert!(function(ir, &format!("_LN2f{}E", n)).contains("%P"));
+        }
+
+        // compilers are dropped with and without their module
+        drop(Compiler::new("test.ly"));
+        let mut compiler = Compiler::new("test.ly");
+        compiler.build(Vec::new()).unwrap();
+        drop(compiler.into_module());
     }
 }
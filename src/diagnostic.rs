@@ -0,0 +1,35 @@
+//! Errors in a program, as the front end reports them instead of
+//! panicking, so tools using it as a library can show them.
+
+use crate::parser::ast;
+use std::fmt::{Display, Formatter, Result};
+
+/// An error at a line and column of a file of the program. Errors about
+/// a whole file, like one that cannot be read, are at line 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(file: &str, pos: ast::Pos, message: String) -> Self {
+        Self {
+            file: file.to_string(),
+            line: pos.line,
+            col: pos.col,
+            message,
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.line {
+            0 => write!(f, "{}: {}", self.file, self.message),
+            line => write!(f, "{}:{}:{}: {}", self.file, line, self.col, self.message),
+        }
+    }
+}
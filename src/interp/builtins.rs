@@ -0,0 +1,256 @@
+//! The C functions interpreted programs can call through `extern fn`
+//! declarations. They work on the interpreter's memory, so only those
+//! reimplemented here are available.
+
+use super::{Address, Data, Interpreter, Value};
+use crate::parser::ast;
+use crate::semantics::typed::Function;
+use std::io::Write;
+
+/// Calls the extern function `function` with `args`, which have the types
+/// of its declared parameters followed by the promoted variadic arguments.
+pub(super) fn call(interp: &mut Interpreter, function: &Function, args: Vec<Value>) -> Value {
+    let (name, rtyp) = (function.symbol.as_str(), function.rtyp.clone());
+
+    let result = match name {
+        "printf" => {
+            let out = format(interp, &args);
+            print(&out);
+            out.len() as i128
+        }
+        "puts" => {
+            let mut out = string(interp, &args[0]);
+            out.push(b'\n');
+            print(&out);
+            0
+        }
+        "putchar" => {
+            let c = args[0].as_int().unwrap();
+            print(&[c as u8]);
+            c
+        }
+        "exit" => {
+            let _ = std::io::stdout().flush();
+            std::process::exit(args[0].as_int().unwrap() as i32)
+        }
+        "abort" => {
+            let _ = std::io::stdout().flush();
+            std::process::abort()
+        }
+        "abs" | "labs" => args[0].as_int().unwrap().wrapping_abs(),
+        "strlen" => string(interp, &args[0]).len() as i128,
+        "getenv" => {
+            let var = String::from_utf8_lossy(&string(interp, &args[0])).into_owned();
+            let data = match std::env::var(var) {
+                Ok(value) => Data::Pointer(Some(interp.string(&value))),
+                Err(_) => Data::Pointer(None),
+            };
+            return Value {
+                data,
+                typ: rtyp.unwrap_or_else(|| ast::Type::new_simple("void")),
+            };
+        }
+        _ => panic!(
+            "{}: extern function `{}` is not available in the interpreter",
+            interp.file, name
+        ),
+    };
+
+    match rtyp {
+        Some(typ) if typ.is_integer() => Value::int(result, typ),
+        Some(typ) => panic!(
+            "{}: `{}` is declared to return `{}`, which the interpreter doesn't support",
+            interp.file, name, typ
+        ),
+        None => Value::void(),
+    }
+}
+
+fn print(bytes: &[u8]) {
+    let mut stdout = std::io::stdout();
+    stdout.write_all(bytes).expect("failed to write to stdout");
+}
+
+/// Reads the nul-terminated string a `*i8` points to.
+fn string(interp: &Interpreter, value: &Value) -> Vec<u8> {
+    let mut addr = interp.deref(&value.data);
+    let mut bytes = Vec::new();
+    loop {
+        match interp.read(&addr) {
+            Data::Int(0) => return bytes,
+            Data::Int(byte) => bytes.push(*byte as u8),
+            _ => interp.invalid_access(),
+        }
+        addr = next(addr);
+    }
+}
+
+fn next(mut addr: Address) -> Address {
+    match addr.path.last_mut() {
+        Some(idx) => *idx += 1,
+        None => addr.path.push(1),
+    }
+    addr
+}
+
+/// Formats the arguments of `printf` like C does. Integers are printed
+/// with the width the length modifier asks for.
+fn format(interp: &Interpreter, args: &[Value]) -> Vec<u8> {
+    let fmt = string(interp, &args[0]);
+    let mut args = args[1..].iter();
+    let mut next_arg = || {
+        args.next()
+            .unwrap_or_else(|| panic!("{}: too few arguments for `printf`", interp.file))
+    };
+
+    let mut out = Vec::new();
+    let mut chars = fmt.iter().copied().peekable();
+    while let Some(c) = chars.next() {
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut flags = Vec::new();
+        while let Some(flag @ (b'-' | b'+' | b' ' | b'#' | b'0')) = chars.peek().copied() {
+            flags.push(flag);
+            chars.next();
+        }
+        let number = |chars: &mut std::iter::Peekable<_>| {
+            let mut n = None;
+            while let Some(digit @ b'0'..=b'9') = chars.peek().copied() {
+                n = Some(n.unwrap_or(0) * 10 + (digit - b'0') as usize);
+                chars.next();
+            }
+            n
+        };
+        let width = number(&mut chars);
+        let precision = match chars.peek() {
+            Some(b'.') => {
+                chars.next();
+                Some(number(&mut chars).unwrap_or(0))
+            }
+            _ => None,
+        };
+
+        let mut bits = 32;
+        while let Some(modifier @ (b'h' | b'l' | b'z' | b'j' | b't')) = chars.peek().copied() {
+            bits = match (modifier, bits) {
+                (b'h', 16) => 8,
+                (b'h', _) => 16,
+                _ => 64,
+            };
+            chars.next();
+        }
+
+        let conversion = chars
+            .next()
+            .unwrap_or_else(|| panic!("{}: incomplete `printf` conversion", interp.file));
+        let (mut digits, prefix) = match conversion {
+            b'%' => {
+                out.push(b'%');
+                continue;
+            }
+            b'd' | b'i' => {
+                let value = int_arg(next_arg(), bits, true);
+                let sign = if value < 0 {
+                    "-"
+                } else if flags.contains(&b'+') {
+                    "+"
+                } else if flags.contains(&b' ') {
+                    " "
+                } else {
+                    ""
+                };
+                (
+                    value.unsigned_abs().to_string().into_bytes(),
+                    sign.to_string(),
+                )
+            }
+            b'u' | b'x' | b'X' | b'o' => {
+                let value = int_arg(next_arg(), bits, false) as u128;
+                let alternate = flags.contains(&b'#') && value != 0;
+                match conversion {
+                    b'u' => (value.to_string().into_bytes(), String::new()),
+                    b'x' => (
+                        format!("{:x}", value).into_bytes(),
+                        if alternate { "0x" } else { "" }.to_string(),
+                    ),
+                    b'X' => (
+                        format!("{:X}", value).into_bytes(),
+                        if alternate { "0X" } else { "" }.to_string(),
+                    ),
+                    _ => (
+                        format!("{:o}", value).into_bytes(),
+                        if alternate { "0" } else { "" }.to_string(),
+                    ),
+                }
+            }
+            b'c' => (vec![int_arg(next_arg(), 8, false) as u8], String::new()),
+            b's' => {
+                let mut s = string(interp, next_arg());
+                if let Some(precision) = precision {
+                    s.truncate(precision);
+                }
+                (s, String::new())
+            }
+            b'p' => {
+                let arg = next_arg();
+                let s = match &arg.data {
+                    Data::Pointer(None) => "(nil)".to_string(),
+                    Data::Pointer(Some(addr)) => format!("{:?}", addr),
+                    _ => panic!("{}: `%p` expects a pointer", interp.file),
+                };
+                (s.into_bytes(), String::new())
+            }
+            c => panic!(
+                "{}: `printf` conversion `%{}` is not supported by the interpreter",
+                interp.file, c as char
+            ),
+        };
+
+        let numeric = matches!(conversion, b'd' | b'i' | b'u' | b'x' | b'X' | b'o');
+        if let (true, Some(precision)) = (numeric, precision) {
+            if precision == 0 && digits == b"0" {
+                digits.clear();
+            }
+            while digits.len() < precision {
+                digits.insert(0, b'0');
+            }
+        }
+
+        let len = prefix.len() + digits.len();
+        let pad = width.unwrap_or(0).saturating_sub(len);
+        if flags.contains(&b'-') {
+            out.extend(prefix.bytes());
+            out.extend(&digits);
+            out.extend(std::iter::repeat_n(b' ', pad));
+        } else if flags.contains(&b'0') && numeric && precision.is_none() {
+            out.extend(prefix.bytes());
+            out.extend(std::iter::repeat_n(b'0', pad));
+            out.extend(&digits);
+        } else {
+            out.extend(std::iter::repeat_n(b' ', pad));
+            out.extend(prefix.bytes());
+            out.extend(&digits);
+        }
+    }
+
+    out
+}
+
+/// Reads an integer argument as C would, truncated to the width `bits`
+/// and extended to 128 bits by its signedness.
+fn int_arg(value: &Value, bits: u32, signed: bool) -> i128 {
+    let value = match value.data {
+        Data::Int(value) => value,
+        _ => panic!("`printf` expects an integer but found `{}`", value.typ),
+    };
+
+    let shift = 128 - bits;
+    if signed {
+        (value << shift) >> shift
+    } else {
+        value & ((1 << bits) - 1)
+    }
+}
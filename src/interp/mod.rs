@@ -0,0 +1,919 @@
+//! A tree-walking interpreter running lyth programs without LLVM. It
+//! checks the declarations the compiler takes and evaluates the typed
+//! program. Integers wrap at the width of their type and shifts and
+//! division follow their signedness. Indexing out of bounds calls
+//! `lyth_panic_bounds`, and division or remainder by zero, signed
+//! `MIN / -1` and shifts by the width of the type or more call
+//! `lyth_panic_arith`, as in compiled code. Where compiled code then
+//! traps, the interpreter panics with an error. Within the limits below it
+//! is the reference the compiled code can be tested against.
+//!
+//! Programs are checked up front, so the interpreter rejects the same
+//! programs the compiler does. Memory is made of typed slots, not bytes,
+//! so pointers can't be turned into integers and memory can't be
+//! reinterpreted as another type. Extern functions are limited to the C
+//! functions `builtins` provides.
+
+mod builtins;
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::token::TokenKind;
+use crate::parser::ast;
+use crate::semantics::typed::{Arm, Expr, ExprKind, Place, PlaceKind, Program, Stmt};
+use crate::semantics::{wrap, Checker, ARITH_PANIC_HANDLER, BOUNDS_PANIC_HANDLER};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A value together with its lyth type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Value {
+    data: Data,
+    typ: ast::Type,
+}
+
+impl Value {
+    /// An integer of type `typ`, wrapped to its width.
+    pub fn int(value: i128, typ: ast::Type) -> Self {
+        assert!(typ.is_integer(), "`{}` is not an integer type", typ);
+        Self {
+            data: Data::Int(wrap(value, &typ)),
+            typ,
+        }
+    }
+
+    pub fn bool(value: bool) -> Self {
+        Self {
+            data: Data::Bool(value),
+            typ: ast::Type::new_simple("bool"),
+        }
+    }
+
+    pub fn typ(&self) -> &ast::Type {
+        &self.typ
+    }
+
+    /// The value of an integer, unsigned ones of 128 bits are returned as
+    /// their bit pattern.
+    pub fn as_int(&self) -> Option<i128> {
+        match self.data {
+            Data::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.data {
+            Data::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn void() -> Self {
+        Self {
+            data: Data::Void,
+            typ: ast::Type::new_simple("void"),
+        }
+    }
+}
+
+/// The representation of values. Integers are kept sign-extended if their
+/// type is signed and zero-extended otherwise.
+#[derive(Clone, Debug, PartialEq)]
+enum Data {
+    Int(i128),
+    Bool(bool),
+    Void,
+    Pointer(Option<Address>),
+    /// The members of a struct or the elements of an array.
+    Aggregate(Vec<Data>),
+    /// A variant of an enum, also one without payload.
+    Variant {
+        tag: i32,
+        fields: Vec<Data>,
+    },
+    Optional(Option<Box<Data>>),
+    Slice {
+        first: Option<Address>,
+        len: i64,
+    },
+    /// A trait object, the data it refers to and the vtable of the type
+    /// implementing the trait.
+    Object {
+        data: Address,
+        vtable: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Region {
+    Global,
+    Stack,
+}
+
+/// A memory location, a slot and the member and element indices leading
+/// to the value inside it.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Address {
+    region: Region,
+    slot: usize,
+    path: Vec<i64>,
+}
+
+impl Address {
+    fn member(&self, idx: i64) -> Address {
+        let mut path = self.path.clone();
+        path.push(idx);
+        Address {
+            region: self.region,
+            slot: self.slot,
+            path,
+        }
+    }
+}
+
+/// Unwinds the evaluation of a function body to the call on `return`.
+struct Return(Box<Value>);
+
+type Flow<T> = Result<T, Return>;
+
+/// Runs a lyth program by evaluating its typed program.
+pub struct Interpreter<'a> {
+    file: &'a str,
+    // shared, so function bodies can be evaluated while the interpreter
+    // changes
+    program: Rc<Program>,
+    functions: HashMap<String, usize>,
+    statics: HashMap<String, Address>,
+    strings: HashMap<String, Address>,
+    globals: Vec<Data>,
+    stack: Vec<Data>,
+    /// The slots of the locals of the function being run, by their index.
+    locals: Vec<Option<Address>>,
+    bounds_checks: bool,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Creates an interpreter for the program whose root is `file`, which
+    /// is also the file named in error messages.
+    pub fn new(file: &'a str) -> Self {
+        Self {
+            file,
+            program: Rc::default(),
+            functions: HashMap::new(),
+            statics: HashMap::new(),
+            strings: HashMap::new(),
+            globals: Vec::new(),
+            stack: Vec::new(),
+            locals: Vec::new(),
+            bounds_checks: true,
+        }
+    }
+
+    /// Enables or disables the bounds checks of indexing. Without them an
+    /// index out of bounds is still an error, but `lyth_panic_bounds` is
+    /// not called.
+    pub fn set_bounds_checks(&mut self, enabled: bool) {
+        self.bounds_checks = enabled;
+    }
+
+    /// Checks the declarations of a loaded program and evaluates the
+    /// initializers of its globals, so its functions can be called, or
+    /// returns the errors in the program.
+    pub fn declare(&mut self, decls: Vec<ast::Decl>) -> Result<(), Vec<Diagnostic>> {
+        let program = Rc::new(Checker::new(self.file).check(decls)?);
+        self.program = Rc::clone(&program);
+
+        for (idx, function) in program.functions.iter().enumerate() {
+            self.functions.insert(function.name.clone(), idx);
+        }
+
+        // every static has its slot before any initializer runs, so they
+        // can refer to each other
+        for decl in &program.statics {
+            let addr = self.alloc(Region::Global, Data::Void);
+            self.statics.insert(decl.symbol.clone(), addr);
+        }
+        for decl in &program.statics {
+            let value = decl
+                .value
+                .as_ref()
+                .unwrap_or_else(|| unreachable!("statics of programs have an initializer"));
+            let value = self.eval(value).unwrap_or_else(|_| unreachable!());
+            let addr = self.statics[&decl.symbol].clone();
+            self.store(&addr, value.data);
+        }
+        Ok(())
+    }
+
+    /// Calls the function `name`, which must not be generic, with `args`
+    /// of the types of its parameters.
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Value {
+        let idx = *self
+            .functions
+            .get(name)
+            .unwrap_or_else(|| panic!("no function `{}` to call", name));
+        let params = &self.program.functions[idx].params;
+        assert_eq!(
+            args.len(),
+            params.len(),
+            "function `{}` takes {} arguments but {} were given",
+            name,
+            params.len(),
+            args.len()
+        );
+        for (arg, typ) in args.iter().zip(params) {
+            expect_type(self.file, &arg.typ, typ);
+        }
+
+        self.invoke(idx, args)
+    }
+
+    /// Runs `main` like the C runtime does, passing it the number of
+    /// arguments and the arguments themselves if it takes them, and
+    /// returns its exit status.
+    pub fn run_main(&mut self, args: &[String]) -> i32 {
+        let idx = match self.functions.get("main") {
+            Some(&idx) if self.program.functions[idx].body.is_some() => idx,
+            _ => panic!("{}: no function `main` to run", self.file),
+        };
+        let params = self.program.functions[idx].params.clone();
+
+        let argv: Vec<_> = args
+            .iter()
+            .map(|arg| Data::Pointer(Some(self.string(arg))))
+            .chain([Data::Pointer(None)])
+            .collect();
+        let argv = self.alloc(Region::Global, Data::Aggregate(argv));
+        let args = [
+            Data::Int(args.len() as i128),
+            Data::Pointer(Some(argv.member(0))),
+        ];
+
+        let args = args
+            .into_iter()
+            .zip(params)
+            .map(|(data, typ)| Value { data, typ })
+            .collect();
+        let status = self.invoke(idx, args);
+        status.as_int().map_or(0, |status| status as i32)
+    }
+
+    /// Stores `data` in a new slot. Slots on the stack are freed when the
+    /// function that allocated them returns.
+    fn alloc(&mut self, region: Region, data: Data) -> Address {
+        let slots = match region {
+            Region::Global => &mut self.globals,
+            Region::Stack => &mut self.stack,
+        };
+        slots.push(data);
+        Address {
+            region,
+            slot: slots.len() - 1,
+            path: Vec::new(),
+        }
+    }
+
+    /// Stores `data` in a new stack slot, as variables are represented.
+    fn temporary(&mut self, data: Data) -> Address {
+        self.alloc(Region::Stack, data)
+    }
+
+    /// Returns a pointer to the first byte of a string with a nul byte
+    /// appended. Equal literals share their memory.
+    fn string(&mut self, s: &str) -> Address {
+        if let Some(addr) = self.strings.get(s) {
+            return addr.clone();
+        }
+
+        assert!(
+            !s.contains('\0'),
+            "{}: string literals cannot contain nul bytes",
+            self.file
+        );
+        let bytes = s
+            .bytes()
+            .chain([0])
+            .map(|byte| Data::Int(byte as i8 as i128))
+            .collect();
+        let addr = self.alloc(Region::Global, Data::Aggregate(bytes)).member(0);
+        self.strings.insert(s.to_string(), addr.clone());
+        addr
+    }
+
+    fn invalid_access(&self) -> ! {
+        panic!(
+            "{}: invalid memory access, the pointer is dangling or out of bounds",
+            self.file
+        )
+    }
+
+    fn read(&self, addr: &Address) -> &Data {
+        let slots = match addr.region {
+            Region::Global => &self.globals,
+            Region::Stack => &self.stack,
+        };
+        let data = slots.get(addr.slot).and_then(|slot| {
+            addr.path.iter().try_fold(slot, |data, idx| match data {
+                Data::Aggregate(items) => items.get(usize::try_from(*idx).ok()?),
+                _ => None,
+            })
+        });
+        data.unwrap_or_else(|| self.invalid_access())
+    }
+
+    /// Reads the value of type `typ` at `addr`. Integers are read as `typ`
+    /// even if the pointer they were written through had another type.
+    fn load(&self, addr: &Address, typ: &ast::Type) -> Value {
+        let data = match self.read(addr) {
+            Data::Int(value) if typ.is_integer() => Data::Int(wrap(*value, typ)),
+            data => data.clone(),
+        };
+        Value {
+            data,
+            typ: typ.clone(),
+        }
+    }
+
+    fn store(&mut self, addr: &Address, data: Data) {
+        let slots = match addr.region {
+            Region::Global => &mut self.globals,
+            Region::Stack => &mut self.stack,
+        };
+        let slot = slots.get_mut(addr.slot).and_then(|slot| {
+            addr.path.iter().try_fold(slot, |data, idx| match data {
+                Data::Aggregate(items) => items.get_mut(usize::try_from(*idx).ok()?),
+                _ => None,
+            })
+        });
+        match slot {
+            Some(slot) => *slot = data,
+            None => self.invalid_access(),
+        }
+    }
+
+    /// The address a pointer refers to, which must not be null.
+    fn deref(&self, data: &Data) -> Address {
+        match data {
+            Data::Pointer(Some(addr)) => addr.clone(),
+            Data::Pointer(None) => panic!("{}: null pointer dereference", self.file),
+            _ => unreachable!("not a pointer"),
+        }
+    }
+
+    /// Calls the function `idx` of the program with arguments of its
+    /// parameter types.
+    fn invoke(&mut self, idx: usize, args: Vec<Value>) -> Value {
+        let program = Rc::clone(&self.program);
+        let function = &program.functions[idx];
+        let body = match &function.body {
+            Some(body) => body,
+            None => return builtins::call(self, function, args),
+        };
+
+        // the stack slots of the function are freed when it returns
+        let base = self.stack.len();
+        let outer = std::mem::replace(&mut self.locals, vec![None; function.locals.len()]);
+        for (local, value) in args.into_iter().enumerate() {
+            self.locals[local] = Some(self.temporary(value.data));
+        }
+
+        // the tail of the body is the return value
+        let value = match self.eval(body) {
+            Ok(value) => value,
+            Err(Return(value)) => *value,
+        };
+
+        self.stack.truncate(base);
+        self.locals = outer;
+        value
+    }
+
+    /// Calls the panic handler `name` if the program defines it in lyth.
+    fn call_panic_handler(&mut self, name: &str, args: Vec<Value>) {
+        let handler = self.functions.get(name).copied();
+        if let Some(idx) = handler.filter(|&idx| self.program.functions[idx].body.is_some()) {
+            self.invoke(idx, args);
+        }
+    }
+
+    fn eval_stmt(&mut self, stmt: &Stmt) -> Flow<()> {
+        match stmt {
+            Stmt::Let { local, value, .. } => {
+                let value = self.eval(value)?;
+                self.locals[*local] = Some(self.temporary(value.data));
+            }
+            Stmt::Assign {
+                place, op, value, ..
+            } => {
+                let addr = self.eval_place(place)?;
+
+                let data = match op {
+                    None => self.eval(value)?.data,
+                    Some(op) => {
+                        // the place is evaluated only once, `a[f()] += 1` calls `f` once
+                        let current = self.load(&addr, &place.typ);
+                        let rhs = self.eval(value)?;
+
+                        match &place.typ {
+                            ast::Type::Pointer(_) => self.offset_pointer(
+                                &current.data,
+                                &rhs.data,
+                                *op == TokenKind::Minus,
+                            ),
+                            typ => self.infix(*op, typ, current.data, rhs.data),
+                        }
+                    }
+                };
+
+                self.store(&addr, data);
+            }
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => self.eval(value)?,
+                    None => Value::void(),
+                };
+                return Err(Return(Box::new(value)));
+            }
+            Stmt::Expr { value, .. } => {
+                self.eval(value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates a `match`. The first arm whose cases contain the
+    /// discriminant or value is taken, with its captures referring to a
+    /// copy of the value.
+    fn eval_match(&mut self, scrutinee: &Expr, arms: &[Arm]) -> Flow<Value> {
+        let value = self.eval(scrutinee)?;
+        let key = match &value.data {
+            Data::Variant { tag, .. } => *tag as i128,
+            Data::Int(value) => *value,
+            Data::Bool(value) => *value as i128,
+            _ => unreachable!("cannot match on `{}`", value.typ),
+        };
+
+        let arm = arms
+            .iter()
+            .find(|arm| arm.cases.as_ref().is_none_or(|cases| cases.contains(&key)))
+            .unwrap_or_else(|| unreachable!("the match is exhaustive"));
+
+        for capture in &arm.captures {
+            let data = match (capture.field, &value.data) {
+                (Some((_, idx)), Data::Variant { fields, .. }) => fields[idx].clone(),
+                (None, data) => data.clone(),
+                _ => unreachable!("not a variant"),
+            };
+            self.locals[capture.local] = Some(self.temporary(data));
+        }
+
+        self.eval(&arm.body)
+    }
+
+    /// Finds the memory a place denotes.
+    fn eval_place(&mut self, place: &Place) -> Flow<Address> {
+        let addr = match &place.kind {
+            PlaceKind::Local(local) => self.locals[*local]
+                .clone()
+                .unwrap_or_else(|| unreachable!("locals are bound before they are used")),
+            PlaceKind::Static(symbol) => self.statics[symbol].clone(),
+            PlaceKind::Temporary(value) => {
+                let value = self.eval(value)?;
+                self.temporary(value.data)
+            }
+            PlaceKind::Deref(pointer) => {
+                let pointer = self.eval(pointer)?;
+                self.deref(&pointer.data)
+            }
+            PlaceKind::Field { base, index } => self.eval_place(base)?.member(*index as i64),
+            PlaceKind::Index { base, index } => {
+                let addr = self.eval_place(base)?;
+                let (first, len) = match &base.typ {
+                    ast::Type::Array { len, .. } => (Some(addr.member(0)), *len as i64),
+                    _ => match self.read(&addr) {
+                        Data::Slice { first, len } => (first.clone(), *len),
+                        _ => unreachable!("not a slice"),
+                    },
+                };
+
+                let idx = self.eval(index)?.as_int().unwrap() as i64;
+                self.bounds_check(idx, len);
+                self.offset(first.as_ref(), idx)
+            }
+        };
+
+        Ok(addr)
+    }
+
+    /// Calls the bounds panic handler unless `idx < len`, comparing them
+    /// as unsigned integers. Without a handler in lyth the program fails
+    /// like the compiled one traps.
+    fn bounds_check(&mut self, idx: i64, len: i64) {
+        if !self.bounds_checks || (idx as u64) < (len as u64) {
+            return;
+        }
+
+        let i64_type = ast::Type::new_simple("i64");
+        let args = vec![
+            Value::int(idx as i128, i64_type.clone()),
+            Value::int(len as i128, i64_type),
+        ];
+        self.call_panic_handler(BOUNDS_PANIC_HANDLER, args);
+
+        panic!(
+            "{}: index {} is out of bounds for length {}",
+            self.file, idx, len
+        )
+    }
+
+    /// Offsets a pointer by `offset` elements. Only pointers to elements
+    /// of arrays can be moved.
+    fn offset(&self, first: Option<&Address>, offset: i64) -> Address {
+        let mut addr = first
+            .cloned()
+            .unwrap_or_else(|| panic!("{}: null pointer dereference", self.file));
+        match addr.path.last_mut() {
+            Some(idx) => *idx = idx.wrapping_add(offset),
+            None if offset == 0 => {}
+            None => panic!(
+                "{}: pointer arithmetic is only supported on pointers into arrays",
+                self.file
+            ),
+        }
+        addr
+    }
+
+    /// Moves a raw pointer by an integer number of elements.
+    fn offset_pointer(&self, pointer: &Data, offset: &Data, negate: bool) -> Data {
+        let offset = match offset {
+            Data::Int(offset) if negate => offset.wrapping_neg(),
+            Data::Int(offset) => *offset,
+            _ => unreachable!("not an integer"),
+        };
+        match pointer {
+            Data::Pointer(addr) => Data::Pointer(Some(self.offset(addr.as_ref(), offset as i64))),
+            _ => unreachable!("not a pointer"),
+        }
+    }
+
+    /// The distance in elements between two raw pointers, which have to
+    /// point into the same array.
+    fn distance(&self, lhs: &Data, rhs: &Data) -> i128 {
+        match (self.deref(lhs), self.deref(rhs)) {
+            (lhs, rhs)
+                if (lhs.region, lhs.slot) == (rhs.region, rhs.slot)
+                    && lhs.path.len() == rhs.path.len()
+                    && lhs.path.split_last().map(|(_, path)| path)
+                        == rhs.path.split_last().map(|(_, path)| path) =>
+            {
+                (lhs.path.last().unwrap_or(&0) - rhs.path.last().unwrap_or(&0)) as i128
+            }
+            _ => panic!(
+                "{}: cannot compute the distance between pointers into different arrays",
+                self.file
+            ),
+        }
+    }
+
+    /// Calls the object method `method` of a trait object, the one of the
+    /// type the object was made from. The object's data is passed as
+    /// `self`.
+    fn eval_dyn_call(&mut self, object: &Expr, method: usize, args: &[Expr]) -> Flow<Value> {
+        let object = self.eval(object)?;
+        let (data, vtable) = match object.data {
+            Data::Object { data, vtable } => (data, vtable),
+            _ => panic!("{}: null pointer dereference", self.file),
+        };
+
+        let program = Rc::clone(&self.program);
+        let idx = self.functions[&program.vtables[&vtable].methods[method]];
+        let mut values = vec![Value {
+            data: Data::Pointer(Some(data)),
+            typ: program.functions[idx].params[0].clone(),
+        }];
+        for arg in args {
+            values.push(self.eval(arg)?);
+        }
+
+        Ok(self.invoke(idx, values))
+    }
+
+    /// Evaluates the postfix `!` operator, which unwraps an optional value
+    /// or checks a raw pointer for null, and fails if there is no value.
+    fn unwrap(&self, data: Data) -> Data {
+        match data {
+            Data::Optional(Some(data)) => *data,
+            Data::Optional(None) | Data::Pointer(None) => {
+                panic!("{}: unwrapped a value that is null", self.file)
+            }
+            data => data,
+        }
+    }
+
+    /// Applies a prefix operator to an operand of type `typ`.
+    fn prefix(&self, op: TokenKind, typ: &ast::Type, x: Data) -> Data {
+        match (op, x) {
+            (TokenKind::Minus, Data::Int(value)) => Data::Int(wrap(value.wrapping_neg(), typ)),
+            // `!` is logical negation only, integers use `~`
+            (TokenKind::Bang, Data::Bool(value)) => Data::Bool(!value),
+            (TokenKind::Tilde, Data::Int(value)) => Data::Int(wrap(!value, typ)),
+            _ => unreachable!("not a valid prefix operator"),
+        }
+    }
+
+    /// Applies a binary operator to two operands of type `typ`.
+    fn infix(&mut self, op: TokenKind, typ: &ast::Type, lhs: Data, rhs: Data) -> Data {
+        let signed = typ.is_signed();
+
+        if let (Data::Int(lhs), Data::Int(rhs)) = (&lhs, &rhs) {
+            self.arith_check(op, typ, *lhs, *rhs);
+        }
+
+        match (op, lhs, rhs) {
+            (TokenKind::Equal, lhs, rhs) => Data::Bool(equal(&lhs, &rhs)),
+            (TokenKind::UnEqual, lhs, rhs) => Data::Bool(!equal(&lhs, &rhs)),
+            (
+                TokenKind::LessThan
+                | TokenKind::GreaterThan
+                | TokenKind::LessEqual
+                | TokenKind::GreaterEqual,
+                lhs,
+                rhs,
+            ) => {
+                let ordering = match (lhs, rhs) {
+                    (Data::Int(lhs), Data::Int(rhs)) if signed => lhs.cmp(&rhs),
+                    (Data::Int(lhs), Data::Int(rhs)) => (lhs as u128).cmp(&(rhs as u128)),
+                    // pointers are compared as addresses
+                    (Data::Pointer(lhs), Data::Pointer(rhs)) => lhs.cmp(&rhs),
+                    _ => unreachable!("`{}` cannot be ordered", typ),
+                };
+                Data::Bool(match op {
+                    TokenKind::LessThan => ordering.is_lt(),
+                    TokenKind::GreaterThan => ordering.is_gt(),
+                    TokenKind::LessEqual => ordering.is_le(),
+                    _ => ordering.is_ge(),
+                })
+            }
+            (TokenKind::And, Data::Bool(lhs), Data::Bool(rhs)) => Data::Bool(lhs & rhs),
+            (TokenKind::Pipe, Data::Bool(lhs), Data::Bool(rhs)) => Data::Bool(lhs | rhs),
+            (TokenKind::Caret, Data::Bool(lhs), Data::Bool(rhs)) => Data::Bool(lhs ^ rhs),
+            (op, Data::Int(lhs), Data::Int(rhs)) => {
+                let value = match op {
+                    TokenKind::Plus => lhs.wrapping_add(rhs),
+                    TokenKind::Minus => lhs.wrapping_sub(rhs),
+                    TokenKind::Asterisk => lhs.wrapping_mul(rhs),
+                    TokenKind::Slash if signed => lhs.wrapping_div(rhs),
+                    TokenKind::Slash => ((lhs as u128) / (rhs as u128)) as i128,
+                    TokenKind::Percent if signed => lhs.wrapping_rem(rhs),
+                    TokenKind::Percent => ((lhs as u128) % (rhs as u128)) as i128,
+                    TokenKind::ShiftLeft => lhs << rhs,
+                    // signed integers shift in their sign bit, unsigned ones zeros
+                    TokenKind::ShiftRight if signed => lhs >> rhs,
+                    TokenKind::ShiftRight => ((lhs as u128) >> rhs) as i128,
+                    TokenKind::And => lhs & rhs,
+                    TokenKind::Pipe => lhs | rhs,
+                    TokenKind::Caret => lhs ^ rhs,
+                    _ => unreachable!("not a valid infix operator"),
+                };
+                Data::Int(wrap(value, typ))
+            }
+            _ => unreachable!("not a valid infix operator"),
+        }
+    }
+
+    /// Calls the arithmetic panic handler if `op` has no result for the
+    /// operands: a division or remainder by zero, a signed `MIN / -1` or
+    /// `MIN % -1`, or a shift by the width of `typ` or more. Without a
+    /// handler in lyth the program fails like the compiled one traps.
+    fn arith_check(&mut self, op: TokenKind, typ: &ast::Type, lhs: i128, rhs: i128) {
+        let width = match typ.int_width() {
+            Some(width) => width,
+            None => return,
+        };
+
+        let error = match op {
+            TokenKind::Slash | TokenKind::Percent if rhs == 0 => "division by zero".to_string(),
+            TokenKind::Slash | TokenKind::Percent
+                if typ.is_signed() && lhs == i128::MIN >> (128 - width) && rhs == -1 =>
+            {
+                let symbol = if op == TokenKind::Slash { "/" } else { "%" };
+                format!("`{} {} -1` overflows `{}`", lhs, symbol, typ)
+            }
+            // negative amounts are huge as unsigned values
+            TokenKind::ShiftLeft | TokenKind::ShiftRight if rhs as u128 >= width as u128 => {
+                format!("shift amount {} is out of range for `{}`", rhs, typ)
+            }
+            _ => return,
+        };
+
+        self.call_panic_handler(ARITH_PANIC_HANDLER, Vec::new());
+        panic!("{}: {}", self.file, error);
+    }
+
+    /// Evaluates `&&` and `||`, which only evaluate their right operand if
+    /// the left one doesn't already decide the result.
+    fn short_circuit(&mut self, op: TokenKind, left: &Expr, right: &Expr) -> Flow<Value> {
+        let lhs = self.eval(left)?;
+
+        // `a && b` is false without looking at `b` if `a` is false,
+        // `a || b` is true if `a` is true.
+        if lhs.data == Data::Bool(op == TokenKind::LogicalOr) {
+            return Ok(lhs);
+        }
+        self.eval(right)
+    }
+
+    /// Converts a value of type `from` to `to`.
+    fn cast(&self, data: Data, from: &ast::Type, to: &ast::Type) -> Data {
+        let is_pointer = |typ: &ast::Type| typ.pointee().is_some();
+
+        match (from, to, data) {
+            (from, to, data) if from == to => data,
+            (_, to, Data::Int(value)) if to.is_integer() => Data::Int(wrap(value, to)),
+            // enums without payload cast to their discriminant
+            (_, to, Data::Variant { tag, .. }) if to.is_integer() => {
+                Data::Int(wrap(tag as i128, to))
+            }
+            (_, to, Data::Bool(value)) if to.is_integer() => Data::Int(value as i128),
+            // a pointer to an array is also one to its first element
+            (from, to, Data::Pointer(Some(addr))) if is_pointer(to) => match from.pointee() {
+                Some(ast::Type::Array { typ: elem, .. }) if Some(elem.as_ref()) == to.pointee() => {
+                    Data::Pointer(Some(addr.member(0)))
+                }
+                _ => Data::Pointer(Some(addr)),
+            },
+            (from, to, data) if is_pointer(from) && is_pointer(to) => data,
+            (_, to, Data::Pointer(None)) if to.is_integer() => Data::Int(0),
+            (_, to, Data::Int(0)) if is_pointer(to) => Data::Pointer(None),
+            (from, to, _) => panic!(
+                "{}: the interpreter cannot cast `{}` to `{}`, its pointers have no numeric address",
+                self.file, from, to
+            ),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Flow<Value> {
+        let typ = &expr.typ;
+        let data = match &expr.kind {
+            ExprKind::Int(value) => Data::Int(wrap(*value, typ)),
+            ExprKind::Bool(b) => Data::Bool(*b),
+            ExprKind::Str(s) => Data::Pointer(Some(self.string(s))),
+            ExprKind::Null => match typ {
+                ast::Type::Optional(_) => Data::Optional(None),
+                _ => Data::Pointer(None),
+            },
+            ExprKind::Load(place) => {
+                let addr = self.eval_place(place)?;
+                return Ok(self.load(&addr, &place.typ));
+            }
+            ExprKind::AddrOf(place) => Data::Pointer(Some(self.eval_place(place)?)),
+            ExprKind::Call { function, args } => {
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.eval(arg)?);
+                }
+                return Ok(self.invoke(self.functions[function], values));
+            }
+            ExprKind::DynCall {
+                object,
+                method,
+                args,
+            } => return self.eval_dyn_call(object, *method, args),
+            ExprKind::Variant { variant, fields } => {
+                let tag = self.program.enum_of(typ).unwrap().variants[*variant].tag;
+                let mut values = Vec::new();
+                for field in fields {
+                    values.push(self.eval(field)?.data);
+                }
+                Data::Variant {
+                    tag,
+                    fields: values,
+                }
+            }
+            ExprKind::Struct(members) => {
+                let len = self.program.struct_members(typ).unwrap().len();
+                let mut data = vec![Data::Void; len];
+                for (idx, member) in members {
+                    data[*idx] = self.eval(member)?.data;
+                }
+                Data::Aggregate(data)
+            }
+            ExprKind::Array(items) => {
+                let mut values = Vec::new();
+                for item in items {
+                    values.push(self.eval(item)?.data);
+                }
+                Data::Aggregate(values)
+            }
+            ExprKind::Unary { op, operand } => {
+                let x = self.eval(operand)?;
+                self.prefix(*op, typ, x.data)
+            }
+            ExprKind::Binary { op, left, right } => {
+                let lhs = self.eval(left)?;
+                let rhs = self.eval(right)?;
+                self.infix(*op, &left.typ, lhs.data, rhs.data)
+            }
+            ExprKind::Logical { op, left, right } => return self.short_circuit(*op, left, right),
+            ExprKind::Offset {
+                pointer,
+                offset,
+                negate,
+            } => {
+                let pointer = self.eval(pointer)?;
+                let offset = self.eval(offset)?;
+                self.offset_pointer(&pointer.data, &offset.data, *negate)
+            }
+            ExprKind::Distance { left, right } => {
+                let lhs = self.eval(left)?;
+                let rhs = self.eval(right)?;
+                Data::Int(self.distance(&lhs.data, &rhs.data))
+            }
+            ExprKind::Cast(value) => {
+                let from = self.eval(value)?;
+                self.cast(from.data, &value.typ, typ)
+            }
+            ExprKind::NullCheck { operand, is_null } => {
+                let value = self.eval(operand)?;
+                let has_value = !matches!(value.data, Data::Optional(None) | Data::Pointer(None));
+                Data::Bool(has_value != *is_null)
+            }
+            ExprKind::Unwrap(value) => {
+                let value = self.eval(value)?;
+                self.unwrap(value.data)
+            }
+            ExprKind::Some(value) => Data::Optional(Some(Box::new(self.eval(value)?.data))),
+            ExprKind::Object { pointer, vtable } => match self.eval(pointer)?.data {
+                Data::Pointer(Some(data)) => Data::Object {
+                    data,
+                    vtable: vtable.clone(),
+                },
+                data => data,
+            },
+            ExprKind::Slice { pointer, len } => match self.eval(pointer)?.data {
+                Data::Pointer(addr) => Data::Slice {
+                    first: addr.map(|addr| addr.member(0)),
+                    len: *len as i64,
+                },
+                _ => unreachable!("not a pointer"),
+            },
+            ExprKind::Len(place) => {
+                // the array is still evaluated for its side effects
+                let addr = self.eval_place(place)?;
+                match (&place.typ, self.read(&addr)) {
+                    (ast::Type::Array { len, .. }, _) => Data::Int(*len as i128),
+                    (_, Data::Slice { len, .. }) => Data::Int(*len as i128),
+                    _ => unreachable!("not a slice"),
+                }
+            }
+            ExprKind::Ptr(place) => {
+                let addr = self.eval_place(place)?;
+                match self.read(&addr) {
+                    Data::Slice { first, .. } => Data::Pointer(first.clone()),
+                    _ => unreachable!("not a slice"),
+                }
+            }
+            ExprKind::If { cond, then, elze } => {
+                let cond = self.eval(cond)?;
+                return match (cond.data, elze) {
+                    (Data::Bool(true), _) => self.eval(then),
+                    (_, Some(elze)) => self.eval(elze),
+                    (_, None) => Ok(Value::void()),
+                };
+            }
+            ExprKind::Block { stmts, tail } => {
+                for stmt in stmts {
+                    self.eval_stmt(stmt)?;
+                }
+                return match tail {
+                    Some(tail) => self.eval(tail),
+                    None => Ok(Value::void()),
+                };
+            }
+            ExprKind::Match { scrutinee, arms } => return self.eval_match(scrutinee, arms),
+        };
+
+        Ok(Value {
+            data,
+            typ: typ.clone(),
+        })
+    }
+}
+
+/// `==` on integers, booleans, pointers and enums without payload. Trait
+/// objects are the same if they refer to the same data.
+fn equal(lhs: &Data, rhs: &Data) -> bool {
+    match (lhs, rhs) {
+        (Data::Object { data: lhs, .. }, Data::Object { data: rhs, .. }) => lhs == rhs,
+        (lhs, rhs) => lhs == rhs,
+    }
+}
+
+fn expect_type(file: &str, found: &ast::Type, expected: &ast::Type) {
+    assert_eq!(
+        found, expected,
+        "{}: expected a value of type `{}` but found `{}`",
+        file, expected, found
+    );
+}
@@ -47,7 +47,7 @@ pub struct Rule {
 }
 
 lazy_static! {
-    static ref STRING_REGEX: Regex = Regex::new(r#"^"((\\"|\\\\)|[^\\"])*""#).unwrap();
+    static ref STRING_REGEX: Regex = Regex::new(r#"^"(\\.|[^\\"])*""#).unwrap();
     static ref INTEGER_REGEX: Regex =
         Regex::new(r#"^((0o[0-7]+)|(0b[01]+)|(0x[0-9A-Fa-f]+)|([0-9]+))"#).unwrap();
     static ref IDENTIFIER_REGEX: Regex = Regex::new(r##"^([A-Za-z]|_)([A-Za-z]|_|\d)*"##).unwrap();
@@ -57,6 +57,8 @@ pub fn get_rules() -> Vec<Rule> {
     vec![
         rule!(TokenKind::Equal, '=', '='),
         rule!(TokenKind::UnEqual, '!', '='),
+        rule!(TokenKind::ShiftLeft, '<', '<'),
+        rule!(TokenKind::ShiftRight, '>', '>'),
         rule!(TokenKind::LessThan, '<'),
         rule!(TokenKind::GreaterThan, '>'),
         rule!(TokenKind::LessEqual, '<', '='),
@@ -66,20 +68,48 @@ pub fn get_rules() -> Vec<Rule> {
         rule!(TokenKind::Asterisk, '*'),
         rule!(TokenKind::Slash, '/'),
         rule!(TokenKind::Percent, '%'),
+        rule!(TokenKind::LogicalAnd, '&', '&'),
+        rule!(TokenKind::LogicalOr, '|', '|'),
         rule!(TokenKind::And, '&'),
         rule!(TokenKind::Pipe, '|'),
         rule!(TokenKind::Caret, '^'),
         rule!(TokenKind::Bang, '!'),
         rule!(TokenKind::Tilde, '~'),
         rule!(TokenKind::Assign, '='),
+        rule!(TokenKind::PlusAssign, '+', '='),
+        rule!(TokenKind::MinusAssign, '-', '='),
+        rule!(TokenKind::AsteriskAssign, '*', '='),
+        rule!(TokenKind::SlashAssign, '/', '='),
+        rule!(TokenKind::PercentAssign, '%', '='),
+        rule!(TokenKind::AndAssign, '&', '='),
+        rule!(TokenKind::PipeAssign, '|', '='),
+        rule!(TokenKind::CaretAssign, '^', '='),
         rule!(TokenKind::Dot, '.'),
         rule!(TokenKind::Comma, ','),
         rule!(TokenKind::Colon, ':'),
+        rule!(TokenKind::PathSep, ':', ':'),
         rule!(TokenKind::SemiColon, ';'),
+        rule!(TokenKind::FatArrow, '=', '>'),
+        rule!(TokenKind::Question, '?'),
+        rule!(TokenKind::Hash, '#'),
         rule!(TokenKind::OpenParen, '('),
         rule!(TokenKind::ClosedParen, ')'),
         rule!(TokenKind::OpenBrace, '{'),
         rule!(TokenKind::ClosedBrace, '}'),
+        rule!(TokenKind::OpenBracket, '['),
+        rule!(TokenKind::ClosedBracket, ']'),
+        Rule {
+            kind: TokenKind::ShiftLeftAssign,
+            matches: |input| match_word(input, "<<="),
+        },
+        Rule {
+            kind: TokenKind::ShiftRightAssign,
+            matches: |input| match_word(input, ">>="),
+        },
+        Rule {
+            kind: TokenKind::Ellipsis,
+            matches: |input| match_word(input, "..."),
+        },
         Rule {
             kind: TokenKind::Struct,
             matches: |input| match_word(input, "struct"),
@@ -120,6 +150,58 @@ pub fn get_rules() -> Vec<Rule> {
             kind: TokenKind::Return,
             matches: |input| match_word(input, "return"),
         },
+        Rule {
+            kind: TokenKind::Extern,
+            matches: |input| match_word(input, "extern"),
+        },
+        Rule {
+            kind: TokenKind::Mut,
+            matches: |input| match_word(input, "mut"),
+        },
+        Rule {
+            kind: TokenKind::As,
+            matches: |input| match_word(input, "as"),
+        },
+        Rule {
+            kind: TokenKind::Const,
+            matches: |input| match_word(input, "const"),
+        },
+        Rule {
+            kind: TokenKind::Static,
+            matches: |input| match_word(input, "static"),
+        },
+        Rule {
+            kind: TokenKind::Enum,
+            matches: |input| match_word(input, "enum"),
+        },
+        Rule {
+            kind: TokenKind::Match,
+            matches: |input| match_word(input, "match"),
+        },
+        Rule {
+            kind: TokenKind::Impl,
+            matches: |input| match_word(input, "impl"),
+        },
+        Rule {
+            kind: TokenKind::Trait,
+            matches: |input| match_word(input, "trait"),
+        },
+        Rule {
+            kind: TokenKind::Dyn,
+            matches: |input| match_word(input, "dyn"),
+        },
+        Rule {
+            kind: TokenKind::Import,
+            matches: |input| match_word(input, "import"),
+        },
+        Rule {
+            kind: TokenKind::Mod,
+            matches: |input| match_word(input, "mod"),
+        },
+        Rule {
+            kind: TokenKind::Pub,
+            matches: |input| match_word(input, "pub"),
+        },
         Rule {
             kind: TokenKind::Inline,
             matches: |input| match_word(input, "inline"),
@@ -132,6 +214,10 @@ pub fn get_rules() -> Vec<Rule> {
             kind: TokenKind::Boolean,
             matches: |input| match_word(input, "false"),
         },
+        Rule {
+            kind: TokenKind::Null,
+            matches: |input| match_word(input, "null"),
+        },
         Rule {
             kind: TokenKind::Identifier,
             matches: |input| match_regex(input, &IDENTIFIER_REGEX),
@@ -9,6 +9,7 @@ pub enum TokenKind {
     String,
     Integer,
     Boolean,
+    Null,
 
     // Arithmetic
     Plus,
@@ -21,7 +22,23 @@ pub enum TokenKind {
     Caret,
     Bang,
     Tilde,
+    ShiftLeft,
+    ShiftRight,
     Assign,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
+    PercentAssign,
+    AndAssign,
+    PipeAssign,
+    CaretAssign,
+    ShiftLeftAssign,
+    ShiftRightAssign,
+
+    // Logical
+    LogicalAnd,
+    LogicalOr,
 
     // Comparision
     Equal,
@@ -35,11 +52,18 @@ pub enum TokenKind {
     Dot,
     Comma,
     Colon,
+    PathSep,
     SemiColon,
+    FatArrow,
+    Ellipsis,
+    Question,
+    Hash,
     OpenParen,
     ClosedParen,
     OpenBrace,
     ClosedBrace,
+    OpenBracket,
+    ClosedBracket,
 
     // Keywords
     Struct,
@@ -52,11 +76,71 @@ pub enum TokenKind {
     Break,
     Continue,
     Return,
+    Extern,
+    Mut,
+    As,
+    Const,
+    Static,
+    Enum,
+    Match,
+    Impl,
+    Trait,
+    Dyn,
+    Import,
+    Mod,
+    Pub,
 
     // Extension Keywords
     Inline,
 }
 
+impl TokenKind {
+    /// Maps a compound assignment like `+=` to the operator it applies.
+    pub fn compound_operator(&self) -> Option<TokenKind> {
+        let op = match self {
+            TokenKind::PlusAssign => TokenKind::Plus,
+            TokenKind::MinusAssign => TokenKind::Minus,
+            TokenKind::AsteriskAssign => TokenKind::Asterisk,
+            TokenKind::SlashAssign => TokenKind::Slash,
+            TokenKind::PercentAssign => TokenKind::Percent,
+            TokenKind::AndAssign => TokenKind::And,
+            TokenKind::PipeAssign => TokenKind::Pipe,
+            TokenKind::CaretAssign => TokenKind::Caret,
+            TokenKind::ShiftLeftAssign => TokenKind::ShiftLeft,
+            TokenKind::ShiftRightAssign => TokenKind::ShiftRight,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// The source text of an operator, e.g. `<<` for `ShiftLeft`.
+    pub fn operator(&self) -> &'static str {
+        match self {
+            TokenKind::Plus => "+",
+            TokenKind::Minus => "-",
+            TokenKind::Asterisk => "*",
+            TokenKind::Slash => "/",
+            TokenKind::Percent => "%",
+            TokenKind::And => "&",
+            TokenKind::Pipe => "|",
+            TokenKind::Caret => "^",
+            TokenKind::Bang => "!",
+            TokenKind::Tilde => "~",
+            TokenKind::ShiftLeft => "<<",
+            TokenKind::ShiftRight => ">>",
+            TokenKind::LogicalAnd => "&&",
+            TokenKind::LogicalOr => "||",
+            TokenKind::Equal => "==",
+            TokenKind::UnEqual => "!=",
+            TokenKind::LessThan => "<",
+            TokenKind::GreaterThan => ">",
+            TokenKind::LessEqual => "<=",
+            TokenKind::GreaterEqual => ">=",
+            _ => panic!("{:?} is not an operator", self),
+        }
+    }
+}
+
 pub struct Token<'a> {
     pub kind: TokenKind,
     pub text: &'a str,
@@ -0,0 +1,23 @@
+//! The lyth compiler. `loader::load` parses a program and its modules,
+//! `compiler::Compiler` turns them into an object file or, through
+//! `compiler::Jit`, runs them in-process, which `repl` does interactively.
+//! `interp::Interpreter` runs them by walking the checked program instead,
+//! and `cgen::Generator` emits C source for them.
+//!
+//! Everything that needs LLVM is behind the default `llvm` feature. Built
+//! with `default-features = false`, the front end, `lexer`, `parser` and
+//! `semantics`, is usable on its own, e.g. by editors and linters, which
+//! get the errors in a program from `semantics::check`.
+
+pub mod cgen;
+#[cfg(feature = "llvm")]
+pub mod compiler;
+pub mod diagnostic;
+pub mod interp;
+pub mod lexer;
+pub mod loader;
+pub mod mangle;
+pub mod parser;
+#[cfg(feature = "llvm")]
+pub mod repl;
+pub mod semantics;
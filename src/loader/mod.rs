@@ -0,0 +1,229 @@
+//! Loads a program spread over several files. Every file is a module:
+//! `import foo;` (or `mod foo;`) loads `foo.ly` next to the importing file
+//! and `import foo::bar;` loads `foo/bar.ly`, which is then known as `bar`.
+//!
+//! Items are named after the path of their file from the root file, so
+//! `fn area` in `geo/shapes.ly` becomes `geo::shapes::area`, and the names
+//! used in each module are resolved to those qualified names. Paths
+//! starting with `crate::` are resolved from the root file instead. Extern
+//! functions keep their name, it is the symbol they link against.
+
+mod resolve;
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{ast, Parser};
+use resolve::Resolver;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A loaded file.
+struct Module {
+    /// Path of the module from the root, empty for the root file.
+    prefix: String,
+    file: String,
+    items: Vec<ast::Item>,
+    /// The items declared in the module, by their unqualified name.
+    symbols: HashMap<String, Symbol>,
+    /// The modules imported into the module, by their last path segment.
+    imports: HashMap<String, Import>,
+}
+
+struct Symbol {
+    qualified: String,
+    public: bool,
+}
+
+struct Import {
+    module: usize,
+    public: bool,
+}
+
+struct Loader {
+    /// The modules in the order they finished loading, the root is last.
+    modules: Vec<Module>,
+    loaded: HashMap<PathBuf, usize>,
+    /// The files being loaded, to report import cycles.
+    stack: Vec<(PathBuf, String)>,
+}
+
+/// Loads the program whose root is `file` together with all modules it
+/// imports, and returns their declarations with fully qualified names.
+pub fn load(file: &str) -> Result<Vec<ast::Decl>, Diagnostic> {
+    let mut loader = Loader {
+        modules: Vec::new(),
+        loaded: HashMap::new(),
+        stack: Vec::new(),
+    };
+
+    loader.load_file(Path::new(file), String::new())?;
+    loader.resolve()
+}
+
+impl Loader {
+    /// Loads the module at `path` and, before it, the modules it imports.
+    /// Files imported more than once are only loaded the first time.
+    fn load_file(&mut self, path: &Path, prefix: String) -> Result<usize, Diagnostic> {
+        let file = path.display().to_string();
+        let key = canonical(path);
+
+        if let Some(&module) = self.loaded.get(&key) {
+            return Ok(module);
+        }
+
+        let input = std::fs::read_to_string(path).map_err(|err| {
+            Diagnostic::new(
+                &file,
+                ast::Pos::default(),
+                format!("cannot read module: {}", err),
+            )
+        })?;
+        let items = Parser::new(&file, &input).parse()?;
+
+        self.stack.push((key.clone(), file.clone()));
+
+        // imports are relative to the directory of the importing file
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let dir_prefix = prefix.rsplit_once("::").map_or("", |(dir, _)| dir);
+
+        let mut imports = HashMap::new();
+        for item in items.iter() {
+            if let ast::Decl::Import {
+                path: import, pos, ..
+            } = &item.decl
+            {
+                let error = |message| Diagnostic::new(&file, *pos, message);
+                let segments: Vec<_> = import.split("::").collect();
+                let name = segments[segments.len() - 1];
+
+                let mut import_path = dir.join(segments.join("/"));
+                import_path.set_extension("ly");
+                let import_prefix = match dir_prefix {
+                    "" => import.clone(),
+                    dir_prefix => format!("{}::{}", dir_prefix, import),
+                };
+
+                if !import_path.is_file() {
+                    return Err(error(format!(
+                        "cannot find module `{}` at `{}`",
+                        import,
+                        import_path.display()
+                    )));
+                }
+
+                let import_key = canonical(&import_path);
+                if let Some(start) = self
+                    .stack
+                    .iter()
+                    .position(|(loading, _)| loading == &import_key)
+                {
+                    let import_file = import_path.display().to_string();
+                    let cycle: Vec<_> = self.stack[start..]
+                        .iter()
+                        .map(|(_, file)| file.as_str())
+                        .chain([import_file.as_str()])
+                        .collect();
+                    return Err(error(format!("import cycle: {}", cycle.join(" -> "))));
+                }
+
+                let module = self.load_file(&import_path, import_prefix)?;
+                let previous = imports.insert(
+                    name.to_string(),
+                    Import {
+                        module,
+                        public: item.public,
+                    },
+                );
+                if previous.is_some() {
+                    return Err(error(format!(
+                        "module `{}` is imported more than once",
+                        name
+                    )));
+                }
+            }
+        }
+
+        let mut symbols = HashMap::new();
+        for item in items.iter() {
+            let name = match &item.decl {
+                ast::Decl::Function { name, .. }
+                | ast::Decl::Extern { name, .. }
+                | ast::Decl::Const { name, .. }
+                | ast::Decl::Static { name, .. }
+                | ast::Decl::Enum { name, .. }
+                | ast::Decl::Trait { name, .. }
+                | ast::Decl::Struct {
+                    name: ast::Type::Named { name, .. },
+                    ..
+                } => name,
+                _ => continue,
+            };
+
+            let qualified = match &item.decl {
+                ast::Decl::Extern { .. } => name.clone(),
+                _ if prefix.is_empty() => name.clone(),
+                _ => format!("{}::{}", prefix, name),
+            };
+
+            let (_, pos) = item.decl.location();
+            let declared_twice =
+                || Diagnostic::new(&file, pos, format!("`{}` is declared more than once", name));
+            if imports.contains_key(name) {
+                return Err(declared_twice());
+            }
+            let previous = symbols.insert(
+                name.clone(),
+                Symbol {
+                    qualified,
+                    public: item.public,
+                },
+            );
+            if previous.is_some() {
+                return Err(declared_twice());
+            }
+        }
+
+        self.stack.pop();
+        self.modules.push(Module {
+            prefix,
+            file,
+            items,
+            symbols,
+            imports,
+        });
+        self.loaded.insert(key, self.modules.len() - 1);
+
+        Ok(self.modules.len() - 1)
+    }
+
+    /// Collects the declarations of all modules, with the names in them
+    /// resolved to qualified names.
+    fn resolve(mut self) -> Result<Vec<ast::Decl>, Diagnostic> {
+        let items: Vec<_> = self
+            .modules
+            .iter_mut()
+            .map(|module| std::mem::take(&mut module.items))
+            .collect();
+
+        let mut decls = Vec::new();
+        for (module, items) in items.into_iter().enumerate() {
+            let mut resolver = Resolver::new(&self, module);
+            for item in items {
+                let mut decl = item.decl;
+                if let ast::Decl::Import { .. } = decl {
+                    continue;
+                }
+
+                resolver.decl(&mut decl)?;
+                decls.push(decl);
+            }
+        }
+
+        Ok(decls)
+    }
+}
+
+/// The path files are told apart by, so one imported under different
+/// relative paths is loaded once.
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
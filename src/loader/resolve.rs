@@ -0,0 +1,405 @@
+use super::{Loader, Module};
+use crate::diagnostic::Diagnostic;
+use crate::parser::ast;
+
+/// Rewrites the names used in the declarations of one module to the
+/// qualified names of the items they refer to. Local variables and type
+/// parameters shadow items, names that are neither are left alone for
+/// the compiler to report.
+pub(super) struct Resolver<'l> {
+    loader: &'l Loader,
+    module: usize,
+    /// The local variables in scope, innermost scope last.
+    scopes: Vec<Vec<String>>,
+    type_params: Vec<String>,
+    /// The position of the declaration or statement being resolved.
+    pos: ast::Pos,
+}
+
+impl<'l> Resolver<'l> {
+    pub fn new(loader: &'l Loader, module: usize) -> Self {
+        Self {
+            loader,
+            module,
+            scopes: Vec::new(),
+            type_params: Vec::new(),
+            pos: ast::Pos::default(),
+        }
+    }
+
+    fn module(&self) -> &'l Module {
+        &self.loader.modules[self.module]
+    }
+
+    pub fn decl(&mut self, decl: &mut ast::Decl) -> Result<(), Diagnostic> {
+        self.pos = decl.location().1;
+        match decl {
+            ast::Decl::Function {
+                name,
+                type_params,
+                params,
+                body,
+                rtyp,
+                ..
+            } => {
+                *name = self.qualify(name);
+                self.function(type_params, params, body, rtyp)?;
+            }
+            ast::Decl::Extern { params, rtyp, .. } => {
+                for (_, typ) in params.iter_mut() {
+                    self.typ(typ)?;
+                }
+                if let Some(rtyp) = rtyp {
+                    self.typ(rtyp)?;
+                }
+            }
+            ast::Decl::Struct { name, members, .. } => {
+                if let ast::Type::Named { name, generics } = name {
+                    self.type_params = generics.iter().map(|typ| typ.to_string()).collect();
+                    *name = self.qualify(name);
+                }
+                for (_, typ) in members.iter_mut() {
+                    self.typ(typ)?;
+                }
+                self.type_params.clear();
+            }
+            ast::Decl::Const {
+                name, typ, value, ..
+            }
+            | ast::Decl::Static {
+                name, typ, value, ..
+            } => {
+                *name = self.qualify(name);
+                self.typ(typ)?;
+                self.expr(value)?;
+            }
+            ast::Decl::Enum { name, variants, .. } => {
+                *name = self.qualify(name);
+                for variant in variants.iter_mut() {
+                    for typ in variant.fields.iter_mut() {
+                        self.typ(typ)?;
+                    }
+                    if let Some(discriminant) = &mut variant.discriminant {
+                        self.expr(discriminant)?;
+                    }
+                }
+            }
+            ast::Decl::Impl {
+                type_params,
+                trait_name,
+                typ,
+                functions,
+                ..
+            } => {
+                self.bounds(type_params)?;
+                self.type_params = type_params.iter().map(|param| param.name.clone()).collect();
+                if let Some(trait_name) = trait_name {
+                    *trait_name = self.path(trait_name)?;
+                }
+                self.typ(typ)?;
+
+                for function in functions.iter_mut() {
+                    if let ast::Decl::Function {
+                        type_params,
+                        params,
+                        body,
+                        rtyp,
+                        pos,
+                        ..
+                    } = function
+                    {
+                        self.pos = *pos;
+                        self.function(type_params, params, body, rtyp)?;
+                    }
+                }
+                self.type_params.clear();
+            }
+            ast::Decl::Trait {
+                name, functions, ..
+            } => {
+                *name = self.qualify(name);
+                for function in functions.iter_mut() {
+                    self.pos = function.pos;
+                    for (_, typ) in function.params.iter_mut() {
+                        self.typ(typ)?;
+                    }
+                    if let Some(rtyp) = &mut function.rtyp {
+                        self.typ(rtyp)?;
+                    }
+                    if let Some(body) = &mut function.body {
+                        self.scopes.push(
+                            function
+                                .params
+                                .iter()
+                                .map(|(name, _)| name.clone())
+                                .collect(),
+                        );
+                        self.expr(body)?;
+                        self.scopes.pop();
+                    }
+                }
+            }
+            ast::Decl::Import { .. } => unreachable!("imports are handled by the loader"),
+        }
+
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        type_params: &mut [ast::TypeParam],
+        params: &mut [(String, ast::Type)],
+        body: &mut ast::Expr,
+        rtyp: &mut Option<ast::Type>,
+    ) -> Result<(), Diagnostic> {
+        let outer = self.type_params.len();
+        self.bounds(type_params)?;
+        self.type_params
+            .extend(type_params.iter().map(|param| param.name.clone()));
+
+        for (_, typ) in params.iter_mut() {
+            self.typ(typ)?;
+        }
+        if let Some(rtyp) = rtyp {
+            self.typ(rtyp)?;
+        }
+
+        self.scopes
+            .push(params.iter().map(|(name, _)| name.clone()).collect());
+        self.expr(body)?;
+        self.scopes.pop();
+
+        self.type_params.truncate(outer);
+        Ok(())
+    }
+
+    fn bounds(&self, type_params: &mut [ast::TypeParam]) -> Result<(), Diagnostic> {
+        for bound in type_params.iter_mut().flat_map(|param| &mut param.bounds) {
+            *bound = self.path(bound)?;
+        }
+        Ok(())
+    }
+
+    /// The qualified name of an item declared in this module.
+    fn qualify(&self, name: &str) -> String {
+        self.module().symbols[name].qualified.clone()
+    }
+
+    /// Resolves a path to the qualified name of the item it refers to,
+    /// followed by the rest of the path, like the variant in
+    /// `geo::Shape::Circle`. Only `pub` items of other modules can be
+    /// reached, except for those of the root which every module can use.
+    /// Names not declared in this module, like builtin types, are
+    /// returned unchanged.
+    fn path(&self, path: &str) -> Result<String, Diagnostic> {
+        let mut module = self.module();
+        let mut segments = path.split("::");
+        let mut own = true;
+
+        if let Some(rest) = path.strip_prefix("crate::") {
+            module = self.loader.modules.last().unwrap();
+            segments = rest.split("::");
+        }
+
+        while let Some(segment) = segments.next() {
+            if let Some(symbol) = module.symbols.get(segment) {
+                if !own && !symbol.public {
+                    return Err(self.error(format!(
+                        "`{}` is private to module `{}`",
+                        segment, module.prefix
+                    )));
+                }
+                return Ok(std::iter::once(symbol.qualified.as_str())
+                    .chain(segments)
+                    .collect::<Vec<_>>()
+                    .join("::"));
+            }
+
+            match module.imports.get(segment) {
+                Some(import) => {
+                    if !own && !import.public {
+                        return Err(self.error(format!(
+                            "module `{}` is private to module `{}`",
+                            segment, module.prefix
+                        )));
+                    }
+                    module = &self.loader.modules[import.module];
+                    own = false;
+                }
+                None if own => return Ok(path.to_string()),
+                None => {
+                    return Err(self.error(format!(
+                        "cannot find `{}` in module `{}`",
+                        segment, module.prefix
+                    )))
+                }
+            }
+        }
+
+        Err(self.error(format!("expected an item but found module `{}`", path)))
+    }
+
+    /// An error at the declaration or statement being resolved.
+    fn error(&self, message: String) -> Diagnostic {
+        Diagnostic::new(&self.module().file, self.pos, message)
+    }
+
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.iter().any(|local| local == name))
+    }
+
+    fn bind(&mut self, name: &str) {
+        self.scopes.last_mut().unwrap().push(name.to_string());
+    }
+
+    fn typ(&self, typ: &mut ast::Type) -> Result<(), Diagnostic> {
+        match typ {
+            ast::Type::Named { name, generics } => {
+                if !generics.is_empty() || !self.type_params.contains(name) {
+                    *name = self.path(name)?;
+                }
+                for typ in generics.iter_mut() {
+                    self.typ(typ)?;
+                }
+            }
+            ast::Type::Pointer(typ)
+            | ast::Type::Ref { typ, .. }
+            | ast::Type::Array { typ, .. }
+            | ast::Type::Slice(typ)
+            | ast::Type::Optional(typ) => self.typ(typ)?,
+            ast::Type::Dyn(name) => *name = self.path(name)?,
+        }
+
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &mut ast::Stmt) -> Result<(), Diagnostic> {
+        self.pos = stmt.pos();
+        match stmt {
+            ast::Stmt::Let {
+                name, typ, value, ..
+            } => {
+                self.expr(value)?;
+                if let Some(typ) = typ {
+                    self.typ(typ)?;
+                }
+                self.bind(name);
+            }
+            ast::Stmt::Assign { target, value, .. } => {
+                self.expr(target)?;
+                self.expr(value)?;
+            }
+            ast::Stmt::Expr { value, .. } => self.expr(value)?,
+            ast::Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expr(value)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &mut ast::Expr) -> Result<(), Diagnostic> {
+        match expr {
+            ast::Expr::Literal(_) => {}
+            ast::Expr::Ident(name) => {
+                if !self.is_local(name) {
+                    *name = self.path(name)?;
+                }
+            }
+            ast::Expr::Call { name, args } => {
+                *name = self.path(name)?;
+                for arg in args.iter_mut() {
+                    self.expr(arg)?;
+                }
+            }
+            ast::Expr::Prefix { expr, .. }
+            | ast::Expr::Postfix { expr, .. }
+            | ast::Expr::AddrOf { expr, .. }
+            | ast::Expr::Field { expr, .. } => self.expr(expr)?,
+            ast::Expr::Infix { left, right, .. } => {
+                self.expr(left)?;
+                self.expr(right)?;
+            }
+            ast::Expr::Array(items) => {
+                for item in items.iter_mut() {
+                    self.expr(item)?;
+                }
+            }
+            ast::Expr::Index { expr, index } => {
+                self.expr(expr)?;
+                self.expr(index)?;
+            }
+            ast::Expr::Cast { expr, typ } => {
+                self.expr(expr)?;
+                self.typ(typ)?;
+            }
+            ast::Expr::If { cond, then, elze } => {
+                self.expr(cond)?;
+                self.expr(then)?;
+                if let Some(elze) = elze {
+                    self.expr(elze)?;
+                }
+            }
+            ast::Expr::Block { body, tail } => {
+                let pos = self.pos;
+                self.scopes.push(Vec::new());
+                for stmt in body.iter_mut() {
+                    self.stmt(stmt)?;
+                }
+                if let Some(tail) = tail {
+                    self.expr(tail)?;
+                }
+                self.scopes.pop();
+                self.pos = pos;
+            }
+            ast::Expr::Match { expr, arms } => {
+                self.expr(expr)?;
+                for arm in arms.iter_mut() {
+                    self.scopes.push(Vec::new());
+                    self.pattern(&mut arm.pattern)?;
+                    self.expr(&mut arm.body)?;
+                    self.scopes.pop();
+                }
+            }
+            ast::Expr::Struct { name, fields } => {
+                *name = self.path(name)?;
+                for (_, value) in fields.iter_mut() {
+                    self.expr(value)?;
+                }
+            }
+            ast::Expr::MethodCall { receiver, args, .. } => {
+                self.expr(receiver)?;
+                for arg in args.iter_mut() {
+                    self.expr(arg)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn pattern(&mut self, pattern: &mut ast::Pattern) -> Result<(), Diagnostic> {
+        match pattern {
+            ast::Pattern::Wildcard => {}
+            ast::Pattern::Binding(name) => self.bind(name),
+            ast::Pattern::Literal(expr) => self.expr(expr)?,
+            ast::Pattern::Variant { path, fields } => {
+                *path = self.path(path)?;
+                for field in fields.iter().filter(|field| *field != "_") {
+                    self.bind(field);
+                }
+            }
+            ast::Pattern::Or(patterns) => {
+                for pattern in patterns.iter_mut() {
+                    self.pattern(pattern)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,11 +1,124 @@
-mod compiler;
-mod lexer;
-mod parser;
+#[cfg(feature = "llvm")]
+use lyth::compiler;
+use lyth::diagnostic::Diagnostic;
+use lyth::{cgen, interp, loader};
 
 fn main() {
-    let inp = std::fs::read_to_string("test.ly").unwrap();
-    let input = inp.as_ref();
-    let mut c = compiler::Compiler::new("test.ly", input);
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("run") => run(&args[2..]),
+        #[cfg(feature = "llvm")]
+        Some("repl") => {
+            let stdin = std::io::stdin();
+            return lyth::repl::run(stdin.lock(), &mut std::io::stdout(), &mut std::io::stderr());
+        }
+        #[cfg(not(feature = "llvm"))]
+        Some("repl") => panic!("the REPL needs LLVM, but lyth was built without it"),
+        _ => {}
+    }
 
-    c.compile("a.out");
+    let decls = loader::load("test.ly").unwrap_or_else(|error| fail(&[error]));
+
+    // `--backend=c` writes C source to `a.c` instead of an object file
+    if args.iter().any(|arg| arg == "--backend=c") {
+        let mut g = cgen::Generator::new("test.ly");
+        if args.iter().any(|arg| arg == "--no-bounds-checks") {
+            g.set_bounds_checks(false);
+        }
+        if args.iter().any(|arg| arg == "-g") {
+            g.set_line_directives(true);
+        }
+        return g
+            .compile(decls, "a.c")
+            .unwrap_or_else(|errors| fail(&errors));
+    }
+
+    compile(decls);
+}
+
+/// Reports the errors in the program and exits.
+fn fail(errors: &[Diagnostic]) -> ! {
+    for error in errors {
+        eprintln!("error: {}", error);
+    }
+    std::process::exit(1)
+}
+
+#[cfg(feature = "llvm")]
+fn compile(decls: Vec<lyth::parser::ast::Decl>) {
+    let mut c = compiler::Compiler::new("test.ly");
+
+    if std::env::args().any(|arg| arg == "--no-bounds-checks") {
+        c.set_bounds_checks(false);
+    }
+    if std::env::args().any(|arg| arg == "-g") {
+        c.set_debug_info(true);
+    }
+
+    c.compile(decls, "a.out")
+        .unwrap_or_else(|errors| fail(&errors));
+}
+
+#[cfg(not(feature = "llvm"))]
+fn compile(_: Vec<lyth::parser::ast::Decl>) {
+    panic!("lyth was built without LLVM, use `--backend=c` to generate C instead");
+}
+
+/// `lyth run file.ly [args...]` compiles the program in memory and runs its
+/// `main`, passing it the file and the arguments after it. With
+/// `lyth run --interp file.ly` it is interpreted instead.
+#[cfg(feature = "llvm")]
+fn run(args: &[String]) -> ! {
+    if args.first().map(String::as_str) == Some("--interp") {
+        interpret(&args[1..]);
+    }
+
+    let file = args
+        .first()
+        .unwrap_or_else(|| panic!("usage: lyth run [--interp] <file.ly> [args...]"));
+
+    let decls = loader::load(file).unwrap_or_else(|error| fail(&[error]));
+    let mut c = compiler::Compiler::new(file);
+    c.build(decls).unwrap_or_else(|errors| fail(&errors));
+
+    let jit = compiler::Jit::new(c.into_module());
+    let status = jit.run_main(args);
+    drop(jit);
+    std::process::exit(status)
+}
+
+/// Without LLVM programs are always interpreted.
+#[cfg(not(feature = "llvm"))]
+fn run(args: &[String]) -> ! {
+    match args.first().map(String::as_str) {
+        Some("--interp") => interpret(&args[1..]),
+        _ => interpret(args),
+    }
 }
+
+/// Interprets the program. The interpreter recurses as deep as the program
+/// does, so it runs on a thread with a stack large enough for that.
+fn interpret(args: &[String]) -> ! {
+    let file = args
+        .first()
+        .unwrap_or_else(|| panic!("usage: lyth run --interp <file.ly> [args...]"))
+        .clone();
+    let args = args.to_vec();
+
+    let status = std::thread::Builder::new()
+        .stack_size(INTERP_STACK_SIZE)
+        .spawn(move || {
+            let decls = loader::load(&file).unwrap_or_else(|error| fail(&[error]));
+            let mut interp = interp::Interpreter::new(&file);
+            interp.declare(decls).unwrap_or_else(|errors| fail(&errors));
+            interp.run_main(&args)
+        })
+        .expect("failed to spawn the interpreter thread")
+        .join()
+        .unwrap_or(101);
+
+    std::io::Write::flush(&mut std::io::stdout()).expect("failed to flush stdout");
+    std::process::exit(status)
+}
+
+const INTERP_STACK_SIZE: usize = 256 << 20;
@@ -0,0 +1,463 @@
+//! Symbol names of lyth functions and statics.
+//!
+//! Items live in modules, can be generic and belong to types, so their
+//! lyth names can't be used as symbols: they aren't unique and would clash
+//! with C functions of the same name. Instead they are mangled as
+//!
+//! ```text
+//! symbol   = "_L" path [generics]
+//! path     = "N" segment+ "E"
+//! segment  = <length> <identifier>
+//!          | "X" type path                   `<Type as Trait>`
+//! generics = "I" type+ "E"
+//! type     = "a" | "h" | "s" | "t" | "i"     i8 u8 i16 u16 i32
+//!          | "j" | "l" | "m" | "n" | "o"     u32 i64 u64 i128 u128
+//!          | "b" | "v"                       bool void
+//!          | path [generics]                 structs and enums
+//!          | "P" type | "R" type | "Q" type  *T &T &mut T
+//!          | "A" <length> "_" type           [T; length]
+//!          | "S" type | "O" type             [T] ?T
+//!          | "D" path                        dyn Trait
+//! ```
+//!
+//! so `geo::area` becomes `_LN3geo4areaE`, `Pair::new` instantiated for
+//! `i64` becomes `_LN4Pair3newEIlE` and `area` of the `Shape`
+//! implementation of `Circle` becomes `_LNXN6CircleEN5ShapeE4areaE`.
+//! Backends emitting C name the types of a program with `type_name`.
+//! `demangle` turns both back into lyth names, e.g. for tools reading the
+//! symbols of an object file.
+
+use crate::parser::ast;
+
+/// Returns the symbol for the function or static `name`. It is mangled
+/// unless `#[no_mangle]` or `#[export]` keep its own name, or
+/// `#[link_name = "..."]` sets one. Malformed or conflicting attributes
+/// are an error.
+pub fn symbol(name: &str, attrs: &[ast::Attribute]) -> Result<String, String> {
+    let mut symbol = None;
+
+    for attr in attrs.iter().filter(|attr| names_symbol(attr)) {
+        let named = match (attr.name.as_str(), attr.args.is_empty(), &attr.value) {
+            ("no_mangle" | "export", true, None) => name.rsplit("::").next().unwrap().to_string(),
+            ("link_name", true, Some(value)) => value.clone(),
+            _ => return Err(format!("malformed attribute `{}` on `{}`", attr, name)),
+        };
+        if symbol.is_some() {
+            return Err(format!(
+                "`{}` has more than one attribute naming its symbol",
+                name
+            ));
+        }
+        symbol = Some(named);
+    }
+
+    Ok(symbol.unwrap_or_else(|| mangle(name, &[])))
+}
+
+/// Whether `attr` sets the symbol of what it is attached to.
+pub fn names_symbol(attr: &ast::Attribute) -> bool {
+    matches!(attr.name.as_str(), "no_mangle" | "export" | "link_name")
+}
+
+/// Mangles the function or static `path`, instantiated for `type_args`
+/// if it is generic.
+pub fn mangle(path: &str, type_args: &[ast::Type]) -> String {
+    let mut symbol = String::from("_L");
+    mangle_path(&mut symbol, path);
+    mangle_generics(&mut symbol, type_args);
+    symbol
+}
+
+/// The name of `typ` in generated C source, `_LT` followed by the mangled
+/// type, e.g. `_LTN5PointE` for `Point` and `_LTSl` for `[i64]`.
+pub fn type_name(typ: &ast::Type) -> String {
+    let mut name = String::from("_LT");
+    mangle_type(&mut name, typ);
+    name
+}
+
+fn mangle_path(out: &mut String, path: &str) {
+    out.push('N');
+    for segment in segments(path) {
+        match segment
+            .strip_prefix('<')
+            .and_then(|segment| segment.strip_suffix('>'))
+        {
+            Some(implementation) => {
+                let (typ, trait_name) = implementation
+                    .split_once(" as ")
+                    .expect("trait implementations are named `<Type as Trait>`");
+                out.push('X');
+                mangle_path(out, typ);
+                mangle_path(out, trait_name);
+            }
+            None => {
+                out.push_str(&segment.len().to_string());
+                out.push_str(segment);
+            }
+        }
+    }
+    out.push('E');
+}
+
+fn mangle_generics(out: &mut String, generics: &[ast::Type]) {
+    if generics.is_empty() {
+        return;
+    }
+
+    out.push('I');
+    for typ in generics {
+        mangle_type(out, typ);
+    }
+    out.push('E');
+}
+
+fn mangle_type(out: &mut String, typ: &ast::Type) {
+    match typ {
+        ast::Type::Named { name, generics } => {
+            let builtin = match name.as_str() {
+                "i8" => "a",
+                "u8" => "h",
+                "i16" => "s",
+                "u16" => "t",
+                "i32" => "i",
+                "u32" => "j",
+                "i64" => "l",
+                "u64" => "m",
+                "i128" => "n",
+                "u128" => "o",
+                "bool" => "b",
+                "void" => "v",
+                name => {
+                    mangle_path(out, name);
+                    mangle_generics(out, generics);
+                    return;
+                }
+            };
+            out.push_str(builtin);
+        }
+        ast::Type::Pointer(typ) => {
+            out.push('P');
+            mangle_type(out, typ);
+        }
+        ast::Type::Ref { mutable, typ } => {
+            out.push(if *mutable { 'Q' } else { 'R' });
+            mangle_type(out, typ);
+        }
+        ast::Type::Array { typ, len } => {
+            out.push('A');
+            out.push_str(&len.to_string());
+            out.push('_');
+            mangle_type(out, typ);
+        }
+        ast::Type::Slice(typ) => {
+            out.push('S');
+            mangle_type(out, typ);
+        }
+        ast::Type::Optional(typ) => {
+            out.push('O');
+            mangle_type(out, typ);
+        }
+        ast::Type::Dyn(name) => {
+            out.push('D');
+            mangle_path(out, name);
+        }
+    }
+}
+
+/// Turns a symbol made by `mangle`, or a C type name made by `type_name`,
+/// back into the lyth name, e.g. `Pair::new<i64>` for `_LN4Pair3newEIlE`.
+/// Returns `None` for anything else.
+pub fn demangle(symbol: &str) -> Option<String> {
+    let mut demangler;
+    let name = match symbol.strip_prefix("_LT") {
+        Some(typ) => {
+            demangler = Demangler { rest: typ };
+            demangler.typ()?
+        }
+        None => {
+            demangler = Demangler {
+                rest: symbol.strip_prefix("_L")?,
+            };
+            demangler.path()? + &demangler.generics()?
+        }
+    };
+    demangler.rest.is_empty().then_some(name)
+}
+
+/// Reads the grammar in the module documentation from the front of `rest`.
+struct Demangler<'a> {
+    rest: &'a str,
+}
+
+impl Demangler<'_> {
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let len = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let number = self.rest[..len].parse().ok()?;
+        self.rest = &self.rest[len..];
+        Some(number)
+    }
+
+    fn path(&mut self) -> Option<String> {
+        if !self.eat('N') {
+            return None;
+        }
+
+        let mut segments = Vec::new();
+        while !self.eat('E') {
+            if self.eat('X') {
+                let typ = self.path()?;
+                let trait_name = self.path()?;
+                segments.push(format!("<{} as {}>", typ, trait_name));
+            } else {
+                let len = self.number()?;
+                let segment = self.rest.get(..len)?;
+                self.rest = &self.rest[len..];
+                segments.push(segment.to_string());
+            }
+        }
+
+        (!segments.is_empty()).then(|| segments.join("::"))
+    }
+
+    fn generics(&mut self) -> Option<String> {
+        if !self.eat('I') {
+            return Some(String::new());
+        }
+
+        let mut generics = Vec::new();
+        while !self.eat('E') {
+            generics.push(self.typ()?);
+        }
+        if generics.is_empty() {
+            return None;
+        }
+        Some(format!("<{}>", generics.join(", ")))
+    }
+
+    fn typ(&mut self) -> Option<String> {
+        let builtin = match self.rest.chars().next()? {
+            'a' => "i8",
+            'h' => "u8",
+            's' => "i16",
+            't' => "u16",
+            'i' => "i32",
+            'j' => "u32",
+            'l' => "i64",
+            'm' => "u64",
+            'n' => "i128",
+            'o' => "u128",
+            'b' => "bool",
+            'v' => "void",
+            'N' => return Some(self.path()? + &self.generics()?),
+            c => {
+                self.rest = &self.rest[c.len_utf8()..];
+                return match c {
+                    'P' => Some(format!("*{}", self.typ()?)),
+                    'R' => Some(format!("&{}", self.typ()?)),
+                    'Q' => Some(format!("&mut {}", self.typ()?)),
+                    'A' => {
+                        let len = self.number()?;
+                        if !self.eat('_') {
+                            return None;
+                        }
+                        Some(format!("[{}; {}]", self.typ()?, len))
+                    }
+                    'S' => Some(format!("[{}]", self.typ()?)),
+                    'O' => Some(format!("?{}", self.typ()?)),
+                    'D' => Some(format!("dyn {}", self.path()?)),
+                    _ => None,
+                };
+            }
+        };
+        self.rest = &self.rest[1..];
+        Some(builtin.to_string())
+    }
+}
+
+/// Splits a path at the `::` outside of `<Type as Trait>` segments.
+fn segments(path: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+
+    for (idx, c) in path.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            ':' if depth == 0 && path[idx..].starts_with("::") => {
+                segments.push(&path[start..idx]);
+                start = idx + 2;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&path[start..]);
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn typ(input: &str) -> ast::Type {
+        Parser::new("test.ly", input).type_().unwrap()
+    }
+
+    fn attr(name: &str, value: Option<&str>) -> ast::Attribute {
+        ast::Attribute {
+            name: name.to_string(),
+            args: Vec::new(),
+            value: value.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn documented_symbols() {
+        assert_eq!(mangle("geo::area", &[]), "_LN3geo4areaE");
+        assert_eq!(mangle("Pair::new", &[typ("i64")]), "_LN4Pair3newEIlE");
+        assert_eq!(
+            mangle("<Circle as Shape>::area", &[]),
+            "_LNXN6CircleEN5ShapeE4areaE"
+        );
+        assert_eq!(type_name(&typ("Point")), "_LTN5PointE");
+        assert_eq!(type_name(&typ("[i64]")), "_LTSl");
+    }
+
+    #[test]
+    fn symbols_round_trip() {
+        // functions in modules, methods, trait implementations and generic
+        // instances, as the compiler emits them
+        let symbols: &[(&str, &[&str], &str)] = &[
+            ("test2", &[], "_LN5test2E"),
+            ("geo::shapes::area", &[], "_LN3geo6shapes4areaE"),
+            ("Pair::new", &["i64"], "_LN4Pair3newEIlE"),
+            (
+                "<Circle as Shape>::area",
+                &[],
+                "_LNXN6CircleEN5ShapeE4areaE",
+            ),
+            (
+                "<geo::Circle as geo::Shape>::scale",
+                &[],
+                "_LNXN3geo6CircleEN3geo5ShapeE5scaleE",
+            ),
+            (
+                "<Pair as Show>::show",
+                &["i64"],
+                "_LNXN4PairEN4ShowE4showEIlE",
+            ),
+            ("swap", &["*u8"], "_LN4swapEIPhE"),
+            ("swap", &["?Pair<u16>"], "_LN4swapEION4PairEItEE"),
+            (
+                "describe",
+                &["&dyn geo::Shape", "[i32; 4]"],
+                "_LN8describeEIRDN3geo5ShapeEA4_iE",
+            ),
+        ];
+
+        for (path, type_args, symbol) in symbols {
+            let type_args: Vec<_> = type_args.iter().map(|arg| typ(arg)).collect();
+            assert_eq!(mangle(path, &type_args), *symbol);
+
+            let mut name = path.to_string();
+            if !type_args.is_empty() {
+                let args: Vec<_> = type_args.iter().map(ToString::to_string).collect();
+                name = format!("{}<{}>", name, args.join(", "));
+            }
+            assert_eq!(demangle(symbol), Some(name));
+        }
+    }
+
+    #[test]
+    fn type_names_round_trip() {
+        let types = [
+            "i8",
+            "u8",
+            "i16",
+            "u16",
+            "i32",
+            "u32",
+            "i64",
+            "u64",
+            "i128",
+            "u128",
+            "bool",
+            "void",
+            "Point",
+            "geo::Circle",
+            "Pair<i64, *u8>",
+            "*i8",
+            "&Point",
+            "&mut [u8]",
+            "[[i32; 3]; 12]",
+            "?&dyn Shape",
+        ];
+
+        for name in types {
+            assert_eq!(demangle(&type_name(&typ(name))).as_deref(), Some(name));
+        }
+    }
+
+    #[test]
+    fn foreign_symbols_do_not_demangle() {
+        for symbol in [
+            "printf",
+            "_L",
+            "_LNE",
+            "_LN3geo",
+            "_LN9geoE",
+            "_LN3geo4areaEx",
+            "_LN3geo4areaEIE",
+            "_LN4Pair3newEIzE",
+            "_LTA4l",
+            "_LT",
+        ] {
+            assert_eq!(demangle(symbol), None, "{}", symbol);
+        }
+    }
+
+    #[test]
+    fn attributes_name_symbols() {
+        assert_eq!(symbol("geo::area", &[]).unwrap(), "_LN3geo4areaE");
+        assert_eq!(
+            symbol("geo::area", &[attr("no_mangle", None)]).unwrap(),
+            "area"
+        );
+        assert_eq!(
+            symbol("geo::area", &[attr("export", None)]).unwrap(),
+            "area"
+        );
+        assert_eq!(
+            symbol("geo::area", &[attr("link_name", Some("geo_area"))]).unwrap(),
+            "geo_area"
+        );
+    }
+
+    #[test]
+    fn conflicting_symbol_attributes() {
+        assert_eq!(
+            symbol(
+                "area",
+                &[attr("export", None), attr("link_name", Some("geo_area"))],
+            ),
+            Err(String::from(
+                "`area` has more than one attribute naming its symbol"
+            ))
+        );
+    }
+}
@@ -1,33 +1,246 @@
 use crate::lexer::token::TokenKind;
 use std::fmt::{Display, Formatter, Result};
 
+/// A declaration at the top level of a file. Only `pub` ones can be
+/// used by the modules importing the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub public: bool,
+    pub decl: Decl,
+}
+
+/// A declaration, which knows the file and position it is declared at.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Decl {
     Function {
+        attrs: Vec<Attribute>,
+        inline: Option<Inline>,
+        name: String,
+        type_params: Vec<TypeParam>,
+        params: Vec<(String, Type)>,
+        body: Expr,
+        rtyp: Option<Type>,
+        file: String,
+        pos: Pos,
+    },
+    Extern {
+        attrs: Vec<Attribute>,
         name: String,
+        abi: Option<String>,
         params: Vec<(String, Type)>,
-        body: Stmt,
+        variadic: bool,
         rtyp: Option<Type>,
+        file: String,
+        pos: Pos,
     },
     Struct {
         name: Type,
         members: Vec<(String, Type)>,
+        file: String,
+        pos: Pos,
+    },
+    Const {
+        name: String,
+        typ: Type,
+        value: Expr,
+        file: String,
+        pos: Pos,
+    },
+    Static {
+        attrs: Vec<Attribute>,
+        name: String,
+        mutable: bool,
+        typ: Type,
+        value: Expr,
+        file: String,
+        pos: Pos,
+    },
+    Enum {
+        name: String,
+        variants: Vec<Variant>,
+        file: String,
+        pos: Pos,
+    },
+    /// Functions associated with `typ`. The ones taking `self` as first
+    /// parameter are methods. With a `trait_name` they implement that trait.
+    Impl {
+        type_params: Vec<TypeParam>,
+        trait_name: Option<String>,
+        typ: Type,
+        functions: Vec<Decl>,
+        file: String,
+        pos: Pos,
+    },
+    Trait {
+        name: String,
+        functions: Vec<TraitFunction>,
+        file: String,
+        pos: Pos,
+    },
+    /// `import foo::bar;` or `mod foo::bar;`, loading `foo/bar.ly`.
+    Import {
+        path: String,
+        file: String,
+        pos: Pos,
     },
 }
 
+impl Decl {
+    /// The attributes of the declarations that can have any.
+    pub fn attrs_mut(&mut self) -> Option<&mut Vec<Attribute>> {
+        match self {
+            Decl::Function { attrs, .. }
+            | Decl::Extern { attrs, .. }
+            | Decl::Static { attrs, .. } => Some(attrs),
+            _ => None,
+        }
+    }
+
+    /// The file and position the declaration is at.
+    pub fn location(&self) -> (&str, Pos) {
+        match self {
+            Decl::Function { file, pos, .. }
+            | Decl::Extern { file, pos, .. }
+            | Decl::Struct { file, pos, .. }
+            | Decl::Const { file, pos, .. }
+            | Decl::Static { file, pos, .. }
+            | Decl::Enum { file, pos, .. }
+            | Decl::Impl { file, pos, .. }
+            | Decl::Trait { file, pos, .. }
+            | Decl::Import { file, pos, .. } => (file, *pos),
+        }
+    }
+}
+
+/// `inline fn` asks for the function to be inlined, `inline(always) fn`
+/// requires it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Inline {
+    Hint,
+    Always,
+}
+
+/// `#[name]`, `#[name(arg, ...)]` or `#[name = "value"]` in front of a
+/// declaration.
 #[derive(Debug, Clone, PartialEq)]
-pub struct Type {
+pub struct Attribute {
     pub name: String,
-    pub generics: Vec<Type>,
+    pub args: Vec<String>,
+    pub value: Option<String>,
+}
+
+/// A type parameter and the traits its arguments have to implement,
+/// like `T: Shape`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeParam {
+    pub name: String,
+    pub bounds: Vec<String>,
+}
+
+/// A function declared by a trait. `Self` stands for the implementing
+/// type, a body is the default for implementations that leave it out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraitFunction {
+    pub name: String,
+    pub params: Vec<(String, Type)>,
+    pub rtyp: Option<Type>,
+    pub body: Option<Expr>,
+    pub file: String,
+    pub pos: Pos,
+}
+
+/// A line and column in a source file, kept for error messages and debug
+/// info.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<Type>,
+    pub discriminant: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Named {
+        name: String,
+        generics: Vec<Type>,
+    },
+    Pointer(Box<Type>),
+    Ref {
+        mutable: bool,
+        typ: Box<Type>,
+    },
+    Array {
+        typ: Box<Type>,
+        len: usize,
+    },
+    Slice(Box<Type>),
+    Optional(Box<Type>),
+    /// A trait object, only usable behind a reference or pointer.
+    Dyn(String),
 }
 
 impl Type {
     pub fn new_simple(name: &str) -> Self {
-        Self {
+        Type::Named {
             name: String::from(name),
             generics: Vec::new(),
         }
     }
+
+    pub fn new_pointer(typ: Type) -> Self {
+        Type::Pointer(Box::new(typ))
+    }
+
+    pub fn new_ref(typ: Type, mutable: bool) -> Self {
+        Type::Ref {
+            mutable,
+            typ: Box::new(typ),
+        }
+    }
+
+    /// The type behind a raw pointer or a reference.
+    pub fn pointee(&self) -> Option<&Type> {
+        match self {
+            Type::Pointer(typ) | Type::Ref { typ, .. } => Some(typ),
+            _ => None,
+        }
+    }
+
+    pub fn is_named(&self, name: &str) -> bool {
+        matches!(self, Type::Named { name: n, generics } if n == name && generics.is_empty())
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.int_width().is_some()
+    }
+
+    /// Bit width of the builtin integer types, `None` for everything else.
+    pub fn int_width(&self) -> Option<u32> {
+        match self {
+            Type::Named { name, generics } if generics.is_empty() => match name.as_ref() {
+                "i128" | "u128" => Some(128),
+                "i64" | "u64" => Some(64),
+                "i32" | "u32" => Some(32),
+                "i16" | "u16" => Some(16),
+                "i8" | "u8" => Some(8),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        match self {
+            Type::Named { name, .. } => name.starts_with('i') && self.is_integer(),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -36,28 +249,36 @@ pub enum Stmt {
         name: String,
         typ: Option<Type>,
         value: Box<Expr>,
+        pos: Pos,
     },
     Assign {
-        name: String,
+        target: Box<Expr>,
         op: Option<TokenKind>,
         value: Box<Expr>,
-    },
-    If {
-        expr: Box<Expr>,
-        body: Box<Stmt>,
-        elze: Option<Box<Stmt>>,
-    },
-    Block {
-        body: Vec<Stmt>,
+        pos: Pos,
     },
     Expr {
         value: Box<Expr>,
+        pos: Pos,
     },
     Return {
         value: Option<Box<Expr>>,
+        pos: Pos,
     },
 }
 
+impl Stmt {
+    /// Where the statement starts.
+    pub fn pos(&self) -> Pos {
+        match self {
+            Stmt::Let { pos, .. }
+            | Stmt::Assign { pos, .. }
+            | Stmt::Expr { pos, .. }
+            | Stmt::Return { pos, .. } => *pos,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Literal(Lit),
@@ -79,6 +300,64 @@ pub enum Expr {
         op: TokenKind,
         expr: Box<Expr>,
     },
+    AddrOf {
+        mutable: bool,
+        expr: Box<Expr>,
+    },
+    Array(Vec<Box<Expr>>),
+    Index {
+        expr: Box<Expr>,
+        index: Box<Expr>,
+    },
+    Field {
+        expr: Box<Expr>,
+        name: String,
+    },
+    Cast {
+        expr: Box<Expr>,
+        typ: Type,
+    },
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        elze: Option<Box<Expr>>,
+    },
+    Block {
+        body: Vec<Stmt>,
+        tail: Option<Box<Expr>>,
+    },
+    Match {
+        expr: Box<Expr>,
+        arms: Vec<Arm>,
+    },
+    Struct {
+        name: String,
+        fields: Vec<(String, Box<Expr>)>,
+    },
+    MethodCall {
+        receiver: Box<Expr>,
+        name: String,
+        args: Vec<Box<Expr>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Binding(String),
+    Literal(Expr),
+    /// `Enum::Variant` or `Enum::Variant(a, _)`, binding the payload fields.
+    Variant {
+        path: String,
+        fields: Vec<String>,
+    },
+    Or(Vec<Pattern>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -86,6 +365,49 @@ pub enum Lit {
     Integer(usize),
     String(String),
     Boolean(bool),
+    Null,
+}
+
+impl Display for Attribute {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "#[{}", self.name)?;
+        if !self.args.is_empty() {
+            write!(f, "({})", self.args.join(", "))?;
+        }
+        if let Some(value) = &self.value {
+            write!(f, " = \"{}\"", value)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Type::Named { name, generics } => {
+                write!(f, "{}", name)?;
+                if !generics.is_empty() {
+                    write!(f, "<")?;
+                    for (i, generic) in generics.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", generic)?;
+                    }
+                    write!(f, ">")?;
+                }
+                Ok(())
+            }
+            Type::Pointer(typ) => write!(f, "*{}", typ),
+            Type::Ref { mutable, typ } => {
+                write!(f, "&{}{}", if *mutable { "mut " } else { "" }, typ)
+            }
+            Type::Array { typ, len } => write!(f, "[{}; {}]", typ, len),
+            Type::Slice(typ) => write!(f, "[{}]", typ),
+            Type::Optional(typ) => write!(f, "?{}", typ),
+            Type::Dyn(name) => write!(f, "dyn {}", name),
+        }
+    }
 }
 
 impl Display for Expr {
@@ -103,6 +425,87 @@ impl Display for Expr {
             Expr::Prefix { op, expr } => write!(f, "({:?} {})", op, expr),
             Expr::Infix { op, left, right } => write!(f, "({} {:?} {})", left, op, right),
             Expr::Postfix { op, expr } => write!(f, "({} {:?})", expr, op),
+            Expr::AddrOf { mutable, expr } => {
+                write!(f, "(&{}{})", if *mutable { "mut " } else { "" }, expr)
+            }
+            Expr::Array(items) => {
+                write!(f, "[")?;
+                for item in items {
+                    write!(f, "{},", item)?;
+                }
+                write!(f, "]")
+            }
+            Expr::Index { expr, index } => write!(f, "{}[{}]", expr, index),
+            Expr::Field { expr, name } => write!(f, "{}.{}", expr, name),
+            Expr::Cast { expr, typ } => write!(f, "({} as {})", expr, typ),
+            Expr::If { cond, then, elze } => {
+                write!(f, "if {} {}", cond, then)?;
+                if let Some(elze) = elze {
+                    write!(f, " else {}", elze)?;
+                }
+                Ok(())
+            }
+            Expr::Block { body, tail } => {
+                write!(f, "{{ ")?;
+                if !body.is_empty() {
+                    write!(f, "...; ")?;
+                }
+                if let Some(tail) = tail {
+                    write!(f, "{} ", tail)?;
+                }
+                write!(f, "}}")
+            }
+            Expr::MethodCall {
+                receiver,
+                name,
+                args,
+            } => {
+                write!(f, "{}.{}(", receiver, name)?;
+                for arg in args {
+                    write!(f, "{},", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Struct { name, fields } => {
+                write!(f, "{} {{ ", name)?;
+                for (field, value) in fields {
+                    write!(f, "{}: {}, ", field, value)?;
+                }
+                write!(f, "}}")
+            }
+            Expr::Match { expr, arms } => {
+                write!(f, "match {} {{ ", expr)?;
+                for arm in arms {
+                    write!(f, "{} => {}, ", arm.pattern, arm.body)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Binding(name) => write!(f, "{}", name),
+            Pattern::Literal(expr) => write!(f, "{}", expr),
+            Pattern::Variant { path, fields } => {
+                write!(f, "{}", path)?;
+                if !fields.is_empty() {
+                    write!(f, "({})", fields.join(", "))?;
+                }
+                Ok(())
+            }
+            Pattern::Or(patterns) => {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", pattern)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -113,6 +516,7 @@ impl Display for Lit {
             Lit::Integer(i) => write!(f, "{}", i),
             Lit::Boolean(b) => write!(f, "{}", b),
             Lit::String(s) => write!(f, r#""{}""#, s),
+            Lit::Null => write!(f, "null"),
         }
     }
 }
@@ -1,80 +1,134 @@
-use super::{ast, Parser};
+use super::{ast, error_at, Parser};
+use crate::diagnostic::Diagnostic;
 use crate::lexer::token::TokenKind;
 
 impl<'a> Parser<'a> {
     #[inline]
-    pub fn expression(&mut self) -> ast::Expr {
+    pub fn expression(&mut self) -> Result<ast::Expr, Diagnostic> {
         self.parse_expression(0)
     }
 
-    pub fn parse_expression(&mut self, binding_power: u8) -> ast::Expr {
+    pub fn parse_expression(&mut self, binding_power: u8) -> Result<ast::Expr, Diagnostic> {
         let mut lhs = match self.peek() {
-            lit @ TokenKind::Integer | lit @ TokenKind::Boolean | lit @ TokenKind::String => {
-                let literal_text = {
-                    let literal_token = self.next().unwrap();
-                    self.text(literal_token)
+            lit @ TokenKind::Integer
+            | lit @ TokenKind::Boolean
+            | lit @ TokenKind::String
+            | lit @ TokenKind::Null => {
+                let literal_token = self.advance()?;
+                let loc = literal_token.loc.clone();
+                let literal_text = self.text(literal_token);
+                let invalid = |what: &str| {
+                    error_at(
+                        &loc,
+                        format!("invalid {} literal: `{}`", what, literal_text),
+                    )
                 };
 
                 let lit = match lit {
-                    TokenKind::Integer => ast::Lit::Integer(
-                        literal_text
-                            .parse()
-                            .expect(&format!("invalid integer literal: `{}`", literal_text)),
-                    ),
-                    TokenKind::Boolean => ast::Lit::Boolean(
-                        literal_text
-                            .parse()
-                            .expect(&format!("invalid bool literal: `{}`", literal_text)),
-                    ),
-                    TokenKind::String => {
-                        ast::Lit::String(literal_text[1..(literal_text.len() - 1)].to_string())
+                    TokenKind::Integer => {
+                        ast::Lit::Integer(literal_text.parse().map_err(|_| invalid("integer"))?)
+                    }
+                    TokenKind::Boolean => {
+                        ast::Lit::Boolean(literal_text.parse().map_err(|_| invalid("bool"))?)
                     }
+                    TokenKind::String => ast::Lit::String(
+                        unescape(&literal_text[1..(literal_text.len() - 1)])
+                            .map_err(|message| error_at(&loc, message))?,
+                    ),
+                    TokenKind::Null => ast::Lit::Null,
                     _ => unreachable!(),
                 };
 
                 ast::Expr::Literal(lit)
             }
             TokenKind::Identifier => {
-                let ident_token = self.next().unwrap();
-                let name = self.text(ident_token).to_string();
+                let name = self.path()?;
 
-                if !self.at(TokenKind::OpenParen) {
-                    // plain identifier
-                    ast::Expr::Ident(name)
-                } else {
+                if self.at(TokenKind::OpenParen) {
                     //  function call
                     let mut args = Vec::new();
-                    self.consume(TokenKind::OpenParen);
+                    self.consume(TokenKind::OpenParen)?;
                     while !self.at(TokenKind::ClosedParen) {
-                        let arg = self.parse_expression(0);
+                        let arg = self.with_struct_literals(true, |parser| parser.expression())?;
                         args.push(Box::new(arg));
                         if self.at(TokenKind::Comma) {
-                            self.consume(TokenKind::Comma);
+                            self.consume(TokenKind::Comma)?;
                         }
                     }
-                    self.consume(TokenKind::ClosedParen);
+                    self.consume(TokenKind::ClosedParen)?;
                     ast::Expr::Call { name, args }
+                } else if self.at(TokenKind::OpenBrace) && self.struct_literals {
+                    // struct literal
+                    let mut fields = Vec::new();
+                    self.consume(TokenKind::OpenBrace)?;
+                    while !self.at(TokenKind::ClosedBrace) {
+                        let field = self.consume(TokenKind::Identifier)?;
+                        let field = self.text(field).to_string();
+                        self.consume(TokenKind::Colon)?;
+
+                        let value = self.expression()?;
+                        fields.push((field, Box::new(value)));
+                        if self.at(TokenKind::Comma) {
+                            self.consume(TokenKind::Comma)?;
+                        }
+                    }
+                    self.consume(TokenKind::ClosedBrace)?;
+                    ast::Expr::Struct { name, fields }
+                } else {
+                    // plain identifier
+                    ast::Expr::Ident(name)
                 }
             }
             TokenKind::OpenParen => {
                 // There is no AST node for grouped expressions.
                 // Parentheses just influence the tree structure.
-                self.consume(TokenKind::OpenParen);
-                let expr = self.parse_expression(0);
-                self.consume(TokenKind::ClosedParen);
+                self.consume(TokenKind::OpenParen)?;
+                let expr = self.with_struct_literals(true, |parser| parser.expression())?;
+                self.consume(TokenKind::ClosedParen)?;
                 expr
             }
-            op @ TokenKind::Minus | op @ TokenKind::Bang => {
-                self.consume(op);
+            TokenKind::If | TokenKind::Match | TokenKind::OpenBrace => self.block_like()?,
+            TokenKind::OpenBracket => {
+                let mut items = Vec::new();
+                self.consume(TokenKind::OpenBracket)?;
+                while !self.at(TokenKind::ClosedBracket) {
+                    let item = self.parse_expression(0)?;
+                    items.push(Box::new(item));
+                    if self.at(TokenKind::Comma) {
+                        self.consume(TokenKind::Comma)?;
+                    }
+                }
+                self.consume(TokenKind::ClosedBracket)?;
+                ast::Expr::Array(items)
+            }
+            TokenKind::And => {
+                self.consume(TokenKind::And)?;
+                let mutable = self.at(TokenKind::Mut);
+                if mutable {
+                    self.consume(TokenKind::Mut)?;
+                }
+
+                let ((), right_binding_power) = TokenKind::And.prefix_binding_power();
+                let expr = self.parse_expression(right_binding_power)?;
+                ast::Expr::AddrOf {
+                    mutable,
+                    expr: Box::new(expr),
+                }
+            }
+            op @ TokenKind::Minus
+            | op @ TokenKind::Bang
+            | op @ TokenKind::Tilde
+            | op @ TokenKind::Asterisk => {
+                self.consume(op)?;
                 let ((), right_binding_power) = op.prefix_binding_power();
-                let expr = self.parse_expression(right_binding_power);
+                let expr = self.parse_expression(right_binding_power)?;
                 ast::Expr::Prefix {
                     op,
                     expr: Box::new(expr),
                 }
             }
             kind => {
-                panic!("Unknown start of expression: `{:?}`", kind);
+                return Err(self.error(format!("unknown start of expression: `{:?}`", kind)));
             }
         };
         loop {
@@ -85,22 +139,33 @@ impl<'a> Parser<'a> {
                 | op @ TokenKind::Slash
                 | op @ TokenKind::Percent
                 | op @ TokenKind::Caret
+                | op @ TokenKind::ShiftLeft
+                | op @ TokenKind::ShiftRight
                 | op @ TokenKind::Equal
                 | op @ TokenKind::UnEqual
                 | op @ TokenKind::And
                 | op @ TokenKind::Pipe
+                | op @ TokenKind::LogicalAnd
+                | op @ TokenKind::LogicalOr
                 | op @ TokenKind::LessThan
                 | op @ TokenKind::LessEqual
                 | op @ TokenKind::GreaterThan
                 | op @ TokenKind::GreaterEqual
-                | op @ TokenKind::Bang => op,
+                | op @ TokenKind::Bang
+                | op @ TokenKind::As
+                | op @ TokenKind::OpenBracket
+                | op @ TokenKind::Dot => op,
                 TokenKind::Eof => break,
                 TokenKind::ClosedParen
                 | TokenKind::ClosedBrace
                 | TokenKind::OpenBrace
                 | TokenKind::Comma
-                | TokenKind::SemiColon => break,
-                kind => panic!("Unknown operator: `{:?}`", kind),
+                | TokenKind::SemiColon
+                | TokenKind::ClosedBracket
+                | TokenKind::FatArrow
+                | TokenKind::Assign => break,
+                kind if kind.compound_operator().is_some() => break,
+                kind => return Err(self.error(format!("unknown operator: `{:?}`", kind))),
             };
 
             if let Some((left_binding_power, ())) = op.postfix_binding_power() {
@@ -109,7 +174,60 @@ impl<'a> Parser<'a> {
                     break;
                 }
 
-                self.consume(op);
+                self.consume(op)?;
+
+                match op {
+                    TokenKind::As => {
+                        // the right hand side of a cast is a type, not an expression
+                        lhs = ast::Expr::Cast {
+                            expr: Box::new(lhs),
+                            typ: self.type_()?,
+                        };
+                        continue;
+                    }
+                    TokenKind::OpenBracket => {
+                        let index = self.parse_expression(0)?;
+                        self.consume(TokenKind::ClosedBracket)?;
+                        lhs = ast::Expr::Index {
+                            expr: Box::new(lhs),
+                            index: Box::new(index),
+                        };
+                        continue;
+                    }
+                    TokenKind::Dot => {
+                        let field = self.consume(TokenKind::Identifier)?;
+                        let name = self.text(field).to_string();
+
+                        if !self.at(TokenKind::OpenParen) {
+                            lhs = ast::Expr::Field {
+                                expr: Box::new(lhs),
+                                name,
+                            };
+                            continue;
+                        }
+
+                        // method call
+                        let mut args = Vec::new();
+                        self.consume(TokenKind::OpenParen)?;
+                        while !self.at(TokenKind::ClosedParen) {
+                            let arg =
+                                self.with_struct_literals(true, |parser| parser.expression())?;
+                            args.push(Box::new(arg));
+                            if self.at(TokenKind::Comma) {
+                                self.consume(TokenKind::Comma)?;
+                            }
+                        }
+                        self.consume(TokenKind::ClosedParen)?;
+                        lhs = ast::Expr::MethodCall {
+                            receiver: Box::new(lhs),
+                            name,
+                            args,
+                        };
+                        continue;
+                    }
+                    _ => {}
+                }
+
                 // no recursive call here, because we have already parsed our operand `lhs`
                 lhs = ast::Expr::Postfix {
                     op,
@@ -125,8 +243,8 @@ impl<'a> Parser<'a> {
                     break;
                 }
 
-                self.consume(op);
-                let rhs = self.parse_expression(right_binding_power);
+                self.consume(op)?;
+                let rhs = self.parse_expression(right_binding_power)?;
                 lhs = ast::Expr::Infix {
                     op,
                     left: Box::new(lhs),
@@ -139,8 +257,34 @@ impl<'a> Parser<'a> {
             break; // Not an operator --> end of expression
         }
 
-        lhs
+        Ok(lhs)
+    }
+}
+
+/// Replaces the escape sequences of a string literal with the characters
+/// they stand for.
+fn unescape(text: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some(other) => return Err(format!("invalid escape sequence: `\\{}`", other)),
+            None => return Err(String::from("unterminated escape sequence")),
+        }
     }
+
+    Ok(result)
 }
 
 trait Operator {
@@ -157,7 +301,11 @@ trait Operator {
 impl Operator for TokenKind {
     fn prefix_binding_power(&self) -> ((), u8) {
         match self {
-            TokenKind::Minus | TokenKind::Bang => ((), 51),
+            TokenKind::Minus
+            | TokenKind::Bang
+            | TokenKind::Tilde
+            | TokenKind::And
+            | TokenKind::Asterisk => ((), 51),
             // Prefixes are the only operators we have already seen
             // when we call this, so we know the token must be
             // one of the above
@@ -167,16 +315,20 @@ impl Operator for TokenKind {
 
     fn infix_binding_power(&self) -> Option<(u8, u8)> {
         let result = match self {
-            TokenKind::Pipe => (1, 2),
-            TokenKind::And => (3, 4),
-            TokenKind::Equal | TokenKind::UnEqual => (5, 6),
-            TokenKind::LessThan
+            TokenKind::LogicalOr => (1, 2),
+            TokenKind::LogicalAnd => (3, 4),
+            TokenKind::Equal
+            | TokenKind::UnEqual
+            | TokenKind::LessThan
             | TokenKind::GreaterThan
             | TokenKind::LessEqual
-            | TokenKind::GreaterEqual => (7, 8),
+            | TokenKind::GreaterEqual => (5, 6),
+            TokenKind::Pipe => (7, 8),
             TokenKind::Caret => (9, 10),
-            TokenKind::Plus | TokenKind::Minus => (11, 12),
-            TokenKind::Asterisk | TokenKind::Slash | TokenKind::Percent => (13, 14),
+            TokenKind::And => (11, 12),
+            TokenKind::ShiftLeft | TokenKind::ShiftRight => (13, 14),
+            TokenKind::Plus | TokenKind::Minus => (15, 16),
+            TokenKind::Asterisk | TokenKind::Slash | TokenKind::Percent => (17, 18),
             _ => return None,
         };
         Some(result)
@@ -184,9 +336,138 @@ impl Operator for TokenKind {
 
     fn postfix_binding_power(&self) -> Option<(u8, ())> {
         let result = match self {
-            TokenKind::Bang => (101, ()),
+            TokenKind::As => (49, ()),
+            TokenKind::Bang | TokenKind::OpenBracket | TokenKind::Dot => (101, ()),
             _ => return None,
         };
         Some(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `input` as an expression and prints it fully parenthesised.
+    fn parse(input: &str) -> String {
+        Parser::new("test.ly", input)
+            .expression()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn arithmetic_precedence() {
+        assert_eq!(parse("a + b * c"), "(a Plus (b Asterisk c))");
+        assert_eq!(parse("a * b + c"), "((a Asterisk b) Plus c)");
+        assert_eq!(parse("a - b % c"), "(a Minus (b Percent c))");
+        assert_eq!(parse("(a + b) * c"), "((a Plus b) Asterisk c)");
+    }
+
+    #[test]
+    fn left_associativity() {
+        assert_eq!(parse("a - b - c"), "((a Minus b) Minus c)");
+        assert_eq!(parse("a / b / c"), "((a Slash b) Slash c)");
+        assert_eq!(parse("a << b << c"), "((a ShiftLeft b) ShiftLeft c)");
+        assert_eq!(parse("a && b && c"), "((a LogicalAnd b) LogicalAnd c)");
+        assert_eq!(parse("a || b || c"), "((a LogicalOr b) LogicalOr c)");
+    }
+
+    #[test]
+    fn bitwise_operators_bind_tighter_than_comparisons() {
+        assert_eq!(parse("x & 1 == 0"), "((x And 1) Equal 0)");
+        assert_eq!(parse("x | y != z"), "((x Pipe y) UnEqual z)");
+        assert_eq!(parse("x ^ y < z"), "((x Caret y) LessThan z)");
+    }
+
+    #[test]
+    fn bitwise_precedence() {
+        assert_eq!(parse("a | b ^ c & d"), "(a Pipe (b Caret (c And d)))");
+        assert_eq!(parse("a & b << c"), "(a And (b ShiftLeft c))");
+        assert_eq!(parse("a << b + c"), "(a ShiftLeft (b Plus c))");
+    }
+
+    #[test]
+    fn logical_operators_bind_loosest() {
+        assert_eq!(
+            parse("a == b && c < d"),
+            "((a Equal b) LogicalAnd (c LessThan d))"
+        );
+        assert_eq!(parse("a || b && c"), "(a LogicalOr (b LogicalAnd c))");
+        assert_eq!(parse("a & b || c | d"), "((a And b) LogicalOr (c Pipe d))");
+    }
+
+    #[test]
+    fn prefix_operators() {
+        assert_eq!(parse("-a * b"), "((Minus a) Asterisk b)");
+        assert_eq!(parse("!a && b"), "((Bang a) LogicalAnd b)");
+        assert_eq!(parse("~a & b"), "((Tilde a) And b)");
+        assert_eq!(parse("*p + 1"), "((Asterisk p) Plus 1)");
+    }
+
+    #[test]
+    fn postfix_operators() {
+        assert_eq!(parse("a + b as i64"), "(a Plus (b as i64))");
+        assert_eq!(parse("-a as u8"), "((Minus a) as u8)");
+        assert_eq!(parse("-x!"), "(Minus (x Bang))");
+        assert_eq!(parse("a[i] * s.len"), "(a[i] Asterisk s.len)");
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(parse("&x"), "(&x)");
+        assert_eq!(parse("&mut x.y"), "(&mut x.y)");
+        assert_eq!(parse("&a[i]"), "(&a[i])");
+        assert_eq!(parse("**p"), "(Asterisk (Asterisk p))");
+        assert_eq!(parse("null"), "null");
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(parse("[1, 2, 3]"), "[1,2,3,]");
+        assert_eq!(parse("[]"), "[]");
+        assert_eq!(parse("a[i][j]"), "a[i][j]");
+        assert_eq!(parse("a[i + 1] * 2"), "(a[(i Plus 1)] Asterisk 2)");
+    }
+
+    #[test]
+    fn shifts() {
+        assert_eq!(parse("a << b < c"), "((a ShiftLeft b) LessThan c)");
+        assert_eq!(parse("a >> b >> c"), "((a ShiftRight b) ShiftRight c)");
+        assert_eq!(parse("~x << 2"), "((Tilde x) ShiftLeft 2)");
+    }
+
+    #[test]
+    fn unwrap_operator() {
+        assert_eq!(parse("x! + 1"), "((x Bang) Plus 1)");
+        assert_eq!(parse("a.b!"), "(a.b Bang)");
+        assert_eq!(parse("f(x)!"), "(f(x,) Bang)");
+        assert_eq!(parse("!x!"), "(Bang (x Bang))");
+    }
+
+    #[test]
+    fn block_expressions() {
+        assert_eq!(parse("if c { 1 } else { 2 }"), "if c { 1 } else { 2 }");
+        assert_eq!(
+            parse("if a { 1 } else if b { 2 } else { 3 }"),
+            "if a { 1 } else if b { 2 } else { 3 }"
+        );
+        assert_eq!(parse("{ 1 }"), "{ 1 }");
+    }
+
+    #[test]
+    fn match_expressions() {
+        assert_eq!(
+            parse("match x { 0 | 1 => a, S::P(v, _) => v, _ => b }"),
+            "match x { 0 | 1 => a, S::P(v, _) => v, _ => b, }"
+        );
+        assert_eq!(parse("S::P(1, 2)"), "S::P(1,2,)");
+    }
+
+    #[test]
+    fn method_calls() {
+        assert_eq!(parse("p.len() + 1"), "(p.len() Plus 1)");
+        assert_eq!(parse("Point::new(1, 2).len()"), "Point::new(1,2,).len()");
+        assert_eq!(parse("a.b.c(1)"), "a.b.c(1,)");
+    }
+}
@@ -1,295 +1,1042 @@
 use super::{ast, Parser};
+use crate::diagnostic::Diagnostic;
 use crate::lexer::token::TokenKind;
 
+/// Everything of a function declaration up to its body.
+struct FunctionHeader {
+    pos: ast::Pos,
+    name: String,
+    type_params: Vec<ast::TypeParam>,
+    params: Vec<(String, ast::Type)>,
+    rtyp: Option<ast::Type>,
+}
+
 impl<'a> Parser<'a> {
-    pub fn parse(&mut self) -> Vec<ast::Decl> {
-        let mut decls = Vec::new();
+    pub fn parse(&mut self) -> Result<Vec<ast::Item>, Diagnostic> {
+        let mut items = Vec::new();
 
         while !self.at(TokenKind::Eof) {
-            let decl = self.decl();
-            decls.push(decl);
+            let attrs = self.attributes()?;
+            let public = self.at(TokenKind::Pub);
+            if public {
+                self.consume(TokenKind::Pub)?;
+            }
+
+            let mut decl = self.decl()?;
+            self.attach(&mut decl, attrs)?;
+            if public && matches!(decl, ast::Decl::Impl { .. }) {
+                return Err(self.error(String::from(
+                    "`impl` blocks cannot be `pub`, their functions are visible wherever the type is",
+                )));
+            }
+            items.push(ast::Item { public, decl });
         }
 
-        decls
+        Ok(items)
     }
 
-    pub fn decl(&mut self) -> ast::Decl {
-        match self.peek() {
-            TokenKind::Function => {
-                self.consume(TokenKind::Function);
-                let mut params = Vec::new();
-
-                let ident = self.next().unwrap_or_else(|| {
-                    panic!(
-                        "{}: tried to parse the function name but found eof",
-                        self.last
-                    )
-                });
-
-                assert_eq!(
-                    ident.kind,
-                    TokenKind::Identifier,
-                    "{}: expected {:?} but found {:?}",
-                    ident.loc,
-                    TokenKind::Identifier,
-                    ident.kind
-                );
+    pub fn decl(&mut self) -> Result<ast::Decl, Diagnostic> {
+        let pos = self.pos();
+        let file = self.last.file.to_string();
 
-                let name = self.text(ident).to_string();
+        let decl = match self.peek() {
+            TokenKind::Function | TokenKind::Inline => self.function(None)?,
+            TokenKind::Impl => {
+                self.consume(TokenKind::Impl)?;
+                let type_params = self.type_params()?;
+                let mut typ = self.type_()?;
 
-                self.consume(TokenKind::OpenParen);
-                while !self.at(TokenKind::ClosedParen) {
-                    let param_ident = self.next().unwrap_or_else(|| {
-                        panic!("{}: tried to parse the parameter but found eof", self.last)
-                    });
+                let mut trait_name = None;
+                if self.at(TokenKind::For) {
+                    self.consume(TokenKind::For)?;
+                    match typ {
+                        ast::Type::Named { name, generics } if generics.is_empty() => {
+                            trait_name = Some(name)
+                        }
+                        typ => {
+                            return Err(self.error(format!(
+                                "expected a trait name before `for` but found `{}`",
+                                typ
+                            )))
+                        }
+                    }
+                    typ = self.type_()?;
+                }
 
-                    assert_eq!(
-                        param_ident.kind,
-                        TokenKind::Identifier,
-                        "{}: expected {:?} but found {:?}",
-                        param_ident.loc,
-                        TokenKind::Identifier,
-                        param_ident.kind
-                    );
+                if !matches!(typ, ast::Type::Named { .. }) {
+                    return Err(self.error(format!("cannot implement functions for `{}`", typ)));
+                }
+
+                let mut functions = Vec::new();
+                self.consume(TokenKind::OpenBrace)?;
+                while !self.at(TokenKind::ClosedBrace) {
+                    let attrs = self.attributes()?;
+                    let kind = self.peek();
+                    if !matches!(kind, TokenKind::Function | TokenKind::Inline) {
+                        return Err(self.error(format!(
+                            "expected a function in `impl` block but found {:?}",
+                            kind
+                        )));
+                    }
 
-                    let param_name = self.text(param_ident).to_string();
-                    self.consume(TokenKind::Colon);
+                    let mut function = self.function(Some(&typ))?;
+                    self.attach(&mut function, attrs)?;
+                    functions.push(function);
+                }
+                self.consume(TokenKind::ClosedBrace)?;
 
-                    let param_type = self.type_();
-                    params.push((param_name, param_type));
+                ast::Decl::Impl {
+                    type_params,
+                    trait_name,
+                    typ,
+                    functions,
+                    file,
+                    pos,
+                }
+            }
+            TokenKind::Trait => {
+                self.consume(TokenKind::Trait)?;
 
-                    if self.at(TokenKind::Comma) {
-                        self.consume(TokenKind::Comma);
+                let ident = self.consume(TokenKind::Identifier)?;
+                let name = self.text(ident).to_string();
+                let self_type = ast::Type::new_simple("Self");
+
+                let mut functions = Vec::new();
+                self.consume(TokenKind::OpenBrace)?;
+                while !self.at(TokenKind::ClosedBrace) {
+                    let kind = self.peek();
+                    if kind != TokenKind::Function {
+                        return Err(self.error(format!(
+                            "expected a function in `trait` block but found {:?}",
+                            kind
+                        )));
                     }
+
+                    let FunctionHeader {
+                        pos,
+                        name,
+                        type_params,
+                        params,
+                        rtyp,
+                    } = self.function_header(Some(&self_type))?;
+                    if !type_params.is_empty() {
+                        return Err(self.error(String::from("trait functions cannot be generic")));
+                    }
+
+                    // without a body implementations have to provide it
+                    let mut body = None;
+                    if self.at(TokenKind::SemiColon) {
+                        self.consume(TokenKind::SemiColon)?;
+                    } else {
+                        body = Some(self.block()?);
+                    }
+
+                    functions.push(ast::TraitFunction {
+                        name,
+                        params,
+                        rtyp,
+                        body,
+                        file: self.last.file.to_string(),
+                        pos,
+                    });
                 }
-                self.consume(TokenKind::ClosedParen);
+                self.consume(TokenKind::ClosedBrace)?;
 
-                let mut rtyp = None;
-                if self.peek() != TokenKind::OpenBrace {
-                    rtyp = Some(self.type_());
+                ast::Decl::Trait {
+                    name,
+                    functions,
+                    file,
+                    pos,
                 }
+            }
+            TokenKind::Import | TokenKind::Mod => {
+                self.advance()?;
+                let path = self.path()?;
+                self.consume(TokenKind::SemiColon)?;
 
-                assert!(
-                    self.at(TokenKind::OpenBrace),
-                    "{}: expected a block after function header",
-                    self.last,
-                );
+                ast::Decl::Import { path, file, pos }
+            }
+            TokenKind::Extern => {
+                self.consume(TokenKind::Extern)?;
+
+                let mut abi = None;
+                if self.at(TokenKind::String) {
+                    let abi_token = self.advance()?;
+                    let abi_text = self.text(abi_token);
+                    abi = Some(abi_text[1..(abi_text.len() - 1)].to_string());
+                }
+
+                self.consume(TokenKind::Function)?;
+
+                let ident = self.consume(TokenKind::Identifier)?;
+
+                let name = self.text(ident).to_string();
+                let (params, variadic) = self.parameters(None)?;
+
+                let mut rtyp = None;
+                if !self.at(TokenKind::SemiColon) {
+                    rtyp = Some(self.type_()?);
+                }
 
-                let body = self.statement();
+                self.consume(TokenKind::SemiColon)?;
 
-                ast::Decl::Function {
+                ast::Decl::Extern {
+                    attrs: Vec::new(),
                     name,
+                    abi,
                     params,
-                    body,
+                    variadic,
                     rtyp,
+                    file,
+                    pos,
                 }
             }
             TokenKind::Struct => {
-                self.consume(TokenKind::Struct);
+                self.consume(TokenKind::Struct)?;
 
                 let mut members = Vec::new();
-                let name = self.type_();
+                let name = self.type_()?;
 
-                self.consume(TokenKind::OpenBrace);
+                self.consume(TokenKind::OpenBrace)?;
                 while !self.at(TokenKind::ClosedBrace) {
-                    let member_ident = self.next().unwrap_or_else(|| {
-                        panic!(
-                            "{}: tried to parse the struct member but found eof",
-                            self.last
-                        )
-                    });
-
-                    assert_eq!(
-                        member_ident.kind,
-                        TokenKind::Identifier,
-                        "{} expected {:?} but found {:?}",
-                        member_ident.loc,
-                        TokenKind::Identifier,
-                        member_ident.kind
-                    );
+                    let member_ident = self.consume(TokenKind::Identifier)?;
 
                     let member_name = self.text(member_ident).to_string();
-                    self.consume(TokenKind::Colon);
+                    self.consume(TokenKind::Colon)?;
 
-                    let member_type = self.type_();
+                    let member_type = self.type_()?;
                     members.push((member_name, member_type));
 
                     if self.at(TokenKind::Comma) {
-                        self.consume(TokenKind::Comma);
+                        self.consume(TokenKind::Comma)?;
                     }
                 }
 
-                self.consume(TokenKind::ClosedBrace);
-                ast::Decl::Struct { name, members }
+                self.consume(TokenKind::ClosedBrace)?;
+                ast::Decl::Struct {
+                    name,
+                    members,
+                    file,
+                    pos,
+                }
+            }
+            TokenKind::Enum => {
+                self.consume(TokenKind::Enum)?;
+
+                let ident = self.consume(TokenKind::Identifier)?;
+                let name = self.text(ident).to_string();
+
+                let mut variants = Vec::new();
+                self.consume(TokenKind::OpenBrace)?;
+                while !self.at(TokenKind::ClosedBrace) {
+                    let variant_ident = self.consume(TokenKind::Identifier)?;
+                    let variant_name = self.text(variant_ident).to_string();
+
+                    let mut fields = Vec::new();
+                    if self.at(TokenKind::OpenParen) {
+                        self.consume(TokenKind::OpenParen)?;
+                        while !self.at(TokenKind::ClosedParen) {
+                            fields.push(self.type_()?);
+                            if self.at(TokenKind::Comma) {
+                                self.consume(TokenKind::Comma)?;
+                            }
+                        }
+                        self.consume(TokenKind::ClosedParen)?;
+                    }
+
+                    let mut discriminant = None;
+                    if self.at(TokenKind::Assign) {
+                        self.consume(TokenKind::Assign)?;
+                        discriminant = Some(self.expression()?);
+                    }
+
+                    variants.push(ast::Variant {
+                        name: variant_name,
+                        fields,
+                        discriminant,
+                    });
+
+                    if self.at(TokenKind::Comma) {
+                        self.consume(TokenKind::Comma)?;
+                    }
+                }
+                self.consume(TokenKind::ClosedBrace)?;
+
+                ast::Decl::Enum {
+                    name,
+                    variants,
+                    file,
+                    pos,
+                }
+            }
+            TokenKind::Const | TokenKind::Static => {
+                let keyword = self.advance()?.kind;
+
+                let mutable = self.at(TokenKind::Mut);
+                if mutable {
+                    if keyword != TokenKind::Static {
+                        return Err(self.error(String::from(
+                            "constants cannot be mutable, use `static mut` instead",
+                        )));
+                    }
+                    self.consume(TokenKind::Mut)?;
+                }
+
+                let ident = self.consume(TokenKind::Identifier)?;
+
+                let name = self.text(ident).to_string();
+                self.consume(TokenKind::Colon)?;
+                let typ = self.type_()?;
+
+                self.consume(TokenKind::Assign)?;
+                let value = self.expression()?;
+                self.consume(TokenKind::SemiColon)?;
+
+                if keyword == TokenKind::Const {
+                    ast::Decl::Const {
+                        name,
+                        typ,
+                        value,
+                        file,
+                        pos,
+                    }
+                } else {
+                    ast::Decl::Static {
+                        attrs: Vec::new(),
+                        name,
+                        mutable,
+                        typ,
+                        value,
+                        file,
+                        pos,
+                    }
+                }
+            }
+            _ => return Err(self.error(String::from("unknown start of declaration"))),
+        };
+
+        Ok(decl)
+    }
+
+    /// Parses a function. Inside an `impl` block for `self_type` its first
+    /// parameter may be `self`, `&self` or `&mut self`.
+    pub fn function(&mut self, self_type: Option<&ast::Type>) -> Result<ast::Decl, Diagnostic> {
+        let mut inline = None;
+        if self.at(TokenKind::Inline) {
+            self.consume(TokenKind::Inline)?;
+            inline = Some(ast::Inline::Hint);
+
+            if self.at(TokenKind::OpenParen) {
+                self.consume(TokenKind::OpenParen)?;
+                let always = self.advance()?;
+                if always.kind != TokenKind::Identifier || always.text != "always" {
+                    return Err(super::error_at(
+                        &always.loc,
+                        format!("expected `always` but found `{}`", always.text),
+                    ));
+                }
+                self.consume(TokenKind::ClosedParen)?;
+                inline = Some(ast::Inline::Always);
             }
-            _ => panic!("{}: unknown start of declaration", self.last),
         }
+
+        let FunctionHeader {
+            pos,
+            name,
+            type_params,
+            params,
+            rtyp,
+        } = self.function_header(self_type)?;
+
+        if !self.at(TokenKind::OpenBrace) {
+            return Err(self.error(String::from("expected a block after function header")));
+        }
+
+        let body = self.block()?;
+
+        Ok(ast::Decl::Function {
+            attrs: Vec::new(),
+            inline,
+            name,
+            type_params,
+            params,
+            body,
+            rtyp,
+            file: self.last.file.to_string(),
+            pos,
+        })
     }
 
-    pub fn type_(&mut self) -> ast::Type {
-        let ident = self
-            .next()
-            .unwrap_or_else(|| panic!("{}: tried to parse a type but found eof", self.last));
+    /// Parses the attributes in front of a declaration, like `#[no_mangle]`
+    /// or `#[link_name = "write"]`.
+    pub fn attributes(&mut self) -> Result<Vec<ast::Attribute>, Diagnostic> {
+        let mut attrs = Vec::new();
 
-        assert_eq!(
-            ident.kind,
-            TokenKind::Identifier,
-            "{}: expected an {:?} at the start of a type but found {:?}",
-            ident.loc,
-            TokenKind::Identifier,
-            ident.kind
-        );
+        while self.at(TokenKind::Hash) {
+            self.consume(TokenKind::Hash)?;
+            self.consume(TokenKind::OpenBracket)?;
+
+            let ident = self.advance()?;
+            // `inline` is a keyword but also names an attribute
+            if !matches!(ident.kind, TokenKind::Identifier | TokenKind::Inline) {
+                return Err(super::error_at(
+                    &ident.loc,
+                    format!(
+                        "expected {:?} but found {:?}",
+                        TokenKind::Identifier,
+                        ident.kind
+                    ),
+                ));
+            }
+            let name = self.text(ident).to_string();
+
+            let mut args = Vec::new();
+            if self.at(TokenKind::OpenParen) {
+                self.consume(TokenKind::OpenParen)?;
+                while !self.at(TokenKind::ClosedParen) {
+                    let arg = self.advance()?;
+                    if !matches!(arg.kind, TokenKind::Identifier | TokenKind::Integer) {
+                        return Err(super::error_at(
+                            &arg.loc,
+                            format!(
+                                "expected an {:?} or {:?} as attribute argument but found {:?}",
+                                TokenKind::Identifier,
+                                TokenKind::Integer,
+                                arg.kind
+                            ),
+                        ));
+                    }
+                    args.push(self.text(arg).to_string());
+
+                    if !self.at(TokenKind::ClosedParen) {
+                        self.consume(TokenKind::Comma)?;
+                    }
+                }
+                self.consume(TokenKind::ClosedParen)?;
+            }
+
+            let mut value = None;
+            if self.at(TokenKind::Assign) {
+                self.consume(TokenKind::Assign)?;
+                let string = self.consume(TokenKind::String)?;
+                let text = self.text(string);
+                value = Some(text[1..(text.len() - 1)].to_string());
+            }
+            self.consume(TokenKind::ClosedBracket)?;
+
+            attrs.push(ast::Attribute { name, args, value });
+        }
+
+        Ok(attrs)
+    }
+
+    /// Attaches the attributes parsed before `decl` to it.
+    pub(crate) fn attach(
+        &self,
+        decl: &mut ast::Decl,
+        attrs: Vec<ast::Attribute>,
+    ) -> Result<(), Diagnostic> {
+        if attrs.is_empty() {
+            return Ok(());
+        }
+
+        match decl.attrs_mut() {
+            Some(slot) => {
+                *slot = attrs;
+                Ok(())
+            }
+            None => Err(self.error(String::from(
+                "attributes can only be used on functions and statics",
+            ))),
+        }
+    }
+
+    fn function_header(
+        &mut self,
+        self_type: Option<&ast::Type>,
+    ) -> Result<FunctionHeader, Diagnostic> {
+        let pos = self.pos();
+        self.consume(TokenKind::Function)?;
+
+        let ident = self.consume(TokenKind::Identifier)?;
 
         let name = self.text(ident).to_string();
+        let type_params = self.type_params()?;
+        let (params, variadic) = self.parameters(self_type)?;
+        if variadic {
+            return Err(self.error(String::from("only extern functions can be variadic")));
+        }
+
+        let mut rtyp = None;
+        if !self.at(TokenKind::OpenBrace) && !self.at(TokenKind::SemiColon) {
+            rtyp = Some(self.type_()?);
+        }
+
+        Ok(FunctionHeader {
+            pos,
+            name,
+            type_params,
+            params,
+            rtyp,
+        })
+    }
+
+    /// Parses the type parameters of a generic function, like `<A, B>`.
+    /// Each may be bounded by traits, like `<T: Shape + Debug>`.
+    pub fn type_params(&mut self) -> Result<Vec<ast::TypeParam>, Diagnostic> {
+        let mut type_params = Vec::new();
+        if !self.at(TokenKind::LessThan) {
+            return Ok(type_params);
+        }
+
+        self.consume(TokenKind::LessThan)?;
+        while !self.at(TokenKind::GreaterThan) {
+            let ident = self.consume(TokenKind::Identifier)?;
+            let name = self.text(ident).to_string();
+
+            let mut bounds = Vec::new();
+            if self.at(TokenKind::Colon) {
+                self.consume(TokenKind::Colon)?;
+                loop {
+                    bounds.push(self.path()?);
+
+                    if !self.at(TokenKind::Plus) {
+                        break;
+                    }
+                    self.consume(TokenKind::Plus)?;
+                }
+            }
+            type_params.push(ast::TypeParam { name, bounds });
+
+            if self.at(TokenKind::Comma) {
+                self.consume(TokenKind::Comma)?;
+            }
+        }
+        self.consume(TokenKind::GreaterThan)?;
+
+        Ok(type_params)
+    }
+
+    /// Parses a parenthesised parameter list. The returned flag is set if
+    /// the list ends in a C-style `...`.
+    pub fn parameters(
+        &mut self,
+        self_type: Option<&ast::Type>,
+    ) -> Result<(Vec<(String, ast::Type)>, bool), Diagnostic> {
+        let mut params = Vec::new();
+        let mut variadic = false;
+
+        self.consume(TokenKind::OpenParen)?;
+
+        if let Some(self_type) = self_type {
+            if let Some(typ) = self.self_parameter(self_type)? {
+                params.push((String::from("self"), typ));
+                if self.at(TokenKind::Comma) {
+                    self.consume(TokenKind::Comma)?;
+                }
+            }
+        }
+        while !self.at(TokenKind::ClosedParen) {
+            if self.at(TokenKind::Ellipsis) {
+                self.consume(TokenKind::Ellipsis)?;
+                variadic = true;
+                break;
+            }
+
+            let param_ident = self.consume(TokenKind::Identifier)?;
+
+            let param_name = self.text(param_ident).to_string();
+            self.consume(TokenKind::Colon)?;
+
+            let param_type = self.type_()?;
+            params.push((param_name, param_type));
+
+            if self.at(TokenKind::Comma) {
+                self.consume(TokenKind::Comma)?;
+            }
+        }
+        self.consume(TokenKind::ClosedParen)?;
+
+        Ok((params, variadic))
+    }
+
+    /// Parses `self`, `&self` or `&mut self`, returning the type of `self`.
+    fn self_parameter(&mut self, self_type: &ast::Type) -> Result<Option<ast::Type>, Diagnostic> {
+        let reference = self.at(TokenKind::And);
+        if !reference && !self.at_self() {
+            return Ok(None);
+        }
+
+        let mut mutable = false;
+        if reference {
+            self.consume(TokenKind::And)?;
+            mutable = self.at(TokenKind::Mut);
+            if mutable {
+                self.consume(TokenKind::Mut)?;
+            }
+            if !self.at_self() {
+                return Err(self.error(String::from("expected `self` after `&` in parameter list")));
+            }
+        }
+        self.consume(TokenKind::Identifier)?;
+
+        if reference {
+            Ok(Some(ast::Type::new_ref(self_type.clone(), mutable)))
+        } else {
+            Ok(Some(self_type.clone()))
+        }
+    }
+
+    fn at_self(&mut self) -> bool {
+        self.tokens
+            .peek()
+            .is_some_and(|token| token.kind == TokenKind::Identifier && token.text == "self")
+    }
+
+    pub fn type_(&mut self) -> Result<ast::Type, Diagnostic> {
+        if self.at(TokenKind::Asterisk) {
+            self.consume(TokenKind::Asterisk)?;
+            return Ok(ast::Type::new_pointer(self.type_()?));
+        }
+
+        if self.at(TokenKind::Question) {
+            self.consume(TokenKind::Question)?;
+            return Ok(ast::Type::Optional(Box::new(self.type_()?)));
+        }
+
+        if self.at(TokenKind::And) {
+            self.consume(TokenKind::And)?;
+            let mutable = self.at(TokenKind::Mut);
+            if mutable {
+                self.consume(TokenKind::Mut)?;
+            }
+            return Ok(ast::Type::new_ref(self.type_()?, mutable));
+        }
+
+        if self.at(TokenKind::Dyn) {
+            self.consume(TokenKind::Dyn)?;
+            return Ok(ast::Type::Dyn(self.path()?));
+        }
+
+        if self.at(TokenKind::OpenBracket) {
+            self.consume(TokenKind::OpenBracket)?;
+            let typ = Box::new(self.type_()?);
+
+            if self.at(TokenKind::ClosedBracket) {
+                self.consume(TokenKind::ClosedBracket)?;
+                return Ok(ast::Type::Slice(typ));
+            }
+
+            self.consume(TokenKind::SemiColon)?;
+            let len_token = self.consume(TokenKind::Integer)?;
+            let loc = len_token.loc.clone();
+            let len_text = self.text(len_token);
+            let len = len_text.parse().map_err(|_| {
+                super::error_at(&loc, format!("invalid array length: `{}`", len_text))
+            })?;
+            self.consume(TokenKind::ClosedBracket)?;
+
+            return Ok(ast::Type::Array { typ, len });
+        }
+
+        // types of other modules are named by their path
+        let name = self.path()?;
 
         let mut generics = Vec::new();
 
-        if self.at(TokenKind::LessThan) {
-            panic!("generics aren't supported yet");
-            /*self.consume(TokenKind::LessThan);
+        // builtin types take no arguments, so `x as i64 < y` stays a comparison
+        let builtin = ast::Type::new_simple(&name).is_integer() || name == "bool" || name == "void";
+        if self.at(TokenKind::LessThan) && !builtin {
+            self.consume(TokenKind::LessThan)?;
 
-            while !self.at(TokenKind::GreaterThan) {
-                let generic = self.type_();
+            loop {
+                // `>>` closes two argument lists, split off the first `>`
+                if self.at(TokenKind::ShiftRight) {
+                    let token = self.tokens.peek_mut().unwrap();
+                    token.kind = TokenKind::GreaterThan;
+                    token.text = &token.text[1..];
+                    token.loc.col += 1;
+                    break;
+                }
+                if self.at(TokenKind::GreaterThan) {
+                    self.consume(TokenKind::GreaterThan)?;
+                    break;
+                }
+
+                let generic = self.type_()?;
                 generics.push(generic);
 
                 if self.at(TokenKind::Comma) {
-                    self.consume(TokenKind::Comma);
+                    self.consume(TokenKind::Comma)?;
                 }
             }
-            self.consume(TokenKind::GreaterThan); */
         }
 
-        ast::Type { name, generics }
+        Ok(ast::Type::Named { name, generics })
     }
 
-    pub fn statement(&mut self) -> ast::Stmt {
-        match self.peek() {
+    pub fn statement(&mut self) -> Result<ast::Stmt, Diagnostic> {
+        let pos = self.pos();
+        let stmt = match self.peek() {
             TokenKind::Let => {
-                self.consume(TokenKind::Let);
-                let ident = self
-                    .next()
-                    .unwrap_or_else(|| panic!("{}: expected an identifier after `let`", self.last));
-
-                assert_eq!(
-                    ident.kind,
-                    TokenKind::Identifier,
-                    "{}: expected identifier after `let`, but found `{:?}`",
-                    ident.loc,
-                    ident.kind
-                );
+                self.consume(TokenKind::Let)?;
+                let ident = self.consume(TokenKind::Identifier)?;
                 let name = self.text(ident).to_string();
                 let mut typ = None;
 
                 if self.at(TokenKind::Colon) {
-                    self.consume(TokenKind::Colon);
-                    typ = Some(self.type_());
+                    self.consume(TokenKind::Colon)?;
+                    typ = Some(self.type_()?);
                 }
 
-                self.consume(TokenKind::Assign);
-                let value = self.expression();
-                self.consume(TokenKind::SemiColon);
+                self.consume(TokenKind::Assign)?;
+                let value = self.expression()?;
+                self.consume(TokenKind::SemiColon)?;
 
                 ast::Stmt::Let {
                     name,
                     value: Box::new(value),
                     typ,
-                }
-            }
-            TokenKind::Identifier => {
-                let ident = self.next().unwrap();
-                let name = self.text(ident).to_string();
-
-                if self.at(TokenKind::Assign) {
-                    self.consume(TokenKind::Assign);
-                    let value = self.expression();
-                    self.consume(TokenKind::SemiColon);
-                    ast::Stmt::Assign {
-                        name,
-                        op: None,
-                        value: Box::new(value),
-                    }
-                } else {
-                    let mut args = Vec::new();
-
-                    self.consume(TokenKind::OpenParen);
-                    while !self.at(TokenKind::ClosedParen) {
-                        let arg = self.parse_expression(0);
-                        args.push(Box::new(arg));
-                        if self.at(TokenKind::Comma) {
-                            self.consume(TokenKind::Comma);
-                        }
-                    }
-
-                    self.consume(TokenKind::ClosedParen);
-                    self.consume(TokenKind::SemiColon);
-
-                    ast::Stmt::Expr {
-                        value: Box::new(ast::Expr::Call { name, args }),
-                    }
+                    pos,
                 }
             }
             TokenKind::Return => {
-                self.consume(TokenKind::Return);
+                self.consume(TokenKind::Return)?;
                 if self.peek() == TokenKind::SemiColon {
-                    self.consume(TokenKind::SemiColon);
-                    ast::Stmt::Return { value: None }
+                    self.consume(TokenKind::SemiColon)?;
+                    ast::Stmt::Return { value: None, pos }
                 } else {
-                    let expr = self.expression();
-                    self.consume(TokenKind::SemiColon);
+                    let expr = self.expression()?;
+                    self.consume(TokenKind::SemiColon)?;
                     ast::Stmt::Return {
                         value: Some(Box::new(expr)),
+                        pos,
                     }
                 }
             }
+            TokenKind::If | TokenKind::Match | TokenKind::OpenBrace => {
+                // block-like expressions don't need a trailing semicolon
+                let expr = self.block_like()?;
+                if self.at(TokenKind::SemiColon) {
+                    self.consume(TokenKind::SemiColon)?;
+                }
+
+                ast::Stmt::Expr {
+                    value: Box::new(expr),
+                    pos,
+                }
+            }
+            _ => {
+                let expr = self.expression()?;
+                self.expression_statement(pos, expr)?
+            }
+        };
+
+        Ok(stmt)
+    }
+
+    /// Finishes a statement that started with the expression `expr`: either
+    /// an assignment to it or an expression statement.
+    fn expression_statement(
+        &mut self,
+        pos: ast::Pos,
+        expr: ast::Expr,
+    ) -> Result<ast::Stmt, Diagnostic> {
+        let assign = self.peek();
+        if assign == TokenKind::Assign || assign.compound_operator().is_some() {
+            self.consume(assign)?;
+            let value = self.expression()?;
+            self.consume(TokenKind::SemiColon)?;
+            return Ok(ast::Stmt::Assign {
+                target: Box::new(expr),
+                op: assign.compound_operator(),
+                value: Box::new(value),
+                pos,
+            });
+        }
+
+        self.consume(TokenKind::SemiColon)?;
+
+        Ok(ast::Stmt::Expr {
+            value: Box::new(expr),
+            pos,
+        })
+    }
+
+    /// Parses a `{ ... }` block. An expression that isn't followed by a
+    /// semicolon before the closing brace becomes the value of the block.
+    pub fn block(&mut self) -> Result<ast::Expr, Diagnostic> {
+        self.with_struct_literals(true, |parser| parser.block_body())
+    }
+
+    fn block_body(&mut self) -> Result<ast::Expr, Diagnostic> {
+        self.consume(TokenKind::OpenBrace)?;
+
+        let mut body = Vec::new();
+        let mut tail = None;
+
+        while !self.at(TokenKind::ClosedBrace) {
+            let pos = self.pos();
+            let expr = match self.peek() {
+                TokenKind::If | TokenKind::Match | TokenKind::OpenBrace => {
+                    let expr = self.block_like()?;
+                    if !self.at(TokenKind::ClosedBrace) {
+                        if self.at(TokenKind::SemiColon) {
+                            self.consume(TokenKind::SemiColon)?;
+                        }
+                        body.push(ast::Stmt::Expr {
+                            value: Box::new(expr),
+                            pos,
+                        });
+                        continue;
+                    }
+                    expr
+                }
+                TokenKind::Let | TokenKind::Return => {
+                    body.push(self.statement()?);
+                    continue;
+                }
+                _ => {
+                    let expr = self.expression()?;
+                    if !self.at(TokenKind::ClosedBrace) {
+                        body.push(self.expression_statement(pos, expr)?);
+                        continue;
+                    }
+                    expr
+                }
+            };
+
+            tail = Some(Box::new(expr));
+        }
+
+        self.consume(TokenKind::ClosedBrace)?;
+        Ok(ast::Expr::Block { body, tail })
+    }
+
+    /// Parses the expressions that end in a block, `if` and plain blocks.
+    pub fn block_like(&mut self) -> Result<ast::Expr, Diagnostic> {
+        let expr = match self.peek() {
             TokenKind::If => {
-                self.consume(TokenKind::If);
-                let condition = self.expression();
+                self.consume(TokenKind::If)?;
+                let cond = self.with_struct_literals(false, |parser| parser.expression())?;
 
-                assert!(
-                    self.at(TokenKind::OpenBrace),
-                    "Expected a block after `if` statement"
-                );
-                let body = Box::new(self.statement());
+                if !self.at(TokenKind::OpenBrace) {
+                    return Err(self.error(String::from("expected a block after `if` condition")));
+                }
+                let then = self.block()?;
 
                 let elze = if self.at(TokenKind::Else) {
-                    self.consume(TokenKind::Else);
-                    assert!(
-                        self.at(TokenKind::If) || self.at(TokenKind::OpenBrace),
-                        "Expected a block or an `if` after `else` statement"
-                    );
-                    Some(Box::new(self.statement()))
+                    self.consume(TokenKind::Else)?;
+                    if !self.at(TokenKind::If) && !self.at(TokenKind::OpenBrace) {
+                        return Err(
+                            self.error(String::from("expected a block or an `if` after `else`"))
+                        );
+                    }
+                    Some(Box::new(self.block_like()?))
                 } else {
                     None
                 };
 
-                ast::Stmt::If {
-                    expr: Box::new(condition),
-                    body,
+                ast::Expr::If {
+                    cond: Box::new(cond),
+                    then: Box::new(then),
                     elze,
                 }
             }
-            TokenKind::OpenBrace => {
-                self.consume(TokenKind::OpenBrace);
-
-                let mut body = Vec::new();
+            TokenKind::Match => {
+                self.consume(TokenKind::Match)?;
+                let expr = self.with_struct_literals(false, |parser| parser.expression())?;
 
+                let mut arms = Vec::new();
+                self.consume(TokenKind::OpenBrace)?;
                 while !self.at(TokenKind::ClosedBrace) {
-                    let stmt = self.statement();
-                    body.push(stmt);
+                    let pattern = self.pattern()?;
+                    self.consume(TokenKind::FatArrow)?;
+
+                    // a block ends the arm, it doesn't need a comma
+                    let block_like = matches!(
+                        self.peek(),
+                        TokenKind::If | TokenKind::Match | TokenKind::OpenBrace
+                    );
+                    let body = if block_like {
+                        self.block_like()?
+                    } else {
+                        self.expression()?
+                    };
+
+                    if self.at(TokenKind::Comma) {
+                        self.consume(TokenKind::Comma)?;
+                    } else if !block_like && !self.at(TokenKind::ClosedBrace) {
+                        return Err(self.error(String::from("expected `,` after match arm")));
+                    }
+
+                    arms.push(ast::Arm { pattern, body });
                 }
+                self.consume(TokenKind::ClosedBrace)?;
 
-                self.consume(TokenKind::ClosedBrace);
-                ast::Stmt::Block { body }
+                ast::Expr::Match {
+                    expr: Box::new(expr),
+                    arms,
+                }
             }
-            _ => {
-                // Expression statement
-                let expr = self.expression();
-                self.consume(TokenKind::SemiColon);
+            TokenKind::OpenBrace => self.block()?,
+            kind => {
+                return Err(self.error(format!(
+                    "expected `if`, `match` or a block but found {:?}",
+                    kind
+                )))
+            }
+        };
 
-                ast::Stmt::Expr {
-                    value: Box::new(expr),
+        Ok(expr)
+    }
+
+    /// Parses a pattern of a `match` arm, alternatives are separated by `|`.
+    pub fn pattern(&mut self) -> Result<ast::Pattern, Diagnostic> {
+        let first = self.single_pattern()?;
+        if !self.at(TokenKind::Pipe) {
+            return Ok(first);
+        }
+
+        let mut patterns = vec![first];
+        while self.at(TokenKind::Pipe) {
+            self.consume(TokenKind::Pipe)?;
+            patterns.push(self.single_pattern()?);
+        }
+
+        Ok(ast::Pattern::Or(patterns))
+    }
+
+    fn single_pattern(&mut self) -> Result<ast::Pattern, Diagnostic> {
+        let pattern = match self.peek() {
+            TokenKind::Integer | TokenKind::Boolean => {
+                // a literal on its own, `|` separates alternatives here
+                ast::Pattern::Literal(self.parse_expression(101)?)
+            }
+            TokenKind::Minus => {
+                self.consume(TokenKind::Minus)?;
+                if !self.at(TokenKind::Integer) {
+                    return Err(
+                        self.error(String::from("expected an integer after `-` in pattern"))
+                    );
+                }
+                ast::Pattern::Literal(ast::Expr::Prefix {
+                    op: TokenKind::Minus,
+                    expr: Box::new(self.parse_expression(101)?),
+                })
+            }
+            TokenKind::Identifier => {
+                let path = self.path()?;
+                if path == "_" {
+                    return Ok(ast::Pattern::Wildcard);
+                }
+                if !path.contains("::") {
+                    return Ok(ast::Pattern::Binding(path));
                 }
+
+                let mut fields = Vec::new();
+                if self.at(TokenKind::OpenParen) {
+                    self.consume(TokenKind::OpenParen)?;
+                    while !self.at(TokenKind::ClosedParen) {
+                        let field = self.consume(TokenKind::Identifier)?;
+                        fields.push(self.text(field).to_string());
+
+                        if self.at(TokenKind::Comma) {
+                            self.consume(TokenKind::Comma)?;
+                        }
+                    }
+                    self.consume(TokenKind::ClosedParen)?;
+                }
+
+                ast::Pattern::Variant { path, fields }
+            }
+            kind => return Err(self.error(format!("expected a pattern but found {:?}", kind))),
+        };
+
+        Ok(pattern)
+    }
+
+    /// Parses a name that may be qualified with `::`, like `Color::Red`.
+    pub fn path(&mut self) -> Result<String, Diagnostic> {
+        let mut path = String::new();
+
+        loop {
+            let ident = self.consume(TokenKind::Identifier)?;
+            path.push_str(self.text(ident));
+
+            if !self.at(TokenKind::PathSep) {
+                return Ok(path);
             }
+            self.consume(TokenKind::PathSep)?;
+            path.push_str("::");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `input` as a type and prints it.
+    fn typ(input: &str) -> String {
+        Parser::new("test.ly", input).type_().unwrap().to_string()
+    }
+
+    #[test]
+    fn generic_types() {
+        assert_eq!(typ("Pair<i64, *Node<T>>"), "Pair<i64, *Node<T>>");
+        assert_eq!(typ("[Pair<u8, u8>; 4]"), "[Pair<u8, u8>; 4]");
+        assert_eq!(typ("&mut Node<Node<T>>"), "&mut Node<Node<T>>");
+    }
+
+    #[test]
+    fn attributes() {
+        let input = "#[inline(always)]\n#[section = \".hot\"] #[frobnicate(a, b)]\nfn f() {}";
+        let mut items = Parser::new("test.ly", input).parse().unwrap();
+        let attrs: Vec<_> = items[0]
+            .decl
+            .attrs_mut()
+            .unwrap()
+            .iter()
+            .map(|attr| attr.to_string())
+            .collect();
+        assert_eq!(
+            attrs,
+            [
+                "#[inline(always)]",
+                "#[section = \".hot\"]",
+                "#[frobnicate(a, b)]"
+            ]
+        );
+
+        let error = Parser::new("test.ly", "#[section = \".x\"]\nstruct S { a: i64 }")
+            .parse()
+            .unwrap_err();
+        assert_eq!(
+            error.message,
+            "attributes can only be used on functions and statics"
+        );
+    }
+
+    #[test]
+    fn inline_functions() {
+        let input = "inline fn f() {} inline(always) fn g() {} fn h() {}";
+        let items = Parser::new("test.ly", input).parse().unwrap();
+        let inline: Vec<_> = items
+            .iter()
+            .map(|item| match &item.decl {
+                ast::Decl::Function { inline, .. } => *inline,
+                decl => panic!("`{:?}` is not a function", decl),
+            })
+            .collect();
+        assert_eq!(
+            inline,
+            [Some(ast::Inline::Hint), Some(ast::Inline::Always), None]
+        );
+
+        let error = Parser::new("test.ly", "inline struct S { a: i64 }")
+            .parse()
+            .unwrap_err();
+        assert_eq!(error.message, "expected Function but found Struct");
+    }
+}
@@ -3,6 +3,7 @@ pub mod ast;
 mod expressions;
 mod hierarchy;
 
+use crate::diagnostic::Diagnostic;
 use crate::lexer::token::*;
 use crate::lexer::{Lexer, Location};
 use std::iter::Peekable;
@@ -10,6 +11,9 @@ use std::iter::Peekable;
 pub struct Parser<'a> {
     tokens: Peekable<Lexer<'a>>,
     last: Location<'a>,
+    /// Cleared in `if` conditions and `match` scrutinees, where a `{`
+    /// after a name starts the block and not a struct literal.
+    struct_literals: bool,
 }
 
 impl<'a> Parser<'a> {
@@ -21,6 +25,7 @@ impl<'a> Parser<'a> {
                 line: 1,
                 col: 1,
             },
+            struct_literals: true,
         }
     }
 
@@ -37,6 +42,15 @@ impl<'a> Parser<'a> {
             .unwrap_or(TokenKind::Eof)
     }
 
+    /// The position of the next token, where whatever is parsed next starts.
+    pub(crate) fn pos(&mut self) -> ast::Pos {
+        let loc = self.tokens.peek().map_or(&self.last, |token| &token.loc);
+        ast::Pos {
+            line: loc.line,
+            col: loc.col,
+        }
+    }
+
     #[inline]
     pub(crate) fn at(&mut self, kind: TokenKind) -> bool {
         self.peek() == kind
@@ -47,16 +61,52 @@ impl<'a> Parser<'a> {
         self.tokens.next()
     }
 
-    pub(crate) fn consume(&mut self, expected: TokenKind) {
+    /// Runs `parse` with struct literals allowed or not, restoring the
+    /// previous setting afterwards.
+    pub(crate) fn with_struct_literals<T>(
+        &mut self,
+        allowed: bool,
+        parse: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let outer = std::mem::replace(&mut self.struct_literals, allowed);
+        let result = parse(self);
+        self.struct_literals = outer;
+        result
+    }
+
+    /// Consumes the next token, whatever it is.
+    pub(crate) fn advance(&mut self) -> Result<Token<'a>, Diagnostic> {
         let token = self
             .next()
-            .expect(&format!("{}: unexpected end of file", self.last));
-        assert_eq!(
-            token.kind, expected,
-            "{}: expected {:?} but got {:?}",
-            token.loc, expected, token.kind
-        );
-        self.last = token.loc;
+            .ok_or_else(|| self.error(String::from("unexpected end of file")))?;
+        self.last = token.loc.clone();
         self.last.col += token.text.len();
+        Ok(token)
+    }
+
+    /// Consumes the next token, which has to be of kind `expected`.
+    pub(crate) fn consume(&mut self, expected: TokenKind) -> Result<Token<'a>, Diagnostic> {
+        let token = self.advance()?;
+        if token.kind != expected {
+            return Err(error_at(
+                &token.loc,
+                format!("expected {:?} but found {:?}", expected, token.kind),
+            ));
+        }
+        Ok(token)
+    }
+
+    /// An error right after the last token consumed.
+    pub(crate) fn error(&self, message: String) -> Diagnostic {
+        error_at(&self.last, message)
+    }
+}
+
+fn error_at(loc: &Location, message: String) -> Diagnostic {
+    Diagnostic {
+        file: loc.file.to_string(),
+        line: loc.line,
+        col: loc.col,
+        message,
     }
 }
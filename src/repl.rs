@@ -0,0 +1,300 @@
+//! `lyth repl` reads declarations and statements and runs them with the
+//! JIT. Declarations stay defined for the entries after them and the
+//! value and type of an expression are printed, unless it ends with a
+//! semicolon. An entry spans several lines while its braces are
+//! unbalanced.
+//!
+//! Every entry is compiled into a module of its own and added to the JIT
+//! of the session. The declarations entered before it are only declared
+//! in the module, and its `let` bindings are globals, so they are
+//! evaluated once and later entries read and write the same variables.
+//! References to temporaries of an entry don't outlive it. Errors drop
+//! the entry, but a failing bounds check traps and ends the session like
+//! it ends a program.
+//!
+//! Only values that fit in an `i128`, integers, `bool` and pointers, are
+//! printed. For structs, enums, arrays and slices only the type is, their
+//! fields have to be entered one by one. `pub` is accepted but changes
+//! nothing, and modules cannot be imported.
+
+use crate::compiler::{Compiler, Jit};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::token::TokenKind;
+use crate::parser::{ast, Parser};
+use crate::semantics::Binding;
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+
+const FILE: &str = "repl";
+const ENTRY: &str = "lyth_repl_entry";
+
+extern "C" {
+    fn fflush(stream: *mut std::ffi::c_void) -> i32;
+}
+
+/// What was entered so far. `defined` are the symbols the modules in the
+/// JIT define.
+#[derive(Default)]
+struct Session {
+    jit: Option<Jit>,
+    decls: Vec<ast::Decl>,
+    bindings: Vec<Binding>,
+    defined: HashSet<String>,
+    entries: usize,
+}
+
+/// Runs a session reading the entries from `input`, writing the prompts
+/// and values to `output` and the errors to `errors`.
+pub fn run(input: impl BufRead, output: &mut impl Write, errors: &mut impl Write) {
+    let mut session = Session::default();
+    let mut lines = input.lines();
+    while let Some(input) = read_entry(&mut lines, output) {
+        if input.trim().is_empty() {
+            continue;
+        }
+        // a failed entry leaves the session as it was
+        match session.entry(&input) {
+            Ok(Some(value)) => writeln!(output, "{}", value).unwrap(),
+            Ok(None) => {}
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    writeln!(errors, "error: {}", diagnostic).unwrap();
+                }
+            }
+        }
+    }
+    writeln!(output).unwrap();
+}
+
+/// Reads lines until the braces in them are balanced, `None` at the end
+/// of the input.
+fn read_entry(
+    lines: &mut impl Iterator<Item = std::io::Result<String>>,
+    output: &mut impl Write,
+) -> Option<String> {
+    let mut input = String::new();
+    loop {
+        write!(output, "{}", if input.is_empty() { "> " } else { ". " }).unwrap();
+        output.flush().unwrap();
+
+        match lines.next() {
+            Some(Ok(line)) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            _ => return (!input.is_empty()).then_some(input),
+        }
+        if open_braces(&input) <= 0 {
+            return Some(input);
+        }
+    }
+}
+
+/// The number of braces in `input` that are still open, ignoring those in
+/// string literals.
+fn open_braces(input: &str) -> i32 {
+    let mut open = 0;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => open += 1,
+            '}' => open -= 1,
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    open
+}
+
+impl Session {
+    /// Runs an entry and returns the value to print, if any.
+    fn entry(&mut self, input: &str) -> Result<Option<String>, Vec<Diagnostic>> {
+        let starts_decl = matches!(
+            Parser::new(FILE, input).peek(),
+            TokenKind::Function
+                | TokenKind::Inline
+                | TokenKind::Struct
+                | TokenKind::Enum
+                | TokenKind::Impl
+                | TokenKind::Trait
+                | TokenKind::Extern
+                | TokenKind::Const
+                | TokenKind::Static
+                | TokenKind::Import
+                | TokenKind::Mod
+                | TokenKind::Pub
+                | TokenKind::Hash
+        );
+        if starts_decl {
+            self.declare(input)?;
+            return Ok(None);
+        }
+        // the last expression needs no semicolon, it is printed without
+        let input = input.trim_end();
+        if input.ends_with(';') {
+            self.execute(input, false)
+        } else {
+            self.execute(&format!("{};", input), true)
+        }
+    }
+
+    fn declare(&mut self, input: &str) -> Result<(), Vec<Diagnostic>> {
+        let mut parser = Parser::new(FILE, input);
+        let mut decls = Vec::new();
+        while !parser.at(TokenKind::Eof) {
+            let attrs = parser.attributes().map_err(|error| vec![error])?;
+            // everything is visible in a session
+            if parser.at(TokenKind::Pub) {
+                parser
+                    .consume(TokenKind::Pub)
+                    .map_err(|error| vec![error])?;
+            }
+            let mut decl = parser.decl().map_err(|error| vec![error])?;
+            if let ast::Decl::Import { file, pos, .. } = &decl {
+                let message = String::from("modules cannot be imported in the REPL");
+                return Err(vec![Diagnostic::new(file, *pos, message)]);
+            }
+            parser
+                .attach(&mut decl, attrs)
+                .map_err(|error| vec![error])?;
+            decls.push(decl);
+        }
+
+        let mut compiler = self.compiler();
+        compiler.build(self.decls.iter().chain(&decls).cloned().collect())?;
+        self.load(compiler);
+
+        // declared globals shadow the bindings of the same name
+        self.bindings.retain(|binding| {
+            !decls.iter().any(|decl| match decl {
+                ast::Decl::Const { name, .. } | ast::Decl::Static { name, .. } => {
+                    *name == binding.name
+                }
+                _ => false,
+            })
+        });
+        self.decls.extend(decls);
+        Ok(())
+    }
+
+    fn execute(&mut self, input: &str, print: bool) -> Result<Option<String>, Vec<Diagnostic>> {
+        let mut parser = Parser::new(FILE, input);
+        let mut stmts = Vec::new();
+        while !parser.at(TokenKind::Eof) {
+            stmts.push(parser.statement().map_err(|error| vec![error])?);
+        }
+
+        // every module defines its own entry function
+        let name = format!("{}.{}", ENTRY, self.entries);
+        let mut compiler = self.compiler();
+        let entry = compiler.build_entry(self.decls.clone(), &self.bindings, &name, &stmts)?;
+        self.load(compiler);
+        self.entries += 1;
+        self.bindings.extend(entry.bindings);
+
+        let jit = self.jit.as_ref().unwrap();
+        let value = unsafe { jit.call::<extern "C" fn() -> i128>(&name) }();
+        // C output of the entry comes before its value
+        unsafe { fflush(std::ptr::null_mut()) };
+
+        Ok(match entry.typ {
+            Some(typ) if print => format_value(value, &typ),
+            _ => None,
+        })
+    }
+
+    /// A compiler that only declares what the JIT defines already.
+    fn compiler(&self) -> Compiler<'static> {
+        let mut compiler = Compiler::new(FILE);
+        compiler.set_defined(self.defined.clone());
+        compiler
+    }
+
+    /// Adds the module built by `compiler` to the JIT.
+    fn load(&mut self, compiler: Compiler) {
+        self.defined.extend(compiler.defined_symbols());
+        let module = compiler.into_module();
+        match &mut self.jit {
+            Some(jit) => jit.add(module),
+            None => self.jit = Some(Jit::new(module)),
+        }
+    }
+}
+
+/// The value returned by an entry as it is printed, only the type for
+/// values that aren't returned.
+fn format_value(value: i128, typ: &ast::Type) -> Option<String> {
+    Some(match typ {
+        typ if typ.is_named("void") => return None,
+        typ if typ.is_named("bool") => format!("{}: {}", value != 0, typ),
+        typ if typ.is_signed() => format!("{}: {}", value, typ),
+        typ if typ.is_integer() => format!("{}: {}", value as u128, typ),
+        ast::Type::Pointer(pointee) | ast::Type::Ref { typ: pointee, .. }
+            if !matches!(**pointee, ast::Type::Slice(_) | ast::Type::Dyn(_)) =>
+        {
+            format!("{:#x}: {}", value, typ)
+        }
+        typ => typ.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(input: &str) -> (String, String) {
+        let mut output = Vec::new();
+        let mut errors = Vec::new();
+        run(input.as_bytes(), &mut output, &mut errors);
+        (
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(errors).unwrap(),
+        )
+    }
+
+    #[test]
+    fn scripted_session() {
+        let (output, errors) = session(
+            "let x = 5;
+x + 1
+pub fn twice(n: i64) i64 {
+    return n * 2;
+}
+twice(x)
+if x > 3 {
+    x = x + 1;
+}
+x == 6
+y
+import geo;
+twice(x) as u8
+",
+        );
+        assert_eq!(
+            output,
+            "> > 6: i64\n> . . > 10: i64\n> . . > true: bool\n> > > 12: u8\n> \n"
+        );
+        assert_eq!(
+            errors,
+            "error: repl:1:1: unknown variable `y`\n\
+             error: repl:1:1: modules cannot be imported in the REPL\n"
+        );
+    }
+
+    #[test]
+    fn braces_in_strings() {
+        assert_eq!(open_braces("fn f() {\n"), 1);
+        assert_eq!(open_braces("let s = \"{ \\\" }\";\n"), 0);
+        assert_eq!(open_braces("if x {\n} else {\n}\n"), 0);
+    }
+}
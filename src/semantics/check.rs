@@ -0,0 +1,3056 @@
+//! Checks a program against the rules of the language and lowers it to
+//! the typed program in `typed`. Declarations are processed like a
+//! backend would: types first, then functions and globals in order, then
+//! the bodies of the functions and the instances of generic functions
+//! they call. Errors are reported at the file and position of the
+//! declaration or statement they are in.
+
+use super::typed::{self, Arm, Capture, Expr, ExprKind, Local, Place, PlaceKind, Program, Stmt};
+use super::{
+    binds_names, fold_integer, infer_type_args, is_dyn, is_integer_literal, is_method, is_null,
+    is_place_expr, is_untyped_literal, mentions_params, substitute, type_param_names, wrap,
+    yields_untyped_literal, Traits, ARITH_PANIC_HANDLER, BOUNDS_PANIC_HANDLER,
+};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::token::TokenKind;
+use crate::mangle::{self, names_symbol};
+use crate::parser::ast;
+use std::collections::{HashMap, HashSet};
+
+/// A generic function, checked once for every combination of type
+/// arguments it is called with.
+#[derive(Clone)]
+struct Generic {
+    attrs: Vec<ast::Attribute>,
+    inline: Option<ast::Inline>,
+    type_params: Vec<ast::TypeParam>,
+    params: Vec<(String, ast::Type)>,
+    body: ast::Expr,
+    rtyp: Option<ast::Type>,
+    file: String,
+    pos: ast::Pos,
+}
+
+/// What attributes are attached to, as not all of them apply everywhere.
+#[derive(Clone, Copy, PartialEq)]
+enum AttributeTarget {
+    Function,
+    Extern,
+    Static,
+}
+
+/// An instance of a generic function that is declared but whose body
+/// still has to be checked.
+struct Instance {
+    function: usize,
+    name: String,
+    type_args: HashMap<String, ast::Type>,
+}
+
+/// A `let` at the top of a REPL entry. Its value is kept in a static, so
+/// the entries after it read and write the same variable.
+#[derive(Clone)]
+pub struct Binding {
+    pub name: String,
+    pub typ: ast::Type,
+    symbol: String,
+}
+
+/// What `Checker::check_entry` checked: the type of the value the entry
+/// returns, if it ends with an expression, and the bindings it adds.
+pub struct Entry {
+    pub typ: Option<ast::Type>,
+    pub bindings: Vec<Binding>,
+}
+
+/// Type checks a program, see the module documentation.
+pub struct Checker {
+    /// The file and position of the declaration or statement being
+    /// checked, where errors are reported.
+    file: String,
+    pos: ast::Pos,
+    functions: Vec<typed::Function>,
+    function_index: HashMap<String, usize>,
+    symbols: HashSet<String>,
+    generics: HashMap<String, Generic>,
+    methods: HashSet<String>,
+    instances: Vec<Instance>,
+    type_args: HashMap<String, ast::Type>,
+    structs: HashMap<String, typed::Struct>,
+    enums: HashMap<String, typed::Enum>,
+    traits: Traits,
+    vtables: HashMap<String, typed::Vtable>,
+    constants: HashMap<String, Expr>,
+    statics: Vec<typed::Static>,
+    static_index: HashMap<String, usize>,
+    static_symbols: HashSet<String>,
+    /// The return type, variables and names in scope of the function being
+    /// checked, and whether the code being checked can be reached.
+    function_type: Option<ast::Type>,
+    locals: Vec<Local>,
+    scope: HashMap<String, usize>,
+    terminated: bool,
+    /// The errors in the function bodies checked so far.
+    errors: Vec<Diagnostic>,
+}
+
+impl Checker {
+    /// Creates a checker for the program whose root is `file`.
+    pub fn new(file: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            pos: ast::Pos::default(),
+            functions: Vec::new(),
+            function_index: HashMap::new(),
+            symbols: HashSet::new(),
+            generics: HashMap::new(),
+            methods: HashSet::new(),
+            instances: Vec::new(),
+            type_args: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            traits: Traits::default(),
+            vtables: HashMap::new(),
+            constants: HashMap::new(),
+            statics: Vec::new(),
+            static_index: HashMap::new(),
+            static_symbols: HashSet::new(),
+            function_type: None,
+            locals: Vec::new(),
+            scope: HashMap::new(),
+            terminated: false,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Checks the declarations of a loaded program. Errors in function
+    /// bodies don't stop the checker, all of them are returned.
+    pub fn check(mut self, decls: Vec<ast::Decl>) -> Result<Program, Vec<Diagnostic>> {
+        self.declare(decls).map_err(|error| vec![error])?;
+        self.check_instances();
+        self.into_program()
+    }
+
+    /// Checks `decls` like `check` and a function `name` without
+    /// parameters running `stmts`, for the REPL. If the last statement is
+    /// an expression the function returns its value as an `i128`, provided
+    /// it is an integer, `bool` or pointer, and its type is returned.
+    /// The `let` statements at the top of `stmts` bind statics instead of
+    /// locals, which are returned to be passed as `bindings` to the entries
+    /// after it.
+    pub fn check_entry(
+        mut self,
+        decls: Vec<ast::Decl>,
+        bindings: &[Binding],
+        name: &str,
+        stmts: &[ast::Stmt],
+    ) -> Result<(Program, Entry), Vec<Diagnostic>> {
+        self.declare(decls).map_err(|error| vec![error])?;
+        for binding in bindings {
+            self.declare_binding(binding);
+        }
+
+        let rtyp = ast::Type::new_simple("i128");
+        let function = self
+            .declare_function(
+                name,
+                name.to_string(),
+                Vec::new(),
+                false,
+                Some(rtyp.clone()),
+                self.file.clone(),
+                ast::Pos::default(),
+            )
+            .map_err(|error| vec![error])?;
+        self.enter_function(Some(rtyp.clone()));
+        let (body, entry) = self
+            .check_entry_body(name, stmts, &rtyp)
+            .map_err(|error| vec![error])?;
+        self.leave_function(function, body);
+        self.check_instances();
+
+        Ok((self.into_program()?, entry))
+    }
+
+    /// Checks the statements of the REPL entry `name`, see `check_entry`,
+    /// and returns its body.
+    fn check_entry_body(
+        &mut self,
+        name: &str,
+        stmts: &[ast::Stmt],
+        rtyp: &ast::Type,
+    ) -> Result<(Expr, Entry), Diagnostic> {
+        let (tail, body) = match stmts.split_last() {
+            Some((ast::Stmt::Expr { value, pos }, body)) => (Some((value, *pos)), body),
+            _ => (None, stmts),
+        };
+        let mut checked = Vec::new();
+        let mut new_bindings = Vec::new();
+        for (idx, stmt) in body.iter().enumerate() {
+            if self.terminated {
+                break;
+            }
+            self.pos = stmt.pos();
+            match stmt {
+                ast::Stmt::Let {
+                    name: variable,
+                    typ,
+                    value,
+                    pos,
+                } => {
+                    let value = self.check_let_value(variable, typ, value)?;
+                    if self.terminated {
+                        checked.push(Stmt::Expr { value, pos: *pos });
+                        continue;
+                    }
+
+                    let binding = Binding {
+                        name: variable.clone(),
+                        typ: value.typ.clone(),
+                        symbol: format!("{}.{}.{}", name, variable, idx),
+                    };
+                    let place = self.declare_binding(&binding);
+                    checked.push(Stmt::Assign {
+                        place,
+                        op: None,
+                        value,
+                        pos: *pos,
+                    });
+                    new_bindings.push(binding);
+                }
+                ast::Stmt::Return { .. } => {
+                    return Err(self.error(String::from("`return` can only be used in functions")))
+                }
+                stmt => checked.push(self.check_stmt(stmt)?),
+            }
+        }
+
+        let value = match tail {
+            Some((tail, pos)) => {
+                self.pos = pos;
+                Some((self.check_expr(tail)?, pos))
+            }
+            None => None,
+        };
+        let typ = value.as_ref().map(|(value, _)| value.typ.clone());
+        let result = match value {
+            Some((value, _)) if value.typ.is_integer() || value.typ.is_named("bool") => {
+                self.check_cast(value, rtyp)?
+            }
+            // references to slices and trait objects aren't plain pointers
+            Some((value, _)) if value.typ.pointee().is_some_and(|pointee| !is_dyn(pointee)) => {
+                self.check_cast(value, rtyp)?
+            }
+            Some((value, pos)) => {
+                checked.push(Stmt::Expr { value, pos });
+                Expr::new(ExprKind::Int(0), rtyp.clone())
+            }
+            None => Expr::new(ExprKind::Int(0), rtyp.clone()),
+        };
+
+        let body = Expr::new(
+            ExprKind::Block {
+                stmts: checked,
+                tail: Some(Box::new(result)),
+            },
+            rtyp.clone(),
+        );
+        let entry = Entry {
+            typ,
+            bindings: new_bindings,
+        };
+        Ok((body, entry))
+    }
+
+    /// Declares the static of a REPL binding, which shadows the globals of
+    /// the same name. It has no initializer, it is stored to by the entry
+    /// binding it or defined by an entry before.
+    fn declare_binding(&mut self, binding: &Binding) -> Place {
+        self.declare_static(
+            &binding.name,
+            binding.symbol.clone(),
+            Vec::new(),
+            true,
+            binding.typ.clone(),
+            None,
+        )
+    }
+
+    /// The checked program, or the errors found in function bodies.
+    fn into_program(self) -> Result<Program, Vec<Diagnostic>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        Ok(Program {
+            functions: self.functions,
+            statics: self.statics,
+            structs: self.structs,
+            enums: self.enums,
+            traits: self.traits,
+            vtables: self.vtables,
+        })
+    }
+
+    /// Checks the declarations and the bodies of the functions, but not
+    /// the instances of generic functions yet.
+    fn declare(&mut self, decls: Vec<ast::Decl>) -> Result<(), Diagnostic> {
+        let ast = self.traits.lower_impls(decls)?;
+
+        // types first, functions and globals may use them in any order
+        for decl in ast.iter() {
+            if let ast::Decl::Struct { name, members, .. } = decl {
+                self.locate(decl);
+                self.declare_struct(name, members)?;
+            }
+        }
+        for decl in ast.iter() {
+            if let ast::Decl::Enum { name, variants, .. } = decl {
+                self.locate(decl);
+                self.declare_enum(name, variants)?;
+            }
+        }
+        // payloads may refer to enums declared after them
+        for decl in ast.iter() {
+            if let ast::Decl::Enum { variants, .. } = decl {
+                self.locate(decl);
+                for typ in variants.iter().flat_map(|variant| &variant.fields) {
+                    self.check_type(typ)?;
+                }
+            }
+        }
+
+        for decl in ast.iter() {
+            self.locate(decl);
+            match decl {
+                ast::Decl::Function {
+                    attrs,
+                    inline,
+                    name,
+                    type_params,
+                    params,
+                    body,
+                    rtyp,
+                    file,
+                    pos,
+                } if !type_params.is_empty() => {
+                    if self.function_index.contains_key(name) || self.generics.contains_key(name) {
+                        return Err(
+                            self.error(format!("function `{}` is declared more than once", name))
+                        );
+                    }
+                    if attrs.iter().any(names_symbol) {
+                        return Err(self.error(format!("generic function `{}` cannot name its symbol, its instances are always mangled", name)));
+                    }
+                    for bound in type_params.iter().flat_map(|param| &param.bounds) {
+                        if !self.traits.declared.contains_key(bound) {
+                            return Err(self.error(format!("unknown trait `{}`", bound)));
+                        }
+                    }
+                    if is_method(params) {
+                        self.methods.insert(name.clone());
+                    }
+                    self.generics.insert(
+                        name.clone(),
+                        Generic {
+                            attrs: attrs.clone(),
+                            inline: *inline,
+                            type_params: type_params.clone(),
+                            params: params.clone(),
+                            body: body.clone(),
+                            rtyp: rtyp.clone(),
+                            file: file.clone(),
+                            pos: *pos,
+                        },
+                    );
+                }
+                ast::Decl::Function {
+                    attrs,
+                    inline,
+                    name,
+                    params,
+                    rtyp,
+                    file,
+                    pos,
+                    ..
+                } => {
+                    if self.generics.contains_key(name) {
+                        return Err(
+                            self.error(format!("function `{}` is declared more than once", name))
+                        );
+                    }
+                    if is_method(params) {
+                        self.methods.insert(name.clone());
+                    }
+
+                    // the entry point is looked up by its name
+                    let symbol = match name.as_str() {
+                        "main" if !attrs.iter().any(names_symbol) => name.clone(),
+                        _ => self.here(mangle::symbol(name, attrs))?,
+                    };
+                    if attrs.iter().any(|attr| attr.name == "export") {
+                        for typ in params.iter().map(|(_, typ)| typ).chain(rtyp) {
+                            self.expect_c_type(name, typ)?;
+                        }
+                    }
+
+                    let params = params.iter().map(|(_, typ)| typ.clone()).collect();
+                    let function = self.declare_function(
+                        name,
+                        symbol,
+                        params,
+                        false,
+                        rtyp.clone(),
+                        file.clone(),
+                        *pos,
+                    )?;
+                    self.functions[function].attrs =
+                        self.check_attributes(name, attrs, *inline, AttributeTarget::Function)?;
+                }
+                ast::Decl::Extern {
+                    attrs,
+                    name,
+                    abi,
+                    params,
+                    variadic,
+                    rtyp,
+                    file,
+                    pos,
+                } => {
+                    if let Some(abi) = abi {
+                        if abi != "C" {
+                            return Err(self.error(format!(
+                                "unsupported ABI `{}` for extern function `{}`",
+                                abi, name
+                            )));
+                        }
+                    }
+
+                    // C functions are linked against by their own name
+                    let symbol = match attrs.iter().find(|attr| names_symbol(attr)) {
+                        Some(attr) if attr.name != "link_name" => {
+                            return Err(self.error(format!(
+                            "extern function `{}` can only be renamed by `#[link_name = \"...\"]`",
+                            name
+                        )))
+                        }
+                        Some(_) => self.here(mangle::symbol(name, attrs))?,
+                        None => name.clone(),
+                    };
+                    let params: Vec<_> = params.iter().map(|(_, typ)| typ.clone()).collect();
+
+                    // every module may declare the C functions it uses
+                    if let Some(&function) = self.function_index.get(name) {
+                        let function = &self.functions[function];
+                        if function.params != params
+                            || function.variadic != *variadic
+                            || &function.rtyp != rtyp
+                        {
+                            return Err(self.error(format!(
+                                "extern function `{}` is declared with different signatures",
+                                name
+                            )));
+                        }
+                        continue;
+                    }
+                    let function = self.declare_function(
+                        name,
+                        symbol,
+                        params,
+                        *variadic,
+                        rtyp.clone(),
+                        file.clone(),
+                        *pos,
+                    )?;
+                    self.functions[function].attrs =
+                        self.check_attributes(name, attrs, None, AttributeTarget::Extern)?;
+                }
+                ast::Decl::Const {
+                    name, typ, value, ..
+                } => {
+                    self.expect_unique_global(name)?;
+                    self.check_type(typ)?;
+                    let value = self.check_constant(name, value, typ)?;
+                    self.constants.insert(name.clone(), value);
+                }
+                ast::Decl::Static {
+                    attrs,
+                    name,
+                    mutable,
+                    typ,
+                    value,
+                    ..
+                } => {
+                    self.expect_unique_global(name)?;
+                    self.check_type(typ)?;
+                    let value = self.check_constant(name, value, typ)?;
+
+                    let symbol = self.here(mangle::symbol(name, attrs))?;
+                    if attrs.iter().any(|attr| attr.name == "export") {
+                        self.expect_c_type(name, typ)?;
+                    }
+                    if !self.static_symbols.insert(symbol.clone()) {
+                        return Err(self.error(format!(
+                            "symbol `{}` of `{}` is defined more than once",
+                            symbol, name
+                        )));
+                    }
+
+                    let attrs =
+                        self.check_attributes(name, attrs, None, AttributeTarget::Static)?;
+                    self.declare_static(name, symbol, attrs, *mutable, typ.clone(), Some(value));
+                }
+                _ => {}
+            }
+        }
+
+        let i64_type = ast::Type::new_simple("i64");
+        self.check_panic_handler(
+            BOUNDS_PANIC_HANDLER,
+            &[i64_type.clone(), i64_type],
+            "an `i64` index and an `i64` length",
+        )?;
+        self.check_panic_handler(ARITH_PANIC_HANDLER, &[], "no arguments")?;
+
+        for decl in ast.iter() {
+            self.locate(decl);
+            match decl {
+                ast::Decl::Function {
+                    name,
+                    type_params,
+                    params,
+                    body,
+                    rtyp,
+                    ..
+                } => {
+                    // generic functions are checked when they are called
+                    if type_params.is_empty() {
+                        let function = self.function_index[name];
+                        self.check_function(function, rtyp.clone(), params, body);
+                    }
+                }
+                ast::Decl::Extern { .. }
+                | ast::Decl::Const { .. }
+                | ast::Decl::Static { .. }
+                | ast::Decl::Enum { .. }
+                | ast::Decl::Struct { .. } => {}
+                ast::Decl::Impl { .. } | ast::Decl::Trait { .. } => {
+                    unreachable!("impl blocks and traits are lowered")
+                }
+                ast::Decl::Import { .. } => unreachable!("imports are resolved by the loader"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the instances of generic functions requested so far.
+    fn check_instances(&mut self) {
+        // instantiating a generic function may request further instances
+        while let Some(instance) = self.instances.pop() {
+            let generic = self.generics[&instance.name].clone();
+            self.type_args = instance.type_args;
+            self.file = generic.file.clone();
+            self.pos = generic.pos;
+            self.check_function(
+                instance.function,
+                generic.rtyp.clone(),
+                &generic.params,
+                &generic.body,
+            );
+        }
+        self.type_args.clear();
+    }
+
+    /// Checks the body of the declared function `function`. An error in
+    /// it is kept and the checker goes on with the next function.
+    fn check_function(
+        &mut self,
+        function: usize,
+        rtyp: Option<ast::Type>,
+        params: &[(String, ast::Type)],
+        body: &ast::Expr,
+    ) {
+        self.enter_function(rtyp);
+        match self.check_body(params, body) {
+            Ok(body) => self.leave_function(function, body),
+            Err(error) => self.errors.push(error),
+        }
+    }
+
+    /// The programs's panic handlers are called by the checks the backends
+    /// emit, so they must take what those pass.
+    fn check_panic_handler(
+        &mut self,
+        name: &str,
+        params: &[ast::Type],
+        takes: &str,
+    ) -> Result<(), Diagnostic> {
+        if let Some(&function) = self.function_index.get(name) {
+            self.file = self.functions[function].file.clone();
+            self.pos = self.functions[function].pos;
+            let function = &self.functions[function];
+            if function.params != params || function.rtyp.is_some() {
+                return Err(self.error(format!("`{}` must take {}", name, takes)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the attributes of the function or static `name`, together
+    /// with the `inline` keyword of functions, and returns those that
+    /// change how it is emitted. Attributes that aren't known at all are
+    /// reported and ignored.
+    fn check_attributes(
+        &self,
+        name: &str,
+        attrs: &[ast::Attribute],
+        inline: Option<ast::Inline>,
+        target: AttributeTarget,
+    ) -> Result<Vec<typed::Attribute>, Diagnostic> {
+        let mut checked = Vec::new();
+
+        for attr in attrs {
+            match (attr.name.as_str(), attr.args.as_slice(), &attr.value) {
+                ("section", [], Some(section)) => {
+                    if target == AttributeTarget::Extern {
+                        return Err(self.error(format!(
+                            "`{}` cannot be used on extern function `{}`",
+                            attr, name
+                        )));
+                    }
+                    checked.push(typed::Attribute::Section(section.clone()));
+                }
+                ("align", [align], None) => {
+                    if target == AttributeTarget::Extern {
+                        return Err(self.error(format!(
+                            "`{}` cannot be used on extern function `{}`",
+                            attr, name
+                        )));
+                    }
+                    let align: u32 = align.parse().unwrap_or(0);
+                    if !align.is_power_of_two() {
+                        return Err(
+                            self.error(format!("alignment of `{}` must be a power of two", name))
+                        );
+                    }
+                    checked.push(typed::Attribute::Align(align));
+                }
+                // a weak declaration may stay undefined at link time
+                ("weak", [], None) => checked.push(typed::Attribute::Weak),
+                ("inline" | "noinline" | "cold" | "noreturn", ..) => {
+                    if target == AttributeTarget::Static {
+                        return Err(self.error(format!("`{}` can only be used on functions", attr)));
+                    }
+                }
+                ("no_mangle" | "export" | "link_name", ..) => {}
+                ("section" | "align" | "weak", ..) => {
+                    return Err(self.error(format!("malformed attribute `{}` on `{}`", attr, name)))
+                }
+                _ => eprintln!(
+                    "{}:{}:{}: warning: unknown attribute `{}` on `{}` is ignored",
+                    self.file, self.pos.line, self.pos.col, attr, name
+                ),
+            }
+        }
+        if target == AttributeTarget::Static {
+            return Ok(checked);
+        }
+
+        let keyword = match inline {
+            Some(ast::Inline::Hint) => Some(("inlinehint", "inline")),
+            Some(ast::Inline::Always) => Some(("alwaysinline", "inline(always)")),
+            None => None,
+        };
+        if let Some((kind, _)) = keyword {
+            checked.push(typed::Attribute::Function(kind));
+        }
+
+        let mut inlining = keyword.map(|(_, keyword)| keyword.to_string());
+        for attr in attrs {
+            let kind = match (attr.name.as_str(), attr.args.as_slice(), &attr.value) {
+                ("inline", [], None) => "inlinehint",
+                ("inline", [always], None) if always == "always" => "alwaysinline",
+                ("noinline", [], None) => "noinline",
+                ("cold", [], None) => "cold",
+                ("noreturn", [], None) => "noreturn",
+                ("inline" | "noinline" | "cold" | "noreturn", ..) => {
+                    return Err(self.error(format!("malformed attribute `{}` on `{}`", attr, name)))
+                }
+                _ => continue,
+            };
+            if target == AttributeTarget::Extern && !matches!(kind, "cold" | "noreturn") {
+                return Err(self.error(format!(
+                    "`{}` cannot be used on extern function `{}`",
+                    attr, name
+                )));
+            }
+
+            if matches!(kind, "inlinehint" | "alwaysinline" | "noinline") {
+                if let Some(other) = inlining.replace(attr.to_string()) {
+                    return Err(self.error(format!(
+                        "`{}` conflicts with `{}` on `{}`",
+                        attr, other, name
+                    )));
+                }
+            }
+
+            checked.push(typed::Attribute::Function(kind));
+        }
+
+        Ok(checked)
+    }
+
+    /// Exported functions and statics are used from C, so their types
+    /// have to exist there.
+    fn expect_c_type(&self, name: &str, typ: &ast::Type) -> Result<(), Diagnostic> {
+        let c_type = match typ {
+            ast::Type::Named { .. } => {
+                typ.is_integer()
+                    || typ.is_named("bool")
+                    || typ.is_named("void")
+                    || self.enum_of(typ).is_some_and(|e| !e.has_payload())
+            }
+            ast::Type::Pointer(inner) | ast::Type::Ref { typ: inner, .. } => !is_dyn(inner),
+            _ => false,
+        };
+        if !c_type {
+            return Err(self.error(format!(
+                "`{}` cannot be exported, `{}` has no C equivalent",
+                name, typ
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// An error at the declaration or statement being checked.
+    fn error(&self, message: String) -> Diagnostic {
+        Diagnostic::new(&self.file, self.pos, message)
+    }
+
+    /// Reports the error of a helper, which doesn't know where it is, at
+    /// the declaration or statement being checked.
+    fn here<T>(&self, result: Result<T, String>) -> Result<T, Diagnostic> {
+        result.map_err(|message| self.error(message))
+    }
+
+    /// Reports the errors that follow at `decl`.
+    fn locate(&mut self, decl: &ast::Decl) {
+        let (file, pos) = decl.location();
+        self.file = file.to_string();
+        self.pos = pos;
+    }
+
+    /// Starts checking the body of a function returning `rtyp`. Inside
+    /// generic functions the type parameters are replaced by
+    /// `self.type_args`.
+    fn enter_function(&mut self, rtyp: Option<ast::Type>) {
+        self.function_type = rtyp.map(|typ| self.resolve_type(&typ));
+        self.locals.clear();
+        self.scope.clear();
+        self.terminated = false;
+    }
+
+    /// Binds the parameters and checks `body`, whose value is returned.
+    fn check_body(
+        &mut self,
+        params: &[(String, ast::Type)],
+        body: &ast::Expr,
+    ) -> Result<Expr, Diagnostic> {
+        for (name, typ) in params {
+            let typ = self.resolve_type(typ);
+            self.declare_local(name, typ);
+        }
+
+        // the tail of the body is the return value
+        match self.function_type.clone() {
+            Some(rtyp) => self.check_expr_as(body, &rtyp),
+            None => {
+                let value = self.check_expr(body)?;
+                if !self.terminated {
+                    self.expect_type(&value.typ, &ast::Type::new_simple("void"))?;
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    /// Stores the checked `body` and the variables of the declared
+    /// function `function`.
+    fn leave_function(&mut self, function: usize, body: Expr) {
+        let function = &mut self.functions[function];
+        function.body = Some(body);
+        function.locals = std::mem::take(&mut self.locals);
+        self.scope.clear();
+        self.function_type = None;
+    }
+
+    /// Introduces a new variable, which shadows those of the same name.
+    fn declare_local(&mut self, name: &str, typ: ast::Type) -> usize {
+        self.locals.push(Local {
+            name: name.to_string(),
+            typ,
+        });
+        let local = self.locals.len() - 1;
+        self.scope.insert(name.to_string(), local);
+        local
+    }
+
+    /// Replaces the type parameters of the function being checked.
+    fn resolve_type(&self, typ: &ast::Type) -> ast::Type {
+        substitute(typ, &self.type_args)
+    }
+
+    /// Checks that `typ` and the types it is made of exist. Trait objects
+    /// only exist behind references and pointers.
+    fn check_type(&self, typ: &ast::Type) -> Result<(), Diagnostic> {
+        self.check_type_in(typ, &mut HashSet::new())
+    }
+
+    /// Like `check_type`, `seen` are the struct types checked already,
+    /// which may refer to themselves through pointers.
+    fn check_type_in(&self, typ: &ast::Type, seen: &mut HashSet<String>) -> Result<(), Diagnostic> {
+        match typ {
+            ast::Type::Pointer(inner) | ast::Type::Ref { typ: inner, .. } => match inner.as_ref() {
+                ast::Type::Dyn(trait_name) => {
+                    self.here(self.traits.get(trait_name))?;
+                }
+                inner => self.check_type_in(inner, seen)?,
+            },
+            ast::Type::Dyn(trait_name) => {
+                return Err(self.error(format!(
+                    "`dyn {}` has no known size, use it behind a reference or pointer",
+                    trait_name
+                )))
+            }
+            ast::Type::Array { typ, .. } | ast::Type::Slice(typ) | ast::Type::Optional(typ) => {
+                self.check_type_in(typ, seen)?
+            }
+            ast::Type::Named { name, .. } => match name.as_ref() {
+                "i128" | "u128" | "i64" | "u64" | "i32" | "u32" | "i16" | "u16" | "i8" | "u8"
+                | "bool" | "void" => {}
+                name if self.structs.contains_key(name) => {
+                    if seen.insert(typ.to_string()) {
+                        for (_, member) in self.struct_members(typ)?.unwrap() {
+                            self.check_type_in(&member, seen)?;
+                        }
+                    }
+                }
+                name if self.enums.contains_key(name) => {}
+                name => return Err(self.error(format!("unknown type `{}`", name))),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn declare_struct(
+        &mut self,
+        name: &ast::Type,
+        members: &[(String, ast::Type)],
+    ) -> Result<(), Diagnostic> {
+        let (name, generics) = match name {
+            ast::Type::Named { name, generics } => (name, generics),
+            typ => return Err(self.error(format!("invalid struct name `{}`", typ))),
+        };
+        if self.structs.contains_key(name) || self.enums.contains_key(name) {
+            return Err(self.error(format!("type `{}` is declared more than once", name)));
+        }
+
+        let type_params = generics
+            .iter()
+            .map(|generic| match generic {
+                ast::Type::Named { name, generics } if generics.is_empty() => Ok(name.clone()),
+                typ => Err(self.error(format!("invalid type parameter `{}`", typ))),
+            })
+            .collect::<Result<_, _>>()?;
+
+        for (member, typ) in members {
+            if typ.is_named(name) {
+                return Err(self.error(format!(
+                    "member `{}` of recursive struct `{}` needs a pointer",
+                    member, name
+                )));
+            }
+        }
+
+        self.structs.insert(
+            name.clone(),
+            typed::Struct {
+                type_params,
+                members: members.to_vec(),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// The members of a struct type with its type arguments filled in,
+    /// `None` if `typ` is not a struct.
+    fn struct_members(
+        &self,
+        typ: &ast::Type,
+    ) -> Result<Option<Vec<(String, ast::Type)>>, Diagnostic> {
+        let (name, generics) = match typ {
+            ast::Type::Named { name, generics } => (name, generics),
+            _ => return Ok(None),
+        };
+        let decl = match self.structs.get(name) {
+            Some(decl) => decl,
+            None => return Ok(None),
+        };
+
+        if generics.len() != decl.type_params.len() {
+            return Err(self.error(format!(
+                "struct `{}` takes {} type arguments but {} were given",
+                name,
+                decl.type_params.len(),
+                generics.len()
+            )));
+        }
+
+        Ok(Some(decl.members(generics)))
+    }
+
+    /// Checks a struct literal. Type arguments of a generic struct come
+    /// from the expected type or are inferred from the fields.
+    fn check_struct(
+        &mut self,
+        name: &str,
+        fields: &[(String, Box<ast::Expr>)],
+        expected: Option<&ast::Type>,
+    ) -> Result<Expr, Diagnostic> {
+        let decl = self
+            .structs
+            .get(name)
+            .ok_or_else(|| self.error(format!("unknown struct `{}`", name)))?;
+        let (type_params, members) = (decl.type_params.clone(), decl.members.clone());
+
+        for (idx, (field, _)) in fields.iter().enumerate() {
+            if !members.iter().any(|(member, _)| member == field) {
+                return Err(self.error(format!("struct `{}` has no field `{}`", name, field)));
+            }
+            if fields[..idx].iter().any(|(other, _)| other == field) {
+                return Err(self.error(format!("field `{}` is given more than once", field)));
+            }
+        }
+        for (member, _) in &members {
+            if !fields.iter().any(|(field, _)| field == member) {
+                return Err(self.error(format!("missing field `{}` in `{}`", member, name)));
+            }
+        }
+
+        let mut bindings = HashMap::new();
+        if let Some(ast::Type::Named {
+            name: expected_name,
+            generics,
+        }) = expected
+        {
+            if expected_name == name && generics.len() == type_params.len() {
+                bindings.extend(type_params.iter().cloned().zip(generics.iter().cloned()));
+            }
+        }
+
+        let member_types: Vec<_> = fields
+            .iter()
+            .map(|(field, _)| {
+                let (_, typ) = members.iter().find(|(member, _)| member == field).unwrap();
+                typ.clone()
+            })
+            .collect();
+        let exprs: Vec<_> = fields.iter().map(|(_, value)| value.as_ref()).collect();
+        let values = self.check_inferred(&exprs, &member_types, &type_params, &mut bindings)?;
+
+        let generics = type_params
+            .iter()
+            .map(|param| {
+                bindings.get(param).cloned().ok_or_else(|| {
+                    self.error(format!(
+                        "cannot infer type parameter `{}` of `{}`",
+                        param, name
+                    ))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let typ = ast::Type::Named {
+            name: name.to_string(),
+            generics,
+        };
+        self.check_type(&typ)?;
+        let members = self.struct_members(&typ)?.unwrap();
+
+        let mut checked = Vec::new();
+        for ((field, _), value) in fields.iter().zip(values) {
+            let idx = members
+                .iter()
+                .position(|(member, _)| member == field)
+                .unwrap();
+            checked.push((idx, self.coerce(value, &members[idx].1)?));
+        }
+
+        Ok(Expr::new(ExprKind::Struct(checked), typ))
+    }
+
+    /// Checks `exprs` as values for places of the types `types`, which
+    /// may mention the type parameters `params`. Bindings for them are
+    /// inferred from the values. Untyped literals are checked last, so
+    /// they can take on a type inferred from the others.
+    fn check_inferred(
+        &mut self,
+        exprs: &[&ast::Expr],
+        types: &[ast::Type],
+        params: &[String],
+        bindings: &mut HashMap<String, ast::Type>,
+    ) -> Result<Vec<Expr>, Diagnostic> {
+        let mut values = vec![None; exprs.len()];
+
+        for literals in [false, true] {
+            for (idx, (expr, typ)) in exprs.iter().zip(types).enumerate() {
+                if is_untyped_literal(expr) != literals {
+                    continue;
+                }
+
+                let typ = substitute(typ, bindings);
+                let value = if mentions_params(&typ, params) {
+                    self.check_expr(expr)?
+                } else {
+                    self.check_expr_as(expr, &typ)?
+                };
+
+                infer_type_args(&types[idx], &value.typ, params, bindings);
+                values[idx] = Some(value);
+            }
+        }
+
+        Ok(values.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Calls a generic function, instantiating it for the inferred type
+    /// arguments if this is the first call with them.
+    fn check_generic_call(
+        &mut self,
+        name: &str,
+        receiver: Option<Expr>,
+        args: &[Box<ast::Expr>],
+    ) -> Result<Expr, Diagnostic> {
+        let generic = self.generics[name].clone();
+        let skip = receiver.is_some() as usize;
+
+        if args.len() + skip != generic.params.len() {
+            return Err(self.error(format!(
+                "function `{}` takes {} arguments but {} were given",
+                name,
+                generic.params.len() - skip,
+                args.len()
+            )));
+        }
+
+        let exprs: Vec<_> = args.iter().map(|arg| arg.as_ref()).collect();
+        let param_types: Vec<_> = generic.params.iter().map(|(_, typ)| typ.clone()).collect();
+        let type_params = type_param_names(&generic.type_params);
+        let mut bindings = HashMap::new();
+        if let Some(receiver) = &receiver {
+            infer_type_args(&param_types[0], &receiver.typ, &type_params, &mut bindings);
+        }
+        let values =
+            self.check_inferred(&exprs, &param_types[skip..], &type_params, &mut bindings)?;
+        let values = receiver.into_iter().chain(values);
+
+        let function = self.instantiate(name, bindings)?;
+        let instance = &self.functions[self.function_index[&function]];
+        let (params, rtyp) = (instance.params.clone(), instance.rtyp.clone());
+
+        let args = values
+            .zip(&params)
+            .map(|(value, typ)| self.coerce(value, typ))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Expr::new(
+            ExprKind::Call { function, args },
+            rtyp.unwrap_or_else(|| ast::Type::new_simple("void")),
+        ))
+    }
+
+    /// Returns the name of the generic function `name` instantiated for
+    /// the type arguments in `bindings`, declaring it on first use. The
+    /// type arguments have to implement the bounds of their parameters.
+    fn instantiate(
+        &mut self,
+        name: &str,
+        bindings: HashMap<String, ast::Type>,
+    ) -> Result<String, Diagnostic> {
+        let generic = self.generics[name].clone();
+
+        let type_args: Vec<_> = generic
+            .type_params
+            .iter()
+            .map(|param| {
+                let typ = bindings.get(&param.name).ok_or_else(|| {
+                    self.error(format!(
+                        "cannot infer type parameter `{}` of `{}`",
+                        param.name, name
+                    ))
+                })?;
+                for bound in &param.bounds {
+                    if !self.traits.implements(typ, bound) {
+                        return Err(self.error(format!(
+                            "`{}` does not implement trait `{}`, required by `{}`",
+                            typ, bound, name
+                        )));
+                    }
+                }
+                Ok(typ.clone())
+            })
+            .collect::<Result<_, _>>()?;
+
+        let instance = format!(
+            "{}<{}>",
+            name,
+            type_args
+                .iter()
+                .map(|typ| typ.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        if !self.function_index.contains_key(&instance) {
+            let params = generic
+                .params
+                .iter()
+                .map(|(_, typ)| substitute(typ, &bindings))
+                .collect();
+            let rtyp = generic.rtyp.as_ref().map(|typ| substitute(typ, &bindings));
+            let function = self.declare_function(
+                &instance,
+                mangle::mangle(name, &type_args),
+                params,
+                false,
+                rtyp,
+                generic.file.clone(),
+                generic.pos,
+            )?;
+            self.functions[function].attrs = self.check_attributes(
+                &instance,
+                &generic.attrs,
+                generic.inline,
+                AttributeTarget::Function,
+            )?;
+            self.instances.push(Instance {
+                function,
+                name: name.to_string(),
+                type_args: bindings,
+            });
+        }
+
+        Ok(instance)
+    }
+
+    /// Calls a function. Methods get their already checked `receiver` as
+    /// first argument.
+    fn check_call(
+        &mut self,
+        name: &str,
+        receiver: Option<Expr>,
+        args: &[Box<ast::Expr>],
+    ) -> Result<Expr, Diagnostic> {
+        if self.generics.contains_key(name) {
+            return self.check_generic_call(name, receiver, args);
+        }
+
+        let function = self
+            .function_index
+            .get(name)
+            .map(|&function| &self.functions[function])
+            .ok_or_else(|| self.error(format!("unknown function `{}`", name)))?;
+        let (params, variadic, rtyp) = (
+            function.params.clone(),
+            function.variadic,
+            function.rtyp.clone(),
+        );
+        let skip = receiver.is_some() as usize;
+
+        if !(args.len() + skip == params.len() || (variadic && args.len() + skip > params.len())) {
+            return Err(self.error(format!(
+                "function `{}` takes {}{} arguments but {} were given",
+                name,
+                if variadic { "at least " } else { "" },
+                params.len() - skip,
+                args.len()
+            )));
+        }
+
+        let mut checked = Vec::new();
+
+        if let Some(receiver) = receiver {
+            checked.push(self.coerce(receiver, &params[0])?);
+        }
+
+        for (idx, arg) in args.iter().enumerate() {
+            let value = match params.get(idx + skip) {
+                Some(typ) => self.check_expr_as(arg, typ)?,
+                None => {
+                    let value = self.check_expr(arg)?;
+                    self.promote_vararg(value)?
+                }
+            };
+            checked.push(value);
+        }
+
+        Ok(Expr::new(
+            ExprKind::Call {
+                function: name.to_string(),
+                args: checked,
+            },
+            rtyp.unwrap_or_else(|| ast::Type::new_simple("void")),
+        ))
+    }
+
+    /// Calls the method `name` of the receiver's type. The receiver is
+    /// borrowed or copied as the method's `self` parameter asks for, and
+    /// references and pointers to it are looked through.
+    fn check_method_call(
+        &mut self,
+        receiver: &ast::Expr,
+        name: &str,
+        args: &[Box<ast::Expr>],
+    ) -> Result<Expr, Diagnostic> {
+        let place = self.check_place_or_temporary(receiver)?;
+        let place = self.deref_place(place);
+
+        if let Some(ast::Type::Dyn(trait_name)) = place.typ.pointee() {
+            let trait_name = trait_name.clone();
+            return self.check_dyn_call(place, &trait_name, receiver, name, args);
+        }
+
+        let path = self.here(self.traits.method_path(&self.methods, &place.typ, name))?;
+
+        let self_type = match self.function_index.get(&path) {
+            Some(&function) => self.functions[function].params[0].clone(),
+            None => self.generics[&path].params[0].1.clone(),
+        };
+
+        let receiver = match self_type {
+            ast::Type::Ref { mutable, .. } => {
+                if mutable && !place.mutable {
+                    return Err(self.error(format!("cannot borrow `{}` as mutable, it is immutable or behind a shared reference", receiver)));
+                }
+                let typ = ast::Type::new_ref(place.typ.clone(), mutable);
+                Expr::new(ExprKind::AddrOf(Box::new(place)), typ)
+            }
+            _ => self.load(place),
+        };
+
+        self.check_call(&path, Some(receiver), args)
+    }
+
+    /// Calls the method `name` of the trait object at `place` through its
+    /// vtable.
+    fn check_dyn_call(
+        &mut self,
+        place: Place,
+        trait_name: &str,
+        receiver: &ast::Expr,
+        name: &str,
+        args: &[Box<ast::Expr>],
+    ) -> Result<Expr, Diagnostic> {
+        let methods = self.here(self.traits.object_methods(trait_name))?;
+        let (idx, method) = methods
+            .iter()
+            .enumerate()
+            .find(|(_, method)| method.name == name)
+            .ok_or_else(|| self.error(format!("no method `{}` on `dyn {}`", name, trait_name)))?;
+
+        let mutable = !matches!(place.typ, ast::Type::Ref { mutable: false, .. });
+        if !mutable && !matches!(method.params[0].1, ast::Type::Ref { mutable: false, .. }) {
+            return Err(self.error(format!(
+                "cannot borrow `{}` as mutable, it is immutable or behind a shared reference",
+                receiver
+            )));
+        }
+
+        let params = &method.params[1..];
+        if args.len() != params.len() {
+            return Err(self.error(format!(
+                "function `{}` takes {} arguments but {} were given",
+                name,
+                params.len(),
+                args.len()
+            )));
+        }
+
+        let object = self.load(place);
+        let args = args
+            .iter()
+            .zip(params)
+            .map(|(arg, (_, typ))| self.check_expr_as(arg, typ))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Expr::new(
+            ExprKind::DynCall {
+                object: Box::new(object),
+                method: idx,
+                args,
+            },
+            method
+                .rtyp
+                .clone()
+                .unwrap_or_else(|| ast::Type::new_simple("void")),
+        ))
+    }
+
+    /// Returns the key of the vtable for the implementation of
+    /// `trait_name` by `typ`, adding it the first time it is needed.
+    fn vtable(&mut self, typ: &ast::Type, trait_name: &str) -> Result<String, Diagnostic> {
+        let key = format!("<{} as {}>", typ, trait_name);
+        if self.vtables.contains_key(&key) {
+            return Ok(key);
+        }
+
+        let type_name = match typ {
+            ast::Type::Named { name, .. } => name,
+            _ => unreachable!("only named types implement traits"),
+        };
+
+        let mut methods = Vec::new();
+        for method in self.here(self.traits.object_methods(trait_name))? {
+            let path = format!("<{} as {}>::{}", type_name, trait_name, method.name);
+
+            // methods of generic types are instantiated for the type's arguments
+            let function = match self.generics.get(&path) {
+                Some(generic) => {
+                    let type_params = type_param_names(&generic.type_params);
+                    let mut bindings = HashMap::new();
+                    infer_type_args(
+                        &generic.params[0].1,
+                        &ast::Type::new_ref(typ.clone(), false),
+                        &type_params,
+                        &mut bindings,
+                    );
+                    self.instantiate(&path, bindings)?
+                }
+                None => path,
+            };
+            methods.push(function);
+        }
+
+        self.vtables.insert(
+            key.clone(),
+            typed::Vtable {
+                typ: typ.clone(),
+                trait_name: trait_name.to_string(),
+                methods,
+            },
+        );
+        Ok(key)
+    }
+
+    /// Turns a pointer to a value of type `from` into the trait object
+    /// `typ`, pairing it with the vtable of `from`'s implementation.
+    fn trait_object(
+        &mut self,
+        pointer: Expr,
+        from: &ast::Type,
+        typ: &ast::Type,
+    ) -> Result<Expr, Diagnostic> {
+        let trait_name = match typ.pointee() {
+            Some(ast::Type::Dyn(trait_name)) => trait_name.clone(),
+            _ => unreachable!("`{}` is not a trait object", typ),
+        };
+        if !self.traits.implements(from, &trait_name) {
+            return Err(self.error(format!(
+                "`{}` does not implement trait `{}`",
+                from, trait_name
+            )));
+        }
+
+        let vtable = self.vtable(from, &trait_name)?;
+        Ok(Expr::new(
+            ExprKind::Object {
+                pointer: Box::new(pointer),
+                vtable,
+            },
+            typ.clone(),
+        ))
+    }
+
+    fn declare_enum(&mut self, name: &str, variants: &[ast::Variant]) -> Result<(), Diagnostic> {
+        if self.enums.contains_key(name) {
+            return Err(self.error(format!("enum `{}` is declared more than once", name)));
+        }
+        self.enums.insert(
+            name.to_string(),
+            typed::Enum {
+                variants: Vec::new(),
+            },
+        );
+
+        let i32_type = ast::Type::new_simple("i32");
+        let mut next = 0;
+        let mut seen = HashSet::new();
+        let mut checked = Vec::new();
+
+        for variant in variants {
+            let path = format!("{}::{}", name, variant.name);
+            if checked
+                .iter()
+                .any(|v: &typed::Variant| v.name == variant.name)
+            {
+                return Err(self.error(format!("variant `{}` is declared more than once", path)));
+            }
+
+            let tag = match &variant.discriminant {
+                Some(expr) => match self.check_constant(&path, expr, &i32_type)?.kind {
+                    ExprKind::Int(tag) => tag,
+                    _ => {
+                        return Err(self.error(format!(
+                            "the discriminant of `{}` is not an integer constant",
+                            path
+                        )))
+                    }
+                },
+                None => {
+                    if next > i32::MAX as i128 {
+                        return Err(
+                            self.error(format!("discriminant of `{}` overflows `i32`", path))
+                        );
+                    }
+                    next
+                }
+            };
+
+            if !seen.insert(tag) {
+                return Err(self.error(format!(
+                    "discriminant {} of `{}` is already used",
+                    tag, path
+                )));
+            }
+            next = tag + 1;
+
+            for field in &variant.fields {
+                if field.is_named(name) {
+                    return Err(self.error(format!(
+                        "recursive enum `{}` needs a pointer to refer to itself",
+                        name
+                    )));
+                }
+            }
+
+            checked.push(typed::Variant {
+                name: variant.name.clone(),
+                tag: tag as i32,
+                fields: variant.fields.clone(),
+            });
+        }
+
+        self.enums.get_mut(name).unwrap().variants = checked;
+
+        Ok(())
+    }
+
+    /// Finds the variant `Enum::Variant` refers to, with its index.
+    fn variant(&self, path: &str) -> Option<(usize, &typed::Variant)> {
+        let (name, variant) = path.rsplit_once("::")?;
+        self.enums
+            .get(name)?
+            .variants
+            .iter()
+            .enumerate()
+            .find(|(_, v)| v.name == variant)
+    }
+
+    fn enum_of(&self, typ: &ast::Type) -> Option<&typed::Enum> {
+        match typ {
+            ast::Type::Named { name, generics } if generics.is_empty() => self.enums.get(name),
+            _ => None,
+        }
+    }
+
+    /// Checks the value of an enum variant from its payload.
+    fn check_variant(&mut self, path: &str, args: &[Box<ast::Expr>]) -> Result<Expr, Diagnostic> {
+        let (variant, fields) = self
+            .variant(path)
+            .map(|(idx, variant)| (idx, variant.fields.clone()))
+            .ok_or_else(|| self.error(format!("unknown enum variant `{}`", path)))?;
+        let enum_type = ast::Type::new_simple(path.rsplit_once("::").unwrap().0);
+
+        if args.len() != fields.len() {
+            return Err(self.error(format!(
+                "variant `{}` has {} fields but {} were given",
+                path,
+                fields.len(),
+                args.len()
+            )));
+        }
+
+        let fields = args
+            .iter()
+            .zip(&fields)
+            .map(|(arg, field)| self.check_expr_as(arg, field))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Expr::new(ExprKind::Variant { variant, fields }, enum_type))
+    }
+
+    /// Checks a `match`. The arms are tried in order, the first one whose
+    /// pattern matches is taken.
+    fn check_match(
+        &mut self,
+        expr: &ast::Expr,
+        arms: &[ast::Arm],
+        expected: Option<&ast::Type>,
+    ) -> Result<Expr, Diagnostic> {
+        let scrutinee = self.check_expr(expr)?;
+        let typ = scrutinee.typ.clone();
+
+        if self.enum_of(&typ).is_none() && !(typ.is_integer() || typ.is_named("bool")) {
+            return Err(self.error(format!("cannot match on a value of type `{}`", typ)));
+        }
+
+        // the arms after a catch-all can never match and are left out
+        let mut covered = HashSet::new();
+        let mut reachable = Vec::new();
+        let mut catch_all = false;
+
+        for arm in arms {
+            let cases = match self.pattern_cases(&arm.pattern, &typ)? {
+                Some(values) => Some(
+                    values
+                        .into_iter()
+                        .filter(|value| covered.insert(*value))
+                        .collect::<Vec<_>>(),
+                ),
+                None => {
+                    catch_all = true;
+                    None
+                }
+            };
+
+            reachable.push((arm, cases));
+            if catch_all {
+                break;
+            }
+        }
+
+        if !catch_all {
+            self.check_exhaustive(&typ, &covered)?;
+        }
+
+        // arms that are untyped literals go last, so they can take the
+        // type of the others
+        let mut order: Vec<_> = (0..reachable.len()).collect();
+        if expected.is_none() {
+            order.sort_by_key(|&idx| yields_untyped_literal(&reachable[idx].0.body));
+        }
+
+        let mut result_type = expected.cloned();
+        let mut bodies = vec![None; reachable.len()];
+        let mut reached = false;
+
+        for idx in order {
+            let arm = reachable[idx].0;
+            self.terminated = false;
+
+            let scope = self.scope.clone();
+            let captures = self.bind_pattern(&arm.pattern, &typ);
+            let value = match &result_type {
+                Some(typ) => self.check_expr_as(&arm.body, typ)?,
+                None => self.check_expr(&arm.body)?,
+            };
+            self.scope = scope;
+
+            let found = value.typ.clone();
+            bodies[idx] = Some((captures, value));
+            if self.terminated {
+                continue;
+            }
+
+            reached = true;
+            match &result_type {
+                Some(typ) => self.expect_type(&found, typ)?,
+                None => result_type = Some(found),
+            }
+        }
+        self.terminated = !reached;
+
+        let arms = reachable
+            .into_iter()
+            .zip(bodies)
+            .map(|((_, cases), body)| {
+                let (captures, body) = body.unwrap();
+                Arm {
+                    cases,
+                    captures,
+                    body,
+                }
+            })
+            .collect();
+
+        Ok(Expr::new(
+            ExprKind::Match {
+                scrutinee: Box::new(scrutinee),
+                arms,
+            },
+            result_type.unwrap_or_else(|| ast::Type::new_simple("void")),
+        ))
+    }
+
+    /// The discriminants or values a pattern matches, `None` if it matches
+    /// everything.
+    fn pattern_cases(
+        &mut self,
+        pattern: &ast::Pattern,
+        typ: &ast::Type,
+    ) -> Result<Option<Vec<i128>>, Diagnostic> {
+        match pattern {
+            ast::Pattern::Wildcard | ast::Pattern::Binding(_) => Ok(None),
+            ast::Pattern::Literal(expr) => {
+                if self.enum_of(typ).is_some() {
+                    return Err(self.error(format!(
+                        "expected a variant of `{}` but found `{}`",
+                        typ, expr
+                    )));
+                }
+                let value = self.check_expr_as(expr, typ)?;
+                let value = self
+                    .fold(value)
+                    .and_then(|value| self.constant_value(&value));
+                Ok(Some(vec![value.ok_or_else(|| {
+                    self.error(format!("pattern `{}` is not an integer constant", expr))
+                })?]))
+            }
+            ast::Pattern::Variant { path, fields } => {
+                let (_, variant) = self
+                    .variant(path)
+                    .ok_or_else(|| self.error(format!("unknown enum variant `{}`", path)))?;
+                if !typ.is_named(path.rsplit_once("::").unwrap().0) {
+                    return Err(self.error(format!(
+                        "expected a pattern of type `{}` but found `{}`",
+                        typ, path
+                    )));
+                }
+                if fields.len() != variant.fields.len() {
+                    return Err(self.error(format!(
+                        "variant `{}` has {} fields but the pattern binds {}",
+                        path,
+                        variant.fields.len(),
+                        fields.len()
+                    )));
+                }
+                Ok(Some(vec![variant.tag as i128]))
+            }
+            ast::Pattern::Or(patterns) => {
+                let mut values = Vec::new();
+                for pattern in patterns {
+                    if binds_names(pattern) {
+                        return Err(
+                            self.error(format!("alternatives in `{}` cannot bind names", pattern))
+                        );
+                    }
+                    match self.pattern_cases(pattern, typ)? {
+                        Some(cases) => values.extend(cases),
+                        None => return Ok(None),
+                    }
+                }
+                Ok(Some(values))
+            }
+        }
+    }
+
+    /// Without a catch-all arm, every variant of an enum or both booleans
+    /// have to be covered. Integers always need a catch-all.
+    fn check_exhaustive(&self, typ: &ast::Type, covered: &HashSet<i128>) -> Result<(), Diagnostic> {
+        let missing: Vec<String> = match self.enum_of(typ) {
+            Some(enumeration) => enumeration
+                .variants
+                .iter()
+                .filter(|variant| !covered.contains(&(variant.tag as i128)))
+                .map(|variant| format!("{}::{}", typ, variant.name))
+                .collect(),
+            None if typ.is_named("bool") => [true, false]
+                .iter()
+                .filter(|&&b| !covered.contains(&(b as i128)))
+                .map(|b| b.to_string())
+                .collect(),
+            None => vec![String::from("_")],
+        };
+
+        if !missing.is_empty() {
+            return Err(self.error(format!(
+                "non-exhaustive match on `{}`, missing {}",
+                typ,
+                missing
+                    .iter()
+                    .map(|pattern| format!("`{}`", pattern))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Introduces the names a matching pattern binds. They are copies of
+    /// the matched value of type `typ` or of its fields.
+    fn bind_pattern(&mut self, pattern: &ast::Pattern, typ: &ast::Type) -> Vec<Capture> {
+        let (path, fields) = match pattern {
+            ast::Pattern::Binding(name) => {
+                let local = self.declare_local(name, typ.clone());
+                return vec![Capture { local, field: None }];
+            }
+            ast::Pattern::Variant { path, fields } if binds_names(pattern) => (path, fields),
+            _ => return Vec::new(),
+        };
+
+        let (variant, types) = self
+            .variant(path)
+            .map(|(idx, variant)| (idx, variant.fields.clone()))
+            .unwrap();
+
+        let mut captures = Vec::new();
+        for (idx, (name, typ)) in fields.iter().zip(types).enumerate() {
+            if name == "_" {
+                continue;
+            }
+
+            let local = self.declare_local(name, typ);
+            captures.push(Capture {
+                local,
+                field: Some((variant, idx)),
+            });
+        }
+        captures
+    }
+
+    fn expect_unique_global(&self, name: &str) -> Result<(), Diagnostic> {
+        if self.constants.contains_key(name) || self.static_index.contains_key(name) {
+            return Err(self.error(format!("global `{}` is declared more than once", name)));
+        }
+
+        Ok(())
+    }
+
+    /// Checks and folds the initializer of a `const`, `static` or
+    /// discriminant. Only literals, operators, casts and other constants
+    /// are allowed.
+    fn check_constant(
+        &mut self,
+        name: &str,
+        value: &ast::Expr,
+        typ: &ast::Type,
+    ) -> Result<Expr, Diagnostic> {
+        let message = format!("the initializer of `{}` is not a constant expression", name);
+
+        if !self.is_constant_expr(value) {
+            return Err(self.error(message));
+        }
+        let value = self.check_expr_as(value, typ)?;
+        self.fold(value).ok_or_else(|| self.error(message))
+    }
+
+    /// Whether `expr` can be evaluated at compile time. Names have to refer
+    /// to constants declared before.
+    fn is_constant_expr(&self, expr: &ast::Expr) -> bool {
+        match expr {
+            ast::Expr::Literal(_) => true,
+            ast::Expr::Ident(name) => {
+                self.constants.contains_key(name)
+                    || self.variant(name).is_some_and(|(_, v)| v.fields.is_empty())
+            }
+            ast::Expr::Prefix { op, expr } => {
+                matches!(op, TokenKind::Minus | TokenKind::Bang | TokenKind::Tilde)
+                    && self.is_constant_expr(expr)
+            }
+            ast::Expr::Infix { op, left, right } => {
+                !matches!(op, TokenKind::LogicalAnd | TokenKind::LogicalOr)
+                    && self.is_constant_expr(left)
+                    && self.is_constant_expr(right)
+            }
+            ast::Expr::Cast { expr, .. } => self.is_constant_expr(expr),
+            ast::Expr::Array(items) => items.iter().all(|item| self.is_constant_expr(item)),
+            ast::Expr::Struct { fields, .. } => {
+                fields.iter().all(|(_, value)| self.is_constant_expr(value))
+            }
+            _ => false,
+        }
+    }
+
+    /// Computes the integer and `bool` parts of a constant expression like
+    /// they would be at run time, so backends only have to emit the
+    /// result. `None` if `expr` has no value at compile time, because it
+    /// isn't constant or because it would trap, like a division by zero.
+    fn fold(&self, expr: Expr) -> Option<Expr> {
+        let typ = expr.typ;
+        let kind = match expr.kind {
+            kind @ (ExprKind::Int(_) | ExprKind::Bool(_) | ExprKind::Str(_) | ExprKind::Null) => {
+                kind
+            }
+            ExprKind::Variant { variant, fields } => ExprKind::Variant {
+                variant,
+                fields: self.fold_all(fields)?,
+            },
+            ExprKind::Struct(members) => ExprKind::Struct(
+                members
+                    .into_iter()
+                    .map(|(idx, member)| Some((idx, self.fold(member)?)))
+                    .collect::<Option<_>>()?,
+            ),
+            ExprKind::Array(items) => ExprKind::Array(self.fold_all(items)?),
+            ExprKind::Some(value) => ExprKind::Some(Box::new(self.fold(*value)?)),
+            ExprKind::Unary { op, operand } => {
+                let operand = self.fold(*operand)?;
+                match (op, operand.kind) {
+                    (TokenKind::Minus, ExprKind::Int(x)) => {
+                        ExprKind::Int(wrap(x.wrapping_neg(), &typ))
+                    }
+                    (TokenKind::Tilde, ExprKind::Int(x)) => ExprKind::Int(wrap(!x, &typ)),
+                    (TokenKind::Bang, ExprKind::Bool(b)) => ExprKind::Bool(!b),
+                    _ => return None,
+                }
+            }
+            ExprKind::Binary { op, left, right } => {
+                let (left, right) = (self.fold(*left)?, self.fold(*right)?);
+                self.fold_binary(op, &left, &right, &typ)?
+            }
+            ExprKind::NullCheck { operand, is_null } => match self.fold(*operand)?.kind {
+                ExprKind::Null => ExprKind::Bool(is_null),
+                ExprKind::Some(_) => ExprKind::Bool(!is_null),
+                _ => return None,
+            },
+            ExprKind::Cast(value) => {
+                let value = self.fold(*value)?;
+                match self.constant_value(&value) {
+                    Some(x) if typ.is_integer() => ExprKind::Int(wrap(x, &typ)),
+                    // pointers only get their value when the program is linked
+                    _ => ExprKind::Cast(Box::new(value)),
+                }
+            }
+            _ => return None,
+        };
+
+        Some(Expr::new(kind, typ))
+    }
+
+    fn fold_all(&self, exprs: Vec<Expr>) -> Option<Vec<Expr>> {
+        exprs.into_iter().map(|expr| self.fold(expr)).collect()
+    }
+
+    /// Computes a binary operator on folded operands, `None` if it would
+    /// trap at run time.
+    fn fold_binary(
+        &self,
+        op: TokenKind,
+        left: &Expr,
+        right: &Expr,
+        typ: &ast::Type,
+    ) -> Option<ExprKind> {
+        let (lhs, rhs) = (self.constant_value(left)?, self.constant_value(right)?);
+        let operands = &left.typ;
+        let width = operands.int_width().unwrap_or(1);
+        let signed = operands.is_signed();
+        // unsigned values are zero-extended, so they compare and divide
+        // correctly as `u128`
+        let (ulhs, urhs) = (lhs as u128, rhs as u128);
+
+        let value = match op {
+            TokenKind::Plus => lhs.wrapping_add(rhs),
+            TokenKind::Minus => lhs.wrapping_sub(rhs),
+            TokenKind::Asterisk => lhs.wrapping_mul(rhs),
+            TokenKind::Slash | TokenKind::Percent if rhs == 0 => return None,
+            TokenKind::Slash | TokenKind::Percent
+                if signed && rhs == -1 && lhs == i128::MIN >> (128 - width) =>
+            {
+                return None
+            }
+            TokenKind::Slash if signed => lhs / rhs,
+            TokenKind::Slash => (ulhs / urhs) as i128,
+            TokenKind::Percent if signed => lhs % rhs,
+            TokenKind::Percent => (ulhs % urhs) as i128,
+            TokenKind::ShiftLeft | TokenKind::ShiftRight if urhs >= width as u128 => return None,
+            TokenKind::ShiftLeft => lhs.wrapping_shl(rhs as u32),
+            TokenKind::ShiftRight if signed => lhs >> rhs,
+            TokenKind::ShiftRight => (ulhs >> urhs) as i128,
+            TokenKind::And => lhs & rhs,
+            TokenKind::Pipe => lhs | rhs,
+            TokenKind::Caret => lhs ^ rhs,
+            TokenKind::Equal => (lhs == rhs) as i128,
+            TokenKind::UnEqual => (lhs != rhs) as i128,
+            TokenKind::LessThan if signed => (lhs < rhs) as i128,
+            TokenKind::LessThan => (ulhs < urhs) as i128,
+            TokenKind::GreaterThan if signed => (lhs > rhs) as i128,
+            TokenKind::GreaterThan => (ulhs > urhs) as i128,
+            TokenKind::LessEqual if signed => (lhs <= rhs) as i128,
+            TokenKind::LessEqual => (ulhs <= urhs) as i128,
+            TokenKind::GreaterEqual if signed => (lhs >= rhs) as i128,
+            TokenKind::GreaterEqual => (ulhs >= urhs) as i128,
+            _ => return None,
+        };
+
+        Some(if typ.is_named("bool") {
+            ExprKind::Bool(value != 0)
+        } else {
+            ExprKind::Int(wrap(value, typ))
+        })
+    }
+
+    /// The number a folded integer, `bool` or enum without payload stands
+    /// for.
+    fn constant_value(&self, expr: &Expr) -> Option<i128> {
+        match &expr.kind {
+            ExprKind::Int(x) => Some(*x),
+            ExprKind::Bool(b) => Some(*b as i128),
+            ExprKind::Variant { variant, .. } => {
+                let enumeration = self.enum_of(&expr.typ)?;
+                if enumeration.has_payload() {
+                    return None;
+                }
+                Some(enumeration.variants[*variant].tag as i128)
+            }
+            _ => None,
+        }
+    }
+
+    /// Declares the function `name`, emitted as `symbol`.
+    #[allow(clippy::too_many_arguments)]
+    fn declare_function(
+        &mut self,
+        name: &str,
+        symbol: String,
+        params: Vec<ast::Type>,
+        variadic: bool,
+        rtyp: Option<ast::Type>,
+        file: String,
+        pos: ast::Pos,
+    ) -> Result<usize, Diagnostic> {
+        if self.function_index.contains_key(name) {
+            return Err(self.error(format!("function `{}` is declared more than once", name)));
+        }
+        for typ in params.iter().chain(&rtyp) {
+            self.check_type(typ)?;
+        }
+        if !self.symbols.insert(symbol.clone()) {
+            return Err(self.error(format!(
+                "symbol `{}` of `{}` is defined more than once",
+                symbol, name
+            )));
+        }
+
+        self.functions.push(typed::Function {
+            name: name.to_string(),
+            symbol,
+            attrs: Vec::new(),
+            params,
+            variadic,
+            rtyp,
+            body: None,
+            locals: Vec::new(),
+            file,
+            pos,
+        });
+        self.function_index
+            .insert(name.to_string(), self.functions.len() - 1);
+        Ok(self.functions.len() - 1)
+    }
+
+    /// Declares the static `name`, which shadows any global of that name.
+    fn declare_static(
+        &mut self,
+        name: &str,
+        symbol: String,
+        attrs: Vec<typed::Attribute>,
+        mutable: bool,
+        typ: ast::Type,
+        value: Option<Expr>,
+    ) -> Place {
+        self.statics.push(typed::Static {
+            name: name.to_string(),
+            symbol: symbol.clone(),
+            attrs,
+            mutable,
+            typ: typ.clone(),
+            value,
+        });
+        self.static_index
+            .insert(name.to_string(), self.statics.len() - 1);
+
+        Place {
+            kind: PlaceKind::Static(symbol),
+            typ,
+            mutable,
+        }
+    }
+
+    /// Checks the value `let` binds to `name`, converted to `typ` if it is
+    /// given.
+    fn check_let_value(
+        &mut self,
+        name: &str,
+        typ: &Option<ast::Type>,
+        value: &ast::Expr,
+    ) -> Result<Expr, Diagnostic> {
+        let value = match typ {
+            Some(typ) => {
+                let typ = self.resolve_type(typ);
+                self.check_type(&typ)?;
+                self.check_expr_as(value, &typ)?
+            }
+            None => self.check_expr(value)?,
+        };
+
+        // a diverging initializer binds nothing
+        if !self.terminated && value.typ.is_named("void") {
+            return Err(self.error(format!("cannot bind `{}` to a value of type `void`", name)));
+        }
+        Ok(value)
+    }
+
+    fn check_stmt(&mut self, stmt: &ast::Stmt) -> Result<Stmt, Diagnostic> {
+        self.pos = stmt.pos();
+        match stmt {
+            ast::Stmt::Let {
+                name,
+                typ,
+                value,
+                pos,
+            } => {
+                let value = self.check_let_value(name, typ, value)?;
+                if self.terminated {
+                    return Ok(Stmt::Expr { value, pos: *pos });
+                }
+
+                let local = self.declare_local(name, value.typ.clone());
+                Ok(Stmt::Let {
+                    local,
+                    value,
+                    pos: *pos,
+                })
+            }
+            ast::Stmt::Assign {
+                target,
+                op,
+                value,
+                pos,
+            } => {
+                let place = self.check_place(target)?;
+                if !place.mutable {
+                    return Err(self.error(format!(
+                        "cannot assign to `{}`, it is immutable or behind a shared reference",
+                        target
+                    )));
+                }
+
+                let value = match op {
+                    None => self.check_expr_as(value, &place.typ)?,
+                    Some(op) => {
+                        // typed like `place = place op value`, but the place is
+                        // evaluated only once, `a[f()] += 1` calls `f` once
+                        let current =
+                            Expr::new(ExprKind::Load(Box::new(place.clone())), place.typ.clone());
+                        let result = match (op, &place.typ) {
+                            (TokenKind::Plus | TokenKind::Minus, ast::Type::Pointer(_)) => {
+                                self.check_pointer_arith(*op, current, value)?
+                            }
+                            (TokenKind::ShiftLeft | TokenKind::ShiftRight, typ) => {
+                                let typ = typ.clone();
+                                let rhs = self.check_shift_amount(*op, value, &typ)?;
+                                self.check_infix(*op, current, rhs)?
+                            }
+                            (_, typ) => {
+                                let typ = typ.clone();
+                                let rhs = self.check_expr_as(value, &typ)?;
+                                self.check_infix(*op, current, rhs)?
+                            }
+                        };
+
+                        self.expect_type(&result.typ, &place.typ)?;
+                        match result.kind {
+                            ExprKind::Offset { offset: value, .. }
+                            | ExprKind::Binary { right: value, .. } => *value,
+                            _ => unreachable!("`{}` is not an operator", result.typ),
+                        }
+                    }
+                };
+
+                Ok(Stmt::Assign {
+                    place,
+                    op: *op,
+                    value,
+                    pos: *pos,
+                })
+            }
+            ast::Stmt::Return { value, pos } => {
+                let value = match value {
+                    Some(value) => {
+                        let rtyp = self.function_type.clone().ok_or_else(|| {
+                            self.error(String::from(
+                                "cannot return a value from a function without return type",
+                            ))
+                        })?;
+                        Some(self.check_expr_as(value, &rtyp)?)
+                    }
+                    None => {
+                        if self.function_type.is_some() {
+                            return Err(self.error(String::from("missing return value")));
+                        }
+                        None
+                    }
+                };
+                self.terminated = true;
+                Ok(Stmt::Return { value, pos: *pos })
+            }
+            ast::Stmt::Expr { value, pos } => Ok(Stmt::Expr {
+                value: self.check_expr(value)?,
+                pos: *pos,
+            }),
+        }
+    }
+
+    /// Checks a block. Its variables go out of scope at the end and its
+    /// value is the tail expression, or `void` without one. Statements
+    /// that can't be reached are left out.
+    fn check_block(
+        &mut self,
+        body: &[ast::Stmt],
+        tail: &Option<Box<ast::Expr>>,
+        expected: Option<&ast::Type>,
+    ) -> Result<Expr, Diagnostic> {
+        let scope = self.scope.clone();
+
+        // the tail is reported at the statement or function the block is in
+        let pos = self.pos;
+        let mut stmts = Vec::new();
+        for stmt in body {
+            if self.terminated {
+                break;
+            }
+            stmts.push(self.check_stmt(stmt)?);
+        }
+        self.pos = pos;
+
+        let void = ast::Type::new_simple("void");
+        let (tail, typ) = match tail {
+            _ if self.terminated => (None, expected.cloned().unwrap_or(void)),
+            Some(tail) => {
+                let value = match expected {
+                    Some(typ) => self.check_expr_as(tail, typ)?,
+                    None => self.check_expr(tail)?,
+                };
+                let typ = value.typ.clone();
+                (Some(Box::new(value)), typ)
+            }
+            None => {
+                if let Some(typ) = expected {
+                    self.expect_type(&void, typ)?;
+                }
+                (None, void)
+            }
+        };
+
+        self.scope = scope;
+        Ok(Expr::new(ExprKind::Block { stmts, tail }, typ))
+    }
+
+    /// Checks an `if` expression. Both branches have to agree on a type,
+    /// a missing `else` yields `void`, and branches that diverge don't
+    /// take part.
+    fn check_if(
+        &mut self,
+        cond: &ast::Expr,
+        then: &ast::Expr,
+        elze: &Option<Box<ast::Expr>>,
+        expected: Option<&ast::Type>,
+    ) -> Result<Expr, Diagnostic> {
+        let cond = self.check_expr_as(cond, &ast::Type::new_simple("bool"))?;
+
+        // `if c { 1 } else { x }` takes the type of `x`, so the `else`
+        // branch goes first when the `then` branch is an untyped literal
+        let mut branches = [(0, Some(then)), (1, elze.as_deref())];
+        if expected.is_none() && elze.is_some() && yields_untyped_literal(then) {
+            branches.reverse();
+        }
+
+        let void = ast::Type::new_simple("void");
+        let mut typ = expected.cloned();
+        let mut checked = [None, None];
+        let mut reached = false;
+
+        for (idx, expr) in branches {
+            self.terminated = false;
+
+            let value = match (expr, &typ) {
+                (Some(expr), Some(typ)) => Some(self.check_expr_as(expr, typ)?),
+                (Some(expr), None) => Some(self.check_expr(expr)?),
+                (None, _) => None,
+            };
+            let found = value
+                .as_ref()
+                .map_or(void.clone(), |value| value.typ.clone());
+            checked[idx] = value;
+
+            if self.terminated {
+                continue;
+            }
+
+            reached = true;
+            match &typ {
+                Some(typ) => self.expect_type(&found, typ)?,
+                None => typ = Some(found),
+            }
+        }
+        self.terminated = !reached;
+
+        let [then, elze] = checked;
+        Ok(Expr::new(
+            ExprKind::If {
+                cond: Box::new(cond),
+                then: Box::new(then.unwrap()),
+                elze: elze.map(Box::new),
+            },
+            typ.unwrap_or(void),
+        ))
+    }
+
+    /// Resolves a name to the memory holding it. Local variables shadow
+    /// globals, constants and enum variants are materialized in a
+    /// read-only temporary.
+    fn lookup(&mut self, name: &str) -> Result<Place, Diagnostic> {
+        if let Some(&local) = self.scope.get(name) {
+            return Ok(Place {
+                kind: PlaceKind::Local(local),
+                typ: self.locals[local].typ.clone(),
+                mutable: true,
+            });
+        }
+
+        if let Some(&global) = self.static_index.get(name) {
+            let global = &self.statics[global];
+            return Ok(Place {
+                kind: PlaceKind::Static(global.symbol.clone()),
+                typ: global.typ.clone(),
+                mutable: global.mutable,
+            });
+        }
+
+        let constant = match self.constants.get(name) {
+            Some(constant) => constant.clone(),
+            None if self.variant(name).is_some() => self.check_variant(name, &[])?,
+            None => return Err(self.error(format!("unknown variable `{}`", name))),
+        };
+
+        Ok(temporary(constant, false))
+    }
+
+    /// Checks an expression that denotes a memory location instead of a
+    /// value, i.e. the left hand side of an assignment or the operand of `&`.
+    fn check_place(&mut self, expr: &ast::Expr) -> Result<Place, Diagnostic> {
+        match expr {
+            ast::Expr::Ident(name) => self.lookup(name),
+            ast::Expr::Prefix {
+                op: TokenKind::Asterisk,
+                expr,
+            } => {
+                let pointer = self.check_expr(expr)?;
+                let mutable = !matches!(pointer.typ, ast::Type::Ref { mutable: false, .. });
+                let typ = self.deref_type(&pointer.typ)?;
+
+                Ok(Place {
+                    kind: PlaceKind::Deref(Box::new(pointer)),
+                    typ,
+                    mutable,
+                })
+            }
+            ast::Expr::Index { expr, index } => self.check_index(expr, index),
+            ast::Expr::Field { expr, name } => self.check_field(expr, name),
+            _ => Err(self.error(format!("`{}` is not a place expression", expr))),
+        }
+    }
+
+    fn deref_type(&self, typ: &ast::Type) -> Result<ast::Type, Diagnostic> {
+        match typ.pointee() {
+            Some(inner) if !inner.is_named("void") && !is_dyn(inner) => Ok(inner.clone()),
+            _ => Err(self.error(format!("cannot dereference a value of type `{}`", typ))),
+        }
+    }
+
+    fn expect_type(&self, found: &ast::Type, expected: &ast::Type) -> Result<(), Diagnostic> {
+        if found != expected {
+            return Err(self.error(format!(
+                "expected a value of type `{}` but found `{}`",
+                expected, found
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks `expr` as a value of type `typ`. Integer literals, `null` and
+    /// array literals take on the expected type, everything else has to
+    /// match it or be coercible to it.
+    fn check_expr_as(&mut self, expr: &ast::Expr, typ: &ast::Type) -> Result<Expr, Diagnostic> {
+        match (expr, typ) {
+            (expr, typ) if typ.is_integer() && is_integer_literal(expr) => Ok(Expr::new(
+                ExprKind::Int(self.here(fold_integer(expr, typ))?),
+                typ.clone(),
+            )),
+            (
+                ast::Expr::Literal(ast::Lit::Null),
+                ast::Type::Pointer(_) | ast::Type::Optional(_),
+            ) => Ok(Expr::new(ExprKind::Null, typ.clone())),
+            (expr, ast::Type::Optional(inner)) if is_integer_literal(expr) => {
+                let value = self.check_expr_as(expr, inner)?;
+                Ok(Expr::new(ExprKind::Some(Box::new(value)), typ.clone()))
+            }
+            (ast::Expr::Array(items), ast::Type::Array { typ: elem, .. }) => {
+                let value = self.check_array(items, Some(elem))?;
+                self.coerce(value, typ)
+            }
+            (ast::Expr::If { cond, then, elze }, typ) => self.check_if(cond, then, elze, Some(typ)),
+            (ast::Expr::Block { body, tail }, typ) => self.check_block(body, tail, Some(typ)),
+            (ast::Expr::Match { expr, arms }, typ) => self.check_match(expr, arms, Some(typ)),
+            (ast::Expr::Struct { name, fields }, typ) => {
+                let value = self.check_struct(name, fields, Some(typ))?;
+                self.coerce(value, typ)
+            }
+            _ => {
+                let value = self.check_expr(expr)?;
+                self.coerce(value, typ)
+            }
+        }
+    }
+
+    /// Applies the implicit conversions between pointer-like types:
+    /// references decay to raw pointers or shared references, references
+    /// to arrays turn into slices and those to implementors of a trait
+    /// into trait objects.
+    fn coerce(&mut self, value: Expr, typ: &ast::Type) -> Result<Expr, Diagnostic> {
+        match (value.typ.clone(), typ) {
+            (from, to) if &from == to => Ok(value),
+            (
+                ast::Type::Ref { mutable, typ: from },
+                ast::Type::Ref {
+                    mutable: to_mutable,
+                    typ: to,
+                },
+            ) if (mutable || !*to_mutable) && !is_dyn(&from) && is_dyn(to) => {
+                self.trait_object(value, &from, typ)
+            }
+            (
+                ast::Type::Ref { typ: from, .. } | ast::Type::Pointer(from),
+                ast::Type::Pointer(to),
+            ) if !is_dyn(&from) && is_dyn(to) => self.trait_object(value, &from, typ),
+            (from, ast::Type::Optional(to)) if &from == to.as_ref() => {
+                Ok(Expr::new(ExprKind::Some(Box::new(value)), typ.clone()))
+            }
+            (ast::Type::Ref { typ: from, .. }, ast::Type::Pointer(to)) if &from == to => {
+                Ok(Expr::new(ExprKind::Cast(Box::new(value)), typ.clone()))
+            }
+            (
+                ast::Type::Ref { typ: from, .. },
+                ast::Type::Ref {
+                    mutable: false,
+                    typ: to,
+                },
+            ) if &from == to => Ok(Expr::new(ExprKind::Cast(Box::new(value)), typ.clone())),
+            (ast::Type::Ref { typ: from, .. }, ast::Type::Slice(to))
+            | (ast::Type::Pointer(from), ast::Type::Slice(to)) => match *from {
+                ast::Type::Array { typ: elem, len } if &elem == to => Ok(Expr::new(
+                    ExprKind::Slice {
+                        pointer: Box::new(value),
+                        len,
+                    },
+                    typ.clone(),
+                )),
+                _ => {
+                    self.expect_type(&value.typ, typ)?;
+                    unreachable!()
+                }
+            },
+            _ => {
+                self.expect_type(&value.typ, typ)?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// Checks an array literal. Without an expected element type the first
+    /// item that is not an untyped literal decides it.
+    fn check_array(
+        &mut self,
+        items: &[Box<ast::Expr>],
+        elem: Option<&ast::Type>,
+    ) -> Result<Expr, Diagnostic> {
+        let mut checked = vec![None; items.len()];
+
+        let elem = match elem {
+            Some(elem) => elem.clone(),
+            None => {
+                let idx = items
+                    .iter()
+                    .position(|item| !is_untyped_literal(item))
+                    .unwrap_or(0);
+                let first = items.get(idx).ok_or_else(|| {
+                    self.error(String::from("cannot infer the type of an empty array"))
+                })?;
+                let value = self.check_expr(first)?;
+                let elem = value.typ.clone();
+                checked[idx] = Some(value);
+                elem
+            }
+        };
+
+        for (idx, item) in items.iter().enumerate() {
+            if checked[idx].is_none() {
+                checked[idx] = Some(self.check_expr_as(item, &elem)?);
+            }
+        }
+
+        let typ = ast::Type::Array {
+            typ: Box::new(elem),
+            len: items.len(),
+        };
+        Ok(Expr::new(
+            ExprKind::Array(checked.into_iter().map(Option::unwrap).collect()),
+            typ,
+        ))
+    }
+
+    /// Checks a place expression, or keeps any other value in a temporary
+    /// so it can be indexed or have its fields read.
+    fn check_place_or_temporary(&mut self, expr: &ast::Expr) -> Result<Place, Diagnostic> {
+        if is_place_expr(expr) {
+            return self.check_place(expr);
+        }
+
+        // e.g. an array returned from a call
+        let value = self.check_expr(expr)?;
+        Ok(temporary(value, true))
+    }
+
+    /// Follows references and pointers stored at `place` to the value they
+    /// finally point to.
+    fn deref_place(&mut self, mut place: Place) -> Place {
+        while let Some(pointee) = place.typ.pointee().cloned() {
+            // trait objects are called through the reference they sit behind
+            if is_dyn(&pointee) {
+                break;
+            }
+            let mutable = !matches!(place.typ, ast::Type::Ref { mutable: false, .. });
+            place = Place {
+                kind: PlaceKind::Deref(Box::new(self.load(place))),
+                typ: pointee,
+                mutable,
+            };
+        }
+
+        place
+    }
+
+    /// The value stored at `place`.
+    fn load(&self, place: Place) -> Expr {
+        match place.kind {
+            PlaceKind::Temporary(value) => *value,
+            kind => {
+                let typ = place.typ.clone();
+                Expr::new(
+                    ExprKind::Load(Box::new(Place {
+                        kind,
+                        typ: place.typ,
+                        mutable: place.mutable,
+                    })),
+                    typ,
+                )
+            }
+        }
+    }
+
+    /// Finds a struct member, looking through references and pointers.
+    /// The builtin `len` and `ptr` of arrays and slices are read-only
+    /// temporaries.
+    fn check_field(&mut self, base: &ast::Expr, name: &str) -> Result<Place, Diagnostic> {
+        let place = self.check_place_or_temporary(base)?;
+        let place = self.deref_place(place);
+
+        let builtin = match (&place.typ, name) {
+            (ast::Type::Array { .. } | ast::Type::Slice(_), "len") => {
+                Some((true, ast::Type::new_simple("i64")))
+            }
+            (ast::Type::Slice(typ), "ptr") => {
+                Some((false, ast::Type::new_pointer(typ.as_ref().clone())))
+            }
+            _ => None,
+        };
+        if let Some((len, typ)) = builtin {
+            let place = Box::new(place);
+            let kind = if len {
+                ExprKind::Len(place)
+            } else {
+                ExprKind::Ptr(place)
+            };
+            return Ok(temporary(Expr::new(kind, typ), false));
+        }
+
+        let members = self.struct_members(&place.typ)?.unwrap_or_default();
+        let idx = members
+            .iter()
+            .position(|(member, _)| member == name)
+            .ok_or_else(|| {
+                self.error(format!(
+                    "no field `{}` on a value of type `{}`",
+                    name, place.typ
+                ))
+            })?;
+
+        Ok(Place {
+            typ: members[idx].1.clone(),
+            mutable: place.mutable,
+            kind: PlaceKind::Field {
+                base: Box::new(place),
+                index: idx,
+            },
+        })
+    }
+
+    /// Checks `base[index]`. Arrays and slices are bounds checked, raw
+    /// pointers are offset without any checks.
+    fn check_index(&mut self, base: &ast::Expr, index: &ast::Expr) -> Result<Place, Diagnostic> {
+        let place = self.check_place_or_temporary(base)?;
+
+        let (base, typ, mutable) = match place.typ.clone() {
+            ast::Type::Array { typ, .. } => {
+                let mutable = place.mutable;
+                (place, *typ, mutable)
+            }
+            ast::Type::Slice(typ) => (place, *typ, true),
+            pointer @ (ast::Type::Pointer(_) | ast::Type::Ref { .. }) => {
+                let mutable = !matches!(pointer, ast::Type::Ref { mutable: false, .. });
+                let inner = pointer.pointee().unwrap().clone();
+                let pointer_value = self.load(place);
+                match inner {
+                    ast::Type::Array { typ, .. } => {
+                        let array = Place {
+                            kind: PlaceKind::Deref(Box::new(pointer_value)),
+                            typ: pointer.pointee().unwrap().clone(),
+                            mutable,
+                        };
+                        (array, *typ, mutable)
+                    }
+                    _ if matches!(pointer, ast::Type::Pointer(_)) => {
+                        let typ = self.deref_type(&pointer)?;
+                        let index = self.check_index_value(index)?;
+                        let element = Expr::new(
+                            ExprKind::Offset {
+                                pointer: Box::new(pointer_value),
+                                offset: Box::new(index),
+                                negate: false,
+                            },
+                            pointer,
+                        );
+                        return Ok(Place {
+                            kind: PlaceKind::Deref(Box::new(element)),
+                            typ,
+                            mutable,
+                        });
+                    }
+                    _ => {
+                        return Err(
+                            self.error(format!("cannot index into a value of type `{}`", pointer))
+                        )
+                    }
+                }
+            }
+            typ => return Err(self.error(format!("cannot index into a value of type `{}`", typ))),
+        };
+
+        let index = self.check_index_value(index)?;
+        Ok(Place {
+            kind: PlaceKind::Index {
+                base: Box::new(base),
+                index: Box::new(index),
+            },
+            typ,
+            mutable,
+        })
+    }
+
+    /// Checks an index expression and widens it to `i64`.
+    fn check_index_value(&mut self, index: &ast::Expr) -> Result<Expr, Diagnostic> {
+        let i64_type = ast::Type::new_simple("i64");
+        if is_untyped_literal(index) {
+            return self.check_expr_as(index, &i64_type);
+        }
+
+        let value = self.check_expr(index)?;
+
+        if !value.typ.is_integer() {
+            return Err(self.error(format!(
+                "array indices must be integers but found `{}`",
+                value.typ
+            )));
+        }
+
+        self.check_cast(value, &i64_type)
+    }
+
+    /// Checks the postfix `!` operator, which unwraps an optional value or
+    /// checks a raw pointer for null, and traps if there is no value.
+    fn check_unwrap(&self, value: Expr) -> Result<Expr, Diagnostic> {
+        let typ = match &value.typ {
+            ast::Type::Optional(inner) => inner.as_ref().clone(),
+            typ @ ast::Type::Pointer(_) => typ.clone(),
+            typ => {
+                return Err(self.error(format!(
+                    "operator `!` expects an optional value or a raw pointer but found `{}`",
+                    typ
+                )))
+            }
+        };
+
+        Ok(Expr::new(ExprKind::Unwrap(Box::new(value)), typ))
+    }
+
+    /// Checks `expr == null` and `expr != null` for optional values and
+    /// raw pointers.
+    fn check_null_check(&mut self, op: TokenKind, expr: &ast::Expr) -> Result<Expr, Diagnostic> {
+        let value = self.check_expr(expr)?;
+
+        match &value.typ {
+            ast::Type::Optional(_) | ast::Type::Pointer(_) => {}
+            typ => return Err(self.error(format!("cannot compare `{}` with null", typ))),
+        }
+
+        Ok(Expr::new(
+            ExprKind::NullCheck {
+                operand: Box::new(value),
+                is_null: op == TokenKind::Equal,
+            },
+            ast::Type::new_simple("bool"),
+        ))
+    }
+
+    /// Checks both operands of a binary operator so that they share a
+    /// type. An integer literal or `null` on either side adopts the type of
+    /// the other.
+    fn check_operands(
+        &mut self,
+        left: &ast::Expr,
+        right: &ast::Expr,
+    ) -> Result<(Expr, Expr), Diagnostic> {
+        if is_untyped_literal(left) {
+            let rhs = self.check_expr(right)?;
+            let lhs = self.check_expr_as(left, &rhs.typ)?;
+            Ok((lhs, rhs))
+        } else {
+            let lhs = self.check_expr(left)?;
+            let rhs = self.check_expr_as(right, &lhs.typ)?;
+            Ok((lhs, rhs))
+        }
+    }
+
+    fn check_prefix(&self, op: TokenKind, x: Expr) -> Result<Expr, Diagnostic> {
+        match op {
+            TokenKind::Minus => {
+                if !x.typ.is_signed() {
+                    return Err(self.error(format!(
+                        "operator `{}` expects a signed integer operand but found `{}`",
+                        op.operator(),
+                        x.typ
+                    )));
+                }
+            }
+            // `!` is logical negation only, integers use `~`
+            TokenKind::Bang => {
+                if !x.typ.is_named("bool") {
+                    return Err(self.error(format!(
+                        "operator `{}` expects a bool operand but found `{}`",
+                        op.operator(),
+                        x.typ
+                    )));
+                }
+            }
+            TokenKind::Tilde => {
+                if !x.typ.is_integer() {
+                    return Err(self.error(format!(
+                        "operator `{}` expects an integer operand but found `{}`",
+                        op.operator(),
+                        x.typ
+                    )));
+                }
+            }
+            TokenKind::Asterisk => {
+                let typ = self.deref_type(&x.typ)?;
+                let mutable = !matches!(x.typ, ast::Type::Ref { mutable: false, .. });
+                return Ok(self.load(Place {
+                    kind: PlaceKind::Deref(Box::new(x)),
+                    typ,
+                    mutable,
+                }));
+            }
+            _ => panic!("not a valid prefix operator"),
+        }
+
+        let typ = x.typ.clone();
+        Ok(Expr::new(
+            ExprKind::Unary {
+                op,
+                operand: Box::new(x),
+            },
+            typ,
+        ))
+    }
+
+    /// Checks the right operand of the shift `op` and converts it to the
+    /// type of the shifted value.
+    fn check_shift_amount(
+        &mut self,
+        op: TokenKind,
+        right: &ast::Expr,
+        typ: &ast::Type,
+    ) -> Result<Expr, Diagnostic> {
+        if !typ.is_integer() {
+            return Err(self.error(format!(
+                "operator `{}` expects integer operands but found `{}`",
+                op.operator(),
+                typ
+            )));
+        }
+        if is_untyped_literal(right) {
+            return self.check_expr_as(right, typ);
+        }
+
+        let value = self.check_expr(right)?;
+        if !value.typ.is_integer() {
+            return Err(self.error(format!(
+                "shift amounts must be integers but found `{}`",
+                value.typ
+            )));
+        }
+
+        self.check_cast(value, typ)
+    }
+
+    /// Checks a binary operator on two operands of the same type.
+    fn check_infix(&self, op: TokenKind, lhs: Expr, rhs: Expr) -> Result<Expr, Diagnostic> {
+        let typ = lhs.typ.clone();
+
+        match op {
+            TokenKind::Plus
+            | TokenKind::Minus
+            | TokenKind::Asterisk
+            | TokenKind::Slash
+            | TokenKind::Percent
+            | TokenKind::ShiftLeft
+            | TokenKind::ShiftRight => {
+                if !typ.is_integer() {
+                    return Err(self.error(format!(
+                        "operator `{}` expects integer operands but found `{}`",
+                        op.operator(),
+                        typ
+                    )));
+                }
+            }
+            TokenKind::LessThan
+            | TokenKind::GreaterThan
+            | TokenKind::LessEqual
+            | TokenKind::GreaterEqual => {
+                if !(typ.is_integer() || matches!(typ, ast::Type::Pointer(_))) {
+                    return Err(self.error(format!(
+                        "operator `{}` expects integer or pointer operands but found `{}`",
+                        op.operator(),
+                        typ
+                    )));
+                }
+            }
+            TokenKind::Equal | TokenKind::UnEqual => {
+                if !(typ.is_integer()
+                    || typ.is_named("bool")
+                    || typ.pointee().is_some()
+                    || self.enum_of(&typ).is_some_and(|e| !e.has_payload()))
+                {
+                    return Err(self.error(format!(
+                        "operator `{}` cannot compare values of type `{}`",
+                        op.operator(),
+                        typ
+                    )));
+                }
+            }
+            TokenKind::And | TokenKind::Pipe | TokenKind::Caret => {
+                if !(typ.is_integer() || typ.is_named("bool")) {
+                    return Err(self.error(format!(
+                        "operator `{}` expects integer or bool operands but found `{}`",
+                        op.operator(),
+                        typ
+                    )));
+                }
+            }
+            _ => panic!("not a valid infix operator"),
+        }
+
+        let typ = match op {
+            TokenKind::Equal
+            | TokenKind::UnEqual
+            | TokenKind::LessThan
+            | TokenKind::GreaterThan
+            | TokenKind::LessEqual
+            | TokenKind::GreaterEqual => ast::Type::new_simple("bool"),
+            _ => typ,
+        };
+        Ok(Expr::new(
+            ExprKind::Binary {
+                op,
+                left: Box::new(lhs),
+                right: Box::new(rhs),
+            },
+            typ,
+        ))
+    }
+
+    /// Checks `&&` and `||`. The right operand may not be evaluated, so
+    /// whether it diverges doesn't matter.
+    fn check_short_circuit(
+        &mut self,
+        op: TokenKind,
+        left: &ast::Expr,
+        right: &ast::Expr,
+    ) -> Result<Expr, Diagnostic> {
+        let bool_type = ast::Type::new_simple("bool");
+        let lhs = self.check_expr_as(left, &bool_type)?;
+        let terminated = self.terminated;
+        let rhs = self.check_expr_as(right, &bool_type)?;
+        self.terminated = terminated;
+
+        Ok(Expr::new(
+            ExprKind::Logical {
+                op,
+                left: Box::new(lhs),
+                right: Box::new(rhs),
+            },
+            bool_type,
+        ))
+    }
+
+    /// Checks offsetting a raw pointer by an integer number of elements,
+    /// or the distance in elements between two raw pointers of the same
+    /// type.
+    fn check_pointer_arith(
+        &mut self,
+        op: TokenKind,
+        lhs: Expr,
+        right: &ast::Expr,
+    ) -> Result<Expr, Diagnostic> {
+        let pointee = self.deref_type(&lhs.typ)?;
+
+        let rhs = if is_untyped_literal(right) {
+            self.check_expr_as(right, &ast::Type::new_simple("i64"))?
+        } else {
+            self.check_expr(right)?
+        };
+
+        if rhs.typ.is_integer() {
+            let typ = lhs.typ.clone();
+            return Ok(Expr::new(
+                ExprKind::Offset {
+                    pointer: Box::new(lhs),
+                    offset: Box::new(rhs),
+                    negate: op == TokenKind::Minus,
+                },
+                typ,
+            ));
+        }
+
+        if op != TokenKind::Minus || rhs.typ != lhs.typ {
+            return Err(self.error(format!(
+                "cannot apply `{}` to `{}` and `{}`",
+                op.operator(),
+                lhs.typ,
+                rhs.typ
+            )));
+        }
+        if pointee.is_named("void") {
+            return Err(self.error(format!(
+                "cannot compute the distance between `{}` pointers",
+                lhs.typ
+            )));
+        }
+
+        Ok(Expr::new(
+            ExprKind::Distance {
+                left: Box::new(lhs),
+                right: Box::new(rhs),
+            },
+            ast::Type::new_simple("i64"),
+        ))
+    }
+
+    /// Checks an explicit `as` cast, or one the language implies.
+    fn check_cast(&self, value: Expr, typ: &ast::Type) -> Result<Expr, Diagnostic> {
+        // trait objects are two pointers, they only convert between
+        // references and pointers to the same trait
+        let is_pointer = |typ: &ast::Type| typ.pointee().is_some_and(|inner| !is_dyn(inner));
+        let is_object = |typ: &ast::Type| typ.pointee().is_some_and(is_dyn);
+
+        let valid = match (&value.typ, typ) {
+            (from, to) if from == to => return Ok(value),
+            (from, to) if from.is_integer() && to.is_integer() => true,
+            // enums without payload cast to their discriminant
+            (from, to)
+                if to.is_integer() && self.enum_of(from).is_some_and(|e| !e.has_payload()) =>
+            {
+                true
+            }
+            (from, to) if from.is_named("bool") && to.is_integer() => true,
+            (from, to) if is_pointer(from) && (is_pointer(to) || to.is_integer()) => true,
+            (from, to) if from.is_integer() && is_pointer(to) => true,
+            (from, to) if is_object(from) && is_object(to) => from.pointee() == to.pointee(),
+            _ => false,
+        };
+        if !valid {
+            return Err(self.error(format!("cannot cast `{}` to `{}`", value.typ, typ)));
+        }
+
+        Ok(Expr::new(ExprKind::Cast(Box::new(value)), typ.clone()))
+    }
+
+    /// Applies the C default argument promotions to a variadic argument.
+    fn promote_vararg(&self, value: Expr) -> Result<Expr, Diagnostic> {
+        let narrow = value.typ.int_width().is_some_and(|width| width < 32);
+        if value.typ.is_named("bool") || narrow {
+            self.check_cast(value, &ast::Type::new_simple("i32"))
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn check_expr(&mut self, expr: &ast::Expr) -> Result<Expr, Diagnostic> {
+        match expr {
+            // integer literals default to `i64` without an expected type
+            expr if is_integer_literal(expr) => {
+                self.check_expr_as(expr, &ast::Type::new_simple("i64"))
+            }
+            ast::Expr::Literal(l) => match l {
+                ast::Lit::Integer(_) => unreachable!("integer literals are folded above"),
+                ast::Lit::Boolean(b) => {
+                    Ok(Expr::new(ExprKind::Bool(*b), ast::Type::new_simple("bool")))
+                }
+                ast::Lit::String(s) => {
+                    if s.contains('\0') {
+                        return Err(
+                            self.error(String::from("string literals cannot contain nul bytes"))
+                        );
+                    }
+                    Ok(Expr::new(
+                        ExprKind::Str(s.clone()),
+                        ast::Type::new_pointer(ast::Type::new_simple("i8")),
+                    ))
+                }
+                ast::Lit::Null => Ok(Expr::new(
+                    ExprKind::Null,
+                    ast::Type::new_pointer(ast::Type::new_simple("void")),
+                )),
+            },
+            ast::Expr::Call { name, args } if self.variant(name).is_some() => {
+                self.check_variant(name, args)
+            }
+            ast::Expr::Struct { name, fields } => self.check_struct(name, fields, None),
+            ast::Expr::Call { name, args } => self.check_call(name, None, args),
+            ast::Expr::MethodCall {
+                receiver,
+                name,
+                args,
+            } => self.check_method_call(receiver, name, args),
+            ast::Expr::Prefix { op, expr } => {
+                let x = self.check_expr(expr)?;
+                self.check_prefix(*op, x)
+            }
+            ast::Expr::Postfix {
+                op: TokenKind::Bang,
+                expr,
+            } => {
+                let value = self.check_expr(expr)?;
+                self.check_unwrap(value)
+            }
+            ast::Expr::Array(items) => self.check_array(items, None),
+            ast::Expr::If { cond, then, elze } => self.check_if(cond, then, elze, None),
+            ast::Expr::Block { body, tail } => self.check_block(body, tail, None),
+            ast::Expr::Match { expr, arms } => self.check_match(expr, arms, None),
+            ast::Expr::Index { .. } | ast::Expr::Field { .. } => {
+                let place = self.check_place(expr)?;
+                Ok(self.load(place))
+            }
+            ast::Expr::AddrOf { mutable, expr } => {
+                let place = self.check_place(expr)?;
+                if *mutable && !place.mutable {
+                    return Err(self.error(format!("cannot borrow `{}` as mutable, it is immutable or behind a shared reference", expr)));
+                }
+
+                let typ = ast::Type::new_ref(place.typ.clone(), *mutable);
+                Ok(Expr::new(ExprKind::AddrOf(Box::new(place)), typ))
+            }
+            ast::Expr::Cast { expr, typ } => {
+                let typ = &self.resolve_type(typ);
+                self.check_type(typ)?;
+                let value = if typ.is_integer() && is_untyped_literal(expr) {
+                    self.check_expr_as(expr, typ)?
+                } else {
+                    self.check_expr(expr)?
+                };
+
+                self.check_cast(value, typ)
+            }
+            ast::Expr::Infix {
+                op: op @ (TokenKind::LogicalAnd | TokenKind::LogicalOr),
+                left,
+                right,
+            } => self.check_short_circuit(*op, left, right),
+            ast::Expr::Infix {
+                op: op @ (TokenKind::Equal | TokenKind::UnEqual),
+                left,
+                right,
+            } if is_null(left) || is_null(right) => {
+                let other = if is_null(left) { right } else { left };
+                self.check_null_check(*op, other)
+            }
+            ast::Expr::Infix { op, left, right } => {
+                let (lhs, rhs) = match op {
+                    TokenKind::ShiftLeft | TokenKind::ShiftRight => {
+                        let lhs = self.check_expr(left)?;
+                        let rhs = self.check_shift_amount(*op, right, &lhs.typ)?;
+                        (lhs, rhs)
+                    }
+                    TokenKind::Plus | TokenKind::Minus if !is_untyped_literal(left) => {
+                        let lhs = self.check_expr(left)?;
+                        if let ast::Type::Pointer(_) = lhs.typ {
+                            return self.check_pointer_arith(*op, lhs, right);
+                        }
+
+                        let rhs = self.check_expr_as(right, &lhs.typ)?;
+                        (lhs, rhs)
+                    }
+                    _ => self.check_operands(left, right)?,
+                };
+                self.check_infix(*op, lhs, rhs)
+            }
+            ast::Expr::Ident(name) if self.variant(name).is_some() => self.check_variant(name, &[]),
+            // constants are inlined at every use
+            ast::Expr::Ident(name)
+                if !self.scope.contains_key(name) && self.constants.contains_key(name) =>
+            {
+                Ok(self.constants[name].clone())
+            }
+            ast::Expr::Ident(name) => {
+                let place = self.lookup(name)?;
+                Ok(self.load(place))
+            }
+
+            _ => panic!("not implemented"),
+        }
+    }
+}
+
+/// A new read-only or writable slot holding `value`.
+fn temporary(value: Expr, mutable: bool) -> Place {
+    Place {
+        typ: value.typ.clone(),
+        kind: PlaceKind::Temporary(Box::new(value)),
+        mutable,
+    }
+}
@@ -0,0 +1,1321 @@
+//! Rules of the language that don't depend on how a program is run:
+//! lowering `impl` blocks and checking them against their traits, type
+//! checking, inference and coercions, exhaustiveness of `match` and
+//! folding constants. `check` applies them to a whole program and lowers
+//! it to the typed program in `typed`, which the backends and the
+//! interpreter consume. Tools that only need lyth's front end, like
+//! editors and linters, can call it without LLVM to get the errors in a
+//! program as diagnostics.
+
+mod check;
+pub mod typed;
+
+pub use check::{Binding, Checker, Entry};
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::token::TokenKind;
+use crate::parser::ast;
+use std::collections::{HashMap, HashSet};
+
+/// Called with the index and the length when an index is out of bounds,
+/// instead of trapping, if the program defines it.
+pub const BOUNDS_PANIC_HANDLER: &str = "lyth_panic_bounds";
+/// Called on a division by zero, an overflowing division or an
+/// oversized shift, instead of trapping, if the program defines it.
+pub const ARITH_PANIC_HANDLER: &str = "lyth_panic_arith";
+
+/// Checks the declarations of a loaded program whose root is `file` and
+/// returns its typed program, or the errors in it. Declarations are
+/// checked up to the first error in them, the bodies of all functions are
+/// checked even if some have errors.
+pub fn check(file: &str, decls: &[ast::Decl]) -> Result<typed::Program, Vec<Diagnostic>> {
+    Checker::new(file).check(decls.to_vec())
+}
+
+/// A declared trait. Its functions are checked against every `impl` of
+/// it, the ones with a body are defaults for those leaving them out.
+pub struct Trait {
+    pub functions: Vec<ast::TraitFunction>,
+}
+
+/// The traits of a program and the types implementing them.
+#[derive(Default)]
+pub struct Traits {
+    pub declared: HashMap<String, Trait>,
+    pub impls: HashSet<(String, String)>,
+}
+
+impl Traits {
+    /// The declared trait `trait_name`, an error if there is none.
+    pub fn get(&self, trait_name: &str) -> Result<&Trait, String> {
+        self.declared
+            .get(trait_name)
+            .ok_or_else(|| format!("unknown trait `{}`", trait_name))
+    }
+
+    /// Whether an `impl` of `trait_name` for `typ` was lowered.
+    pub fn implements(&self, typ: &ast::Type, trait_name: &str) -> bool {
+        match typ {
+            ast::Type::Named { name, .. } => {
+                self.impls.contains(&(name.clone(), trait_name.to_string()))
+            }
+            _ => false,
+        }
+    }
+
+    /// Registers the traits and turns the functions of `impl` blocks into
+    /// free functions named `Type::function`, which are also generic over
+    /// the type parameters of the block. Functions implementing a trait
+    /// are named `<Type as Trait>::function` instead.
+    pub fn lower_impls(&mut self, decls: Vec<ast::Decl>) -> Result<Vec<ast::Decl>, Diagnostic> {
+        let mut lowered = Vec::new();
+        let mut impls = Vec::new();
+
+        // traits may be implemented before they are declared
+        for decl in decls {
+            match decl {
+                ast::Decl::Trait {
+                    name,
+                    functions,
+                    file,
+                    pos,
+                } => {
+                    if self.declared.contains_key(&name) {
+                        return Err(Diagnostic::new(
+                            &file,
+                            pos,
+                            format!("trait `{}` is declared more than once", name),
+                        ));
+                    }
+                    self.declared.insert(name, Trait { functions });
+                }
+                ast::Decl::Impl { .. } => impls.push(decl),
+                decl => lowered.push(decl),
+            }
+        }
+
+        for decl in impls {
+            let (type_params, trait_name, typ, mut functions, file, pos) = match decl {
+                ast::Decl::Impl {
+                    type_params,
+                    trait_name,
+                    typ,
+                    functions,
+                    file,
+                    pos,
+                } => (type_params, trait_name, typ, functions, file, pos),
+                _ => unreachable!(),
+            };
+
+            let type_name = match &typ {
+                ast::Type::Named { name, .. } => name.clone(),
+                _ => unreachable!("the parser only accepts named types"),
+            };
+
+            // `Self` in signatures is the implementing type
+            let mut self_type = HashMap::new();
+            self_type.insert(String::from("Self"), typ.clone());
+            for function in functions.iter_mut() {
+                if let ast::Decl::Function { params, rtyp, .. } = function {
+                    for (_, typ) in params.iter_mut() {
+                        *typ = substitute(typ, &self_type);
+                    }
+                    *rtyp = rtyp.as_ref().map(|typ| substitute(typ, &self_type));
+                }
+            }
+
+            let prefix = match trait_name {
+                Some(trait_name) => {
+                    self.implement_trait(&trait_name, &typ, &mut functions)
+                        .map_err(|message| Diagnostic::new(&file, pos, message))?;
+                    format!("<{} as {}>", type_name, trait_name)
+                }
+                None => type_name,
+            };
+
+            for function in functions {
+                if let ast::Decl::Function {
+                    attrs,
+                    inline,
+                    name,
+                    type_params: own_params,
+                    params,
+                    body,
+                    rtyp,
+                    file,
+                    pos,
+                } = function
+                {
+                    lowered.push(ast::Decl::Function {
+                        attrs,
+                        inline,
+                        name: format!("{}::{}", prefix, name),
+                        type_params: type_params.iter().chain(&own_params).cloned().collect(),
+                        params,
+                        body,
+                        rtyp,
+                        file,
+                        pos,
+                    });
+                }
+            }
+        }
+
+        Ok(lowered)
+    }
+
+    /// Checks that `functions` match the declarations of the trait
+    /// `trait_name`, and adds its defaults for the ones left out.
+    fn implement_trait(
+        &mut self,
+        trait_name: &str,
+        typ: &ast::Type,
+        functions: &mut Vec<ast::Decl>,
+    ) -> Result<(), String> {
+        let type_name = match typ {
+            ast::Type::Named { name, .. } => name.clone(),
+            _ => unreachable!("the parser only accepts named types"),
+        };
+        let trait_ = self
+            .declared
+            .get(trait_name)
+            .ok_or_else(|| format!("unknown trait `{}`", trait_name))?;
+        if !self.impls.insert((type_name, trait_name.to_string())) {
+            return Err(format!(
+                "trait `{}` is implemented more than once for `{}`",
+                trait_name, typ
+            ));
+        }
+
+        let mut self_type = HashMap::new();
+        self_type.insert(String::from("Self"), typ.clone());
+        let signature = |function: &ast::TraitFunction| {
+            let params: Vec<_> = function
+                .params
+                .iter()
+                .map(|(name, typ)| (name.clone(), substitute(typ, &self_type)))
+                .collect();
+            let rtyp = function
+                .rtyp
+                .as_ref()
+                .map(|typ| substitute(typ, &self_type));
+            (params, rtyp)
+        };
+
+        for function in functions.iter() {
+            if let ast::Decl::Function {
+                name,
+                type_params,
+                params,
+                rtyp,
+                ..
+            } = function
+            {
+                let declared = trait_
+                    .functions
+                    .iter()
+                    .find(|declared| &declared.name == name)
+                    .ok_or_else(|| {
+                        format!("`{}` is not a function of trait `{}`", name, trait_name)
+                    })?;
+
+                let (expected_params, expected_rtyp) = signature(declared);
+                let same_types = expected_params.len() == params.len()
+                    && expected_params
+                        .iter()
+                        .zip(params)
+                        .all(|((_, expected), (_, found))| expected == found);
+                if !(type_params.is_empty() && same_types && &expected_rtyp == rtyp) {
+                    return Err(format!(
+                        "`{}` of `{}` does not match its declaration in trait `{}`",
+                        name, typ, trait_name
+                    ));
+                }
+            }
+        }
+
+        for declared in &trait_.functions {
+            let provided = functions.iter().any(
+                |function| matches!(function, ast::Decl::Function { name, .. } if name == &declared.name),
+            );
+            if provided {
+                continue;
+            }
+
+            let body = declared.body.clone().ok_or_else(|| {
+                format!(
+                    "`{}` is missing `{}` of trait `{}`",
+                    typ, declared.name, trait_name
+                )
+            })?;
+            let (params, rtyp) = signature(declared);
+            functions.push(ast::Decl::Function {
+                attrs: Vec::new(),
+                inline: None,
+                name: declared.name.clone(),
+                type_params: Vec::new(),
+                params,
+                body,
+                rtyp,
+                file: declared.file.clone(),
+                pos: declared.pos,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The methods of a trait that can be called on trait objects. They
+    /// take `self` by reference and don't mention `Self` otherwise, and
+    /// make up the trait's vtable in declaration order.
+    pub fn object_methods(&self, trait_name: &str) -> Result<Vec<ast::TraitFunction>, String> {
+        let self_param = [String::from("Self")];
+        let methods = self
+            .get(trait_name)?
+            .functions
+            .iter()
+            .filter(|function| {
+                let by_ref = matches!(
+                    function.params.first(),
+                    Some((name, ast::Type::Ref { typ, .. })) if name == "self" && typ.is_named("Self")
+                );
+                by_ref
+                    && !function.params[1..]
+                        .iter()
+                        .chain(&function.rtyp.as_ref().map(|typ| (String::new(), typ.clone())))
+                        .any(|(_, typ)| mentions_params(typ, &self_param))
+            })
+            .cloned()
+            .collect();
+        Ok(methods)
+    }
+
+    /// Finds the function implementing the method `name` of `typ`. The
+    /// type's own methods come first, then those of the traits it
+    /// implements.
+    pub fn method_path(
+        &self,
+        methods: &HashSet<String>,
+        typ: &ast::Type,
+        name: &str,
+    ) -> Result<String, String> {
+        let type_name = match typ {
+            ast::Type::Named { name, .. } => name,
+            typ => return Err(format!("no method `{}` on type `{}`", name, typ)),
+        };
+
+        let path = format!("{}::{}", type_name, name);
+        if methods.contains(&path) {
+            return Ok(path);
+        }
+
+        let mut candidates: Vec<_> = self
+            .impls
+            .iter()
+            .filter(|(implementor, _)| implementor == type_name)
+            .map(|(_, trait_name)| format!("<{} as {}>::{}", type_name, trait_name, name))
+            .filter(|path| methods.contains(path))
+            .collect();
+        candidates.sort();
+
+        match candidates.len() {
+            0 => Err(format!("no method `{}` on type `{}`", name, typ)),
+            1 => Ok(candidates.pop().unwrap()),
+            _ => Err(format!(
+                "method `{}` of type `{}` is ambiguous, candidates are {}",
+                name,
+                typ,
+                candidates.join(", ")
+            )),
+        }
+    }
+}
+
+/// Evaluates an expression made up of integer literals as a constant of
+/// type `typ`. Every intermediate result has to fit into `typ`.
+pub(crate) fn fold_integer(expr: &ast::Expr, typ: &ast::Type) -> Result<i128, String> {
+    let width = typ.int_width().unwrap();
+    let (min, max) = if typ.is_signed() {
+        (-(1i128 << (width - 1)), (1i128 << (width - 1)) - 1)
+    } else {
+        (
+            0,
+            if width == 128 {
+                i128::MAX
+            } else {
+                (1i128 << width) - 1
+            },
+        )
+    };
+
+    let value = match expr {
+        ast::Expr::Literal(ast::Lit::Integer(i)) => Some(*i as i128),
+        // `-128` is a valid `i8` even though `128` on its own isn't
+        ast::Expr::Prefix {
+            op: TokenKind::Minus,
+            expr,
+        } if matches!(expr.as_ref(), ast::Expr::Literal(_)) => {
+            if !typ.is_signed() {
+                return Err(format!("cannot negate a value of unsigned type `{}`", typ));
+            }
+            match expr.as_ref() {
+                ast::Expr::Literal(ast::Lit::Integer(i)) => Some(-(*i as i128)),
+                _ => unreachable!(),
+            }
+        }
+        ast::Expr::Prefix { op, expr } => {
+            let x = fold_integer(expr, typ)?;
+            match op {
+                TokenKind::Minus => {
+                    if !typ.is_signed() {
+                        return Err(format!("cannot negate a value of unsigned type `{}`", typ));
+                    }
+                    x.checked_neg()
+                }
+                // the complement of an unsigned value stays within its width
+                TokenKind::Tilde if typ.is_signed() => Some(!x),
+                TokenKind::Tilde => Some(!x & max),
+                _ => unreachable!(),
+            }
+        }
+        ast::Expr::Infix { op, left, right } => {
+            let lhs = fold_integer(left, typ)?;
+            let rhs = fold_integer(right, typ)?;
+            match op {
+                TokenKind::Plus => lhs.checked_add(rhs),
+                TokenKind::Minus => lhs.checked_sub(rhs),
+                TokenKind::Asterisk => lhs.checked_mul(rhs),
+                TokenKind::Slash | TokenKind::Percent if rhs == 0 => {
+                    return Err(format!("division by zero in `{}`", expr))
+                }
+                TokenKind::Slash => lhs.checked_div(rhs),
+                TokenKind::Percent => lhs.checked_rem(rhs),
+                TokenKind::And => Some(lhs & rhs),
+                TokenKind::Pipe => Some(lhs | rhs),
+                TokenKind::Caret => Some(lhs ^ rhs),
+                TokenKind::ShiftLeft | TokenKind::ShiftRight if rhs >= width as i128 => {
+                    return Err(format!(
+                        "shift amount in `{}` is too large for `{}`",
+                        expr, typ
+                    ))
+                }
+                TokenKind::ShiftLeft => lhs.checked_shl(rhs as u32),
+                TokenKind::ShiftRight => lhs.checked_shr(rhs as u32),
+                _ => unreachable!(),
+            }
+        }
+        _ => unreachable!(),
+    };
+
+    match value {
+        Some(value) if min <= value && value <= max => Ok(value),
+        _ => Err(format!("`{}` is out of range for type `{}`", expr, typ)),
+    }
+}
+
+/// Whether `expr` denotes a memory location that can be assigned to or
+/// borrowed.
+pub(crate) fn is_place_expr(expr: &ast::Expr) -> bool {
+    matches!(
+        expr,
+        ast::Expr::Ident(_)
+            | ast::Expr::Prefix {
+                op: TokenKind::Asterisk,
+                ..
+            }
+            | ast::Expr::Index { .. }
+            | ast::Expr::Field { .. }
+    )
+}
+
+/// Whether the value of `expr` is an untyped literal, directly or as the
+/// tail of a block.
+pub(crate) fn yields_untyped_literal(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Block {
+            tail: Some(tail), ..
+        } => yields_untyped_literal(tail),
+        expr => is_untyped_literal(expr),
+    }
+}
+
+/// The names of the declared type parameters `type_params`.
+pub(crate) fn type_param_names(type_params: &[ast::TypeParam]) -> Vec<String> {
+    type_params.iter().map(|param| param.name.clone()).collect()
+}
+
+/// Whether `typ` is a trait object type, `dyn Trait`.
+pub(crate) fn is_dyn(typ: &ast::Type) -> bool {
+    matches!(typ, ast::Type::Dyn(_))
+}
+
+/// Whether a function with the parameters `params` is a method, which
+/// takes `self` first.
+pub(crate) fn is_method(params: &[(String, ast::Type)]) -> bool {
+    params.first().is_some_and(|(name, _)| name == "self")
+}
+
+/// Replaces the type parameters in `typ` by the types bound to them.
+pub(crate) fn substitute(typ: &ast::Type, args: &HashMap<String, ast::Type>) -> ast::Type {
+    match typ {
+        ast::Type::Named { name, generics } if generics.is_empty() && args.contains_key(name) => {
+            args[name].clone()
+        }
+        ast::Type::Named { name, generics } => ast::Type::Named {
+            name: name.clone(),
+            generics: generics.iter().map(|typ| substitute(typ, args)).collect(),
+        },
+        ast::Type::Pointer(typ) => ast::Type::Pointer(Box::new(substitute(typ, args))),
+        ast::Type::Ref { mutable, typ } => ast::Type::Ref {
+            mutable: *mutable,
+            typ: Box::new(substitute(typ, args)),
+        },
+        ast::Type::Array { typ, len } => ast::Type::Array {
+            typ: Box::new(substitute(typ, args)),
+            len: *len,
+        },
+        ast::Type::Slice(typ) => ast::Type::Slice(Box::new(substitute(typ, args))),
+        ast::Type::Optional(typ) => ast::Type::Optional(Box::new(substitute(typ, args))),
+        ast::Type::Dyn(name) => ast::Type::Dyn(name.clone()),
+    }
+}
+
+/// Whether `typ` refers to any of the type parameters `params`.
+pub(crate) fn mentions_params(typ: &ast::Type, params: &[String]) -> bool {
+    match typ {
+        ast::Type::Named { name, generics } => {
+            (generics.is_empty() && params.contains(name))
+                || generics.iter().any(|typ| mentions_params(typ, params))
+        }
+        ast::Type::Pointer(typ)
+        | ast::Type::Ref { typ, .. }
+        | ast::Type::Array { typ, .. }
+        | ast::Type::Slice(typ)
+        | ast::Type::Optional(typ) => mentions_params(typ, params),
+        ast::Type::Dyn(_) => false,
+    }
+}
+
+/// Binds the type parameters in `pattern` to the corresponding parts of
+/// `actual`. Parameters that are bound already keep their type, a
+/// mismatch is reported when the value is converted to the parameter.
+pub(crate) fn infer_type_args(
+    pattern: &ast::Type,
+    actual: &ast::Type,
+    params: &[String],
+    bindings: &mut HashMap<String, ast::Type>,
+) {
+    match (pattern, actual) {
+        (ast::Type::Named { name, generics }, actual)
+            if generics.is_empty() && params.contains(name) =>
+        {
+            bindings
+                .entry(name.clone())
+                .or_insert_with(|| actual.clone());
+        }
+        (
+            ast::Type::Named {
+                name: pattern_name,
+                generics: pattern_generics,
+            },
+            ast::Type::Named { name, generics },
+        ) if pattern_name == name && pattern_generics.len() == generics.len() => {
+            for (pattern, actual) in pattern_generics.iter().zip(generics) {
+                infer_type_args(pattern, actual, params, bindings);
+            }
+        }
+        // references coerce to raw pointers
+        (
+            ast::Type::Pointer(pattern),
+            ast::Type::Pointer(actual) | ast::Type::Ref { typ: actual, .. },
+        )
+        | (ast::Type::Ref { typ: pattern, .. }, ast::Type::Ref { typ: actual, .. })
+        | (ast::Type::Array { typ: pattern, .. }, ast::Type::Array { typ: actual, .. })
+        | (ast::Type::Slice(pattern), ast::Type::Slice(actual))
+        | (ast::Type::Optional(pattern), ast::Type::Optional(actual)) => {
+            infer_type_args(pattern, actual, params, bindings)
+        }
+        _ => {}
+    }
+}
+
+/// Whether matching `pattern` introduces any names.
+pub(crate) fn binds_names(pattern: &ast::Pattern) -> bool {
+    match pattern {
+        ast::Pattern::Binding(_) => true,
+        ast::Pattern::Variant { fields, .. } => fields.iter().any(|field| field != "_"),
+        ast::Pattern::Or(patterns) => patterns.iter().any(binds_names),
+        ast::Pattern::Wildcard | ast::Pattern::Literal(_) => false,
+    }
+}
+
+/// Whether `expr` is the `null` literal.
+pub(crate) fn is_null(expr: &ast::Expr) -> bool {
+    matches!(expr, ast::Expr::Literal(ast::Lit::Null))
+}
+
+/// Untyped literals take on the type their context expects. Operators
+/// applied only to integer literals, like `1 << 4`, count as literals too.
+pub(crate) fn is_untyped_literal(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Literal(ast::Lit::Null) => true,
+        _ => is_integer_literal(expr),
+    }
+}
+
+/// Whether `expr` is an integer literal, or an operator applied to
+/// integer literals only.
+pub(crate) fn is_integer_literal(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Literal(ast::Lit::Integer(_)) => true,
+        ast::Expr::Prefix {
+            op: TokenKind::Minus | TokenKind::Tilde,
+            expr,
+        } => is_integer_literal(expr),
+        ast::Expr::Infix {
+            op:
+                TokenKind::Plus
+                | TokenKind::Minus
+                | TokenKind::Asterisk
+                | TokenKind::Slash
+                | TokenKind::Percent
+                | TokenKind::And
+                | TokenKind::Pipe
+                | TokenKind::Caret
+                | TokenKind::ShiftLeft
+                | TokenKind::ShiftRight,
+            left,
+            right,
+        } => is_integer_literal(left) && is_integer_literal(right),
+        _ => false,
+    }
+}
+
+/// Truncates `value` to the width of the integer type `typ`.
+pub(crate) fn wrap(value: i128, typ: &ast::Type) -> i128 {
+    match typ.int_width() {
+        Some(width) if width < 128 => {
+            let shift = 128 - width;
+            if typ.is_signed() {
+                (value << shift) >> shift
+            } else {
+                value & ((1 << width) - 1)
+            }
+        }
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn decls(input: &str) -> Vec<ast::Decl> {
+        let items = Parser::new("test.ly", input).parse().unwrap();
+        items.into_iter().map(|item| item.decl).collect()
+    }
+
+    fn errors(input: &str) -> Vec<Diagnostic> {
+        match check("test.ly", &decls(input)) {
+            Ok(_) => panic!("`{}` was accepted", input),
+            Err(errors) => errors,
+        }
+    }
+
+    #[test]
+    fn checked_program() {
+        let input = "fn triple(x: i32) i32 { let y = x * 3; return y; }";
+        let program = check("test.ly", &decls(input)).unwrap();
+        assert_eq!(program.functions.len(), 1);
+        assert_eq!(program.functions[0].name, "triple");
+        assert_eq!(
+            program.functions[0].locals[1].typ,
+            ast::Type::new_simple("i32")
+        );
+    }
+
+    #[test]
+    fn errors_are_diagnostics() {
+        assert_eq!(
+            errors("fn main() i32 { return y; }"),
+            [Diagnostic {
+                file: String::from("test.ly"),
+                line: 1,
+                col: 17,
+                message: String::from("unknown variable `y`"),
+            }]
+        );
+        assert_eq!(
+            errors("fn main() i32 { return true; }")[0].message,
+            "expected a value of type `i32` but found `bool`"
+        );
+    }
+
+    #[test]
+    fn errors_in_every_function() {
+        let input = "fn f() i32 {\n    return true;\n}\n\nfn g() {\n    let x: u8 = y;\n}\n";
+        let errors: Vec<_> = errors(input)
+            .into_iter()
+            .map(|error| (error.line, error.col, error.message))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    2,
+                    5,
+                    String::from("expected a value of type `i32` but found `bool`")
+                ),
+                (6, 5, String::from("unknown variable `y`")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_errors_are_diagnostics() {
+        let error = Parser::new("test.ly", "fn f() {\n    let = 1;\n}\n")
+            .parse()
+            .unwrap_err();
+        assert_eq!((error.line, error.col), (2, 9));
+        assert_eq!(error.to_string(), format!("test.ly:2:9: {}", error.message));
+    }
+
+    #[test]
+    fn errors_in_imported_modules() {
+        let dir = std::env::temp_dir().join(format!("lyth-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.ly").display().to_string();
+        let bad = dir.join("bad.ly").display().to_string();
+        std::fs::write(
+            &main,
+            "import bad;\n\nfn main() i32 {\n    return bad::f();\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &bad,
+            "pub fn f() i32 {\n    let x = 1;\n    let y: i64 = true;\n    return 0;\n}\n",
+        )
+        .unwrap();
+
+        let result = check(&main, &crate::loader::load(&main).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        match result {
+            Ok(_) => panic!("`{}` was accepted", bad),
+            Err(errors) => assert_eq!(
+                errors,
+                [Diagnostic {
+                    file: bad,
+                    line: 3,
+                    col: 5,
+                    message: String::from("expected a value of type `i64` but found `bool`"),
+                }]
+            ),
+        }
+    }
+
+    #[test]
+    fn operator_operands() {
+        assert_eq!(
+            errors("fn f() bool { return !5; }")[0].message,
+            "operator `!` expects a bool operand but found `i64`"
+        );
+        assert_eq!(
+            errors("fn f(x: u8) u8 { return -x; }")[0].message,
+            "operator `-` expects a signed integer operand but found `u8`"
+        );
+        assert_eq!(
+            errors("fn f() { let x: u8 = -1; }")[0].message,
+            "cannot negate a value of unsigned type `u8`"
+        );
+        assert_eq!(
+            errors("fn f(b: bool) bool { return ~b; }")[0].message,
+            "operator `~` expects an integer operand but found `bool`"
+        );
+        assert_eq!(
+            errors("fn f(b: bool) bool { return b * b; }")[0].message,
+            "operator `*` expects integer operands but found `bool`"
+        );
+    }
+
+    #[test]
+    fn constants_fold() {
+        let input = "
+            const A: i8 = -128;
+            static B: i8 = -A;
+            static C: u8 = ~1;
+            static D: bool = !(A < 0);
+            static E: u16 = (1 << 15) + 7;
+            static F: i32 = -7 / 2 + -7 % 2;
+        ";
+        let program = check("test.ly", &decls(input)).unwrap();
+        let values: Vec<_> = program
+            .statics
+            .iter()
+            .map(|s| match s.value.as_ref().map(|value| &value.kind) {
+                Some(typed::ExprKind::Int(x)) => *x,
+                Some(typed::ExprKind::Bool(b)) => *b as i128,
+                _ => panic!("`{}` is not folded", s.name),
+            })
+            .collect();
+        assert_eq!(values, [-128, 254, 0, 32775, -4]);
+    }
+
+    #[test]
+    fn extern_functions() {
+        let input = "
+            extern \"C\" fn printf(fmt: *i8, ...) i32;
+            fn f() i32 { printf(\"%d %s\\n\", 5, \"five\") }
+        ";
+        let program = check("test.ly", &decls(input)).unwrap();
+        assert!(program.functions[0].body.is_none());
+        assert_eq!(program.functions[0].symbol, "printf");
+
+        assert_eq!(
+            errors("extern fn abs(x: i32) i32; fn f() i32 { abs(true) }")[0].message,
+            "expected a value of type `i32` but found `bool`"
+        );
+        assert_eq!(
+            errors("extern fn abs(x: i32) i32; fn f() i32 { abs(1, 2) }")[0].message,
+            "function `abs` takes 1 arguments but 2 were given"
+        );
+        assert_eq!(
+            errors("extern fn printf(fmt: *i8, ...) i32; fn f() i32 { printf() }")[0].message,
+            "function `printf` takes at least 1 arguments but 0 were given"
+        );
+        assert_eq!(
+            errors("extern \"Rust\" fn f(x: i32) i32;")[0].message,
+            "unsupported ABI `Rust` for extern function `f`"
+        );
+    }
+
+    #[test]
+    fn pointers_and_references() {
+        let input = "
+            fn f(m: &mut i64, p: *i64) i64 {
+                *m = 2;
+                let r: &i64 = m;
+                let q: *i64 = null;
+                q = p + 1;
+                *q + *r + (q - p)
+            }
+        ";
+        check("test.ly", &decls(input)).unwrap();
+
+        assert_eq!(
+            errors("fn f() { let x = 1; let p = &x; *p = 2; }")[0].message,
+            "cannot assign to `(Asterisk p)`, it is immutable or behind a shared reference"
+        );
+        assert_eq!(
+            errors("fn f(r: &i64) { let m: &mut i64 = r; }")[0].message,
+            "expected a value of type `&mut i64` but found `&i64`"
+        );
+        assert_eq!(
+            errors("fn f(p: *i64) { let q: *u8 = p; }")[0].message,
+            "expected a value of type `*u8` but found `*i64`"
+        );
+        assert_eq!(
+            errors("fn f(x: i64) i64 { *x }")[0].message,
+            "cannot dereference a value of type `i64`"
+        );
+        assert_eq!(
+            errors("fn f(p: *void) i64 { *p }")[0].message,
+            "cannot dereference a value of type `*void`"
+        );
+        assert_eq!(
+            errors("fn f() { let p = &5; }")[0].message,
+            "`5` is not a place expression"
+        );
+    }
+
+    #[test]
+    fn arrays_and_slices() {
+        let input = "
+            fn sum(s: [i64]) i64 { if s.len == 0 { 0 } else { s[0] + s[s.len - 1] } }
+            fn f(a: &mut [u8; 2]) i64 {
+                let b: [i64; 3] = [1, 2, 3];
+                a[1] = 7;
+                sum(&b) + a[0] as i64
+            }
+        ";
+        check("test.ly", &decls(input)).unwrap();
+
+        assert_eq!(
+            errors("fn f() { let a: [i64; 3] = [1, 2]; }")[0].message,
+            "expected a value of type `[i64; 3]` but found `[i64; 2]`"
+        );
+        assert_eq!(
+            errors("fn f() { let a = []; }")[0].message,
+            "cannot infer the type of an empty array"
+        );
+        assert_eq!(
+            errors("fn f(a: [i64; 3]) i64 { a[true] }")[0].message,
+            "array indices must be integers but found `bool`"
+        );
+        assert_eq!(
+            errors("fn f(x: i64) i64 { x[0] }")[0].message,
+            "cannot index into a value of type `i64`"
+        );
+        assert_eq!(
+            errors("fn f(s: [i64]) { s.len = 2; }")[0].message,
+            "cannot assign to `s.len`, it is immutable or behind a shared reference"
+        );
+    }
+
+    #[test]
+    fn shifts() {
+        let input = "
+            const A: i8 = -1 >> 1;
+            static B: u8 = ~0 >> 4;
+            fn f(x: u8, n: i32) u8 {
+                x <<= 3;
+                x >>= n;
+                ~x << n
+            }
+        ";
+        check("test.ly", &decls(input)).unwrap();
+
+        assert_eq!(
+            errors("fn f(b: bool) bool { b << 1 }")[0].message,
+            "operator `<<` expects integer operands but found `bool`"
+        );
+        assert_eq!(
+            errors("fn f(b: bool, n: u8) { b >>= n; }")[0].message,
+            "operator `>>` expects integer operands but found `bool`"
+        );
+        assert_eq!(
+            errors("fn f(x: u8) { x <<= true; }")[0].message,
+            "shift amounts must be integers but found `bool`"
+        );
+        assert_eq!(
+            errors("const A: u8 = 1 << 8;")[0].message,
+            "shift amount in `(1 ShiftLeft 8)` is too large for `u8`"
+        );
+    }
+
+    #[test]
+    fn unwrap_operator() {
+        let input = "
+            fn maybe(b: bool) ?i64 { if b { 5 } else { null } }
+            fn f(p: *i64, x: ?bool) i64 {
+                if maybe(true) == null || !x! { 0 } else { maybe(true)! + *p! }
+            }
+        ";
+        check("test.ly", &decls(input)).unwrap();
+
+        assert_eq!(
+            errors("fn f(x: i64) i64 { x! }")[0].message,
+            "operator `!` expects an optional value or a raw pointer but found `i64`"
+        );
+        assert_eq!(
+            errors("fn f(x: ?i64) { let y: i64 = x; }")[0].message,
+            "expected a value of type `i64` but found `?i64`"
+        );
+    }
+
+    #[test]
+    fn block_expressions() {
+        let input = "
+            fn f(c: bool) i64 {
+                let x = if c { 1 } else { 2 };
+                let y: u8 = { let z: u8 = 3; z };
+                if c { return x; } else { x + y as i64 }
+            }
+        ";
+        check("test.ly", &decls(input)).unwrap();
+
+        assert_eq!(
+            errors("fn f(c: bool) i64 { if c { 1 } else { true } }")[0].message,
+            "expected a value of type `i64` but found `bool`"
+        );
+        assert_eq!(
+            errors("fn f(c: bool) i64 { if c { 1 } }")[0].message,
+            "expected a value of type `i64` but found `void`"
+        );
+        assert_eq!(
+            errors("fn f() i64 { 1; }")[0].message,
+            "expected a value of type `i64` but found `void`"
+        );
+        assert_eq!(
+            errors("fn f() { let x = { 1; }; }")[0].message,
+            "cannot bind `x` to a value of type `void`"
+        );
+    }
+
+    #[test]
+    fn globals() {
+        let input = "
+            const LIMIT: u8 = 250;
+            static TABLE: [i64; 2] = [LIMIT as i64, 2];
+            static mut TOTAL: i64 = 5;
+            fn f() i64 { TOTAL += 1; TOTAL + TABLE[1] + LIMIT as i64 }
+        ";
+        check("test.ly", &decls(input)).unwrap();
+
+        assert_eq!(
+            errors("const A: i64 = 1; fn f() { A = 2; }")[0].message,
+            "cannot assign to `A`, it is immutable or behind a shared reference"
+        );
+        assert_eq!(
+            errors("static B: i64 = 1; fn f() { B = 2; }")[0].message,
+            "cannot assign to `B`, it is immutable or behind a shared reference"
+        );
+        assert_eq!(
+            errors("fn g() i64 { 1 } const A: i64 = g();")[0].message,
+            "the initializer of `A` is not a constant expression"
+        );
+        assert_eq!(
+            errors("static A: u8 = 256;")[0].message,
+            "`256` is out of range for type `u8`"
+        );
+        assert_eq!(
+            errors("const A: i8 = 127 + 1;")[0].message,
+            "`(127 Plus 1)` is out of range for type `i8`"
+        );
+        assert_eq!(
+            errors("const A: i64 = 1 / 0;")[0].message,
+            "division by zero in `(1 Slash 0)`"
+        );
+    }
+
+    #[test]
+    fn enums_and_match() {
+        let input = "
+            enum Color { Red, Green = 5, Blue }
+            enum Shape { Circle(i64), Rect(i64, i64), Empty }
+            fn area(s: Shape) i64 {
+                match s {
+                    Shape::Circle(r) => 3 * r * r,
+                    Shape::Rect(w, h) => w * h,
+                    Shape::Empty => 0,
+                }
+            }
+            fn f(c: Color, b: bool, x: u8) i64 {
+                let c = match c { Color::Red => 1, Color::Green | Color::Blue => 2 };
+                let b = match b { true => 1, false => 0 };
+                c + b + match x { 0 | 1 => 1, n => n as i64 }
+            }
+        ";
+        let program = check("test.ly", &decls(input)).unwrap();
+        let color = program.enum_of(&ast::Type::new_simple("Color")).unwrap();
+        let discriminants: Vec<_> = color.variants.iter().map(|variant| variant.tag).collect();
+        assert_eq!(discriminants, [0, 5, 6]);
+
+        assert_eq!(
+            errors("enum C { A, B } fn f(c: C) i64 { match c { C::A => 1 } }")[0].message,
+            "non-exhaustive match on `C`, missing `C::B`"
+        );
+        assert_eq!(
+            errors("fn f(b: bool) i64 { match b { true => 1 } }")[0].message,
+            "non-exhaustive match on `bool`, missing `false`"
+        );
+        assert_eq!(
+            errors("fn f(x: u8) i64 { match x { 0 => 1 } }")[0].message,
+            "non-exhaustive match on `u8`, missing `_`"
+        );
+        assert_eq!(
+            errors("enum C { A = 1, B = 1 }")[0].message,
+            "discriminant 1 of `C::B` is already used"
+        );
+        assert_eq!(
+            errors(
+                "enum S { P(i64), Q } fn f(s: S) i64 { match s { S::P(a, b) => a, S::Q => 0 } }"
+            )[0]
+            .message,
+            "variant `S::P` has 1 fields but the pattern binds 2"
+        );
+        assert_eq!(
+            errors("enum C { A, B } fn f(c: C) i64 { match c { 1 => 0, _ => 1 } }")[0].message,
+            "expected a variant of `C` but found `1`"
+        );
+        assert_eq!(
+            errors("enum S { P(i64), Q } fn f(s: S, t: S) bool { s == t }")[0].message,
+            "operator `==` cannot compare values of type `S`"
+        );
+    }
+
+    #[test]
+    fn generic_instances() {
+        let input = "
+            struct Pair<A, B> { a: A, b: B }
+            fn max<T>(a: T, b: T) T { if a > b { a } else { b } }
+            fn first<A, B>(p: Pair<A, B>) A { p.a }
+            fn f() i64 {
+                let p = Pair { a: max(1 as u8, 2 as u8), b: true };
+                max(3, 4) + first(p) as i64 + max(5, 6)
+            }
+        ";
+        let program = check("test.ly", &decls(input)).unwrap();
+        let mut instances: Vec<_> = program
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.symbol.as_str()))
+            .collect();
+        instances.sort();
+        // one instance per list of type arguments, the generic functions
+        // themselves aren't in the program
+        assert_eq!(
+            instances,
+            [
+                ("f", "_LN1fE"),
+                ("first<u8, bool>", "_LN5firstEIhbE"),
+                ("max<i64>", "_LN3maxEIlE"),
+                ("max<u8>", "_LN3maxEIhE"),
+            ]
+        );
+
+        assert_eq!(
+            errors("fn g<T>() T { g() } fn f() { g(); }")[0].message,
+            "cannot infer type parameter `T` of `g`"
+        );
+        assert_eq!(
+            errors("struct P<A, B> { a: A, b: B } fn f() { let p: P<i64> = P { a: 1, b: 2 }; }")[0]
+                .message,
+            "struct `P` takes 2 type arguments but 1 were given"
+        );
+        assert_eq!(
+            errors("fn max<T>(a: T, b: T) T { a } fn f() i64 { max(1 as i32, 2 as i64) }")[0]
+                .message,
+            "expected a value of type `i32` but found `i64`"
+        );
+        // instances are checked with their type arguments
+        let input = "fn max<T>(a: T, b: T) T { if a > b { a } else { b } }
+                     fn f() bool { max(true, false) }";
+        let error = &errors(input)[0];
+        assert_eq!(
+            (error.line, error.col, error.message.as_str()),
+            (
+                1,
+                1,
+                "operator `>` expects integer or pointer operands but found `bool`"
+            )
+        );
+    }
+
+    #[test]
+    fn methods() {
+        let input = "
+            struct Point { x: i64, y: i64 }
+            impl Point {
+                fn new(x: i64, y: i64) Point { Point { x: x, y: y } }
+                fn len(self) i64 { self.x * self.x + self.y * self.y }
+                fn get(&self) i64 { self.x }
+                fn scale(&mut self, k: i64) { self.x *= k; }
+            }
+            fn len(p: Point) i64 {
+                let q = Point::new(1, 2);
+                q.scale(2);
+                p.len() + q.get()
+            }
+        ";
+        let program = check("test.ly", &decls(input)).unwrap();
+        let symbols: Vec<_> = program
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.symbol.as_str()))
+            .collect();
+        // methods don't collide with free functions of the same name
+        assert_eq!(
+            symbols,
+            [
+                ("len", "_LN3lenE"),
+                ("Point::new", "_LN5Point3newE"),
+                ("Point::len", "_LN5Point3lenE"),
+                ("Point::get", "_LN5Point3getE"),
+                ("Point::scale", "_LN5Point5scaleE"),
+            ]
+        );
+
+        let point = "struct P { x: i64 }";
+        assert_eq!(
+            errors(&format!(
+                "{} impl P {{ fn a(self) {{}} }} fn f(p: P) {{ p.b(); }}",
+                point
+            ))[0]
+                .message,
+            "no method `b` on type `P`"
+        );
+        assert_eq!(
+            errors(&format!(
+                "{} impl P {{ fn set(&mut self) {{ self.x = 1; }} }} fn f(p: &P) {{ p.set(); }}",
+                point
+            ))[0]
+                .message,
+            "cannot borrow `p` as mutable, it is immutable or behind a shared reference"
+        );
+        assert_eq!(
+            errors(&format!(
+                "{} impl P {{ fn set(&self) {{ self.x = 1; }} }}",
+                point
+            ))[0]
+                .message,
+            "cannot assign to `self.x`, it is immutable or behind a shared reference"
+        );
+        assert_eq!(
+            errors(&format!(
+                "{} impl P {{ fn a(self) {{}} fn a(self) {{}} }}",
+                point
+            ))[0]
+                .message,
+            "function `P::a` is declared more than once"
+        );
+        assert_eq!(
+            errors("impl Q { fn a(self) {} }")[0].message,
+            "unknown type `Q`"
+        );
+    }
+
+    #[test]
+    fn traits() {
+        let input = "
+            trait Shape {
+                fn area(&self) i64;
+                fn twice(&self) i64 { self.area() * 2 }
+            }
+            struct Circle { r: i64 }
+            struct Rect { w: i64, h: i64 }
+            impl Shape for Circle { fn area(&self) i64 { 3 * self.r * self.r } }
+            impl Shape for Rect {
+                fn area(&self) i64 { self.w * self.h }
+                fn twice(&self) i64 { 1000 }
+            }
+            fn total<T: Shape>(s: &T) i64 { s.area() + s.twice() }
+            fn f(c: &Circle, r: &Rect) i64 {
+                let d: &dyn Shape = r;
+                total(c) + d.twice()
+            }
+        ";
+        let program = check("test.ly", &decls(input)).unwrap();
+        // only types made into trait objects have a vtable
+        assert_eq!(program.vtables.len(), 1);
+        assert_eq!(
+            program.vtables["<Rect as Shape>"].methods,
+            ["<Rect as Shape>::area", "<Rect as Shape>::twice"]
+        );
+        // `Circle` gets the default `twice`
+        assert!(program
+            .functions
+            .iter()
+            .any(|function| function.name == "<Circle as Shape>::twice"));
+
+        let shape = "trait S { fn area(&self) i64; } struct C { r: i64 }";
+        assert_eq!(
+            errors(&format!("{} impl S for C {{ }}", shape))[0].message,
+            "`C` is missing `area` of trait `S`"
+        );
+        assert_eq!(
+            errors(&format!(
+                "{} impl S for C {{ fn area(&self) i32 {{ 1 }} }}",
+                shape
+            ))[0]
+                .message,
+            "`area` of `C` does not match its declaration in trait `S`"
+        );
+        assert_eq!(
+            errors(&format!(
+                "{} impl S for C {{ fn area(&self) i64 {{ 1 }} fn x(&self) {{}} }}",
+                shape
+            ))[0]
+                .message,
+            "`x` is not a function of trait `S`"
+        );
+        assert_eq!(
+            errors(&format!(
+                "{} fn t<T: S>(s: &T) i64 {{ s.area() }} fn f(c: C) i64 {{ t(&c) }}",
+                shape
+            ))[0]
+                .message,
+            "`C` does not implement trait `S`, required by `t`"
+        );
+        assert_eq!(
+            errors(&format!("{} fn f(c: &C) {{ let d: &dyn S = c; }}", shape))[0].message,
+            "`C` does not implement trait `S`"
+        );
+        assert_eq!(
+            errors(&format!("{} fn f(s: &dyn S) i64 {{ s.other() }}", shape))[0].message,
+            "no method `other` on `dyn S`"
+        );
+        assert_eq!(
+            errors("impl Nope for i64 {}")[0].message,
+            "unknown trait `Nope`"
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        let input = "
+            #[inline(always)] #[cold] #[section = \".hot\"] fn f() {}
+            #[noinline] #[weak] #[frobnicate] fn g() {}
+            #[align(16)] #[section = \".tables\"] static A: i64 = 1;
+        ";
+        let program = check("test.ly", &decls(input)).unwrap();
+        let attrs: Vec<_> = program
+            .functions
+            .iter()
+            .map(|function| &function.attrs[..])
+            .chain([&program.statics[0].attrs[..]])
+            .collect();
+        assert_eq!(
+            attrs,
+            [
+                &[
+                    typed::Attribute::Section(String::from(".hot")),
+                    typed::Attribute::Function("alwaysinline"),
+                    typed::Attribute::Function("cold"),
+                ][..],
+                &[
+                    typed::Attribute::Weak,
+                    typed::Attribute::Function("noinline"),
+                ],
+                &[
+                    typed::Attribute::Align(16),
+                    typed::Attribute::Section(String::from(".tables")),
+                ],
+            ]
+        );
+
+        assert_eq!(
+            errors("#[inline(sometimes)] fn f() {}")[0].message,
+            "malformed attribute `#[inline(sometimes)]` on `f`"
+        );
+        assert_eq!(
+            errors("#[align(3)] static A: i64 = 1;")[0].message,
+            "alignment of `A` must be a power of two"
+        );
+        assert_eq!(
+            errors("#[cold] static A: i64 = 1;")[0].message,
+            "`#[cold]` can only be used on functions"
+        );
+        assert_eq!(
+            errors("#[inline] #[noinline] fn f() {}")[0].message,
+            "`#[noinline]` conflicts with `#[inline]` on `f`"
+        );
+    }
+
+    #[test]
+    fn inline_keyword() {
+        let input = "inline fn f() {} #[cold] inline(always) fn g() {}";
+        let program = check("test.ly", &decls(input)).unwrap();
+        assert_eq!(
+            program.functions[0].attrs,
+            [typed::Attribute::Function("inlinehint")]
+        );
+        // the keyword comes before the attributes
+        assert_eq!(
+            program.functions[1].attrs,
+            [
+                typed::Attribute::Function("alwaysinline"),
+                typed::Attribute::Function("cold"),
+            ]
+        );
+
+        assert_eq!(
+            errors("#[inline] inline fn f() {}")[0].message,
+            "`#[inline]` conflicts with `inline` on `f`"
+        );
+        assert_eq!(
+            errors("#[noinline] inline fn f() {}")[0].message,
+            "`#[noinline]` conflicts with `inline` on `f`"
+        );
+    }
+}
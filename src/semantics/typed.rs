@@ -0,0 +1,356 @@
+//! The typed program `check` turns the syntax tree into. Every expression
+//! carries its type, untyped literals have taken on the type their
+//! context expects, implicit conversions are explicit nodes, names are
+//! resolved to locals, statics and functions, and generic functions are
+//! instantiated. The backends and the interpreter only lower it.
+
+use super::Traits;
+use crate::lexer::token::TokenKind;
+use crate::parser::ast;
+use std::collections::HashMap;
+
+/// A checked program.
+#[derive(Default)]
+pub struct Program {
+    /// Functions and extern functions in declaration order, followed by
+    /// the instances of generic functions in the order they were needed.
+    pub functions: Vec<Function>,
+    pub statics: Vec<Static>,
+    pub structs: HashMap<String, Struct>,
+    pub enums: HashMap<String, Enum>,
+    pub traits: Traits,
+    /// The vtables trait objects are made from, by `<Type as Trait>`.
+    pub vtables: HashMap<String, Vtable>,
+}
+
+impl Program {
+    /// The members of a struct type with its type arguments filled in,
+    /// `None` if `typ` is not a struct.
+    pub fn struct_members(&self, typ: &ast::Type) -> Option<Vec<(String, ast::Type)>> {
+        let (name, generics) = match typ {
+            ast::Type::Named { name, generics } => (name, generics),
+            _ => return None,
+        };
+        let decl = self.structs.get(name)?;
+        Some(decl.members(generics))
+    }
+
+    pub fn enum_of(&self, typ: &ast::Type) -> Option<&Enum> {
+        match typ {
+            ast::Type::Named { name, generics } if generics.is_empty() => self.enums.get(name),
+            _ => None,
+        }
+    }
+
+    /// The object methods of the trait `trait_name`, in vtable order.
+    pub fn object_methods(&self, trait_name: &str) -> Vec<ast::TraitFunction> {
+        self.traits
+            .object_methods(trait_name)
+            .unwrap_or_else(|_| unreachable!("checked programs only use known object-safe traits"))
+    }
+}
+
+/// A function with its parameters and return type resolved. Extern
+/// functions have no body.
+pub struct Function {
+    /// How calls refer to it: the declared name, `Type::method` or
+    /// `<Type as Trait>::method`, and `name<T, U>` for instances.
+    pub name: String,
+    pub symbol: String,
+    pub attrs: Vec<Attribute>,
+    pub params: Vec<ast::Type>,
+    pub variadic: bool,
+    pub rtyp: Option<ast::Type>,
+    pub body: Option<Expr>,
+    /// The variables of the body, the parameters come first.
+    pub locals: Vec<Local>,
+    pub file: String,
+    pub pos: ast::Pos,
+}
+
+/// An attribute of a function or static that changes how it is emitted.
+/// `section`, `align` and `weak` come before the function attributes,
+/// each in the order they are declared. Attributes naming symbols are
+/// applied to `symbol` already, and unknown ones are left out.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Attribute {
+    Section(String),
+    Align(u32),
+    Weak,
+    /// `inlinehint`, `alwaysinline`, `noinline`, `cold` or `noreturn`, as
+    /// LLVM names them. The `inline` keyword comes first.
+    Function(&'static str),
+}
+
+/// A variable of a function, a parameter, a `let` or a name bound by a
+/// pattern. Shadowing introduces a new local.
+#[derive(Clone, Debug)]
+pub struct Local {
+    pub name: String,
+    pub typ: ast::Type,
+}
+
+/// A global variable. Without an initializer it is zero-initialized, or
+/// defined by a module built before, like the bindings of the REPL.
+pub struct Static {
+    pub name: String,
+    pub symbol: String,
+    pub attrs: Vec<Attribute>,
+    pub mutable: bool,
+    pub typ: ast::Type,
+    /// A constant expression whose integer and `bool` parts are folded.
+    pub value: Option<Expr>,
+}
+
+pub struct Struct {
+    pub type_params: Vec<String>,
+    pub members: Vec<(String, ast::Type)>,
+}
+
+impl Struct {
+    /// The members with the type parameters bound to `generics`.
+    pub fn members(&self, generics: &[ast::Type]) -> Vec<(String, ast::Type)> {
+        let args = self
+            .type_params
+            .iter()
+            .cloned()
+            .zip(generics.iter().cloned())
+            .collect();
+        self.members
+            .iter()
+            .map(|(member, typ)| (member.clone(), super::substitute(typ, &args)))
+            .collect()
+    }
+}
+
+/// An enum. Enums without payloads are represented by their
+/// discriminant alone.
+pub struct Enum {
+    pub variants: Vec<Variant>,
+}
+
+impl Enum {
+    pub fn has_payload(&self) -> bool {
+        self.variants
+            .iter()
+            .any(|variant| !variant.fields.is_empty())
+    }
+}
+
+#[derive(Clone)]
+pub struct Variant {
+    pub name: String,
+    pub tag: i32,
+    pub fields: Vec<ast::Type>,
+}
+
+/// The implementation of a trait by a type, as trait objects call it.
+pub struct Vtable {
+    pub typ: ast::Type,
+    pub trait_name: String,
+    /// The functions implementing the object methods of the trait.
+    pub methods: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub typ: ast::Type,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, typ: ast::Type) -> Self {
+        Expr { kind, typ }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ExprKind {
+    Int(i128),
+    Bool(bool),
+    Str(String),
+    /// A null pointer, an empty optional or a null trait object, as `typ`
+    /// says.
+    Null,
+    Load(Box<Place>),
+    AddrOf(Box<Place>),
+    /// Calls a function with arguments of its parameter types. Variadic
+    /// arguments are promoted like C does.
+    Call {
+        function: String,
+        args: Vec<Expr>,
+    },
+    /// Calls the object method `method` of a trait object, whose data
+    /// pointer is passed as `self`.
+    DynCall {
+        object: Box<Expr>,
+        method: usize,
+        args: Vec<Expr>,
+    },
+    Variant {
+        variant: usize,
+        fields: Vec<Expr>,
+    },
+    /// The members of a struct by their index, in the order the literal
+    /// gives them, which is the order they are evaluated in.
+    Struct(Vec<(usize, Expr)>),
+    Array(Vec<Expr>),
+    /// `-`, `!` or `~`.
+    Unary {
+        op: TokenKind,
+        operand: Box<Expr>,
+    },
+    /// Arithmetic, bitwise operators and comparisons on operands of the
+    /// same type. The amount of a shift has the type of the shifted value.
+    Binary {
+        op: TokenKind,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// `&&` and `||`, which evaluate `right` only if `left` doesn't decide
+    /// the result.
+    Logical {
+        op: TokenKind,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// A raw pointer moved by an integer number of elements.
+    Offset {
+        pointer: Box<Expr>,
+        offset: Box<Expr>,
+        negate: bool,
+    },
+    /// The distance in elements between two raw pointers, an `i64`.
+    Distance {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// Converts between integers, from `bool` and enums without payload to
+    /// integers, and between pointers and integers.
+    Cast(Box<Expr>),
+    /// Whether an optional or a raw pointer is empty or null, or the
+    /// opposite.
+    NullCheck {
+        operand: Box<Expr>,
+        is_null: bool,
+    },
+    /// The value of an optional or a raw pointer, trapping if there is
+    /// none.
+    Unwrap(Box<Expr>),
+    Some(Box<Expr>),
+    /// A pointer or reference turned into a trait object with the vtable
+    /// of its type.
+    Object {
+        pointer: Box<Expr>,
+        vtable: String,
+    },
+    /// A pointer or reference to an array turned into a slice.
+    Slice {
+        pointer: Box<Expr>,
+        len: usize,
+    },
+    /// The length of an array or slice, an `i64`.
+    Len(Box<Place>),
+    /// The pointer to the first element of a slice.
+    Ptr(Box<Place>),
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        elze: Option<Box<Expr>>,
+    },
+    Block {
+        stmts: Vec<Stmt>,
+        tail: Option<Box<Expr>>,
+    },
+    /// Selects the first arm whose cases contain the discriminant or value
+    /// of the scrutinee. Unreachable arms are left out and a missing match
+    /// can't happen.
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<Arm>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Arm {
+    /// The discriminants or values the arm matches, each only in the first
+    /// arm having it. `None` for a catch-all.
+    pub cases: Option<Vec<i128>>,
+    pub captures: Vec<Capture>,
+    pub body: Expr,
+}
+
+/// A local bound by a pattern to a copy of the scrutinee, or to the field
+/// `index` of the variant `variant` of that copy.
+#[derive(Clone, Debug)]
+pub struct Capture {
+    pub local: usize,
+    pub field: Option<(usize, usize)>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Stmt {
+    Let {
+        local: usize,
+        value: Expr,
+        pos: ast::Pos,
+    },
+    /// Stores `value` at `place`. With `op` it is combined with the value
+    /// there first, evaluating the place only once. A raw pointer is moved
+    /// by `+` and `-`.
+    Assign {
+        place: Place,
+        op: Option<TokenKind>,
+        value: Expr,
+        pos: ast::Pos,
+    },
+    Expr {
+        value: Expr,
+        pos: ast::Pos,
+    },
+    Return {
+        value: Option<Expr>,
+        pos: ast::Pos,
+    },
+}
+
+impl Stmt {
+    pub fn pos(&self) -> ast::Pos {
+        match self {
+            Stmt::Let { pos, .. }
+            | Stmt::Assign { pos, .. }
+            | Stmt::Expr { pos, .. }
+            | Stmt::Return { pos, .. } => *pos,
+        }
+    }
+}
+
+/// A memory location of type `typ`.
+#[derive(Clone, Debug)]
+pub struct Place {
+    pub kind: PlaceKind,
+    pub typ: ast::Type,
+    /// Whether it can be assigned to or borrowed mutably.
+    pub mutable: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum PlaceKind {
+    Local(usize),
+    /// A static by its symbol.
+    Static(String),
+    /// A new slot holding the value of an expression.
+    Temporary(Box<Expr>),
+    /// What a pointer or reference points to.
+    Deref(Box<Expr>),
+    Field {
+        base: Box<Place>,
+        index: usize,
+    },
+    /// An element of an array or a slice, bounds checked. `index` is an
+    /// `i64`.
+    Index {
+        base: Box<Place>,
+        index: Box<Expr>,
+    },
+}
@@ -0,0 +1,155 @@
+//! Runs the programs in `tests/programs` with every backend, the
+//! interpreter, the JIT, object files and generated C, and checks that they
+//! print the same and exit with the same status. Programs that trap define
+//! the panic handlers to exit with a status of their own, as a trap would
+//! kill the compiled ones with a signal and panic the interpreter.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const LYTH: &str = env!("CARGO_BIN_EXE_lyth");
+
+/// What a backend ran the program as and how it went.
+struct Run {
+    backend: &'static str,
+    status: Option<i32>,
+    stdout: String,
+}
+
+fn program(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/programs")
+        .join(name)
+        .with_extension("ly")
+}
+
+/// A fresh directory for the files the backends compiling ahead of time
+/// write, which always go to the working directory.
+fn scratch(name: &str, backend: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "lyth-differential-{}-{}-{}",
+        std::process::id(),
+        name,
+        backend
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("cannot create a scratch directory");
+    std::fs::copy(program(name), dir.join("test.ly")).expect("cannot copy the program");
+    dir
+}
+
+fn output(command: &mut Command) -> Output {
+    command
+        .output()
+        .unwrap_or_else(|err| panic!("cannot run {:?}: {}", command, err))
+}
+
+/// Runs `command` and checks that it succeeds, for the steps building a
+/// program.
+fn build(command: &mut Command) {
+    let output = output(command);
+    assert!(
+        output.status.success(),
+        "{:?} failed:\n{}",
+        command,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn cc() -> Command {
+    Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+}
+
+fn finish(backend: &'static str, output: Output) -> Run {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        !stderr.contains("panicked"),
+        "lyth panicked running the program with the {}:\n{}",
+        backend,
+        stderr
+    );
+
+    Run {
+        backend,
+        status: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+    }
+}
+
+fn interpret(name: &str) -> Run {
+    let output = output(
+        Command::new(LYTH)
+            .args(["run", "--interp"])
+            .arg(program(name)),
+    );
+    finish("interpreter", output)
+}
+
+#[cfg(feature = "llvm")]
+fn jit(name: &str) -> Run {
+    let output = output(Command::new(LYTH).arg("run").arg(program(name)));
+    finish("JIT", output)
+}
+
+#[cfg(feature = "llvm")]
+fn object(name: &str) -> Run {
+    let dir = scratch(name, "object");
+    build(Command::new(LYTH).current_dir(&dir));
+    build(cc().current_dir(&dir).args(["a.out", "-o", "program"]));
+
+    let output = output(&mut Command::new(dir.join("program")));
+    let _ = std::fs::remove_dir_all(&dir);
+    finish("object file", output)
+}
+
+fn c(name: &str) -> Run {
+    let dir = scratch(name, "c");
+    build(Command::new(LYTH).arg("--backend=c").current_dir(&dir));
+    build(
+        cc().current_dir(&dir)
+            .args(["-std=c99", "a.c", "-o", "program"]),
+    );
+
+    let output = output(&mut Command::new(dir.join("program")));
+    let _ = std::fs::remove_dir_all(&dir);
+    finish("C backend", output)
+}
+
+fn differential(name: &str) {
+    let mut runs = vec![interpret(name)];
+    #[cfg(feature = "llvm")]
+    runs.extend([jit(name), object(name)]);
+    runs.push(c(name));
+
+    let expected = &runs[0];
+    for run in &runs[1..] {
+        assert_eq!(
+            (run.status, &run.stdout),
+            (expected.status, &expected.stdout),
+            "{}: the {} and the {} disagree",
+            name,
+            run.backend,
+            expected.backend
+        );
+    }
+}
+
+macro_rules! programs {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                differential(stringify!($name));
+            }
+        )*
+    };
+}
+
+programs! {
+    arithmetic,
+    arith_trap,
+    bounds_trap,
+    enums,
+    generics,
+    traits,
+}
@@ -0,0 +1,73 @@
+//! Builds a program with LLVM's opaque pointers, the only pointers LLVM 17
+//! and later have, and calls it through the JIT. Older LLVM releases need
+//! them enabled before the first context is created, and they stay
+//! enabled for the whole process, so this test runs in a binary of its
+//! own.
+
+#![cfg(feature = "llvm")]
+
+use lyth::compiler::{Compiler, Jit};
+use lyth::parser::Parser;
+use std::ffi::CString;
+
+const PROGRAM: &str = "
+    struct Node { value: i64, next: *Node }
+    struct Pair<T> { a: T, b: T }
+    trait Shape { fn area(&self) i64; }
+    struct Rect { w: i64, h: i64 }
+    impl Shape for Rect { fn area(&self) i64 { self.w * self.h } }
+    enum List { Cons(i64, *List), Nil }
+
+    static mut CALLS: i64 = 0;
+
+    fn length(n: *Node) i64 { if n == null { 0 } else { 1 + length(n.next) } }
+    fn sum(xs: [i64], i: i64) i64 { if i == xs.len { 0 } else { xs[i] + sum(xs, i + 1) } }
+    fn total(l: *List) i64 {
+        match *l {
+            List::Cons(v, next) => v + total(next),
+            List::Nil => 0,
+        }
+    }
+    fn larger<T>(p: &Pair<T>) T { if p.a > p.b { p.a } else { p.b } }
+    fn area(s: &dyn Shape) i64 { s.area() }
+    fn bump(counter: &mut i64) { *counter += 1; }
+
+    fn run(x: i64) i64 {
+        bump(&mut CALLS);
+        let n3 = Node { value: 3, next: null };
+        let n2 = Node { value: 2, next: &n3 };
+        let n1 = Node { value: 1, next: &n2 };
+        let arr: [i64; 3] = [x, 20, 30];
+        arr[1] += 1;
+        let p = &arr;
+        let nil = List::Nil;
+        let two = List::Cons(2, &nil);
+        let r = Rect { w: 2, h: 5 };
+        let pair = Pair { a: x, b: 7 };
+        CALLS * 10000000 + length(&n1) * 1000000 + sum(p, 0) * 1000 + total(&two) * 100
+            + area(&r) + larger(&pair)
+    }
+";
+
+#[test]
+fn opaque_pointers() {
+    let args = [
+        CString::new("lyth").unwrap(),
+        CString::new("-opaque-pointers").unwrap(),
+    ];
+    let args: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+    unsafe {
+        llvm_sys::support::LLVMParseCommandLineOptions(2, args.as_ptr(), std::ptr::null());
+    }
+
+    let items = Parser::new("test.ly", PROGRAM).parse().unwrap();
+    let mut compiler = Compiler::new("test.ly");
+    compiler
+        .build(items.into_iter().map(|item| item.decl).collect())
+        .unwrap();
+    let jit = Jit::new(compiler.into_module());
+
+    let run = unsafe { jit.call::<extern "C" fn(i64) -> i64>("run") };
+    assert_eq!(run(10), 10_000_000 + 3_000_000 + 61_000 + 200 + 10 + 10);
+    assert_eq!(run(4), 20_000_000 + 3_000_000 + 55_000 + 200 + 10 + 7);
+}